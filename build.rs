@@ -15,6 +15,9 @@ fn main() {
     // Rust version
     let rust_version = rustc_version();
 
+    // Commit the binary was built from
+    let git_sha = git_sha();
+
     // Read environment variable for dep versions, output by Cargo, via cargo metadata.
     // Use cargo_metadata to collect dependencies & versions
     let deps = get_lib_version_map().unwrap();
@@ -57,9 +60,11 @@ pub const PROJECT_NAME: &str = {pkg_name:?};
 pub const PROJECT_VERSION: &str = {pkg_version:?};
 pub const BUILD_TIME_UTC: &str = "{}";
 pub const RUSTC_VERSION: &str = {rust_version:?};
+pub const GIT_SHA: &str = {git_sha:?};
 pub const LIB_VERSIONS: [LibVersion; {libs_count}] = ["#,
         build_time.to_rfc3339(),
         rust_version = rust_version,
+        git_sha = git_sha,
         pkg_name = pkg_name,
         pkg_version = pkg_version,
         libs_count = libs_count
@@ -96,6 +101,18 @@ fn rustc_version() -> String {
     }
 }
 
+fn git_sha() -> String {
+    use std::process::Command;
+    let output = Command::new("git").args(["rev-parse", "HEAD"]).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            stdout.trim().to_string()
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
 struct LibVersion {
     name: &'static str,
     version: &'static str,
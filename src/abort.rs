@@ -0,0 +1,49 @@
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::bail;
+
+/// Set once `MAX_RUNTIME_SECS` is exceeded. Async work is cancelled by
+/// dropping its future; blocking archive and dump tasks cannot be, so they
+/// poll this flag and stop at the next file or buffer.
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+pub fn trigger() {
+    ABORTED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_aborted() -> bool {
+    ABORTED.load(Ordering::Relaxed)
+}
+
+/// Fail if the run has been aborted.
+pub fn check() -> anyhow::Result<()> {
+    if is_aborted() {
+        bail!("Aborted: MAX_RUNTIME_SECS exceeded");
+    }
+    Ok(())
+}
+
+/// `std::io::copy` that stops with an `Interrupted` error once the run is
+/// aborted. Each buffer copied counts as progress for the systemd watchdog.
+pub fn copy<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> std::io::Result<u64> {
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut total: u64 = 0;
+    loop {
+        if is_aborted() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "aborted: MAX_RUNTIME_SECS exceeded",
+            ));
+        }
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+        crate::systemd::progress();
+    }
+}
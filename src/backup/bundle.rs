@@ -0,0 +1,372 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::BackupKind;
+use super::naming::{artifact_timestamp, claim_artifact_path};
+use crate::config::config::Config;
+
+/// Name of the manifest entry written at the root of every bundle.
+pub const BUNDLE_MANIFEST_NAME: &str = "manifest.json";
+
+/// Extension of a db bundle; plain dumps end in `.dump`, `.dump.zst` or `.sql.gz`.
+pub const BUNDLE_EXTENSION: &str = ".tar.zst";
+
+/// Directory inside the bundle holding the dumps, one subdirectory per
+/// database.
+const BUNDLE_DUMP_DIR: &str = "dumps";
+
+#[derive(Debug, Serialize)]
+pub struct BundleManifest {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub entries: Vec<BundleEntry>,
+}
+
+/// Maps a path inside the bundle back to the local file it was built from.
+#[derive(Debug, Serialize)]
+pub struct BundleEntry {
+    pub path: String,
+    pub database: String,
+    pub source_name: String,
+    pub size_bytes: u64,
+}
+
+/// Tar the dumps of a run, each paired with its database, into a single
+/// `db_bundle_<ts>.tar.zst`, with a `manifest.json` describing each inner
+/// file. The inputs are left in place for the caller to remove.
+pub async fn bundle_db_outputs(
+    config: &Config,
+    inputs: &[(String, PathBuf)],
+) -> anyhow::Result<PathBuf> {
+    let now = chrono::Utc::now();
+    let stem = format!(
+        "{}bundle_{}",
+        BackupKind::Db.artifact_prefix(config.host_tag.as_deref()),
+        artifact_timestamp(config, BackupKind::Db).await
+    );
+    let mut entries = Vec::with_capacity(inputs.len());
+    for (database, input) in inputs {
+        let source_name = match input.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => {
+                error!(path = %input.display(), "Bundle input has no valid UTF-8 file name");
+                bail!(
+                    "Bundle input has no valid UTF-8 file name: {}",
+                    input.display()
+                );
+            }
+        };
+        let size_bytes = match tokio::fs::metadata(input).await {
+            Ok(m) => m.len(),
+            Err(e) => {
+                error!(error = %e, path = %input.display(), "Failed to stat bundle input");
+                bail!("Failed to stat bundle input {}: {}", input.display(), e);
+            }
+        };
+        entries.push(BundleEntry {
+            path: format!("{}/{}/{}", BUNDLE_DUMP_DIR, database, source_name),
+            database: database.clone(),
+            source_name,
+            size_bytes,
+        });
+    }
+
+    let manifest = BundleManifest {
+        created_at: now,
+        entries,
+    };
+    let manifest_json = match serde_json::to_vec_pretty(&manifest) {
+        Ok(j) => j,
+        Err(e) => {
+            error!(error = %e, "Failed to serialize bundle manifest");
+            bail!("Failed to serialize bundle manifest: {}", e);
+        }
+    };
+
+    let output_path = claim_artifact_path(
+        &config.backup_temp_dir,
+        &stem,
+        BUNDLE_EXTENSION,
+        config.naming_collision,
+    )
+    .await?;
+
+    info!(
+        output = %output_path.display(),
+        file_count = inputs.len(),
+        "Bundling db outputs into a single archive"
+    );
+
+    let out = output_path.clone();
+    let sparse = config.tar_sparse;
+    let sources: Vec<(PathBuf, String)> = inputs
+        .iter()
+        .map(|(_, input)| input.clone())
+        .zip(manifest.entries.iter().map(|e| e.path.clone()))
+        .collect();
+
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+        let file = match File::create(&out) {
+            Ok(f) => f,
+            Err(e) => {
+                error!(error = %e, path = %out.display(), "Failed to create bundle file");
+                bail!("Failed to create bundle file {}: {}", out.display(), e);
+            }
+        };
+        let writer = BufWriter::with_capacity(512 * 1024, file);
+
+        let encoder = match zstd::Encoder::new(writer, 3) {
+            Ok(enc) => enc,
+            Err(e) => {
+                error!(error = %e, "Failed to create zstd encoder");
+                bail!("Failed to create zstd encoder: {}", e);
+            }
+        };
+
+        let mut tar_builder = tar::Builder::new(encoder);
+        tar_builder.sparse(sparse);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now.timestamp().max(0) as u64);
+        header.set_cksum();
+        if let Err(e) =
+            tar_builder.append_data(&mut header, BUNDLE_MANIFEST_NAME, manifest_json.as_slice())
+        {
+            error!(error = %e, "Failed to append bundle manifest");
+            bail!("Failed to append bundle manifest: {}", e);
+        }
+
+        for (source, inner_path) in &sources {
+            if let Err(e) = tar_builder.append_path_with_name(source, inner_path) {
+                error!(error = %e, path = %source.display(), "Failed to append file to bundle");
+                bail!("Failed to append {} to bundle: {}", source.display(), e);
+            }
+        }
+
+        let encoder = match tar_builder.into_inner() {
+            Ok(enc) => enc,
+            Err(e) => {
+                error!(error = %e, "Failed to finalize bundle tar archive");
+                bail!("Failed to finalize bundle tar archive: {}", e);
+            }
+        };
+
+        let writer = match encoder.finish() {
+            Ok(w) => w,
+            Err(e) => {
+                error!(error = %e, "Failed to finalize zstd compression");
+                bail!("Failed to finalize zstd compression: {}", e);
+            }
+        };
+
+        let file = match writer.into_inner() {
+            Ok(f) => f,
+            Err(e) => {
+                error!(error = %e, "Failed to flush bundle buffer");
+                bail!("Failed to flush bundle buffer: {}", e);
+            }
+        };
+
+        match file.metadata() {
+            Ok(m) => Ok(m.len()),
+            Err(e) => {
+                error!(error = %e, "Failed to get bundle file metadata");
+                bail!("Failed to get bundle file metadata: {}", e);
+            }
+        }
+    })
+    .await;
+
+    let size_bytes = match result {
+        Ok(Ok(size)) => size,
+        Ok(Err(e)) => {
+            cleanup_temp_file(&output_path).await;
+            return Err(e);
+        }
+        Err(e) => {
+            cleanup_temp_file(&output_path).await;
+            error!(error = %e, "Bundle task panicked");
+            bail!("Bundle blocking task panicked: {}", e);
+        }
+    };
+
+    info!(
+        path = %output_path.display(),
+        size_bytes = size_bytes,
+        file_count = manifest.entries.len(),
+        "DB bundle created"
+    );
+
+    Ok(output_path)
+}
+
+/// Unpack the dump of `db_name` held in a bundle made by
+/// [`bundle_db_outputs`] into `dest_dir` and return its path. Bundles made
+/// before they held several databases keep their one dump directly under
+/// `dumps/`, which is taken whatever the database. Only the file name of the
+/// entry is used, so a crafted bundle cannot write outside `dest_dir`.
+pub async fn extract_bundle_dump(
+    bundle: &Path,
+    dest_dir: &Path,
+    db_name: &str,
+) -> anyhow::Result<PathBuf> {
+    let bundle = bundle.to_path_buf();
+    let dest_dir = dest_dir.to_path_buf();
+    let db_name = db_name.to_string();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<PathBuf> {
+        let file = match File::open(&bundle) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to open bundle {}: {}", bundle.display(), e),
+        };
+        let decoder = match zstd::Decoder::with_buffer(BufReader::with_capacity(512 * 1024, file)) {
+            Ok(d) => d,
+            Err(e) => bail!("Failed to create zstd decoder: {}", e),
+        };
+        let mut archive = tar::Archive::new(decoder);
+        let entries = match archive.entries() {
+            Ok(e) => e,
+            Err(e) => bail!("Failed to read bundle {}: {}", bundle.display(), e),
+        };
+
+        for entry in entries {
+            let mut entry = match entry {
+                Ok(e) => e,
+                Err(e) => bail!("Failed to read bundle {}: {}", bundle.display(), e),
+            };
+            let inner = match entry.path() {
+                Ok(p) => p.into_owned(),
+                Err(e) => bail!(
+                    "Bundle {} has an unreadable entry path: {}",
+                    bundle.display(),
+                    e
+                ),
+            };
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let Ok(relative) = inner.strip_prefix(BUNDLE_DUMP_DIR) else {
+                continue;
+            };
+            if let Some(database) = relative.parent()
+                && !database.as_os_str().is_empty()
+                && database != Path::new(&db_name)
+            {
+                continue;
+            }
+            let Some(name) = relative.file_name() else {
+                continue;
+            };
+            let dest = dest_dir.join(name);
+            info!(entry = %inner.display(), dest = %dest.display(), "Extracting dump from bundle");
+            if let Err(e) = entry.unpack(&dest) {
+                error!(error = %e, entry = %inner.display(), "Failed to extract dump from bundle");
+                bail!(
+                    "Failed to extract {} from {}: {}",
+                    inner.display(),
+                    bundle.display(),
+                    e
+                );
+            }
+            return Ok(dest);
+        }
+        bail!("Bundle {} holds no dump of {}", bundle.display(), db_name)
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => bail!("Bundle extraction task panicked: {}", e),
+    }
+}
+
+async fn cleanup_temp_file(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        error!(
+            error = %e,
+            path = %path.display(),
+            "Failed to clean up partial bundle file"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a bundle holding `files`, as (path inside the bundle, contents).
+    fn write_bundle(path: &Path, files: &[(&str, &str)]) {
+        let encoder = zstd::Encoder::new(File::create(path).unwrap(), 3).unwrap();
+        let mut builder = tar::Builder::new(encoder);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bundle-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn the_dump_of_the_named_database_is_extracted() {
+        let dir = scratch("named");
+        let bundle = dir.join("db_bundle_20261016.tar.zst");
+        write_bundle(
+            &bundle,
+            &[
+                (BUNDLE_MANIFEST_NAME, "{}"),
+                ("dumps/app/db_app_20261016.dump", "app"),
+                ("dumps/app_test/db_app_test_20261016.dump", "app_test"),
+            ],
+        );
+        let extracted = extract_bundle_dump(&bundle, &dir, "app_test").await;
+        let missing = extract_bundle_dump(&bundle, &dir, "other").await;
+        let contents = extracted
+            .as_ref()
+            .ok()
+            .map(|path| std::fs::read_to_string(path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            extracted.unwrap().file_name().unwrap(),
+            "db_app_test_20261016.dump"
+        );
+        assert_eq!(contents.unwrap(), "app_test");
+        assert!(
+            missing
+                .unwrap_err()
+                .to_string()
+                .contains("no dump of other")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_single_database_bundle_is_extracted_whatever_the_name() {
+        let dir = scratch("flat");
+        let bundle = dir.join("db_bundle_20261016.tar.zst");
+        write_bundle(&bundle, &[("dumps/db_app_20261016.dump", "app")]);
+        let extracted = extract_bundle_dump(&bundle, &dir, "postgres").await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            extracted.unwrap().file_name().unwrap(),
+            "db_app_20261016.dump"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::status::ChainState;
+
+/// Whether Minecraft backups are always full or differential against the last
+/// full backup (`MC_BACKUP_MODE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupMode {
+    Full,
+    Differential,
+}
+
+/// How often a differential chain is re-anchored with a full backup
+/// (`FULL_BACKUP_EVERY`): every N runs, or once the last full is this old.
+#[derive(Debug, Clone, Copy)]
+pub enum FullBackupEvery {
+    Runs(u32),
+    Interval(Duration),
+}
+
+/// What the next Minecraft archive should contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveScope {
+    Full,
+    /// Only files modified after the given instant.
+    Differential {
+        since: DateTime<Utc>,
+    },
+    /// Only files modified after the given instant, anchored on the previous
+    /// successful backup of any scope rather than the last full.
+    Incremental {
+        since: DateTime<Utc>,
+    },
+}
+
+impl ArchiveScope {
+    pub fn is_full(&self) -> bool {
+        matches!(self, ArchiveScope::Full)
+    }
+}
+
+/// Whether the archive `name`, of a kind whose names start with `prefix`,
+/// only holds changes on top of a full backup.
+pub fn is_dependent_archive(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix)
+        .is_some_and(|rest| rest.starts_with("diff_") || rest.starts_with("incr_"))
+}
+
+/// A full backup together with the differential and incremental archives
+/// taken after it and before the next full: everything a restore to any of
+/// them needs. Members are indices into a newest-first listing, newest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreChain {
+    pub members: Vec<usize>,
+    /// The full backup the chain builds on; `None` for archives older than
+    /// every full in the listing, which cannot be restored.
+    pub full: Option<usize>,
+}
+
+/// Group a newest-first listing of archive names into restore chains, newest
+/// chain first.
+pub fn restore_chains<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    prefix: &str,
+) -> Vec<RestoreChain> {
+    let mut chains = Vec::new();
+    let mut members = Vec::new();
+    for (index, name) in names.into_iter().enumerate() {
+        members.push(index);
+        if !is_dependent_archive(name, prefix) {
+            chains.push(RestoreChain {
+                members: std::mem::take(&mut members),
+                full: Some(index),
+            });
+        }
+    }
+    if !members.is_empty() {
+        chains.push(RestoreChain {
+            members,
+            full: None,
+        });
+    }
+    chains
+}
+
+/// Decide whether this run is full or differential, with a human-readable reason.
+pub fn decide_scope(
+    mode: BackupMode,
+    every: Option<FullBackupEvery>,
+    chain: Option<&ChainState>,
+    now: DateTime<Utc>,
+) -> (ArchiveScope, String) {
+    if mode == BackupMode::Full {
+        return (ArchiveScope::Full, "MC_BACKUP_MODE=full".to_string());
+    }
+
+    let Some(chain) = chain else {
+        return (
+            ArchiveScope::Full,
+            "no previous full backup to anchor a differential".to_string(),
+        );
+    };
+
+    match every {
+        Some(FullBackupEvery::Runs(n)) if chain.diffs_since_full + 1 >= n => {
+            return (
+                ArchiveScope::Full,
+                format!(
+                    "{} differential runs since last full (FULL_BACKUP_EVERY={})",
+                    chain.diffs_since_full, n
+                ),
+            );
+        }
+        Some(FullBackupEvery::Interval(interval)) => {
+            let age = (now - chain.last_full_at).to_std().unwrap_or_default();
+            if age >= interval {
+                return (
+                    ArchiveScope::Full,
+                    format!(
+                        "last full backup is {}s old (FULL_BACKUP_EVERY={}s)",
+                        age.as_secs(),
+                        interval.as_secs()
+                    ),
+                );
+            }
+        }
+        _ => {}
+    }
+
+    (
+        ArchiveScope::Differential {
+            since: chain.last_full_at,
+        },
+        format!(
+            "differential #{} against full backup from {}",
+            chain.diffs_since_full + 1,
+            chain.last_full_at.to_rfc3339()
+        ),
+    )
+}
+
+/// Decide the scope of a `--since-last` run from the start of the last
+/// successful backup: anything changed while that run was archiving may have
+/// been missed by it. The cutoff is moved back by `overlap` so files stamped
+/// by a slightly skewed clock are not missed either.
+pub fn decide_incremental(
+    last_started_at: Option<DateTime<Utc>>,
+    overlap: Duration,
+    now: DateTime<Utc>,
+) -> (ArchiveScope, String) {
+    let Some(last_started_at) = last_started_at else {
+        return (
+            ArchiveScope::Full,
+            "no previous successful backup to base an incremental on".to_string(),
+        );
+    };
+
+    if last_started_at > now {
+        return (
+            ArchiveScope::Full,
+            format!(
+                "last successful backup started {} in the future; clock moved backwards",
+                last_started_at.to_rfc3339()
+            ),
+        );
+    }
+
+    let overlap = chrono::Duration::from_std(overlap).unwrap_or_default();
+    let since = last_started_at - overlap;
+    (
+        ArchiveScope::Incremental { since },
+        format!(
+            "incremental since the last success started at {} (overlap {}s)",
+            last_started_at.to_rfc3339(),
+            overlap.num_seconds()
+        ),
+    )
+}
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+/// Extension appended to an artifact's file name for its checksum sidecar.
+pub const SIDECAR_EXTENSION: &str = "sha256";
+
+/// Whether a remote/local file name is a checksum sidecar rather than a backup.
+pub fn is_sidecar_name(name: &str) -> bool {
+    name.ends_with(&format!(".{}", SIDECAR_EXTENSION))
+}
+
+/// Stream a file through SHA-256 on a blocking thread, returning the hex digest.
+pub async fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let owned = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || sha256_file_blocking(&owned)).await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => {
+            error!(error = %e, "Hashing task panicked");
+            bail!("Hashing blocking task panicked: {}", e);
+        }
+    }
+}
+
+/// [`sha256_file`] for callers already on a blocking thread.
+pub fn sha256_file_blocking(path: &Path) -> anyhow::Result<String> {
+    digest_file_blocking::<Sha256>(path)
+}
+
+/// Hex MD5 of a file, as Drive reports in `md5Checksum`. Blocking.
+pub fn md5_file_blocking(path: &Path) -> anyhow::Result<String> {
+    digest_file_blocking::<Md5>(path)
+}
+
+fn digest_file_blocking<D: Digest>(path: &Path) -> anyhow::Result<String> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to open file for hashing");
+            bail!("Failed to open {} for hashing: {}", path.display(), e);
+        }
+    };
+    let mut reader = BufReader::with_capacity(512 * 1024, file);
+    let mut hasher = D::new();
+    let mut buf = vec![0u8; 512 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Failed to read file for hashing");
+                bail!("Failed to read {} for hashing: {}", path.display(), e);
+            }
+        };
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Writer that feeds every byte it passes on into SHA-256, so an artifact's
+/// digest falls out of writing it instead of a second read.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The wrapped writer and the hex digest of everything written through it.
+    pub fn into_parts(self) -> (W, String) {
+        (self.inner, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write `<name>.sha256` next to `artifact` in the `sha256sum -c` format
+/// (`<hex>  <name>`), returning the sidecar path.
+pub async fn write_sha256_sidecar(artifact: &Path, digest: &str) -> anyhow::Result<PathBuf> {
+    let file_name = match artifact.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n,
+        None => {
+            error!(path = %artifact.display(), "Artifact has no valid UTF-8 file name");
+            bail!(
+                "Artifact has no valid UTF-8 file name: {}",
+                artifact.display()
+            );
+        }
+    };
+
+    let sidecar_path = artifact.with_file_name(format!("{}.{}", file_name, SIDECAR_EXTENSION));
+    let contents = format!("{}  {}\n", digest, file_name);
+
+    if let Err(e) = tokio::fs::write(&sidecar_path, contents).await {
+        error!(error = %e, path = %sidecar_path.display(), "Failed to write checksum sidecar");
+        bail!(
+            "Failed to write checksum sidecar {}: {}",
+            sidecar_path.display(),
+            e
+        );
+    }
+
+    info!(
+        path = %sidecar_path.display(),
+        sha256 = digest,
+        "Wrote checksum sidecar"
+    );
+
+    Ok(sidecar_path)
+}
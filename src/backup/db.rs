@@ -1,35 +1,274 @@
-use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::process::{ChildStdin, Stdio};
+use std::sync::OnceLock;
 
 use anyhow::bail;
-use tracing::{error, info};
+use tokio::sync::Semaphore;
+use tracing::{error, info, warn};
 
+use super::naming::{artifact_timestamp, claim_artifact_path};
+use super::sink::{ArchiveSink, ArchiveTooLarge};
+use super::ssh_tunnel::{DbEndpoint, SshTunnel};
+use super::{BackupArtifact, BackupKind};
 use crate::config::config::Config;
+use crate::status::{DbWriteMark, StatusFile};
 
-pub async fn backup_db(config: &Config) -> anyhow::Result<PathBuf> {
-    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("db_{}_{}.dump", config.db_name, timestamp);
-    let output_path = config.backup_temp_dir.join(&filename);
+/// Shared by every dump in the process; sized from `DB_DUMP_CONCURRENCY` on
+/// first use.
+static DUMP_SLOTS: OnceLock<Semaphore> = OnceLock::new();
+
+/// stderr fragments Postgres emits when it refuses a new connection.
+const CONNECTION_REJECTED: [&str; 3] = [
+    "too many clients already",
+    "remaining connection slots are reserved",
+    "too many connections for",
+];
+
+/// stderr fragments from pg_dump hitting `--lock-wait-timeout`.
+const LOCK_TIMED_OUT: [&str; 2] = [
+    "canceling statement due to lock timeout",
+    "could not obtain lock on relation",
+];
+
+/// Returned inside `anyhow::Error` when `DB_SKIP_UNCHANGED` finds too few
+/// writes since the last stored backup to be worth a new dump.
+#[derive(Debug)]
+pub struct DbUnchanged {
+    pub writes_since: u64,
+}
+
+impl std::fmt::Display for DbUnchanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "database unchanged since the last backup ({} row writes)",
+            self.writes_since
+        )
+    }
+}
+
+impl std::error::Error for DbUnchanged {}
+
+/// pg_dump output format (`DB_DUMP_FORMAT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Custom-format archive for pg_restore, `.dump` or `.dump.zst`.
+    Custom,
+    /// Plain SQL piped through gzip into `.sql.gz`, readable by psql, zcat and
+    /// Drive's previewer.
+    SqlGz,
+}
+
+/// The compressor a streamed dump is piped through.
+enum DumpEncoder<W: Write> {
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(flate2::write::GzEncoder<W>),
+}
+
+impl<W: Write> DumpEncoder<W> {
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            DumpEncoder::Zstd(enc) => enc.finish(),
+            DumpEncoder::Gzip(enc) => enc.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for DumpEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DumpEncoder::Zstd(enc) => enc.write(buf),
+            DumpEncoder::Gzip(enc) => enc.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DumpEncoder::Zstd(enc) => enc.flush(),
+            DumpEncoder::Gzip(enc) => enc.flush(),
+        }
+    }
+}
+
+/// Dump `db_name`, one of `DB_NAMES` (or `DB_NAME`), with the shared
+/// connection settings.
+pub async fn backup_db(config: &Config, db_name: &str) -> anyhow::Result<BackupArtifact> {
+    let slots = DUMP_SLOTS.get_or_init(|| Semaphore::new(config.db_dump_concurrency));
+    let _permit = match slots.acquire().await {
+        Ok(p) => p,
+        Err(e) => bail!("Dump concurrency limiter closed: {}", e),
+    };
+
+    // pg_dump connects through the tunnel's local port when DB_SSH_HOST is set
+    let (endpoint, tunnel) = SshTunnel::open_if_configured(config).await?;
+
+    let result = dump_db(config, &endpoint, db_name).await;
+
+    if let Some(tunnel) = tunnel {
+        tunnel.close().await;
+    }
+
+    result
+}
+
+async fn dump_db(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+) -> anyhow::Result<BackupArtifact> {
+    check_pg_dump_version(config, endpoint, db_name).await?;
+    run_precheck(config, endpoint, db_name).await?;
+    let db_writes = if config.db_skip_unchanged {
+        Some(check_changed(config, endpoint, db_name).await?)
+    } else {
+        None
+    };
+
+    let timestamp = artifact_timestamp(config, BackupKind::Db).await;
+    let prefix = BackupKind::Db.artifact_prefix(config.host_tag.as_deref());
+    let stem = format!("{}{}_{}", prefix, db_name, timestamp);
+    let extension = match config.db_dump_format {
+        DumpFormat::SqlGz => ".sql.gz",
+        DumpFormat::Custom if config.db_compress => ".dump.zst",
+        DumpFormat::Custom => ".dump",
+    };
+    let streamed = config.db_compress || config.db_dump_format == DumpFormat::SqlGz;
+    let output_path = claim_artifact_path(
+        &config.backup_temp_dir,
+        &stem,
+        extension,
+        config.naming_collision,
+    )
+    .await?;
 
     info!(
-        db_name = %config.db_name,
-        db_host = %config.db_host,
+        db_name = %db_name,
+        db_host = %endpoint.host,
+        db_port = endpoint.port,
         output = %output_path.display(),
+        compress = config.db_compress,
+        format = ?config.db_dump_format,
         "Starting PostgreSQL backup"
     );
 
+    // pg_dump writes uncompressed dumps itself, so only compressed ones get a digest
+    let sha256 = if streamed {
+        match dump_db_compressed(config, endpoint, db_name, &output_path).await {
+            Ok(digest) => Some(digest),
+            Err(e) => {
+                cleanup_temp_file(&output_path).await;
+                return Err(e);
+            }
+        }
+    } else {
+        dump_db_plain(config, endpoint, db_name, &output_path).await?;
+        None
+    };
+
+    let metadata = match tokio::fs::metadata(&output_path).await {
+        Ok(m) => m,
+        Err(e) => {
+            error!(error = %e, path = %output_path.display(), "Failed to stat pg_dump output file");
+            bail!(
+                "Failed to stat pg_dump output file {}: {}",
+                output_path.display(),
+                e
+            );
+        }
+    };
+
+    // pg_dump writes uncompressed dumps itself, so the cap is checked afterwards
+    if let Some(limit) = config.max_archive_bytes
+        && metadata.len() > limit
+    {
+        error!(
+            path = %output_path.display(),
+            size_bytes = metadata.len(),
+            limit = limit,
+            "Dump exceeds MAX_ARCHIVE_BYTES"
+        );
+        cleanup_temp_file(&output_path).await;
+        bail!(
+            "Dump of {} is {} bytes, over MAX_ARCHIVE_BYTES ({} bytes)",
+            db_name,
+            metadata.len(),
+            limit
+        );
+    }
+
+    info!(
+        path = %output_path.display(),
+        size_bytes = metadata.len(),
+        "PostgreSQL backup completed"
+    );
+
+    if config.db_verify_roundtrip
+        && let Err(e) = match config.db_dump_format {
+            DumpFormat::Custom => verify_roundtrip(&output_path, config.db_compress).await,
+            DumpFormat::SqlGz => verify_gzip(&output_path).await,
+        }
+    {
+        error!(error = %e, path = %output_path.display(), "Dump failed round-trip verification");
+        cleanup_temp_file(&output_path).await;
+        return Err(e);
+    }
+
+    Ok(BackupArtifact {
+        path: output_path,
+        sha256,
+        db_writes: db_writes
+            .map(|mark| (db_name.to_string(), mark))
+            .into_iter()
+            .collect(),
+        database: None,
+    })
+}
+
+/// Connection, format and locking arguments shared by every pg_dump
+/// invocation.
+fn pg_dump_args(config: &Config, endpoint: &DbEndpoint, db_name: &str) -> Vec<String> {
+    let format = match config.db_dump_format {
+        DumpFormat::Custom => "--format=custom",
+        DumpFormat::SqlGz => "--format=plain",
+    };
+    let mut args = vec![
+        format.to_string(),
+        "--host".to_string(),
+        endpoint.host.clone(),
+        "--port".to_string(),
+        endpoint.port.to_string(),
+        "--username".to_string(),
+        config.db_username.clone(),
+        "--dbname".to_string(),
+        db_name.to_string(),
+    ];
+    args.push(if config.db_include_blobs {
+        "--blobs".to_string()
+    } else {
+        "--no-blobs".to_string()
+    });
+    if let Some(timeout) = config.db_lock_wait_timeout {
+        args.push(format!("--lock-wait-timeout={}", timeout.as_millis()));
+    }
+    args
+}
+
+async fn dump_db_plain(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+    output_path: &Path,
+) -> anyhow::Result<()> {
     let output = match tokio::process::Command::new("pg_dump")
-        .arg("--format=custom")
-        .arg("--host")
-        .arg(&config.db_host)
-        .arg("--port")
-        .arg(config.db_port.to_string())
-        .arg("--username")
-        .arg(&config.db_username)
-        .arg("--dbname")
-        .arg(&config.db_name)
+        .args(pg_dump_args(config, endpoint, db_name))
         .arg("--file")
-        .arg(&output_path)
+        .arg(output_path)
         .env("PGPASSWORD", &config.db_password)
+        .envs(config.db_sslmode.as_deref().map(|mode| ("PGSSLMODE", mode)))
+        // MAX_RUNTIME_SECS cancels by dropping this future
+        .kill_on_drop(true)
         .output()
         .await
     {
@@ -47,29 +286,598 @@ pub async fn backup_db(config: &Config) -> anyhow::Result<PathBuf> {
             stderr = %stderr,
             "pg_dump failed"
         );
-        cleanup_temp_file(&output_path).await;
-        bail!("pg_dump exited with status {}: {}", output.status, stderr);
+        cleanup_temp_file(output_path).await;
+        return Err(pg_dump_failure(
+            config.db_dump_concurrency,
+            config.db_lock_wait_timeout,
+            &output.status,
+            &stderr,
+        ));
     }
 
-    let metadata = match tokio::fs::metadata(&output_path).await {
-        Ok(m) => m,
+    Ok(())
+}
+
+/// Stream pg_dump's stdout through the same zstd pipeline used for Minecraft
+/// archives, or gzip for `DB_DUMP_FORMAT=sql-gz`, so the uncompressed dump
+/// never touches disk.
+async fn dump_db_compressed(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+    output_path: &Path,
+) -> anyhow::Result<String> {
+    let args = pg_dump_args(config, endpoint, db_name);
+    let password = config.db_password.clone();
+    let sslmode = config.db_sslmode.clone();
+    let out = output_path.to_path_buf();
+    let concurrency = config.db_dump_concurrency;
+    let lock_wait_timeout = config.db_lock_wait_timeout;
+    let format = config.db_dump_format;
+    let max_archive_bytes = config.max_archive_bytes;
+
+    // zstd is synchronous - run the whole pipe in a blocking thread
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+        let file = match File::create(&out) {
+            Ok(f) => f,
+            Err(e) => {
+                error!(error = %e, path = %out.display(), "Failed to create output file");
+                bail!("Failed to create output file {}: {}", out.display(), e);
+            }
+        };
+        let writer = ArchiveSink::create(file, max_archive_bytes);
+
+        let mut encoder = match format {
+            DumpFormat::SqlGz => DumpEncoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            DumpFormat::Custom => {
+                let mut enc = match zstd::Encoder::new(writer, 3) {
+                    Ok(enc) => enc,
+                    Err(e) => {
+                        error!(error = %e, "Failed to create zstd encoder");
+                        bail!("Failed to create zstd encoder: {}", e);
+                    }
+                };
+                if let Err(e) = enc.multithread(0) {
+                    error!(error = %e, "Failed to enable zstd multithreading");
+                    bail!("Failed to enable zstd multithreading: {}", e);
+                }
+                DumpEncoder::Zstd(enc)
+            }
+        };
+
+        let mut child = match std::process::Command::new("pg_dump")
+            .args(&args)
+            .env("PGPASSWORD", &password)
+            .envs(sslmode.as_deref().map(|mode| ("PGSSLMODE", mode)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                error!(error = %e, "Failed to spawn pg_dump process");
+                bail!("Failed to spawn pg_dump process: {}", e);
+            }
+        };
+
+        // Drain stderr concurrently so a chatty pg_dump can't block on a full pipe
+        let stderr_reader = child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut buf = String::new();
+                let _ = stderr.read_to_string(&mut buf);
+                buf
+            })
+        });
+
+        let copied = match child.stdout.take() {
+            Some(mut stdout) => crate::abort::copy(&mut stdout, &mut encoder),
+            None => Err(std::io::Error::other("pg_dump stdout was not captured")),
+        };
+        if crate::abort::is_aborted() {
+            let _ = child.kill();
+            let _ = child.wait();
+            crate::abort::check()?;
+        }
+
+        let status = match child.wait() {
+            Ok(s) => s,
+            Err(e) => bail!("Failed to wait for pg_dump: {}", e),
+        };
+        let stderr = match stderr_reader.map(|h| h.join()) {
+            Some(Ok(s)) => s,
+            _ => String::new(),
+        };
+
+        // Hitting the cap closes pg_dump's stdout, so its own failure is a symptom
+        if let Err(e) = &copied
+            && e.get_ref()
+                .is_some_and(|inner| inner.is::<ArchiveTooLarge>())
+        {
+            error!(error = %e, "Compressed dump exceeded MAX_ARCHIVE_BYTES");
+            bail!("Compressed dump aborted: {}", e);
+        }
+        if !status.success() {
+            error!(
+                exit_code = ?status.code(),
+                stderr = %stderr,
+                "pg_dump failed"
+            );
+            return Err(pg_dump_failure(
+                concurrency,
+                lock_wait_timeout,
+                &status,
+                &stderr,
+            ));
+        }
+        if let Err(e) = copied {
+            error!(error = %e, "Failed to compress pg_dump output");
+            bail!("Failed to compress pg_dump output: {}", e);
+        }
+
+        let writer = match encoder.finish() {
+            Ok(w) => w,
+            Err(e) => {
+                error!(error = %e, "Failed to finalize compressed stream");
+                bail!("Failed to finalize compressed stream: {}", e);
+            }
+        };
+        match writer.finish() {
+            Ok(out) => Ok(out.sha256),
+            Err(e) => {
+                error!(error = %e, "Failed to flush compressed dump to disk");
+                bail!("Failed to flush compressed dump to disk: {}", e);
+            }
+        }
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
         Err(e) => {
-            error!(error = %e, path = %output_path.display(), "Failed to stat pg_dump output file");
+            error!(error = %e, "Compressed pg_dump task panicked");
+            bail!("Compressed pg_dump task panicked: {}", e);
+        }
+    }
+}
+
+/// Turn a failed pg_dump into an error, calling out refused connections and
+/// lock timeouts explicitly since the fix is a config change or finding the
+/// blocking transaction rather than a retry.
+fn pg_dump_failure(
+    concurrency: usize,
+    lock_wait_timeout: Option<std::time::Duration>,
+    status: &std::process::ExitStatus,
+    stderr: &str,
+) -> anyhow::Error {
+    if let Some(timeout) = lock_wait_timeout
+        && LOCK_TIMED_OUT.iter().any(|m| stderr.contains(m))
+    {
+        error!(
+            lock_wait_timeout = ?timeout,
+            "pg_dump gave up waiting for a table lock"
+        );
+        return anyhow::anyhow!(
+            "pg_dump could not lock a table within DB_LOCK_WAIT_TIMEOUT_MS ({} ms); a long-running transaction or DDL holds a conflicting lock (see pg_locks): {}",
+            timeout.as_millis(),
+            stderr.trim()
+        );
+    }
+    if CONNECTION_REJECTED.iter().any(|m| stderr.contains(m)) {
+        error!(
+            db_dump_concurrency = concurrency,
+            "Database server rejected the pg_dump connection"
+        );
+        return anyhow::anyhow!(
+            "Database server rejected the pg_dump connection (connection limit reached); lower DB_DUMP_CONCURRENCY (currently {}) or raise max_connections: {}",
+            concurrency,
+            stderr.trim()
+        );
+    }
+    anyhow::anyhow!("pg_dump exited with status {}: {}", status, stderr)
+}
+
+/// Feed the dump (decompressing it first when `compressed`) into
+/// `pg_restore --list`, which reads the whole archive TOC. Success proves the
+/// zstd stream decodes and the custom-format dump is readable.
+async fn verify_roundtrip(path: &Path, compressed: bool) -> anyhow::Result<()> {
+    info!(path = %path.display(), "Verifying dump round-trip with pg_restore --list");
+
+    let path = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let mut command = std::process::Command::new("pg_restore");
+        command
+            .arg("--list")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        if compressed {
+            command.stdin(Stdio::piped());
+        } else {
+            command.arg(&path).stdin(Stdio::null());
+        }
+
+        let mut child = match command.spawn() {
+            Ok(c) => c,
+            Err(e) => bail!("Failed to spawn pg_restore --list: {}", e),
+        };
+
+        // The pipe is closed once fed, so pg_restore sees EOF
+        let fed = match child.stdin.take() {
+            Some(stdin) => feed_decompressed(&path, stdin).map(|_| ()),
+            None => Ok(()),
+        };
+
+        let output = match child.wait_with_output() {
+            Ok(o) => o,
+            Err(e) => bail!("Failed to wait for pg_restore --list: {}", e),
+        };
+
+        if let Err(e) = fed {
+            bail!("Failed to decompress {}: {}", path.display(), e);
+        }
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
             bail!(
-                "Failed to stat pg_dump output file {}: {}",
-                output_path.display(),
-                e
+                "pg_restore --list exited with status {}: {}",
+                output.status,
+                stderr.trim()
             );
         }
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {
+            info!("Dump round-trip verification passed");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e),
+        Err(e) => bail!("Round-trip verification task panicked: {}", e),
+    }
+}
+
+/// Decode the whole of a `.sql.gz` dump, which proves the gzip stream and its
+/// CRC are intact. Plain SQL has no TOC for pg_restore to check.
+async fn verify_gzip(path: &Path) -> anyhow::Result<()> {
+    info!(path = %path.display(), "Verifying gzip dump round-trip");
+
+    let path = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to open {}: {}", path.display(), e),
+        };
+        let mut decoder = flate2::read::GzDecoder::new(BufReader::with_capacity(512 * 1024, file));
+        if let Err(e) = std::io::copy(&mut decoder, &mut std::io::sink()) {
+            bail!("Failed to decompress {}: {}", path.display(), e);
+        }
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {
+            info!("Dump round-trip verification passed");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e),
+        Err(e) => bail!("Round-trip verification task panicked: {}", e),
+    }
+}
+
+/// Decompress `path` to the end, feeding `stdin` for as long as it is read.
+fn feed_decompressed(path: &Path, stdin: ChildStdin) -> std::io::Result<u64> {
+    let file = File::open(path)?;
+    let mut decoder = zstd::Decoder::with_buffer(BufReader::with_capacity(512 * 1024, file))?;
+    let mut sink = PipeThenDiscard { pipe: Some(stdin) };
+    crate::abort::copy(&mut decoder, &mut sink)
+}
+
+/// Writes into a child's stdin until the child stops reading, then discards
+/// the rest. `pg_restore --list` exits once it has read the TOC, but the dump
+/// must still be decompressed to the end for its zstd checksum to be checked.
+struct PipeThenDiscard {
+    pipe: Option<ChildStdin>,
+}
+
+impl Write for PipeThenDiscard {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(pipe) = self.pipe.as_mut() {
+            match pipe.write_all(buf) {
+                Ok(()) => {}
+                // Dropping the handle closes our end of the pipe
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => self.pipe = None,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.pipe.as_mut() {
+            Some(pipe) => pipe.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Major versions of the local pg_dump client and the target server.
+#[derive(Debug, Clone, Copy)]
+pub struct PgVersionCheck {
+    pub client_major: u32,
+    pub server_major: u32,
+}
+
+impl PgVersionCheck {
+    /// pg_dump can dump servers of the same or older major version only.
+    pub fn is_mismatch(&self) -> bool {
+        self.client_major < self.server_major
+    }
+}
+
+/// Preflight comparing `pg_dump --version` with the server's `server_version_num`.
+/// Warns on a major-version mismatch, or fails when `DB_STRICT_VERSION` is set.
+/// If either version cannot be determined the check is skipped with a warning.
+async fn check_pg_dump_version(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+) -> anyhow::Result<()> {
+    let client_major = match pg_dump_major_version().await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(error = %e, "Could not determine pg_dump version, skipping version preflight");
+            return Ok(());
+        }
+    };
+
+    let server_major = match server_major_version(config, endpoint, db_name).await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(error = %e, "Could not determine server version, skipping version preflight");
+            return Ok(());
+        }
+    };
+
+    let check = PgVersionCheck {
+        client_major,
+        server_major,
     };
 
+    if !check.is_mismatch() {
+        info!(
+            pg_dump_major = check.client_major,
+            server_major = check.server_major,
+            "pg_dump version preflight passed"
+        );
+        return Ok(());
+    }
+
+    if config.db_strict_version {
+        error!(
+            pg_dump_major = check.client_major,
+            server_major = check.server_major,
+            "pg_dump is older than the server; refusing to dump (DB_STRICT_VERSION=true)"
+        );
+        bail!(
+            "pg_dump major version {} is older than server major version {}; install a matching pg_dump",
+            check.client_major,
+            check.server_major
+        );
+    }
+
+    warn!(
+        pg_dump_major = check.client_major,
+        server_major = check.server_major,
+        "pg_dump is older than the server - the dump may be incomplete and restores may silently lose objects"
+    );
+
+    Ok(())
+}
+
+/// Run `DB_PRECHECK_QUERY` and refuse to dump unless it prints
+/// `DB_PRECHECK_EXPECT`, e.g. to skip a standby in the middle of a failover.
+async fn run_precheck(config: &Config, endpoint: &DbEndpoint, db_name: &str) -> anyhow::Result<()> {
+    let Some(query) = &config.db_precheck_query else {
+        return Ok(());
+    };
+
+    let result = match run_psql(config, endpoint, db_name, query).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!(error = %e, query = %query, "Pre-dump check query failed");
+            bail!("Pre-dump check query failed: {}", e);
+        }
+    };
+    info!(query = %query, result = %result, "Pre-dump check query returned");
+
+    if result != config.db_precheck_expect.trim() {
+        error!(
+            expected = %config.db_precheck_expect,
+            actual = %result,
+            "Pre-dump check returned an unexpected result; refusing to dump"
+        );
+        bail!(
+            "Pre-dump check returned '{}', expected '{}'",
+            result,
+            config.db_precheck_expect
+        );
+    }
+
+    Ok(())
+}
+
+/// Read the database's write counters and fail with [`DbUnchanged`] when they
+/// moved by at most `DB_SKIP_UNCHANGED_THRESHOLD` since the last stored
+/// backup. Returns the counters to record once this backup is stored.
+async fn check_changed(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+) -> anyhow::Result<DbWriteMark> {
+    let current = match read_write_mark(config, endpoint, db_name).await {
+        Ok(m) => m,
+        Err(e) => {
+            error!(error = %e, "Failed to read pg_stat_database write counters");
+            bail!("Failed to read write counters for DB_SKIP_UNCHANGED: {}", e);
+        }
+    };
+
+    let previous = match StatusFile::load(&config.status_file_path).await {
+        // An unnamed mark predates DB_NAMES and was always DB_NAME's
+        Ok(mut s) => match s.db_writes_by_name.remove(db_name) {
+            Some(mark) => Some(mark),
+            None => s.db_writes.filter(|_| db_name == config.db_name),
+        },
+        Err(e) => {
+            warn!(error = %e, "Could not read the last db write counters, dumping");
+            None
+        }
+    };
+    let Some(previous) = previous else {
+        info!(
+            writes = current.writes,
+            "No db write counters recorded yet, dumping"
+        );
+        return Ok(current);
+    };
+    // A reset restarts the counters, so they say nothing about what changed
+    if previous.stats_reset != current.stats_reset || current.writes < previous.writes {
+        info!("pg_stat_database counters were reset since the last backup, dumping");
+        return Ok(current);
+    }
+
+    let writes_since = current.writes - previous.writes;
+    if writes_since <= config.db_skip_unchanged_threshold {
+        info!(
+            writes_since = writes_since,
+            threshold = config.db_skip_unchanged_threshold,
+            "Database unchanged since the last backup, skipping dump"
+        );
+        return Err(DbUnchanged { writes_since }.into());
+    }
     info!(
-        path = %output_path.display(),
-        size_bytes = metadata.len(),
-        "PostgreSQL backup completed"
+        writes_since = writes_since,
+        "Database changed since the last backup"
     );
+    Ok(current)
+}
+
+async fn read_write_mark(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+) -> anyhow::Result<DbWriteMark> {
+    // Counts cover system catalogs too, so DDL registers as a change
+    let sql = "SELECT (tup_inserted + tup_updated + tup_deleted)::text || '|' || coalesce(stats_reset::text, '') \
+               FROM pg_stat_database WHERE datname = current_database()";
+    let output = run_psql(config, endpoint, db_name, sql).await?;
+    let Some((writes, stats_reset)) = output.split_once('|') else {
+        bail!("Unexpected pg_stat_database output: '{}'", output);
+    };
+    let writes = match writes.parse::<u64>() {
+        Ok(n) => n,
+        Err(e) => bail!("Unexpected write count '{}': {}", writes, e),
+    };
+    Ok(DbWriteMark {
+        writes,
+        stats_reset: Some(stats_reset.to_string()).filter(|s| !s.is_empty()),
+    })
+}
+
+async fn pg_dump_major_version() -> anyhow::Result<u32> {
+    let output = match tokio::process::Command::new("pg_dump")
+        .arg("--version")
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => bail!("Failed to spawn pg_dump --version: {}", e),
+    };
+
+    if !output.status.success() {
+        bail!("pg_dump --version exited with status {}", output.status);
+    }
+
+    // e.g. "pg_dump (PostgreSQL) 16.2 (Ubuntu 16.2-1.pgdg22.04+1)"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = match stdout
+        .split_whitespace()
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+    {
+        Some(v) => v,
+        None => bail!("Unrecognized pg_dump --version output: {}", stdout.trim()),
+    };
+
+    let major = version.split('.').next().unwrap_or(version);
+    match major.parse() {
+        Ok(m) => Ok(m),
+        Err(e) => bail!("Unrecognized pg_dump version '{}': {}", version, e),
+    }
+}
+
+async fn server_major_version(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    db_name: &str,
+) -> anyhow::Result<u32> {
+    let stdout = run_psql(config, endpoint, db_name, "show server_version_num").await?;
+
+    // server_version_num is e.g. 160002 for 16.2
+    let version_num: u32 = match stdout.parse() {
+        Ok(n) => n,
+        Err(e) => bail!("Unrecognized server_version_num '{}': {}", stdout, e),
+    };
+
+    Ok(version_num / 10000)
+}
+
+/// Run a single SQL statement with `psql -tAc` against `dbname` and return its
+/// trimmed, unaligned output.
+pub async fn run_psql(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    dbname: &str,
+    sql: &str,
+) -> anyhow::Result<String> {
+    let output = match tokio::process::Command::new("psql")
+        .arg("--host")
+        .arg(&endpoint.host)
+        .arg("--port")
+        .arg(endpoint.port.to_string())
+        .arg("--username")
+        .arg(&config.db_username)
+        .arg("--dbname")
+        .arg(dbname)
+        .arg("--no-psqlrc")
+        .arg("-v")
+        .arg("ON_ERROR_STOP=1")
+        .arg("-tAc")
+        .arg(sql)
+        .env("PGPASSWORD", &config.db_password)
+        .envs(config.db_sslmode.as_deref().map(|mode| ("PGSSLMODE", mode)))
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => bail!("Failed to spawn psql: {}", e),
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "psql exited with status {}: {}",
+            output.status,
+            stderr.trim()
+        );
+    }
 
-    Ok(output_path)
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
 async fn cleanup_temp_file(path: &Path) {
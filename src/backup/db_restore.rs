@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use tracing::{error, info};
+
+use super::bundle::{BUNDLE_EXTENSION, extract_bundle_dump};
+use super::restore_log::{count_psql_objects, count_restored_objects, record_db};
+use super::ssh_tunnel::SshTunnel;
+use super::validate::{decompress, pg_restore_into, psql_restore_into, remove_file};
+use crate::config::config::Config;
+
+/// Extension of a plain SQL dump made with `DB_DUMP_FORMAT=sql-gz`.
+pub const SQL_GZ_EXTENSION: &str = ".sql.gz";
+
+/// Whether `name` is a db backup that [`restore_db`] can take: a dump
+/// (`.dump`, `.dump.zst`, `.sql.gz`) or a bundle holding dumps.
+pub fn is_db_dump_name(name: &str) -> bool {
+    name.ends_with(".dump")
+        || name.ends_with(".dump.zst")
+        || name.ends_with(SQL_GZ_EXTENSION)
+        || name.ends_with(BUNDLE_EXTENSION)
+}
+
+/// Whether `dump_path` is plain SQL, which psql restores instead of pg_restore.
+pub(super) fn is_plain_sql(dump_path: &Path) -> bool {
+    dump_path.to_string_lossy().ends_with(SQL_GZ_EXTENSION)
+}
+
+/// A `pg_restore`-ready dump unpacked from a backup file, along with the temp
+/// files made on the way.
+pub(super) struct PreparedDump {
+    pub path: PathBuf,
+    temp_files: Vec<PathBuf>,
+}
+
+impl PreparedDump {
+    /// Remove the temp files; the original backup file is left alone.
+    pub async fn cleanup(self) {
+        for path in &self.temp_files {
+            remove_file(path).await;
+        }
+    }
+}
+
+/// Unpack `archive` into `temp_dir` until it is a plain custom-format dump or
+/// a `.sql.gz`: `db_name`'s dump is taken out of a bundle and a `.zst` dump
+/// decompressed.
+pub(super) async fn prepare_dump(
+    archive: &Path,
+    temp_dir: &Path,
+    db_name: &str,
+) -> anyhow::Result<PreparedDump> {
+    let mut prepared = PreparedDump {
+        path: archive.to_path_buf(),
+        temp_files: Vec::new(),
+    };
+
+    let name = archive.to_string_lossy();
+    if name.ends_with(BUNDLE_EXTENSION) {
+        prepared.path = extract_bundle_dump(archive, temp_dir, db_name).await?;
+        prepared.temp_files.push(prepared.path.clone());
+    }
+
+    if prepared.path.extension().is_some_and(|ext| ext == "zst") {
+        let decompressed = prepared.path.with_extension("");
+        if let Err(e) = decompress(&prepared.path, &decompressed).await {
+            remove_file(&decompressed).await;
+            prepared.cleanup().await;
+            return Err(e);
+        }
+        prepared.path = decompressed.clone();
+        prepared.temp_files.push(decompressed);
+    }
+    Ok(prepared)
+}
+
+/// Restore a db backup (`.dump`, `.dump.zst`, `.sql.gz` or a bundle) into
+/// `DB_NAME`. Objects in a custom-format dump are dropped and recreated. With
+/// `tables`, only those tables are restored, after checking that each is in
+/// the dump. A `.sql.gz` holds no DROP statements and no TOC, so it is run
+/// through psql in one transaction, which fails and rolls back if `DB_NAME`
+/// already has its objects, and `tables` is refused. With
+/// `RESTORE_VERIFY_LOG_PATH`, the objects created are counted into that log.
+pub async fn restore_db(config: &Config, archive: &Path, tables: &[String]) -> anyhow::Result<()> {
+    let prepared = prepare_dump(archive, &config.backup_temp_dir, &config.db_name).await?;
+    let result = restore_dump(config, archive, &prepared.path, tables).await;
+    prepared.cleanup().await;
+    result
+}
+
+async fn restore_dump(
+    config: &Config,
+    archive: &Path,
+    dump_path: &Path,
+    tables: &[String],
+) -> anyhow::Result<()> {
+    if is_plain_sql(dump_path) {
+        return restore_sql(config, archive, dump_path, tables).await;
+    }
+
+    let mut args = vec!["--clean".to_string(), "--if-exists".to_string()];
+    // Object counts for the verification log come from the progress report
+    if config.restore_verify_log_path.is_some() {
+        args.push("--verbose".to_string());
+    }
+    if !tables.is_empty() {
+        let present = list_dump_tables(dump_path).await?;
+        let missing: Vec<&str> = tables
+            .iter()
+            .filter(|t| !present.contains(t.as_str()))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            error!(missing = ?missing, dump = %dump_path.display(), "Requested tables are not in the dump");
+            bail!(
+                "Table(s) not found in {}: {}",
+                dump_path.display(),
+                missing.join(", ")
+            );
+        }
+        for table in tables {
+            args.push("--table".to_string());
+            args.push(table.clone());
+        }
+        info!(tables = ?tables, "Restoring selected tables only");
+    }
+
+    let (endpoint, tunnel) = SshTunnel::open_if_configured(config).await?;
+    let result = pg_restore_into(config, &endpoint, &config.db_name, dump_path, &args).await;
+    if let Some(tunnel) = tunnel {
+        tunnel.close().await;
+    }
+    let report = result?;
+
+    info!(database = %config.db_name, dump = %dump_path.display(), "Db restore completed");
+    let counts = count_restored_objects(&report);
+    if let Err(e) = record_db(config, archive, &config.db_name, &counts).await {
+        error!(error = %e, "Db restore completed but its verification log was not written");
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn restore_sql(
+    config: &Config,
+    archive: &Path,
+    dump_path: &Path,
+    tables: &[String],
+) -> anyhow::Result<()> {
+    if !tables.is_empty() {
+        error!(dump = %dump_path.display(), "--table needs a custom-format dump");
+        bail!(
+            "{} is a plain SQL dump; --table only works with DB_DUMP_FORMAT=custom backups",
+            dump_path.display()
+        );
+    }
+
+    let (endpoint, tunnel) = SshTunnel::open_if_configured(config).await?;
+    let result = psql_restore_into(config, &endpoint, &config.db_name, dump_path).await;
+    if let Some(tunnel) = tunnel {
+        tunnel.close().await;
+    }
+    let tags = result?;
+
+    info!(database = %config.db_name, dump = %dump_path.display(), "Db restore completed");
+    let counts = count_psql_objects(&tags);
+    if let Err(e) = record_db(config, archive, &config.db_name, &counts).await {
+        error!(error = %e, "Db restore completed but its verification log was not written");
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Table names in the dump's table of contents (`pg_restore --list`).
+async fn list_dump_tables(dump_path: &Path) -> anyhow::Result<HashSet<String>> {
+    let output = match tokio::process::Command::new("pg_restore")
+        .arg("--list")
+        .arg(dump_path)
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => {
+            error!(error = %e, "Failed to spawn pg_restore --list");
+            bail!("Failed to spawn pg_restore --list: {}", e);
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(exit_code = ?output.status.code(), stderr = %stderr, "pg_restore --list failed");
+        bail!(
+            "pg_restore --list exited with status {}: {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+
+    // Entries look like `215; 1259 16386 TABLE public users owner`
+    let toc = String::from_utf8_lossy(&output.stdout);
+    let tables = toc
+        .lines()
+        .filter(|line| !line.starts_with(';'))
+        .filter_map(|line| line.split_once("; ").map(|(_, entry)| entry))
+        .filter_map(|entry| {
+            let fields: Vec<&str> = entry.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, _, "TABLE", schema, name, ..] if *schema != "DATA" => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect();
+    Ok(tables)
+}
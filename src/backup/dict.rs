@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use tracing::{error, info};
+use walkdir::WalkDir;
+
+/// Files larger than this are left out of training; dictionaries help with
+/// many small files and add little to large ones.
+const MAX_SAMPLE_FILE: u64 = 128 * 1024;
+/// Training input is capped at this multiple of the dictionary size, which
+/// zstd suggests is plenty.
+const SAMPLE_BUDGET_FACTOR: usize = 100;
+
+/// Read the dictionary at `ZSTD_DICT_PATH`, if one is configured.
+pub fn load(path: Option<&Path>) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    match std::fs::read(path) {
+        Ok(dict) => Ok(Some(dict)),
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to read zstd dictionary");
+            bail!("Failed to read ZSTD_DICT_PATH {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Read the dictionary a frame with `needed` was written with: the one at
+/// `ZSTD_DICT_PATH` when its id matches, else the copy [`train`] kept next to
+/// it under [`id_file_name`]. Falls back to `ZSTD_DICT_PATH` so
+/// [`check_matches`] can report the mismatch.
+pub fn load_id(path: Option<&Path>, needed: Option<u32>) -> anyhow::Result<Option<Vec<u8>>> {
+    let current = load(path)?;
+    let (Some(path), Some(needed)) = (path, needed) else {
+        return Ok(current);
+    };
+    if current.as_deref().and_then(dict_id) == Some(needed) {
+        return Ok(current);
+    }
+    let kept = by_id_path(path, needed);
+    if !kept.is_file() {
+        return Ok(current);
+    }
+    info!(path = %kept.display(), dict_id = needed, "Using the kept zstd dictionary this archive was written with");
+    load(Some(&kept))
+}
+
+/// [`load_id`] for the dictionary `archive` was compressed with.
+pub fn load_for(path: Option<&Path>, archive: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let mut header = Vec::with_capacity(18);
+    let read = File::open(archive).and_then(|f| f.take(18).read_to_end(&mut header));
+    if let Err(e) = read {
+        bail!("Failed to read {}: {}", archive.display(), e);
+    }
+    load_id(Some(path), frame_dict_id(&header))
+}
+
+/// Id a trained dictionary carries in its header; raw dictionaries have none.
+pub fn dict_id(dict: &[u8]) -> Option<u32> {
+    zstd::zstd_safe::get_dict_id_from_dict(dict).map(|id| id.get())
+}
+
+/// Name a dictionary with `id` is kept under, locally next to
+/// `ZSTD_DICT_PATH` and remotely next to the archives written with it.
+pub fn id_file_name(id: u32) -> String {
+    format!("zstd-dict-{}.dict", id)
+}
+
+/// Where the dictionary with `id` is kept beside `ZSTD_DICT_PATH`.
+pub fn by_id_path(path: &Path, id: u32) -> PathBuf {
+    path.with_file_name(id_file_name(id))
+}
+
+/// Dictionary id a zstd frame header says it was compressed with.
+pub fn frame_dict_id(header: &[u8]) -> Option<u32> {
+    zstd::zstd_safe::get_dict_id_from_frame(header).map(|id| id.get())
+}
+
+/// Fail unless `dict` is the dictionary a frame with `needed` was written
+/// with. Frames without a dictionary id decode with or without one.
+pub fn check_matches(needed: Option<u32>, dict: Option<&[u8]>) -> anyhow::Result<()> {
+    let Some(needed) = needed else {
+        return Ok(());
+    };
+    let Some(dict) = dict else {
+        bail!(
+            "archive was compressed with zstd dictionary {}; set ZSTD_DICT_PATH to that dictionary",
+            needed
+        );
+    };
+    match dict_id(dict) {
+        Some(id) if id == needed => Ok(()),
+        other => bail!(
+            "archive needs zstd dictionary {} but ZSTD_DICT_PATH holds {}",
+            needed,
+            other.map_or_else(
+                || "a raw dictionary without an id".to_string(),
+                |id| id.to_string()
+            )
+        ),
+    }
+}
+
+/// Open `archive` for decompression with `dict`, after checking it is the
+/// dictionary the archive was written with.
+pub fn open_decoder(
+    archive: &Path,
+    dict: Option<&[u8]>,
+) -> anyhow::Result<zstd::Decoder<'static, BufReader<File>>> {
+    let mut file = match File::open(archive) {
+        Ok(f) => f,
+        Err(e) => bail!("Failed to open archive {}: {}", archive.display(), e),
+    };
+    // The longest frame header is 18 bytes
+    let mut header = Vec::with_capacity(18);
+    if let Err(e) = (&mut file).take(18).read_to_end(&mut header) {
+        bail!("Failed to read {}: {}", archive.display(), e);
+    }
+    check_matches(frame_dict_id(&header), dict)?;
+    if let Err(e) = file.rewind() {
+        bail!("Failed to rewind {}: {}", archive.display(), e);
+    }
+    let reader = BufReader::with_capacity(512 * 1024, file);
+    let decoder = match dict {
+        Some(dict) => zstd::Decoder::with_dictionary(reader, dict),
+        None => zstd::Decoder::with_buffer(reader),
+    };
+    match decoder {
+        Ok(d) => Ok(d),
+        Err(e) => bail!("Failed to create zstd decoder: {}", e),
+    }
+}
+
+/// Train a dictionary of at most `max_size` bytes from the small files under
+/// `source`, reading at most `max_samples` of them.
+pub fn train(source: &Path, max_size: usize, max_samples: usize) -> anyhow::Result<Vec<u8>> {
+    let budget = max_size.saturating_mul(SAMPLE_BUDGET_FACTOR);
+    let mut samples: Vec<Vec<u8>> = Vec::new();
+    let mut total = 0;
+
+    for entry in WalkDir::new(source).follow_links(false) {
+        if samples.len() >= max_samples || total >= budget {
+            break;
+        }
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => bail!("Failed to walk {}: {}", source.display(), e),
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let size = match entry.metadata() {
+            Ok(m) => m.len(),
+            Err(e) => bail!("Failed to stat {}: {}", entry.path().display(), e),
+        };
+        if size == 0 || size > MAX_SAMPLE_FILE {
+            continue;
+        }
+        match std::fs::read(entry.path()) {
+            Ok(bytes) => {
+                total += bytes.len();
+                samples.push(bytes);
+            }
+            Err(e) => bail!("Failed to read {}: {}", entry.path().display(), e),
+        }
+    }
+
+    info!(
+        samples = samples.len(),
+        sample_bytes = total,
+        max_size = max_size,
+        "Training zstd dictionary"
+    );
+    // zstd refuses to train on a handful of samples
+    if samples.len() < 8 {
+        bail!(
+            "Only {} files of at most {} bytes under {}; too few to train a dictionary",
+            samples.len(),
+            MAX_SAMPLE_FILE,
+            source.display()
+        );
+    }
+
+    match zstd::dict::from_samples(&samples, max_size) {
+        Ok(dict) => Ok(dict),
+        Err(e) => bail!("Dictionary training failed: {}", e),
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use tracing::{debug, info};
+
+/// Files a running server holds or rewrites constantly. They are useless in a
+/// backup, and a restored `session.lock` can stop the world from loading.
+pub const DEFAULT_TRANSIENT_PATTERNS: [&str; 2] = ["session.lock", "*.dat_old"];
+
+/// Nether dimension directory inside a world (`MC_SKIP_NETHER`).
+pub const NETHER_DIR: &str = "DIM-1";
+/// End dimension directory inside a world (`MC_SKIP_END`).
+pub const END_DIR: &str = "DIM1";
+
+/// `*` and `?` stay within one path component; `**` crosses them.
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// File-name patterns skipped when archiving the Minecraft server. A pattern is
+/// either an exact file name or `*` followed by a suffix. Directories listed
+/// in `dirs` are skipped with everything below them, as is any path whose
+/// location relative to the server root matches one of the `globs`.
+#[derive(Debug, Clone, Default)]
+pub struct TransientExcludes {
+    patterns: Vec<String>,
+    dirs: Vec<String>,
+    root: PathBuf,
+    globs: Vec<Pattern>,
+    matched: BTreeMap<String, u64>,
+}
+
+impl TransientExcludes {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns,
+            dirs: Vec::new(),
+            root: PathBuf::new(),
+            globs: Vec::new(),
+            matched: BTreeMap::new(),
+        }
+    }
+
+    /// Also skip directories named in `dirs`, such as world dimensions.
+    pub fn with_dirs(mut self, dirs: Vec<String>) -> Self {
+        self.dirs = dirs;
+        self
+    }
+
+    /// Also skip paths under `root` whose relative path matches one of
+    /// `globs` (`MC_EXCLUDE_PATTERNS`), e.g. `logs` or `dynmap/web/tiles`.
+    pub fn with_globs(mut self, root: &Path, globs: Vec<Pattern>) -> Self {
+        self.root = root.to_path_buf();
+        self.globs = globs;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.dirs.is_empty() && self.globs.is_empty()
+    }
+
+    /// Whether the directory at `path` should be left out along with its contents.
+    pub fn excludes_dir(&mut self, path: &Path) -> bool {
+        if self.matches_glob(path) {
+            info!(path = %path.display(), "Excluding directory from archive (MC_EXCLUDE_PATTERNS)");
+            return true;
+        }
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        if !self.dirs.iter().any(|d| d.as_str() == name) {
+            return false;
+        }
+
+        info!(path = %path.display(), "Excluding directory from archive");
+        true
+    }
+
+    /// Whether `path` should be left out, counting the match for [`Self::log_summary`].
+    pub fn excludes(&mut self, path: &Path) -> bool {
+        if self.matches_glob(path) {
+            debug!(path = %path.display(), "Excluding file (MC_EXCLUDE_PATTERNS)");
+            *self
+                .matched
+                .entry("MC_EXCLUDE_PATTERNS".to_string())
+                .or_default() += 1;
+            return true;
+        }
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        let Some(pattern) = self.patterns.iter().find(|p| match p.strip_prefix('*') {
+            Some(suffix) => name.ends_with(suffix),
+            None => name == p.as_str(),
+        }) else {
+            return false;
+        };
+
+        debug!(path = %path.display(), pattern = %pattern, "Excluding transient file");
+        *self.matched.entry(pattern.clone()).or_default() += 1;
+        true
+    }
+
+    fn matches_glob(&self, path: &Path) -> bool {
+        if self.globs.is_empty() {
+            return false;
+        }
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.globs
+            .iter()
+            .any(|g| g.matches_path_with(relative, GLOB_OPTIONS))
+    }
+
+    pub fn log_summary(&self) {
+        for (pattern, count) in &self.matched {
+            info!(pattern = %pattern, files = count, "Excluded transient Minecraft files");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_globs(globs: &[&str]) -> TransientExcludes {
+        let globs = globs.iter().map(|g| Pattern::new(g).unwrap()).collect();
+        TransientExcludes::new(Vec::new()).with_globs(Path::new("/srv/mc"), globs)
+    }
+
+    #[test]
+    fn glob_matches_path_relative_to_root() {
+        let mut excludes = with_globs(&["logs", "dynmap/web/tiles"]);
+        assert!(excludes.excludes_dir(Path::new("/srv/mc/logs")));
+        assert!(excludes.excludes_dir(Path::new("/srv/mc/dynmap/web/tiles")));
+        assert!(!excludes.excludes_dir(Path::new("/srv/mc/world/logs")));
+        assert!(!excludes.excludes_dir(Path::new("/srv/mc/dynmap/web")));
+    }
+
+    #[test]
+    fn single_star_stays_within_one_component() {
+        let mut excludes = with_globs(&["*.log"]);
+        assert!(excludes.excludes(Path::new("/srv/mc/latest.log")));
+        assert!(!excludes.excludes(Path::new("/srv/mc/logs/latest.log")));
+    }
+
+    #[test]
+    fn double_star_crosses_components() {
+        let mut excludes = with_globs(&["**/*.log"]);
+        assert!(excludes.excludes(Path::new("/srv/mc/logs/latest.log")));
+        assert!(excludes.excludes(Path::new("/srv/mc/plugins/a/b/debug.log")));
+        assert!(!excludes.excludes(Path::new("/srv/mc/logs/latest.log.gz")));
+    }
+
+    #[test]
+    fn name_patterns_still_apply_alongside_globs() {
+        let mut excludes = TransientExcludes::new(vec!["session.lock".into(), "*.dat_old".into()])
+            .with_globs(Path::new("/srv/mc"), vec![Pattern::new("cache").unwrap()]);
+        assert!(excludes.excludes(Path::new("/srv/mc/world/session.lock")));
+        assert!(excludes.excludes(Path::new("/srv/mc/world/level.dat_old")));
+        assert!(excludes.excludes_dir(Path::new("/srv/mc/cache")));
+        assert!(!excludes.excludes(Path::new("/srv/mc/world/level.dat")));
+    }
+
+    #[test]
+    fn no_globs_never_match() {
+        let excludes = TransientExcludes::new(Vec::new());
+        assert!(!excludes.matches_glob(Path::new("/srv/mc/logs")));
+    }
+}
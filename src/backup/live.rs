@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::bail;
+use rustix::fs::Advice;
+use tracing::{debug, error, info, warn};
+
+use super::xattrs::XattrCapture;
+
+/// Files that changed under the archiver while a live server directory was
+/// being read. Neither kind fails the backup.
+#[derive(Debug, Default)]
+pub struct RaceStats {
+    /// Deleted between enumeration and read; left out of the archive.
+    pub vanished: u64,
+    /// Size changed while being read; stored at the size first seen.
+    pub resized: u64,
+}
+
+impl RaceStats {
+    pub fn vanished(&mut self, path: &Path) {
+        warn!(path = %path.display(), "File vanished before it was archived, skipping");
+        self.vanished += 1;
+    }
+
+    pub fn log_summary(&self) {
+        if self.vanished > 0 || self.resized > 0 {
+            warn!(
+                vanished = self.vanished,
+                resized = self.resized,
+                "Files changed while archiving; stop the server or use RCON save-off for a consistent snapshot"
+            );
+        } else {
+            info!("No files changed while archiving");
+        }
+    }
+}
+
+/// Append the regular file at `path` as `name`, tolerating a live directory:
+/// a file that is gone by the time it is opened is skipped, and one whose
+/// size changes mid-read is zero-padded or truncated to the size in its
+/// header so the archive stays readable. Its extended attributes are only
+/// written once the file is known to exist. With `fadvise`, the file is read
+/// with sequential read-ahead and marked as read once, so the kernel need not
+/// keep it cached. Pages the server already had cached are left alone.
+pub fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    sparse: bool,
+    fadvise: bool,
+    xattrs: &mut XattrCapture,
+    races: &mut RaceStats,
+) -> anyhow::Result<()> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            races.vanished(path);
+            return Ok(());
+        }
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to open file");
+            bail!("Failed to open {}: {}", path.display(), e);
+        }
+    };
+    // Taken from the open handle, which stays readable even if the path is
+    // unlinked from here on
+    let meta = match file.metadata() {
+        Ok(m) => m,
+        Err(e) => bail!("Failed to stat {}: {}", path.display(), e),
+    };
+    xattrs.append_for(builder, path)?;
+
+    // NOREUSE is a no-op before Linux 6.3; only the read-ahead applies there
+    if fadvise {
+        advise(&file, path, Advice::Sequential);
+        advise(&file, path, Advice::NoReuse);
+    }
+    append_contents(builder, &mut file, path, name, &meta, sparse, races)
+}
+
+fn append_contents<W: Write>(
+    builder: &mut tar::Builder<W>,
+    file: &mut File,
+    path: &Path,
+    name: &Path,
+    meta: &std::fs::Metadata,
+    sparse: bool,
+    races: &mut RaceStats,
+) -> anyhow::Result<()> {
+    let size = meta.len();
+
+    // tar can only store holes through its own reader, which copies whatever
+    // the file holds at the time, so a size change there is fatal
+    if sparse && meta.blocks() * 512 < size {
+        if let Err(e) = builder.append_file(name, file) {
+            error!(error = %e, path = %path.display(), "Failed to append file");
+            bail!("Failed to append {}: {}", path.display(), e);
+        }
+        if file.metadata().map(|m| m.len()).unwrap_or(size) != size {
+            error!(path = %path.display(), "Sparse file changed size while archived");
+            bail!(
+                "{} changed size while being archived; retry with the server stopped",
+                path.display()
+            );
+        }
+        return Ok(());
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(meta);
+    header.set_size(size);
+    let mut data = FixedSize {
+        inner: file,
+        remaining: size,
+        short: false,
+    };
+    if let Err(e) = builder.append_data(&mut header, name, &mut data) {
+        error!(error = %e, path = %path.display(), "Failed to append file");
+        bail!("Failed to append {}: {}", path.display(), e);
+    }
+
+    let shrank = data.short;
+    let grew = matches!(file.read(&mut [0u8; 1]), Ok(n) if n > 0);
+    if shrank || grew {
+        warn!(
+            path = %path.display(),
+            archived_bytes = size,
+            shrank = shrank,
+            grew = grew,
+            "File changed size while archived; stored at its original size"
+        );
+        races.resized += 1;
+    }
+    Ok(())
+}
+
+/// Pass a `posix_fadvise` hint for the whole of `file`. The hint is advisory,
+/// so a filesystem that rejects it only costs a debug line.
+fn advise(file: &File, path: &Path, advice: Advice) {
+    if let Err(e) = rustix::fs::fadvise(file, 0, None, advice) {
+        debug!(error = %e, path = %path.display(), advice = ?advice, "posix_fadvise failed");
+    }
+}
+
+/// Yields exactly `remaining` bytes of `inner`, zero-filling if it ends early.
+struct FixedSize<'a> {
+    inner: &'a mut File,
+    remaining: u64,
+    short: bool,
+}
+
+impl Read for FixedSize<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let want = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = if self.short {
+            0
+        } else {
+            self.inner.read(&mut buf[..want])?
+        };
+        let n = if n == 0 {
+            self.short = true;
+            buf[..want].fill(0);
+            want
+        } else {
+            n
+        };
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
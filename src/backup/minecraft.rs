@@ -1,17 +1,26 @@
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use anyhow::bail;
 use tracing::{error, info};
+use walkdir::WalkDir;
 
+use super::chain::ArchiveScope;
+use super::exclude::{END_DIR, NETHER_DIR, TransientExcludes};
+use super::live::{self, RaceStats};
+use super::naming::{artifact_timestamp, claim_artifact_path};
+use super::scan;
+use super::sink::ArchiveSink;
+use super::xattrs::XattrCapture;
+use super::{BackupArtifact, BackupKind};
 use crate::config::config::Config;
 
-pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
-    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("minecraft_{}.tar.zst", timestamp);
-    let output_path = config.backup_temp_dir.join(&filename);
-
+pub async fn backup_minecraft(
+    config: &Config,
+    scope: ArchiveScope,
+) -> anyhow::Result<BackupArtifact> {
     let mc_path = config.minecraft_server_path.clone();
 
     if !mc_path.exists() {
@@ -22,17 +31,54 @@ pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
         );
     }
 
+    super::running::check_policy(config)?;
+
+    let timestamp = artifact_timestamp(config, BackupKind::Minecraft).await;
+    let prefix = BackupKind::Minecraft.artifact_prefix(config.host_tag.as_deref());
+    let stem = match scope {
+        ArchiveScope::Full => format!("{}{}", prefix, timestamp),
+        ArchiveScope::Differential { .. } => format!("{}diff_{}", prefix, timestamp),
+        ArchiveScope::Incremental { .. } => format!("{}incr_{}", prefix, timestamp),
+    };
+    let output_path = claim_artifact_path(
+        &config.backup_temp_dir,
+        &stem,
+        ".tar.zst",
+        config.naming_collision,
+    )
+    .await?;
+
     info!(
         source = %mc_path.display(),
         output = %output_path.display(),
+        sparse = config.tar_sparse,
+        full = scope.is_full(),
         "Starting Minecraft server backup (streaming tar+zstd)"
     );
 
     let out = output_path.clone();
     let mc = mc_path.clone();
+    let sparse = config.tar_sparse;
+    let fadvise = config.io_fadvise;
+    let max_archive_bytes = config.max_archive_bytes;
+    let scan_threads = config.mc_scan_threads;
+    let spill_dir = config.backup_temp_dir.clone();
+    let dict_path = config.zstd_dict_path.clone();
+    let prefix = config.mc_archive_prefix.clone();
+    let mut xattrs = XattrCapture::new(config.tar_preserve_xattrs);
+    let mut skip_dirs = Vec::new();
+    if config.mc_skip_nether {
+        skip_dirs.push(NETHER_DIR.to_string());
+    }
+    if config.mc_skip_end {
+        skip_dirs.push(END_DIR.to_string());
+    }
+    let mut excludes = TransientExcludes::new(config.mc_default_excludes.clone())
+        .with_dirs(skip_dirs)
+        .with_globs(&mc_path, config.mc_exclude_patterns.clone());
 
     // tar and zstd crates are synchronous - run in a blocking thread
-    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<(u64, String)> {
         let file = match File::create(&out) {
             Ok(f) => f,
             Err(e) => {
@@ -40,9 +86,15 @@ pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
                 bail!("Failed to create output file {}: {}", out.display(), e);
             }
         };
-        let writer = BufWriter::with_capacity(512 * 1024, file);
+        // Cap and hash the compressed bytes on their way to disk
+        let writer = ArchiveSink::create(file, max_archive_bytes);
 
-        let mut encoder = match zstd::Encoder::new(writer, 3) {
+        let dict = super::dict::load(dict_path.as_deref())?;
+        let encoder = match &dict {
+            Some(dict) => zstd::Encoder::with_dictionary(writer, 3, dict),
+            None => zstd::Encoder::new(writer, 3),
+        };
+        let mut encoder = match encoder {
             Ok(enc) => enc,
             Err(e) => {
                 error!(error = %e, "Failed to create zstd encoder");
@@ -59,15 +111,42 @@ pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
         // Don't follow symlinks - prevents chasing links outside the server directory
         // and avoids archiving unexpected/duplicate data
         tar_builder.follow_symlinks(false);
+        // Store zero runs in region files as holes rather than literal zeros;
+        // extraction recreates them by seeking past the gaps.
+        tar_builder.sparse(sparse);
 
-        if let Err(e) = tar_builder.append_dir_all("minecraft", &mc) {
-            error!(
-                error = %e,
-                source_path = %mc.display(),
-                "Failed to build tar archive"
-            );
-            bail!("Failed to build tar archive from {}: {}", mc.display(), e);
+        let since = match scope {
+            ArchiveScope::Full => None,
+            ArchiveScope::Differential { since } | ArchiveScope::Incremental { since } => {
+                Some(SystemTime::from(since))
+            }
+        };
+        // Always walked rather than append_dir_all, which can't be interrupted,
+        // filtered, carry xattrs or survive files changing under it
+        let mut races = RaceStats::default();
+        let mut opts = EntryOptions {
+            since,
+            sparse,
+            fadvise,
+            excludes: &mut excludes,
+            xattrs: &mut xattrs,
+            races: &mut races,
+        };
+        if scan_threads > 1 {
+            append_scanned(
+                &mut tar_builder,
+                &mc,
+                &prefix,
+                scan_threads,
+                &spill_dir,
+                &mut opts,
+            )?;
+        } else {
+            append_walked(&mut tar_builder, &mc, &prefix, &mut opts)?;
         }
+        excludes.log_summary();
+        xattrs.log_summary();
+        races.log_summary();
 
         let encoder = match tar_builder.into_inner() {
             Ok(enc) => enc,
@@ -85,28 +164,18 @@ pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
             }
         };
 
-        let file = match writer.into_inner() {
-            Ok(f) => f,
+        match writer.finish() {
+            Ok(out) => Ok((out.size_bytes, out.sha256)),
             Err(e) => {
                 error!(error = %e, "Failed to flush output buffer");
                 bail!("Failed to flush output buffer: {}", e);
             }
-        };
-
-        let metadata = match file.metadata() {
-            Ok(m) => m,
-            Err(e) => {
-                error!(error = %e, "Failed to get output file metadata");
-                bail!("Failed to get output file metadata: {}", e);
-            }
-        };
-
-        Ok(metadata.len())
+        }
     })
     .await;
 
-    let size_bytes = match result {
-        Ok(Ok(size)) => size,
+    let (size_bytes, sha256) = match result {
+        Ok(Ok(done)) => done,
         Ok(Err(e)) => {
             error!(error = %e, output = %output_path.display(), "Minecraft backup failed");
             cleanup_temp_file(&output_path).await;
@@ -119,13 +188,290 @@ pub async fn backup_minecraft(config: &Config) -> anyhow::Result<PathBuf> {
         }
     };
 
+    if config.verify_archive
+        && let Err(e) = verify_written(&output_path, size_bytes, config).await
+    {
+        error!(error = %e, output = %output_path.display(), "Archive verification failed");
+        cleanup_temp_file(&output_path).await;
+        return Err(e);
+    }
+
     info!(
         path = %output_path.display(),
         size_bytes = size_bytes,
         "Minecraft server backup completed"
     );
 
-    Ok(output_path)
+    Ok(BackupArtifact {
+        path: output_path,
+        sha256: Some(sha256),
+        db_writes: Vec::new(),
+        database: None,
+    })
+}
+
+/// Check that `path` is `expected_size` bytes on disk, then decompress it end
+/// to end with [`super::restore::verify_archive`].
+async fn verify_written(path: &Path, expected_size: u64, config: &Config) -> anyhow::Result<()> {
+    info!(path = %path.display(), "Verifying archive");
+    let on_disk = match tokio::fs::metadata(path).await {
+        Ok(m) => m.len(),
+        Err(e) => bail!("Failed to stat {}: {}", path.display(), e),
+    };
+    if on_disk != expected_size {
+        bail!(
+            "{} is {} bytes on disk, expected {}",
+            path.display(),
+            on_disk,
+            expected_size
+        );
+    }
+    super::restore::verify_archive(path, config.zstd_dict_path.as_deref()).await?;
+    Ok(())
+}
+
+/// Per-entry settings shared by [`append_walked`] and [`append_scanned`].
+struct EntryOptions<'a> {
+    /// Only files modified after this are included; directories always are.
+    since: Option<SystemTime>,
+    sparse: bool,
+    /// Pass `IO_FADVISE` hints for each file read.
+    fadvise: bool,
+    excludes: &'a mut TransientExcludes,
+    xattrs: &'a mut XattrCapture,
+    races: &'a mut RaceStats,
+}
+
+/// What happened to one non-directory entry.
+enum Appended {
+    Included,
+    Unchanged,
+    Skipped,
+}
+
+/// Walk `root` and append it laid out like `append_dir_all(prefix, root)`,
+/// streaming entries as they are found. Files deleted since the anchor backup
+/// are not represented in a differential, so a restore applies full + latest
+/// differential. Each entry is preceded by its extended attributes when
+/// enabled.
+fn append_walked<W: Write>(
+    builder: &mut tar::Builder<W>,
+    root: &Path,
+    prefix: &Path,
+    opts: &mut EntryOptions<'_>,
+) -> anyhow::Result<()> {
+    let mut included: u64 = 0;
+    let mut unchanged: u64 = 0;
+
+    let mut walker = WalkDir::new(root).follow_links(false).into_iter();
+    while let Some(entry) = walker.next() {
+        crate::abort::check()?;
+        crate::systemd::progress();
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e)
+                if e.io_error()
+                    .is_some_and(|io| io.kind() == ErrorKind::NotFound) =>
+            {
+                if let Some(path) = e.path() {
+                    opts.races.vanished(path);
+                }
+                continue;
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to walk Minecraft server directory");
+                bail!("Failed to walk {}: {}", root.display(), e);
+            }
+        };
+
+        let relative = match entry.path().strip_prefix(root) {
+            Ok(r) => r,
+            Err(e) => bail!("Walked path {} escaped root: {}", entry.path().display(), e),
+        };
+        let name = prefix.join(relative);
+
+        if entry.file_type().is_dir() {
+            if entry.depth() > 0 && opts.excludes.excludes_dir(entry.path()) {
+                walker.skip_current_dir();
+                continue;
+            }
+            append_dir_entry(builder, entry.path(), &name, opts)?;
+            continue;
+        }
+
+        let modified = || match entry.metadata() {
+            Ok(m) => m.modified(),
+            Err(e) => Err(std::io::Error::from(e)),
+        };
+        match append_file_entry(
+            builder,
+            entry.path(),
+            &name,
+            entry.file_type().is_file(),
+            modified,
+            opts,
+        )? {
+            Appended::Included => included += 1,
+            Appended::Unchanged => unchanged += 1,
+            Appended::Skipped => {}
+        }
+    }
+
+    info!(
+        changed_files = included,
+        unchanged_files = unchanged,
+        "Directory walk completed"
+    );
+
+    Ok(())
+}
+
+/// Enumerate `root` with a parallel scan, then feed the entries in path order
+/// to the single tar writer one at a time; large trees are sorted through run
+/// files in `spill_dir` rather than in memory. Entries are filtered as in
+/// [`append_walked`].
+fn append_scanned<W: Write>(
+    builder: &mut tar::Builder<W>,
+    root: &Path,
+    prefix: &Path,
+    threads: usize,
+    spill_dir: &Path,
+    opts: &mut EntryOptions<'_>,
+) -> anyhow::Result<()> {
+    let scan_started = std::time::Instant::now();
+    let entries = match scan::scan_tree(root, threads, spill_dir) {
+        Ok(e) => e,
+        Err(e) => {
+            error!(error = %e, "Failed to scan Minecraft server directory");
+            return Err(e);
+        }
+    };
+    info!(
+        entries = entries.total(),
+        spilled_runs = entries.spilled_runs(),
+        threads = threads,
+        elapsed = ?scan_started.elapsed(),
+        "Parallel directory scan completed"
+    );
+
+    let mut included: u64 = 0;
+    let mut unchanged: u64 = 0;
+    // Entries are sorted, so everything under a skipped directory follows it
+    let mut skipped_dir: Option<PathBuf> = None;
+    for entry in entries {
+        crate::abort::check()?;
+        crate::systemd::progress();
+        let entry = entry?;
+        if let Some(dir) = &skipped_dir {
+            if entry.relative.starts_with(dir) {
+                continue;
+            }
+            skipped_dir = None;
+        }
+
+        let name = prefix.join(&entry.relative);
+
+        if entry.is_dir {
+            if !entry.relative.as_os_str().is_empty() && opts.excludes.excludes_dir(&entry.path) {
+                skipped_dir = Some(entry.relative);
+                continue;
+            }
+            append_dir_entry(builder, &entry.path, &name, opts)?;
+            continue;
+        }
+
+        let modified = || Ok(entry.modified);
+        match append_file_entry(builder, &entry.path, &name, entry.is_file, modified, opts)? {
+            Appended::Included => included += 1,
+            Appended::Unchanged => unchanged += 1,
+            Appended::Skipped => {}
+        }
+    }
+
+    info!(
+        files = included,
+        unchanged_files = unchanged,
+        "Archive entries appended"
+    );
+
+    Ok(())
+}
+
+/// Append a directory entry; one removed since it was enumerated is skipped.
+fn append_dir_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    opts: &mut EntryOptions<'_>,
+) -> anyhow::Result<()> {
+    // An empty prefix stores the root's contents without a root entry
+    if name.as_os_str().is_empty() {
+        return Ok(());
+    }
+    if !path.exists() {
+        opts.races.vanished(path);
+        return Ok(());
+    }
+    opts.xattrs.append_for(builder, path)?;
+    if let Err(e) = builder.append_dir(name, path) {
+        error!(error = %e, path = %path.display(), "Failed to append directory");
+        bail!("Failed to append {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Append a non-directory entry unless it is excluded or, with `since`, was
+/// not modified after it. Regular files go through [`live::append_file`];
+/// symlinks and other special files are stored as they are.
+fn append_file_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    is_file: bool,
+    modified: impl FnOnce() -> std::io::Result<SystemTime>,
+    opts: &mut EntryOptions<'_>,
+) -> anyhow::Result<Appended> {
+    if opts.excludes.excludes(path) {
+        return Ok(Appended::Skipped);
+    }
+
+    if let Some(since) = opts.since {
+        let modified = match modified() {
+            Ok(t) => t,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                opts.races.vanished(path);
+                return Ok(Appended::Skipped);
+            }
+            Err(e) => bail!("Failed to read mtime of {}: {}", path.display(), e),
+        };
+        if modified <= since {
+            return Ok(Appended::Unchanged);
+        }
+    }
+
+    if is_file {
+        live::append_file(
+            builder,
+            path,
+            name,
+            opts.sparse,
+            opts.fadvise,
+            opts.xattrs,
+            opts.races,
+        )?;
+        return Ok(Appended::Included);
+    }
+
+    if std::fs::symlink_metadata(path).is_err_and(|e| e.kind() == ErrorKind::NotFound) {
+        opts.races.vanished(path);
+        return Ok(Appended::Skipped);
+    }
+    opts.xattrs.append_for(builder, path)?;
+    if let Err(e) = builder.append_path_with_name(path, name) {
+        error!(error = %e, path = %path.display(), "Failed to append file");
+        bail!("Failed to append {}: {}", path.display(), e);
+    }
+    Ok(Appended::Included)
 }
 
 async fn cleanup_temp_file(path: &std::path::Path) {
@@ -140,3 +486,70 @@ async fn cleanup_temp_file(path: &std::path::Path) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walked_archive_leaves_out_excluded_paths() {
+        let root = std::env::temp_dir().join(format!("mc-exclude-test-{}", std::process::id()));
+        for dir in ["logs", "world/region", "dynmap/web/tiles"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "server.properties",
+            "logs/latest.log",
+            "world/level.dat",
+            "world/session.lock",
+            "world/region/r.0.0.mca",
+            "dynmap/web/tiles/0.png",
+            "dynmap/web/index.html",
+        ] {
+            std::fs::write(root.join(file), file).unwrap();
+        }
+
+        let globs = ["logs", "dynmap/web/tiles"]
+            .iter()
+            .map(|g| glob::Pattern::new(g).unwrap())
+            .collect();
+        let mut excludes =
+            TransientExcludes::new(vec!["session.lock".into()]).with_globs(&root, globs);
+        let mut xattrs = XattrCapture::new(false);
+        let mut races = RaceStats::default();
+        let mut opts = EntryOptions {
+            since: None,
+            sparse: false,
+            fadvise: false,
+            excludes: &mut excludes,
+            xattrs: &mut xattrs,
+            races: &mut races,
+        };
+        let mut builder = tar::Builder::new(Vec::new());
+        let walked = append_walked(&mut builder, &root, Path::new("mc"), &mut opts);
+        let archive = builder.into_inner().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        walked.unwrap();
+
+        let mut names: Vec<String> = tar::Archive::new(archive.as_slice())
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .filter(|n| n.trim_end_matches('/') != "mc")
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "mc/dynmap",
+                "mc/dynmap/web",
+                "mc/dynmap/web/index.html",
+                "mc/server.properties",
+                "mc/world",
+                "mc/world/level.dat",
+                "mc/world/region",
+                "mc/world/region/r.0.0.mca",
+            ]
+        );
+    }
+}
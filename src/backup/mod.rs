@@ -1,2 +1,86 @@
+pub mod bundle;
+pub mod chain;
+pub mod checksum;
 pub mod db;
+pub mod db_restore;
+pub mod dict;
+pub mod exclude;
+pub mod live;
 pub mod minecraft;
+pub mod naming;
+pub mod rcon;
+pub mod restore;
+pub mod restore_log;
+pub mod running;
+pub mod scan;
+pub mod sink;
+pub mod split;
+pub mod ssh_tunnel;
+pub mod validate;
+pub mod xattrs;
+
+/// A backup file ready to upload.
+#[derive(Debug, Clone)]
+pub struct BackupArtifact {
+    pub path: std::path::PathBuf,
+    /// Hex SHA-256 of the file, when it was computed while writing it.
+    pub sha256: Option<String>,
+    /// Db write counters of each database dumped, read before its dump and
+    /// stored once the artifact is uploaded so the next run can tell whether
+    /// anything changed (`DB_SKIP_UNCHANGED`).
+    pub db_writes: Vec<(String, crate::status::DbWriteMark)>,
+    /// The database a db artifact dumps when `DB_NAMES` is set, as labelled
+    /// in status and metrics; the run fills it in.
+    pub database: Option<String>,
+}
+
+/// The kinds of backup this tool produces, used to key persisted state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupKind {
+    Db,
+    Minecraft,
+}
+
+impl BackupKind {
+    pub const ALL: [BackupKind; 2] = [BackupKind::Db, BackupKind::Minecraft];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupKind::Db => "db",
+            BackupKind::Minecraft => "minecraft",
+        }
+    }
+
+    /// Name of the per-type subfolder created under each Drive root.
+    pub fn folder_name(&self) -> &'static str {
+        match self {
+            BackupKind::Db => "DB_Backups",
+            BackupKind::Minecraft => "Minecraft_Backups",
+        }
+    }
+
+    /// Filename prefix shared by every artifact of this kind, used to scope
+    /// retention to a single type.
+    pub fn file_prefix(&self) -> &'static str {
+        match self {
+            BackupKind::Db => "db_",
+            BackupKind::Minecraft => "minecraft_",
+        }
+    }
+
+    /// Prefix for this host's artifacts: `<kind>_<host>_` when `INCLUDE_HOSTNAME`
+    /// is set, so retention on a shared folder only counts this host's files.
+    pub fn artifact_prefix(&self, host_tag: Option<&str>) -> String {
+        match host_tag {
+            Some(host) => format!("{}{}_", self.file_prefix(), host),
+            None => self.file_prefix().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for BackupKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
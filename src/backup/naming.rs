@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use tracing::{error, warn};
+
+use super::BackupKind;
+use crate::config::config::Config;
+use crate::status::next_name_stamp;
+
+/// What to do when an artifact name is already taken (`NAMING_COLLISION`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamingCollision {
+    /// Append `_1`, `_2`, ... to the name.
+    Suffix,
+    /// Use millisecond timestamps, falling back to a suffix if even those clash.
+    Millis,
+    /// Refuse to produce the artifact.
+    Fail,
+}
+
+/// Timestamp embedded in artifact names of `kind`. Successive names never go
+/// backwards, even across a wall-clock step; see [`next_name_stamp`].
+pub async fn artifact_timestamp(config: &Config, kind: BackupKind) -> String {
+    let resolution = match config.naming_collision {
+        NamingCollision::Millis => chrono::Duration::milliseconds(1),
+        NamingCollision::Suffix | NamingCollision::Fail => chrono::Duration::seconds(1),
+    };
+    let stamp = next_name_stamp(&config.status_file_path, kind, resolution).await;
+    match config.naming_collision {
+        NamingCollision::Millis => stamp.last_at.format("%Y%m%d_%H%M%S_%3f").to_string(),
+        NamingCollision::Suffix | NamingCollision::Fail => {
+            stamp.last_at.format("%Y%m%d_%H%M%S").to_string()
+        }
+    }
+}
+
+/// Reserve `<dir>/<stem><extension>` by creating it empty, resolving a clash
+/// per `strategy`. Creating the file (rather than checking for it) keeps two
+/// concurrent backups from settling on the same name.
+pub async fn claim_artifact_path(
+    dir: &Path,
+    stem: &str,
+    extension: &str,
+    strategy: NamingCollision,
+) -> anyhow::Result<PathBuf> {
+    let mut counter: u32 = 0;
+    loop {
+        let name = if counter == 0 {
+            format!("{}{}", stem, extension)
+        } else {
+            format!("{}_{}{}", stem, counter, extension)
+        };
+        let path = dir.join(&name);
+
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                if strategy == NamingCollision::Fail {
+                    error!(path = %path.display(), "Artifact name already in use");
+                    bail!(
+                        "Artifact {} already exists (NAMING_COLLISION=fail)",
+                        path.display()
+                    );
+                }
+                warn!(path = %path.display(), "Artifact name already in use, adding a suffix");
+                counter += 1;
+            }
+            Err(e) => bail!("Failed to create {}: {}", path.display(), e),
+        }
+    }
+}
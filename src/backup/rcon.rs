@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const PACKET_LOGIN: i32 = 3;
+const PACKET_COMMAND: i32 = 2;
+
+/// Upper bound for any single RCON round-trip.
+const RCON_IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A minimal Source RCON client, enough to send console commands such as
+/// `save-all` or `stop` to a Minecraft server.
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    pub async fn connect(addr: &str, password: &str) -> anyhow::Result<Self> {
+        let stream = match tokio::time::timeout(RCON_IO_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(s)) => s,
+            Ok(Err(e)) => bail!("Failed to connect to RCON at {}: {}", addr, e),
+            Err(_) => bail!("Timed out connecting to RCON at {}", addr),
+        };
+
+        let mut client = RconClient { stream, next_id: 1 };
+        let id = client.send(PACKET_LOGIN, password).await?;
+        let (response_id, _) = client.recv().await?;
+        // The server answers a failed login with request id -1
+        if response_id == -1 || response_id != id {
+            bail!("RCON authentication to {} was rejected", addr);
+        }
+
+        Ok(client)
+    }
+
+    /// Run a console command and return the server's reply.
+    pub async fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        let id = self.send(PACKET_COMMAND, command).await?;
+        let (response_id, body) = self.recv().await?;
+        if response_id != id {
+            bail!(
+                "Unexpected RCON response id {} (expected {})",
+                response_id,
+                id
+            );
+        }
+        Ok(body)
+    }
+
+    async fn send(&mut self, packet_type: i32, body: &str) -> anyhow::Result<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // length covers id + type + body + two NUL terminators
+        let length = (4 + 4 + body.len() + 2) as i32;
+        let mut packet = Vec::with_capacity(4 + length as usize);
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&packet_type.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+
+        match tokio::time::timeout(RCON_IO_TIMEOUT, self.stream.write_all(&packet)).await {
+            Ok(Ok(())) => Ok(id),
+            Ok(Err(e)) => bail!("Failed to write RCON packet: {}", e),
+            Err(_) => bail!("Timed out writing RCON packet"),
+        }
+    }
+
+    async fn recv(&mut self) -> anyhow::Result<(i32, String)> {
+        let read = async {
+            let length = self.stream.read_i32_le().await?;
+            if !(10..=4096 + 10).contains(&length) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid RCON packet length {}", length),
+                ));
+            }
+            let mut buf = vec![0u8; length as usize];
+            self.stream.read_exact(&mut buf).await?;
+            Ok(buf)
+        };
+
+        let buf = match tokio::time::timeout(RCON_IO_TIMEOUT, read).await {
+            Ok(Ok(b)) => b,
+            Ok(Err(e)) => bail!("Failed to read RCON packet: {}", e),
+            Err(_) => bail!("Timed out waiting for RCON response"),
+        };
+
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let body = String::from_utf8_lossy(&buf[8..buf.len() - 2]).into_owned();
+        Ok((id, body))
+    }
+}
@@ -0,0 +1,803 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::bail;
+use tracing::{error, info, warn};
+use walkdir::WalkDir;
+
+use super::exclude::{END_DIR, NETHER_DIR};
+use super::rcon::RconClient;
+use crate::config::config::Config;
+
+/// Restore a Minecraft backup over the live server directory: stop the
+/// server, keep a safety archive of the current world, extract the backup
+/// into place, and start the server again. `chain` is a full backup followed,
+/// oldest first, by the differentials and incrementals to apply on top of it;
+/// files deleted since the full backup come back, as those archives only hold
+/// what changed. Returns the path of the safety archive.
+pub async fn restore_minecraft(config: &Config, chain: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    let archive = chain_target(chain)?;
+
+    // Checked before the server is stopped, so a corrupt backup costs no downtime
+    let started = Instant::now();
+    let stats = verify_chain(chain, config.zstd_dict_path.as_deref()).await?;
+
+    let server_path = &config.minecraft_server_path;
+    info!(
+        archive = %archive.display(),
+        chain_len = chain.len(),
+        server_path = %server_path.display(),
+        "Step 1/4: stopping Minecraft server"
+    );
+    stop_server(config).await?;
+
+    info!("Step 2/4: archiving current server state before restore");
+    let safety_archive = match create_safety_archive(config).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to create safety archive, aborting restore");
+            start_server(config).await;
+            return Err(e);
+        }
+    };
+    info!(path = %safety_archive.display(), "Safety archive kept");
+
+    info!(archive = %archive.display(), "Step 3/4: extracting backup into place");
+    if let Err(e) = replace_server_dir(
+        chain,
+        server_path,
+        &config.mc_archive_prefix,
+        config.tar_preserve_xattrs,
+        config.zstd_dict_path.as_deref(),
+    )
+    .await
+    {
+        error!(error = %e, "Extraction failed, the existing server directory was left in place");
+        start_server(config).await;
+        return Err(e);
+    }
+
+    // Listed before the server starts writing to the restored tree
+    let recorded = super::restore_log::record_tree(config, archive, server_path).await;
+    if let Err(e) = &recorded {
+        error!(error = %e, "Restore verification log was not written");
+    }
+
+    info!("Step 4/4: starting Minecraft server");
+    start_server(config).await;
+    recorded?;
+
+    info!(
+        archive = %archive.display(),
+        safety_archive = %safety_archive.display(),
+        entries = stats.entries,
+        decompressed_bytes = stats.decompressed_bytes,
+        elapsed = ?started.elapsed(),
+        "Minecraft restore completed"
+    );
+
+    Ok(safety_archive)
+}
+
+/// Extract a Minecraft backup chain (see [`restore_minecraft`]) into
+/// `target_dir`, leaving the live server alone and writing only the files
+/// `filter` lets through. Every archive is verified end to end first, so
+/// corrupt data is caught before anything is written.
+pub async fn extract_into(
+    config: &Config,
+    chain: &[PathBuf],
+    target_dir: &Path,
+    filter: &ExtFilter,
+) -> anyhow::Result<()> {
+    let archive = chain_target(chain)?;
+    let started = Instant::now();
+    let stats = verify_chain(chain, config.zstd_dict_path.as_deref()).await?;
+
+    info!(
+        archive = %archive.display(),
+        chain_len = chain.len(),
+        target_dir = %target_dir.display(),
+        include_ext = ?filter.include,
+        exclude_ext = ?filter.exclude,
+        "Extracting backup"
+    );
+    for link in chain {
+        if filter.is_empty() {
+            super::split::extract_archive(
+                link,
+                target_dir,
+                config.tar_preserve_xattrs,
+                config.zstd_dict_path.as_deref(),
+            )
+            .await?;
+        } else {
+            let counts = extract_filtered(
+                link,
+                target_dir,
+                filter.clone(),
+                config.tar_preserve_xattrs,
+                config.zstd_dict_path.as_deref(),
+            )
+            .await?;
+            info!(
+                archive = %link.display(),
+                extracted = counts.extracted,
+                skipped = counts.skipped,
+                "Extracted {} entries, skipped {} by extension",
+                counts.extracted,
+                counts.skipped
+            );
+        }
+    }
+    super::restore_log::record_tree(config, archive, target_dir).await?;
+
+    info!(
+        archive = %archive.display(),
+        target_dir = %target_dir.display(),
+        entries = stats.entries,
+        decompressed_bytes = stats.decompressed_bytes,
+        elapsed = ?started.elapsed(),
+        "Minecraft backup extracted"
+    );
+    Ok(())
+}
+
+/// The archive `chain` restores to, its last, after checking that every
+/// archive exists and that the chain starts from a full backup.
+fn chain_target(chain: &[PathBuf]) -> anyhow::Result<&Path> {
+    let (Some(first), Some(last)) = (chain.first(), chain.last()) else {
+        bail!("No Minecraft archive to restore");
+    };
+    for archive in chain {
+        if !archive.is_file() {
+            error!(path = %archive.display(), "Restore archive does not exist");
+            bail!("Restore archive does not exist: {}", archive.display());
+        }
+    }
+    if let Some(name) = first.file_name().and_then(|n| n.to_str())
+        && (name.contains("_diff_") || name.contains("_incr_"))
+    {
+        error!(
+            archive = name,
+            "Refusing to restore a differential or incremental archive without its full backup"
+        );
+        bail!(
+            "'{}' only holds changed files; restore it together with its full backup",
+            name
+        );
+    }
+    Ok(last)
+}
+
+/// [`verify_archive`] every archive of `chain`, adding up what was read.
+pub async fn verify_chain(
+    chain: &[PathBuf],
+    dict_path: Option<&Path>,
+) -> anyhow::Result<ArchiveStats> {
+    let mut total = ArchiveStats {
+        entries: 0,
+        decompressed_bytes: 0,
+    };
+    for archive in chain {
+        let stats = verify_archive(archive, dict_path).await?;
+        total.entries += stats.entries;
+        total.decompressed_bytes += stats.decompressed_bytes;
+    }
+    Ok(total)
+}
+
+/// File extensions a selective extraction keeps (`--include-ext`) or drops
+/// (`--exclude-ext`), lowercased and without the leading dot. An empty
+/// include list keeps everything not excluded.
+#[derive(Debug, Clone, Default)]
+pub struct ExtFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl ExtFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        let normalize = |exts: &[String]| -> Vec<String> {
+            exts.iter()
+                .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect()
+        };
+        Self {
+            include: normalize(include),
+            exclude: normalize(exclude),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether the file at `path` should be extracted. Matching is on the
+    /// end of the file name, so `tar.zst` works as well as `mca`.
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return self.include.is_empty();
+        };
+        let name = name.to_ascii_lowercase();
+        let has_ext = |ext: &String| {
+            name.len() > ext.len() + 1
+                && name.ends_with(ext.as_str())
+                && name[..name.len() - ext.len()].ends_with('.')
+        };
+        (self.include.is_empty() || self.include.iter().any(has_ext))
+            && !self.exclude.iter().any(has_ext)
+    }
+}
+
+/// Entries written and left out by [`extract_filtered`].
+#[derive(Debug, Clone, Copy, Default)]
+struct ExtractCounts {
+    extracted: u64,
+    skipped: u64,
+}
+
+/// Unpack only the entries of `archive` that `filter` lets through into
+/// `dest_dir`. Directory entries are not extracted on their own; the
+/// directories of extracted files are created as needed.
+async fn extract_filtered(
+    archive: &Path,
+    dest_dir: &Path,
+    filter: ExtFilter,
+    unpack_xattrs: bool,
+    dict_path: Option<&Path>,
+) -> anyhow::Result<ExtractCounts> {
+    let dict = super::dict::load_for(dict_path, archive)?;
+    let archive = archive.to_path_buf();
+    let dest = dest_dir.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<ExtractCounts> {
+        if let Err(e) = std::fs::create_dir_all(&dest) {
+            bail!("Failed to create {}: {}", dest.display(), e);
+        }
+        let decoder = super::dict::open_decoder(&archive, dict.as_deref())?;
+        let mut tar_archive = tar::Archive::new(decoder);
+        tar_archive.set_preserve_permissions(true);
+        tar_archive.set_preserve_mtime(true);
+        let restore_xattrs = unpack_xattrs && super::xattrs::unpack_supported(&dest);
+
+        let iter = match tar_archive.entries() {
+            Ok(i) => i,
+            Err(e) => bail!("Failed to read {}: {}", archive.display(), e),
+        };
+        let mut counts = ExtractCounts::default();
+        for entry in iter {
+            let mut entry = match entry {
+                Ok(e) => e,
+                Err(e) => bail!("Failed to read {}: {}", archive.display(), e),
+            };
+            if entry.header().entry_type().is_dir() {
+                continue;
+            }
+            let path = match entry.path() {
+                Ok(p) => p.into_owned(),
+                Err(e) => bail!("Invalid entry path in {}: {}", archive.display(), e),
+            };
+            if !filter.matches(&path) {
+                counts.skipped += 1;
+                continue;
+            }
+            // unpack_in refuses entries that would land outside `dest`
+            match entry.unpack_in(&dest) {
+                Ok(true) => {
+                    counts.extracted += 1;
+                    if restore_xattrs {
+                        let target = super::xattrs::target_path(&dest, &path);
+                        super::xattrs::restore_for(&mut entry, &target);
+                    }
+                }
+                Ok(false) => {
+                    warn!(path = %path.display(), "Skipping archive entry outside the target directory");
+                    counts.skipped += 1;
+                }
+                Err(e) => bail!("Failed to extract {}: {}", path.display(), e),
+            }
+        }
+        Ok(counts)
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => bail!("Extraction task panicked: {}", e),
+    }
+}
+
+/// What a full pass over an archive found.
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveStats {
+    pub entries: u64,
+    pub decompressed_bytes: u64,
+}
+
+/// Decompress `archive` to the end and read every tar entry without writing
+/// anything, failing on corrupt zstd or tar data.
+pub async fn verify_archive(
+    archive: &Path,
+    dict_path: Option<&Path>,
+) -> anyhow::Result<ArchiveStats> {
+    let dict = super::dict::load_for(dict_path, archive)?;
+    let archive_path = archive.to_path_buf();
+    let started = Instant::now();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<ArchiveStats> {
+        let decoder = super::dict::open_decoder(&archive_path, dict.as_deref())?;
+        let mut tar_archive = tar::Archive::new(CountingReader {
+            inner: decoder,
+            count: 0,
+        });
+        let mut entries: u64 = 0;
+        let iter = match tar_archive.entries() {
+            Ok(i) => i,
+            Err(e) => bail!("Failed to read {}: {}", archive_path.display(), e),
+        };
+        for entry in iter {
+            crate::abort::check()?;
+            crate::systemd::progress();
+            let mut entry = match entry {
+                Ok(e) => e,
+                Err(e) => bail!(
+                    "{} is corrupt after {} entries: {}",
+                    archive_path.display(),
+                    entries,
+                    e
+                ),
+            };
+            // Accounts for sparse and PAX sizes, unlike the raw header field
+            let declared = entry.size();
+            let name = entry
+                .path()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default();
+            let read = match std::io::copy(&mut entry, &mut std::io::sink()) {
+                Ok(n) => n,
+                Err(e) => bail!(
+                    "{} is corrupt in entry {}: {}",
+                    archive_path.display(),
+                    name,
+                    e
+                ),
+            };
+            if read != declared {
+                bail!(
+                    "{}: entry {} holds {} bytes but its header declares {}",
+                    archive_path.display(),
+                    name,
+                    read,
+                    declared
+                );
+            }
+            entries += 1;
+        }
+        // Read past the tar footer so the zstd frame checksum is checked too
+        let mut reader = tar_archive.into_inner();
+        if let Err(e) = std::io::copy(&mut reader, &mut std::io::sink()) {
+            bail!(
+                "{} is corrupt past its last entry: {}",
+                archive_path.display(),
+                e
+            );
+        }
+        Ok(ArchiveStats {
+            entries,
+            decompressed_bytes: reader.count,
+        })
+    })
+    .await;
+
+    let stats = match result {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            error!(error = %e, archive = %archive.display(), "Archive verification failed");
+            return Err(e);
+        }
+        Err(e) => bail!("Verification task panicked: {}", e),
+    };
+    info!(
+        archive = %archive.display(),
+        entries = stats.entries,
+        decompressed_bytes = stats.decompressed_bytes,
+        elapsed = ?started.elapsed(),
+        "Archive verified"
+    );
+    Ok(stats)
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Stop the server with `MC_STOP_COMMAND`, or via RCON `stop` when
+/// `MC_RCON_ADDR` is set. With neither configured the server is assumed to be
+/// stopped already.
+async fn stop_server(config: &Config) -> anyhow::Result<()> {
+    if let Some(command) = &config.mc_stop_command {
+        return run_shell("MC_STOP_COMMAND", command).await;
+    }
+
+    let Some(rcon) = &config.mc_rcon else {
+        warn!(
+            "Neither MC_STOP_COMMAND nor MC_RCON_ADDR is set; assuming the server is already stopped"
+        );
+        return Ok(());
+    };
+
+    let mut client = match RconClient::connect(&rcon.addr, &rcon.password).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!(error = %e, addr = %rcon.addr, "Failed to connect to RCON");
+            bail!("Failed to connect to RCON to stop the server: {}", e);
+        }
+    };
+    if let Err(e) = client.command("stop").await {
+        // The server may drop the connection while shutting down
+        warn!(error = %e, "RCON stop did not return a response");
+    }
+    drop(client);
+
+    // The server is down once its RCON port stops accepting connections
+    let deadline = tokio::time::Instant::now() + config.mc_stop_timeout;
+    while tokio::net::TcpStream::connect(rcon.addr.as_str())
+        .await
+        .is_ok()
+    {
+        if tokio::time::Instant::now() >= deadline {
+            error!(timeout = ?config.mc_stop_timeout, "Minecraft server did not stop in time");
+            bail!(
+                "Minecraft server did not stop within {:?}",
+                config.mc_stop_timeout
+            );
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    info!(addr = %rcon.addr, "Minecraft server stopped via RCON");
+    Ok(())
+}
+
+/// Start the server with `MC_START_COMMAND`. Failures are logged rather than
+/// returned so they never mask the outcome of the restore itself.
+async fn start_server(config: &Config) {
+    let Some(command) = &config.mc_start_command else {
+        warn!("MC_START_COMMAND is not set; start the Minecraft server manually");
+        return;
+    };
+
+    if let Err(e) = run_shell("MC_START_COMMAND", command).await {
+        error!(error = %e, "Failed to start Minecraft server after restore");
+    }
+}
+
+async fn run_shell(label: &str, command: &str) -> anyhow::Result<()> {
+    info!(command = command, "Running {}", label);
+
+    let output = match tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => {
+            error!(error = %e, command = command, "Failed to spawn {}", label);
+            bail!("Failed to spawn {}: {}", label, e);
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(
+            exit_code = ?output.status.code(),
+            stderr = %stderr,
+            "{} failed",
+            label
+        );
+        bail!(
+            "{} exited with status {}: {}",
+            label,
+            output.status,
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Archive the whole current server directory into `MC_SAFETY_ARCHIVE_DIR`,
+/// where it is kept after the restore. Unlike a backup, nothing is excluded,
+/// no size cap applies and no dictionary is used, so it brings back exactly
+/// what was replaced. The server is stopped, so the tree is not changing.
+async fn create_safety_archive(config: &Config) -> anyhow::Result<PathBuf> {
+    if let Err(e) = tokio::fs::create_dir_all(&config.mc_safety_archive_dir).await {
+        error!(
+            error = %e,
+            path = %config.mc_safety_archive_dir.display(),
+            "Failed to create safety archive directory"
+        );
+        bail!(
+            "Failed to create safety archive directory {}: {}",
+            config.mc_safety_archive_dir.display(),
+            e
+        );
+    }
+
+    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+    let destination = config
+        .mc_safety_archive_dir
+        .join(format!("minecraft_pre_restore_{}.tar.zst", timestamp));
+    let partial = destination.with_extension("zst.partial");
+
+    let server_path = config.minecraft_server_path.clone();
+    let prefix = config.mc_archive_prefix.clone();
+    let output = partial.clone();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let file = match std::fs::File::create(&output) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to create {}: {}", output.display(), e),
+        };
+        let mut encoder = match zstd::Encoder::new(file, 3) {
+            Ok(enc) => enc,
+            Err(e) => bail!("Failed to create zstd encoder: {}", e),
+        };
+        if let Err(e) = encoder.multithread(0) {
+            bail!("Failed to enable zstd multithreading: {}", e);
+        }
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+        if let Err(e) = builder.append_dir_all(&prefix, &server_path) {
+            bail!("Failed to archive {}: {}", server_path.display(), e);
+        }
+        let encoder = match builder.into_inner() {
+            Ok(enc) => enc,
+            Err(e) => bail!("Failed to finalize tar archive: {}", e),
+        };
+        let file = match encoder.finish() {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to finish zstd stream: {}", e),
+        };
+        if let Err(e) = file.sync_all() {
+            bail!("Failed to sync {}: {}", output.display(), e);
+        }
+        Ok(())
+    })
+    .await;
+    let result = match result {
+        Ok(r) => r,
+        Err(e) => Err(anyhow::anyhow!("Safety archive task panicked: {}", e)),
+    };
+    if let Err(e) = result {
+        error!(error = %e, path = %partial.display(), "Failed to write safety archive");
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+
+    if let Err(e) = tokio::fs::rename(&partial, &destination).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        bail!(
+            "Failed to store safety archive at {}: {}",
+            destination.display(),
+            e
+        );
+    }
+    Ok(destination)
+}
+
+/// Extract the archives of `chain` in order into a staging directory next to
+/// the server, then swap its `prefix` directory in. The existing directory is
+/// only removed once the new one is in place.
+async fn replace_server_dir(
+    chain: &[PathBuf],
+    server_path: &Path,
+    prefix: &Path,
+    preserve_xattrs: bool,
+    dict_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let Some(parent) = server_path.parent() else {
+        bail!(
+            "Minecraft server path has no parent directory: {}",
+            server_path.display()
+        );
+    };
+    let dir_name = match server_path.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => bail!(
+            "Minecraft server path has no file name: {}",
+            server_path.display()
+        ),
+    };
+    let staging = parent.join(format!(".{}.restore-staging", dir_name));
+    let previous = parent.join(format!(".{}.pre-restore", dir_name));
+
+    for leftover in [&staging, &previous] {
+        if leftover.exists()
+            && let Err(e) = tokio::fs::remove_dir_all(leftover).await
+        {
+            bail!(
+                "Failed to remove leftover directory {}: {}",
+                leftover.display(),
+                e
+            );
+        }
+    }
+
+    // Loaded before anything is unpacked, so a missing dictionary fails early
+    let mut archives = Vec::with_capacity(chain.len());
+    for archive in chain {
+        archives.push((archive.clone(), super::dict::load_for(dict_path, archive)?));
+    }
+    let staging_dir = staging.clone();
+    let parent_dir = parent.to_path_buf();
+    let unpacked = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        // Later archives overwrite what earlier ones unpacked
+        for (archive_path, dict) in &archives {
+            let decoder = super::dict::open_decoder(archive_path, dict.as_deref())?;
+            let mut tar_archive = tar::Archive::new(decoder);
+            tar_archive.set_preserve_permissions(true);
+            tar_archive.set_preserve_mtime(true);
+            // Staging sits next to the server, on the same filesystem as its parent
+            let restore_xattrs = preserve_xattrs && super::xattrs::unpack_supported(&parent_dir);
+            if let Err(e) = super::xattrs::unpack(&mut tar_archive, &staging_dir, restore_xattrs) {
+                bail!("Failed to extract {}: {}", archive_path.display(), e);
+            }
+        }
+        Ok(())
+    })
+    .await;
+
+    let extracted_root = staging.join(prefix);
+    let result = match unpacked {
+        Ok(Ok(())) if extracted_root.is_dir() => Ok(()),
+        Ok(Ok(())) => Err(anyhow::anyhow!(
+            "Archive {} does not contain a '{}/' directory; was it made with a different TAR_BASE_DIR?",
+            chain[0].display(),
+            prefix.display()
+        )),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(anyhow::anyhow!("Extraction task panicked: {}", e)),
+    };
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        return Err(e);
+    }
+
+    if server_path.exists()
+        && let Err(e) = tokio::fs::rename(server_path, &previous).await
+    {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        bail!(
+            "Failed to move current server directory aside to {}: {}",
+            previous.display(),
+            e
+        );
+    }
+
+    if let Err(e) = tokio::fs::rename(&extracted_root, server_path).await {
+        error!(error = %e, "Failed to move restored files into place, putting the previous directory back");
+        let _ = tokio::fs::rename(&previous, server_path).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        bail!(
+            "Failed to move restored files into {}: {}",
+            server_path.display(),
+            e
+        );
+    }
+
+    if previous.is_dir() {
+        keep_missing_dimensions(&previous, server_path).await;
+    }
+
+    for dir in [&staging, &previous] {
+        if let Err(e) = tokio::fs::remove_dir_all(dir).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!(error = %e, path = %dir.display(), "Failed to clean up after restore");
+        }
+    }
+
+    info!(server_path = %server_path.display(), "Backup extracted into place");
+    Ok(())
+}
+
+/// Move Nether/End directories the restored tree lacks over from the previous
+/// server directory. Backups taken with `MC_SKIP_NETHER`/`MC_SKIP_END` leave
+/// them out, and the current dimensions beat the server regenerating them.
+async fn keep_missing_dimensions(previous: &Path, server_path: &Path) {
+    // Dimensions sit in `<world>/DIM-1`, or `<world>_nether/DIM-1` on Bukkit
+    let dimensions: Vec<PathBuf> = WalkDir::new(previous)
+        .min_depth(2)
+        .max_depth(2)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_type().is_dir() && matches!(e.file_name().to_str(), Some(NETHER_DIR | END_DIR))
+        })
+        .filter_map(|e| e.path().strip_prefix(previous).ok().map(Path::to_path_buf))
+        .collect();
+
+    for relative in dimensions {
+        let target = server_path.join(&relative);
+        if target.exists() || !target.parent().is_some_and(Path::is_dir) {
+            continue;
+        }
+        match tokio::fs::rename(previous.join(&relative), &target).await {
+            Ok(()) => info!(
+                dimension = %relative.display(),
+                "Backup did not include this dimension, kept the current one"
+            ),
+            Err(e) => warn!(
+                error = %e,
+                dimension = %relative.display(),
+                "Backup did not include this dimension and the current one could not be kept"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> ExtFilter {
+        let owned = |exts: &[&str]| exts.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        ExtFilter::new(&owned(include), &owned(exclude))
+    }
+
+    #[test]
+    fn extensions_are_normalized() {
+        let f = filter(&[" .MCA ", ""], &[".Log"]);
+        assert_eq!(f.include, ["mca"]);
+        assert_eq!(f.exclude, ["log"]);
+    }
+
+    #[test]
+    fn empty_filter_keeps_everything() {
+        let f = filter(&[], &[]);
+        assert!(f.is_empty());
+        assert!(f.matches(Path::new("world/level.dat")));
+        assert!(f.matches(Path::new("README")));
+    }
+
+    #[test]
+    fn include_matches_on_extension_only() {
+        let f = filter(&["mca"], &[]);
+        assert!(f.matches(Path::new("world/region/r.0.0.mca")));
+        assert!(f.matches(Path::new("world/region/R.0.0.MCA")));
+        assert!(!f.matches(Path::new("world/region/r.0.0.mcc")));
+        assert!(!f.matches(Path::new("world/region/xmca")));
+        assert!(!f.matches(Path::new("world/region/.mca")));
+    }
+
+    #[test]
+    fn multi_part_extensions_match() {
+        let f = filter(&["tar.zst"], &[]);
+        assert!(f.matches(Path::new("backups/world.tar.zst")));
+        assert!(!f.matches(Path::new("backups/world.zst")));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let f = filter(&[], &["log", "gz"]);
+        assert!(!f.matches(Path::new("logs/latest.log")));
+        assert!(!f.matches(Path::new("logs/2024-01-01-1.log.gz")));
+        assert!(f.matches(Path::new("world/level.dat")));
+
+        let f = filter(&["gz"], &["log.gz"]);
+        assert!(!f.matches(Path::new("logs/2024-01-01-1.log.gz")));
+        assert!(f.matches(Path::new("dumps/app.sql.gz")));
+    }
+}
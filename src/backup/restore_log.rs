@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::bail;
+use tracing::{error, info};
+use walkdir::WalkDir;
+
+use super::checksum::sha256_file_blocking;
+use crate::config::config::Config;
+
+/// Append every regular file under `root` to `RESTORE_VERIFY_LOG_PATH` with
+/// its size on disk, and its SHA-256 when `RESTORE_VERIFY_HASH` is set. Does
+/// nothing when no log path is configured.
+pub async fn record_tree(config: &Config, archive: &Path, root: &Path) -> anyhow::Result<()> {
+    let Some(log_path) = config.restore_verify_log_path.clone() else {
+        return Ok(());
+    };
+    let hash = config.restore_verify_hash;
+    let archive = archive.to_path_buf();
+    let root = root.to_path_buf();
+    let path = log_path.clone();
+
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<(u64, u64)> {
+        let mut lines = String::new();
+        let mut files: u64 = 0;
+        let mut bytes: u64 = 0;
+        for entry in WalkDir::new(&root).follow_links(false).sort_by_file_name() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => bail!("Failed to walk {}: {}", root.display(), e),
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let size = match entry.metadata() {
+                Ok(m) => m.len(),
+                Err(e) => bail!("Failed to stat {}: {}", entry.path().display(), e),
+            };
+            let digest = if hash {
+                sha256_file_blocking(entry.path())?
+            } else {
+                "-".to_string()
+            };
+            let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            let _ = writeln!(lines, "{}\t{}\t{}", size, digest, relative.display());
+            files += 1;
+            bytes += size;
+        }
+
+        let header = format!(
+            "# {} restore of {} into {}: {} files, {} bytes",
+            chrono::Utc::now().to_rfc3339(),
+            archive.display(),
+            root.display(),
+            files,
+            bytes
+        );
+        append(&path, &header, &lines)?;
+        Ok((files, bytes))
+    })
+    .await;
+
+    let (files, bytes) = match result {
+        Ok(r) => r?,
+        Err(e) => bail!("Restore log task panicked: {}", e),
+    };
+    info!(
+        files = files,
+        bytes = bytes,
+        path = %log_path.display(),
+        "Recorded restored files"
+    );
+    Ok(())
+}
+
+/// Append the objects pg_restore reported creating in `database`, counted by
+/// type. Does nothing when no log path is configured.
+pub async fn record_db(
+    config: &Config,
+    archive: &Path,
+    database: &str,
+    counts: &BTreeMap<String, u64>,
+) -> anyhow::Result<()> {
+    let Some(log_path) = config.restore_verify_log_path.clone() else {
+        return Ok(());
+    };
+    let total: u64 = counts.values().sum();
+    let header = format!(
+        "# {} restore of {} into database {}: {} objects",
+        chrono::Utc::now().to_rfc3339(),
+        archive.display(),
+        database,
+        total
+    );
+    let mut lines = String::new();
+    for (kind, count) in counts {
+        let _ = writeln!(lines, "{}\t{}", count, kind);
+    }
+
+    let path = log_path.clone();
+    let result = tokio::task::spawn_blocking(move || append(&path, &header, &lines)).await;
+    match result {
+        Ok(r) => r?,
+        Err(e) => bail!("Restore log task panicked: {}", e),
+    }
+    info!(objects = total, counts = ?counts, path = %log_path.display(), "Recorded restored db objects");
+    Ok(())
+}
+
+/// Count the objects in `pg_restore --verbose` output by type, e.g.
+/// `creating TABLE "public.users"` as `TABLE` and `processing data for table`
+/// as `TABLE DATA`.
+pub fn count_restored_objects(stderr: &str) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+    for line in stderr.lines() {
+        let Some(message) = line.strip_prefix("pg_restore: ") else {
+            continue;
+        };
+        let kind = if message.starts_with("processing data for table ") {
+            "TABLE DATA".to_string()
+        } else if let Some(rest) = message
+            .strip_prefix("creating ")
+            .or_else(|| message.strip_prefix("executing "))
+        {
+            // The type is the upper-case words before the object's name
+            let words: Vec<&str> = rest
+                .split_whitespace()
+                .take_while(|w| w.chars().all(|c| c.is_ascii_uppercase() || c == '_'))
+                .collect();
+            if words.is_empty() {
+                continue;
+            }
+            words.join(" ")
+        } else {
+            continue;
+        };
+        *counts.entry(kind).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Count the objects in the command tags psql prints while running a plain
+/// SQL dump, in the terms of [`count_restored_objects`]: `CREATE TABLE` as
+/// `TABLE` and each `COPY 42` as `TABLE DATA`.
+pub fn count_psql_objects(stdout: &str) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+    for line in stdout.lines() {
+        let kind = if line.starts_with("COPY ") {
+            "TABLE DATA"
+        } else if let Some(kind) = line.strip_prefix("CREATE ")
+            && kind.chars().all(|c| c.is_ascii_uppercase() || c == ' ')
+        {
+            kind
+        } else {
+            continue;
+        };
+        *counts.entry(kind.to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn append(path: &Path, header: &str, lines: &str) -> anyhow::Result<()> {
+    let mut file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to open restore verification log");
+            bail!(
+                "Failed to open restore verification log {}: {}",
+                path.display(),
+                e
+            );
+        }
+    };
+    if let Err(e) = writeln!(file, "{}\n{}", header, lines) {
+        error!(error = %e, path = %path.display(), "Failed to write restore verification log");
+        bail!(
+            "Failed to write restore verification log {}: {}",
+            path.display(),
+            e
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::path::Path;
+
+use anyhow::bail;
+use tracing::{error, info, warn};
+
+use crate::config::config::Config;
+
+/// What a Minecraft backup does about a server that is still running
+/// (`MC_BACKUP_POLICY`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McBackupPolicy {
+    /// Refuse to archive a running server.
+    RequireStopped,
+    /// Archive it anyway, logging a warning.
+    WarnIfRunning,
+    /// Don't check.
+    Ignore,
+}
+
+/// Apply `MC_BACKUP_POLICY` before a Minecraft archive is taken.
+pub fn check_policy(config: &Config) -> anyhow::Result<()> {
+    if config.mc_backup_policy == McBackupPolicy::Ignore {
+        return Ok(());
+    }
+
+    let Some(found) = find_server(config)? else {
+        info!("Minecraft server is not running");
+        return Ok(());
+    };
+    match config.mc_backup_policy {
+        McBackupPolicy::RequireStopped => {
+            error!(server = %found, "Minecraft server is running and MC_BACKUP_POLICY=require-stopped");
+            bail!(
+                "Refusing to back up a running Minecraft server ({}); stop it first or relax MC_BACKUP_POLICY",
+                found
+            );
+        }
+        McBackupPolicy::WarnIfRunning => warn!(
+            server = %found,
+            "Minecraft server is running; the archive may be inconsistent"
+        ),
+        McBackupPolicy::Ignore => {}
+    }
+    Ok(())
+}
+
+/// Describe the running server, found through `MC_PID_FILE` or else a process
+/// matching `MC_PROCESS_NAME`, or `None` when it is not running.
+fn find_server(config: &Config) -> anyhow::Result<Option<String>> {
+    if let Some(pid_file) = &config.mc_pid_file {
+        return pid_file_alive(pid_file);
+    }
+    match &config.mc_process_name {
+        Some(name) => find_process(name),
+        None => Ok(None),
+    }
+}
+
+/// A missing PID file, or one naming a process that is gone, means stopped.
+fn pid_file_alive(pid_file: &Path) -> anyhow::Result<Option<String>> {
+    let raw = match std::fs::read_to_string(pid_file) {
+        Ok(r) => r,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!("Failed to read MC_PID_FILE {}: {}", pid_file.display(), e),
+    };
+    let pid: u32 = match raw.trim().parse() {
+        Ok(p) => p,
+        Err(e) => bail!(
+            "MC_PID_FILE {} does not hold a PID ('{}'): {}",
+            pid_file.display(),
+            raw.trim(),
+            e
+        ),
+    };
+
+    if Path::new("/proc").join(pid.to_string()).exists() {
+        Ok(Some(format!("pid {} from {}", pid, pid_file.display())))
+    } else {
+        warn!(pid = pid, pid_file = %pid_file.display(), "MC_PID_FILE names a process that is gone");
+        Ok(None)
+    }
+}
+
+/// Scan `/proc` for a process whose command name, program basename or
+/// `-jar` argument basename is exactly `name`. This process and its
+/// ancestors are skipped, since a wrapper that launched the backup may well
+/// mention the server in its own command line.
+fn find_process(name: &str) -> anyhow::Result<Option<String>> {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(e) => bail!(
+            "Failed to list /proc to look for the Minecraft server: {}",
+            e
+        ),
+    };
+    let own_lineage = lineage(std::process::id());
+
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if own_lineage.contains(&pid) {
+            continue;
+        }
+
+        // Processes can exit mid-scan; unreadable ones are skipped
+        let comm = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+        let cmdline = std::fs::read(entry.path().join("cmdline")).unwrap_or_default();
+        let argv: Vec<String> = cmdline
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        if comm.trim_end() == name || argv_matches(&argv, name) {
+            return Ok(Some(format!("pid {} matching '{}'", pid, name)));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the basename of `argv[0]`, or of the argument after `-jar`, is
+/// exactly `name`.
+fn argv_matches(argv: &[String], name: &str) -> bool {
+    let basename = |arg: &str| Path::new(arg).file_name().is_some_and(|f| f == name);
+    let program = argv.first().is_some_and(|arg| basename(arg));
+    let jar = argv
+        .windows(2)
+        .any(|pair| pair[0] == "-jar" && basename(&pair[1]));
+    program || jar
+}
+
+/// `pid` and every process above it, up to init.
+fn lineage(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    let mut current = pid;
+    while let Some(parent) = parent_pid(current) {
+        if parent == 0 || pids.contains(&parent) {
+            break;
+        }
+        pids.push(parent);
+        current = parent;
+    }
+    pids
+}
+
+/// The parent of `pid`, from the field after the state in `/proc/<pid>/stat`.
+/// The command name before it is parenthesised and may hold spaces.
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, after_comm) = stat.rsplit_once(')')?;
+    after_comm.split_whitespace().nth(1)?.parse().ok()
+}
@@ -0,0 +1,406 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::bail;
+
+/// Entries held in memory before they are sorted and spilled to a run file,
+/// so memory stays flat however many files the tree holds.
+const RUN_ENTRIES: usize = 256 * 1024;
+
+/// A filesystem entry found by [`scan_tree`]. Symlinks are reported as
+/// non-directories and never followed.
+pub struct ScannedEntry {
+    pub path: PathBuf,
+    /// Path relative to the scanned root; empty for the root itself.
+    pub relative: PathBuf,
+    pub is_dir: bool,
+    /// A regular file, as opposed to a directory, symlink or special file.
+    pub is_file: bool,
+    pub modified: SystemTime,
+}
+
+/// Directories waiting to be read, plus how many are still being read so
+/// idle workers know whether more work can appear.
+struct Queue {
+    pending: VecDeque<PathBuf>,
+    in_flight: usize,
+    failed: Option<String>,
+}
+
+/// Entries found so far: the unsorted buffer and the sorted runs already
+/// written to `spill_dir`.
+struct Found {
+    buffer: Vec<ScannedEntry>,
+    runs: Vec<PathBuf>,
+    total: usize,
+}
+
+/// Enumerate `root` with `threads` workers reading directories in parallel.
+/// The entries come back sorted by relative path, so output is deterministic
+/// and every directory precedes its contents regardless of scan order. Large
+/// trees are sorted externally through run files in `spill_dir`.
+pub fn scan_tree(root: &Path, threads: usize, spill_dir: &Path) -> anyhow::Result<SortedEntries> {
+    let root_meta = match std::fs::symlink_metadata(root) {
+        Ok(m) => m,
+        Err(e) => bail!("Failed to stat {}: {}", root.display(), e),
+    };
+
+    let queue = Mutex::new(Queue {
+        pending: VecDeque::from([root.to_path_buf()]),
+        in_flight: 0,
+        failed: None,
+    });
+    let wake = Condvar::new();
+    let found = Mutex::new(Found {
+        buffer: vec![ScannedEntry {
+            path: root.to_path_buf(),
+            relative: PathBuf::new(),
+            is_dir: true,
+            is_file: false,
+            modified: root_meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        }],
+        runs: Vec::new(),
+        total: 1,
+    });
+    let spill = Spill::new(spill_dir);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| scan_worker(root, &queue, &wake, &found, &spill));
+        }
+    });
+
+    let found = match found.into_inner() {
+        Ok(f) => f,
+        Err(_) => bail!("Directory scan worker panicked"),
+    };
+    // Run files are owned from here on, so they are removed even on failure
+    let mut sorted = SortedEntries {
+        root: root.to_path_buf(),
+        total: found.total,
+        memory: Vec::new().into_iter(),
+        runs: Vec::new(),
+        heap: BinaryHeap::new(),
+        run_paths: found.runs,
+    };
+
+    let queue = match queue.into_inner() {
+        Ok(q) => q,
+        Err(_) => bail!("Directory scan worker panicked"),
+    };
+    if let Some(e) = queue.failed {
+        bail!("Failed to walk {}: {}", root.display(), e);
+    }
+
+    let mut buffer = found.buffer;
+    buffer.sort_unstable_by(|a, b| a.relative.cmp(&b.relative));
+    if sorted.run_paths.is_empty() {
+        sorted.memory = buffer.into_iter();
+        return Ok(sorted);
+    }
+
+    // The leftover buffer becomes one more run so every source merges alike
+    if !buffer.is_empty() {
+        match spill.write_run(&buffer) {
+            Ok(path) => sorted.run_paths.push(path),
+            Err(e) => bail!("Failed to spill scan entries: {}", e),
+        }
+    }
+    drop(buffer);
+    for (index, path) in sorted.run_paths.iter().enumerate() {
+        let mut reader = match File::open(path) {
+            Ok(f) => BufReader::with_capacity(64 * 1024, f),
+            Err(e) => bail!("Failed to open scan run {}: {}", path.display(), e),
+        };
+        match read_record(&mut reader) {
+            Ok(Some(record)) => sorted
+                .heap
+                .push(Reverse((record.0, index, record.1, record.2))),
+            Ok(None) => {}
+            Err(e) => bail!("Failed to read scan run {}: {}", path.display(), e),
+        }
+        sorted.runs.push(reader);
+    }
+    Ok(sorted)
+}
+
+fn scan_worker(
+    root: &Path,
+    queue: &Mutex<Queue>,
+    wake: &Condvar,
+    found: &Mutex<Found>,
+    spill: &Spill,
+) {
+    loop {
+        let dir = {
+            let Ok(mut q) = queue.lock() else { return };
+            loop {
+                if q.failed.is_some() {
+                    return;
+                }
+                if let Some(dir) = q.pending.pop_front() {
+                    q.in_flight += 1;
+                    break dir;
+                }
+                if q.in_flight == 0 {
+                    return;
+                }
+                q = match wake.wait(q) {
+                    Ok(q) => q,
+                    Err(_) => return,
+                };
+            }
+        };
+
+        let mut result = read_one_dir(root, &dir);
+        if let Ok((entries, _)) = &mut result
+            && let Err(e) = add_found(found, spill, entries)
+        {
+            result = Err(e);
+        }
+
+        let Ok(mut q) = queue.lock() else { return };
+        q.in_flight -= 1;
+        match result {
+            Ok((_, subdirs)) => q.pending.extend(subdirs),
+            Err(e) => q.failed = Some(e),
+        }
+        drop(q);
+        wake.notify_all();
+    }
+}
+
+/// Move `entries` into the shared buffer, spilling it as a sorted run once
+/// it is full. The run is written outside the lock so other workers go on.
+fn add_found(
+    found: &Mutex<Found>,
+    spill: &Spill,
+    entries: &mut Vec<ScannedEntry>,
+) -> Result<(), String> {
+    let mut full = {
+        let Ok(mut f) = found.lock() else {
+            return Err("scan buffer lock poisoned".to_string());
+        };
+        f.total += entries.len();
+        f.buffer.append(entries);
+        if f.buffer.len() < RUN_ENTRIES {
+            return Ok(());
+        }
+        std::mem::take(&mut f.buffer)
+    };
+
+    full.sort_unstable_by(|a, b| a.relative.cmp(&b.relative));
+    let path = spill.write_run(&full)?;
+    match found.lock() {
+        Ok(mut f) => {
+            f.runs.push(path);
+            Ok(())
+        }
+        Err(_) => {
+            let _ = std::fs::remove_file(&path);
+            Err("scan buffer lock poisoned".to_string())
+        }
+    }
+}
+
+type DirContents = (Vec<ScannedEntry>, Vec<PathBuf>);
+
+fn read_one_dir(root: &Path, dir: &Path) -> Result<DirContents, String> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(r) => r,
+        // Removed since its parent was read; the archiver skips its entry too
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+        Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+    };
+
+    let mut entries = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in read_dir {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+        };
+        let path = entry.path();
+        // DirEntry::metadata does not traverse symlinks
+        let metadata = match entry.metadata() {
+            Ok(m) => m,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let relative = match path.strip_prefix(root) {
+            Ok(r) => r.to_path_buf(),
+            Err(_) => return Err(format!("{} escaped the scan root", path.display())),
+        };
+
+        let is_dir = metadata.is_dir();
+        let is_file = metadata.is_file();
+        if is_dir {
+            subdirs.push(path.clone());
+        }
+        entries.push(ScannedEntry {
+            path,
+            relative,
+            is_dir,
+            is_file,
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+
+    Ok((entries, subdirs))
+}
+
+/// Names and writes the run files of one scan.
+struct Spill {
+    dir: PathBuf,
+    next: AtomicUsize,
+}
+
+impl Spill {
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn write_run(&self, entries: &[ScannedEntry]) -> Result<PathBuf, String> {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .dir
+            .join(format!(".scan-run-{}-{}", std::process::id(), n));
+        let result = File::create(&path).and_then(|f| {
+            let mut writer = BufWriter::with_capacity(256 * 1024, f);
+            for entry in entries {
+                write_record(&mut writer, entry)?;
+            }
+            writer.flush()
+        });
+        match result {
+            Ok(()) => Ok(path),
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                Err(format!("{}: {}", path.display(), e))
+            }
+        }
+    }
+}
+
+/// Run record: path length, relative path bytes, type flags, then the
+/// modification time as seconds and nanoseconds since the epoch.
+fn write_record(writer: &mut impl Write, entry: &ScannedEntry) -> std::io::Result<()> {
+    let bytes = entry.relative.as_os_str().as_bytes();
+    let since_epoch = entry
+        .modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    let flags = entry.is_dir as u8 | (entry.is_file as u8) << 1;
+    writer.write_all(&[flags])?;
+    writer.write_all(&since_epoch.as_secs().to_le_bytes())?;
+    writer.write_all(&since_epoch.subsec_nanos().to_le_bytes())
+}
+
+type Record = (PathBuf, u8, SystemTime);
+
+fn read_record(reader: &mut impl Read) -> std::io::Result<Option<Record>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    let mut flags = [0u8; 1];
+    reader.read_exact(&mut flags)?;
+    let mut secs = [0u8; 8];
+    reader.read_exact(&mut secs)?;
+    let mut nanos = [0u8; 4];
+    reader.read_exact(&mut nanos)?;
+
+    let modified =
+        SystemTime::UNIX_EPOCH + Duration::new(u64::from_le_bytes(secs), u32::from_le_bytes(nanos));
+    Ok(Some((
+        PathBuf::from(std::ffi::OsString::from_vec(bytes)),
+        flags[0],
+        modified,
+    )))
+}
+
+/// Scanned entries in relative-path order, yielded either from memory or by
+/// merging the spilled runs. Run files are removed on drop.
+pub struct SortedEntries {
+    root: PathBuf,
+    total: usize,
+    memory: std::vec::IntoIter<ScannedEntry>,
+    runs: Vec<BufReader<File>>,
+    /// Head record of each run, smallest path first.
+    heap: BinaryHeap<Reverse<(PathBuf, usize, u8, SystemTime)>>,
+    run_paths: Vec<PathBuf>,
+}
+
+impl SortedEntries {
+    /// Entries found, including the root.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Run files the entries were merged from; 0 when they fit in memory.
+    pub fn spilled_runs(&self) -> usize {
+        self.run_paths.len()
+    }
+}
+
+impl Iterator for SortedEntries {
+    type Item = anyhow::Result<ScannedEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.runs.is_empty() {
+            return self.memory.next().map(Ok);
+        }
+
+        let Reverse((relative, index, flags, modified)) = self.heap.pop()?;
+        match read_record(&mut self.runs[index]) {
+            Ok(Some((p, d, m))) => self.heap.push(Reverse((p, index, d, m))),
+            Ok(None) => {}
+            Err(e) => {
+                self.heap.clear();
+                return Some(Err(anyhow::anyhow!(
+                    "Failed to read scan run {}: {}",
+                    self.run_paths[index].display(),
+                    e
+                )));
+            }
+        }
+
+        // Joining an empty path would add a trailing separator to the root
+        let path = if relative.as_os_str().is_empty() {
+            self.root.clone()
+        } else {
+            self.root.join(&relative)
+        };
+        Some(Ok(ScannedEntry {
+            path,
+            relative,
+            is_dir: flags & 1 != 0,
+            is_file: flags & 2 != 0,
+            modified,
+        }))
+    }
+}
+
+impl Drop for SortedEntries {
+    fn drop(&mut self) {
+        for path in &self.run_paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
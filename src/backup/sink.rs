@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::checksum::HashingWriter;
+
+/// Where compressed archive bytes go on their way to disk. Each concern is one
+/// `Write` layer, outermost first:
+///
+/// 1. [`CappedWriter`] refuses to grow the archive past `MAX_ARCHIVE_BYTES`,
+///    so an oversized backup aborts mid-stream instead of filling the disk;
+/// 2. [`HashingWriter`] takes the SHA-256 for the sidecar and manifest;
+/// 3. a buffered temp file.
+///
+/// The compressor writes into the outermost layer; [`ArchiveSink::finish`]
+/// unwinds the chain once it is done.
+///
+/// There is deliberately no layer streaming bytes to the upload. Encryption,
+/// failover across accounts and mirrors, and retries all work on the finished
+/// file, and a capped archive must never reach storage half-written.
+pub type ArchiveSink = CappedWriter<HashingWriter<BufWriter<File>>>;
+
+/// What a finished [`ArchiveSink`] wrote.
+pub struct SinkOutput {
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+impl ArchiveSink {
+    /// Build the chain over `file`. `max_bytes` of `None` leaves the size
+    /// uncapped.
+    pub fn create(file: File, max_bytes: Option<u64>) -> Self {
+        let buffered = BufWriter::with_capacity(512 * 1024, file);
+        CappedWriter::new(HashingWriter::new(buffered), max_bytes)
+    }
+
+    /// Flush every layer down to the file and return the size and digest of
+    /// what was written.
+    pub fn finish(self) -> std::io::Result<SinkOutput> {
+        let size_bytes = self.written;
+        let (buffered, sha256) = self.inner.into_parts();
+        if let Err(e) = buffered.into_inner() {
+            return Err(e.into_error());
+        }
+        Ok(SinkOutput { size_bytes, sha256 })
+    }
+}
+
+/// Returned (inside an `io::Error`) once an archive would exceed
+/// `MAX_ARCHIVE_BYTES`.
+#[derive(Debug)]
+pub struct ArchiveTooLarge {
+    pub limit: u64,
+}
+
+impl std::fmt::Display for ArchiveTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "archive would exceed MAX_ARCHIVE_BYTES ({} bytes)",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for ArchiveTooLarge {}
+
+/// Counts the bytes passed on and fails any write that would take the total
+/// past `limit`.
+pub struct CappedWriter<W> {
+    inner: W,
+    limit: Option<u64>,
+    written: u64,
+}
+
+impl<W: Write> CappedWriter<W> {
+    pub fn new(inner: W, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            limit,
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for CappedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(limit) = self.limit
+            && self.written + buf.len() as u64 > limit
+        {
+            return Err(std::io::Error::other(ArchiveTooLarge { limit }));
+        }
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncapped_writer_passes_everything_through() {
+        let mut writer = CappedWriter::new(Vec::new(), None);
+        writer.write_all(&[1; 4096]).unwrap();
+        assert_eq!(writer.written, 4096);
+        assert_eq!(writer.inner.len(), 4096);
+    }
+
+    #[test]
+    fn writes_up_to_the_limit_succeed() {
+        let mut writer = CappedWriter::new(Vec::new(), Some(10));
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b"world").unwrap();
+        assert_eq!(writer.inner, b"helloworld");
+    }
+
+    #[test]
+    fn write_past_the_limit_fails_without_passing_bytes_on() {
+        let mut writer = CappedWriter::new(Vec::new(), Some(8));
+        writer.write_all(b"hello").unwrap();
+        let err = writer.write_all(b"world").unwrap_err();
+        let too_large = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ArchiveTooLarge>());
+        assert_eq!(too_large.map(|e| e.limit), Some(8));
+        assert_eq!(writer.inner, b"hello");
+        assert_eq!(writer.written, 5);
+    }
+}
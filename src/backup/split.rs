@@ -0,0 +1,261 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+use super::checksum::sha256_file;
+use crate::drive::auth::DriveHub;
+use crate::drive::download::local_path;
+
+/// Suffix of the index stored next to the parts of a split archive:
+/// `<archive>.index.json` describes `<archive>.part001`, `<archive>.part002`, ...
+pub const INDEX_SUFFIX: &str = ".index.json";
+
+/// Describes how an archive was cut into parts, in order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SplitIndex {
+    pub archive_name: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+    pub parts: Vec<SplitPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SplitPart {
+    pub name: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+/// Download the index `index_file_id` and every part it lists from
+/// `folder_id`, verify each part, and join them into `<dest_dir>/<archive>`.
+/// Parts already on disk with the right checksum are reused, so an interrupted
+/// fetch resumes where it stopped. The joined archive is verified as a whole
+/// before its path is returned.
+pub async fn fetch_split_archive(
+    hub: &DriveHub,
+    folder_id: &str,
+    index_file_id: &str,
+    dest_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    if let Err(e) = tokio::fs::create_dir_all(dest_dir).await {
+        bail!("Failed to create {}: {}", dest_dir.display(), e);
+    }
+
+    let index_path = dest_dir.join(format!("{}{}", index_file_id, INDEX_SUFFIX));
+    crate::drive::download::download_file(hub, index_file_id, &index_path).await?;
+    let index = read_index(&index_path).await;
+    let _ = tokio::fs::remove_file(&index_path).await;
+    let index = index?;
+
+    info!(
+        archive = %index.archive_name,
+        parts = index.parts.len(),
+        size_bytes = index.size_bytes,
+        "Fetching split archive"
+    );
+
+    let remote = crate::drive::prune::list_all_files_in_folder(hub, folder_id).await?;
+    let mut part_paths = Vec::with_capacity(index.parts.len());
+    for (number, part) in index.parts.iter().enumerate() {
+        let path = local_path(dest_dir, &part.name)?;
+
+        if path.is_file() && sha256_file(&path).await? == part.sha256 {
+            info!(part = %part.name, "Part already downloaded, skipping");
+            part_paths.push(path);
+            continue;
+        }
+
+        let Some(file_id) = remote
+            .iter()
+            .find(|f| f.name.as_deref() == Some(part.name.as_str()))
+            .and_then(|f| f.id.clone())
+        else {
+            error!(part = %part.name, "Split archive part is missing from Drive");
+            bail!(
+                "Part {} of {} ('{}') is missing from the Drive folder",
+                number + 1,
+                index.parts.len(),
+                part.name
+            );
+        };
+
+        crate::drive::download::download_file(hub, &file_id, &path).await?;
+        let digest = sha256_file(&path).await?;
+        if digest != part.sha256 {
+            error!(part = %part.name, expected = %part.sha256, actual = %digest, "Split archive part is corrupt");
+            let _ = tokio::fs::remove_file(&path).await;
+            bail!(
+                "Part '{}' is corrupt: expected sha256 {}, got {}",
+                part.name,
+                part.sha256,
+                digest
+            );
+        }
+        part_paths.push(path);
+    }
+
+    let archive_path = local_path(dest_dir, &index.archive_name)?;
+    join_parts(&part_paths, &archive_path).await?;
+
+    let digest = sha256_file(&archive_path).await?;
+    if digest != index.sha256 {
+        error!(archive = %index.archive_name, expected = %index.sha256, actual = %digest, "Joined archive checksum mismatch");
+        let _ = tokio::fs::remove_file(&archive_path).await;
+        bail!(
+            "Joined archive '{}' does not match its index: expected sha256 {}, got {}",
+            index.archive_name,
+            index.sha256,
+            digest
+        );
+    }
+
+    for path in &part_paths {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+
+    info!(path = %archive_path.display(), "Split archive joined and verified");
+    Ok(archive_path)
+}
+
+async fn read_index(path: &Path) -> anyhow::Result<SplitIndex> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(b) => b,
+        Err(e) => bail!("Failed to read split index {}: {}", path.display(), e),
+    };
+    let index: SplitIndex = match serde_json::from_slice(&bytes) {
+        Ok(i) => i,
+        Err(e) => bail!("Failed to parse split index: {}", e),
+    };
+    if index.parts.is_empty() {
+        bail!("Split index for '{}' lists no parts", index.archive_name);
+    }
+    Ok(index)
+}
+
+/// Concatenate `parts` in order into `dest`.
+async fn join_parts(parts: &[PathBuf], dest: &Path) -> anyhow::Result<()> {
+    let mut output = match tokio::fs::File::create(dest).await {
+        Ok(f) => f,
+        Err(e) => bail!("Failed to create {}: {}", dest.display(), e),
+    };
+
+    for part in parts {
+        let mut input = match tokio::fs::File::open(part).await {
+            Ok(f) => f,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(dest).await;
+                bail!("Failed to open part {}: {}", part.display(), e);
+            }
+        };
+        if let Err(e) = tokio::io::copy(&mut input, &mut output).await {
+            let _ = tokio::fs::remove_file(dest).await;
+            bail!("Failed to append part {}: {}", part.display(), e);
+        }
+    }
+
+    if let Err(e) = output.flush().await {
+        bail!("Failed to flush {}: {}", dest.display(), e);
+    }
+    Ok(())
+}
+
+/// Unpack a joined `.tar.zst` archive into `dest_dir`, restoring extended
+/// attributes when `unpack_xattrs` is set and `dest_dir` supports them. A
+/// dictionary-compressed archive needs its dictionary at `dict_path`.
+pub async fn extract_archive(
+    archive: &Path,
+    dest_dir: &Path,
+    unpack_xattrs: bool,
+    dict_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let dict = super::dict::load_for(dict_path, archive)?;
+    let archive = archive.to_path_buf();
+    let dest = dest_dir.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let decoder = super::dict::open_decoder(&archive, dict.as_deref())?;
+        let mut tar_archive = tar::Archive::new(decoder);
+        tar_archive.set_preserve_permissions(true);
+        tar_archive.set_preserve_mtime(true);
+        if let Err(e) = std::fs::create_dir_all(&dest) {
+            bail!("Failed to create {}: {}", dest.display(), e);
+        }
+        let restore_xattrs = unpack_xattrs && super::xattrs::unpack_supported(&dest);
+        if let Err(e) = super::xattrs::unpack(&mut tar_archive, &dest, restore_xattrs) {
+            bail!("Failed to extract {}: {}", archive.display(), e);
+        }
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => bail!("Extraction task panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("split-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn parts_are_joined_in_index_order() {
+        let dir = scratch("join");
+        let parts: Vec<PathBuf> = ["b", "a", "c"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(format!("world.tar.zst.part-{}", name));
+                std::fs::write(&path, name.repeat(3)).unwrap();
+                path
+            })
+            .collect();
+        let joined = dir.join("world.tar.zst");
+        let result = join_parts(&parts, &joined).await;
+        let contents = std::fs::read_to_string(&joined);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert_eq!(contents.unwrap(), "bbbaaaccc");
+    }
+
+    #[tokio::test]
+    async fn missing_part_fails_without_leaving_the_archive() {
+        let dir = scratch("missing");
+        let present = dir.join("world.tar.zst.part001");
+        std::fs::write(&present, b"data").unwrap();
+        let parts = [present, dir.join("world.tar.zst.part002")];
+        let joined = dir.join("world.tar.zst");
+        let result = join_parts(&parts, &joined).await;
+        let left_behind = joined.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.unwrap_err().to_string().contains("part002"));
+        assert!(!left_behind);
+    }
+
+    #[tokio::test]
+    async fn index_must_list_parts() {
+        let dir = scratch("index");
+        let index = dir.join("world.tar.zst.index.json");
+        std::fs::write(
+            &index,
+            r#"{"archive_name":"world.tar.zst","size_bytes":0,"sha256":"","parts":[]}"#,
+        )
+        .unwrap();
+        let empty = read_index(&index).await;
+        std::fs::write(&index, b"not json").unwrap();
+        let garbage = read_index(&index).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(empty.unwrap_err().to_string().contains("lists no parts"));
+        assert!(garbage.is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::bail;
+use tokio::io::AsyncReadExt;
+use tokio::process::Child;
+use tracing::{error, info, warn};
+
+use crate::config::config::{Config, SshTunnelConfig};
+
+/// How long to wait for the forwarded port to start accepting connections.
+const TUNNEL_READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where pg_dump/psql should connect: the configured server, or the local end
+/// of an SSH port-forward.
+#[derive(Debug, Clone)]
+pub struct DbEndpoint {
+    pub host: String,
+    pub port: u16,
+}
+
+/// A running `ssh -N -L` port-forward. The child is killed on `close` or drop.
+pub struct SshTunnel {
+    child: Child,
+    local_port: u16,
+}
+
+impl SshTunnel {
+    /// Open a tunnel if `DB_SSH_HOST` is configured, returning the endpoint
+    /// clients should connect to.
+    pub async fn open_if_configured(
+        config: &Config,
+    ) -> anyhow::Result<(DbEndpoint, Option<SshTunnel>)> {
+        let Some(ssh) = &config.db_ssh else {
+            let endpoint = DbEndpoint {
+                host: config.db_host.clone(),
+                port: config.db_port,
+            };
+            return Ok((endpoint, None));
+        };
+
+        let tunnel = Self::open(ssh, &config.db_host, config.db_port).await?;
+        let endpoint = DbEndpoint {
+            host: "127.0.0.1".to_string(),
+            port: tunnel.local_port,
+        };
+        Ok((endpoint, Some(tunnel)))
+    }
+
+    async fn open(ssh: &SshTunnelConfig, db_host: &str, db_port: u16) -> anyhow::Result<Self> {
+        let local_port = match pick_free_local_port().await {
+            Ok(p) => p,
+            Err(e) => {
+                error!(error = %e, "Failed to reserve a local port for the SSH tunnel");
+                bail!("Failed to reserve a local port for the SSH tunnel: {}", e);
+            }
+        };
+
+        info!(
+            ssh_host = %ssh.host,
+            ssh_user = %ssh.user,
+            ssh_port = ssh.port,
+            local_port = local_port,
+            remote = %format!("{}:{}", db_host, db_port),
+            "Opening SSH tunnel to database"
+        );
+
+        let mut child = match tokio::process::Command::new("ssh")
+            .arg("-N")
+            .arg("-L")
+            .arg(format!("127.0.0.1:{}:{}:{}", local_port, db_host, db_port))
+            .arg("-i")
+            .arg(&ssh.key_path)
+            .arg("-p")
+            .arg(ssh.port.to_string())
+            .arg("-o")
+            .arg("ExitOnForwardFailure=yes")
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("-o")
+            .arg("ServerAliveInterval=15")
+            .arg(format!("{}@{}", ssh.user, ssh.host))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                error!(error = %e, "Failed to spawn ssh for database tunnel");
+                bail!("Failed to spawn ssh for database tunnel: {}", e);
+            }
+        };
+
+        let deadline = tokio::time::Instant::now() + TUNNEL_READY_TIMEOUT;
+        loop {
+            if let Ok(Some(status)) = child.try_wait() {
+                let stderr = read_stderr(&mut child).await;
+                error!(exit_status = %status, stderr = %stderr, "SSH tunnel exited during setup");
+                bail!(
+                    "SSH tunnel exited during setup ({}): {}",
+                    status,
+                    stderr.trim()
+                );
+            }
+
+            if tokio::net::TcpStream::connect(("127.0.0.1", local_port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                let _ = child.kill().await;
+                error!(
+                    timeout = ?TUNNEL_READY_TIMEOUT,
+                    "SSH tunnel did not become ready in time"
+                );
+                bail!(
+                    "SSH tunnel to {} did not become ready within {:?}",
+                    ssh.host,
+                    TUNNEL_READY_TIMEOUT
+                );
+            }
+
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        info!(local_port = local_port, "SSH tunnel established");
+
+        Ok(SshTunnel { child, local_port })
+    }
+
+    pub async fn close(mut self) {
+        if let Err(e) = self.child.kill().await {
+            warn!(error = %e, "Failed to stop SSH tunnel process");
+        } else {
+            info!(local_port = self.local_port, "SSH tunnel closed");
+        }
+    }
+}
+
+/// Ask the OS for an unused loopback port. There is a small window between
+/// releasing it here and ssh binding it, which `ExitOnForwardFailure` surfaces.
+async fn pick_free_local_port() -> std::io::Result<u16> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+    Ok(listener.local_addr()?.port())
+}
+
+async fn read_stderr(child: &mut Child) -> String {
+    let mut buf = String::new();
+    if let Some(stderr) = child.stderr.as_mut() {
+        let _ = stderr.read_to_string(&mut buf).await;
+    }
+    buf
+}
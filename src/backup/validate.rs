@@ -0,0 +1,342 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, Stdio};
+
+use anyhow::bail;
+use tracing::{error, info, warn};
+
+use super::BackupKind;
+use super::checksum::is_sidecar_name;
+use super::db::run_psql;
+use super::db_restore::{is_db_dump_name, is_plain_sql, prepare_dump};
+use super::ssh_tunnel::{DbEndpoint, SshTunnel};
+use crate::config::config::Config;
+use crate::drive::auth::DriveHub;
+
+/// Restore the newest db dump in `folder_id` into a throwaway database, run
+/// `DB_VALIDATE_QUERY` against it and return the query's output. The scratch
+/// database and downloaded files are removed whether or not validation passes.
+/// An encrypted `.enc` dump is decrypted with `BACKUP_ENCRYPTION_KEY` first.
+pub async fn validate_latest_db_backup(
+    config: &Config,
+    hub: &DriveHub,
+    folder_id: &str,
+) -> anyhow::Result<String> {
+    let prefix = BackupKind::Db.artifact_prefix(config.host_tag.as_deref());
+    // Listing is newest first
+    let latest = crate::drive::prune::list_all_files_in_folder(hub, folder_id)
+        .await?
+        .into_iter()
+        .find(|f| match &f.name {
+            Some(name) => {
+                name.starts_with(&prefix)
+                    && !is_sidecar_name(name)
+                    && is_db_dump_name(crate::crypto::plaintext_name(name))
+            }
+            None => false,
+        });
+
+    let (file_id, file_name) = match latest {
+        Some(f) => match (f.id, f.name) {
+            (Some(id), Some(name)) => (id, name),
+            _ => bail!("Latest db backup has no id or name"),
+        },
+        None => {
+            error!(folder_id = folder_id, "No db backups found to validate");
+            bail!("No db backups found in Drive folder {}", folder_id);
+        }
+    };
+
+    info!(file_name = %file_name, file_id = %file_id, "Validating latest db backup");
+
+    let downloaded = crate::drive::download::local_path(&config.backup_temp_dir, &file_name)?;
+    crate::drive::download::download_file(hub, &file_id, &downloaded).await?;
+
+    let decrypted = match crate::crypto::decrypt_if_encrypted(config, &downloaded).await {
+        Ok(d) => d,
+        Err(e) => {
+            remove_file(&downloaded).await;
+            return Err(e);
+        }
+    };
+    let dump = decrypted.as_deref().unwrap_or(&downloaded);
+
+    let prepared = prepare_dump(dump, &config.backup_temp_dir, &config.db_name).await;
+    let prepared = match prepared {
+        Ok(p) => p,
+        Err(e) => {
+            if let Some(d) = &decrypted {
+                remove_file(d).await;
+            }
+            remove_file(&downloaded).await;
+            return Err(e);
+        }
+    };
+
+    let result = match SshTunnel::open_if_configured(config).await {
+        Ok((endpoint, tunnel)) => {
+            let result = restore_and_query(config, &endpoint, &prepared.path).await;
+            if let Some(tunnel) = tunnel {
+                tunnel.close().await;
+            }
+            result
+        }
+        Err(e) => Err(e),
+    };
+
+    prepared.cleanup().await;
+    if let Some(d) = &decrypted {
+        remove_file(d).await;
+    }
+    remove_file(&downloaded).await;
+
+    result
+}
+
+async fn restore_and_query(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    dump_path: &Path,
+) -> anyhow::Result<String> {
+    let scratch_db = format!(
+        "{}_validate_{}",
+        config.db_name,
+        chrono::Utc::now().format("%Y%m%d_%H%M%S")
+    );
+
+    info!(database = %scratch_db, "Creating scratch database");
+    if let Err(e) = run_psql(
+        config,
+        endpoint,
+        &config.db_name,
+        &format!("CREATE DATABASE {}", quote_ident(&scratch_db)),
+    )
+    .await
+    {
+        error!(error = %e, database = %scratch_db, "Failed to create scratch database");
+        bail!("Failed to create scratch database {}: {}", scratch_db, e);
+    }
+
+    let result = async {
+        if is_plain_sql(dump_path) {
+            psql_restore_into(config, endpoint, &scratch_db, dump_path).await?;
+        } else {
+            pg_restore_into(config, endpoint, &scratch_db, dump_path, &[]).await?;
+        }
+
+        info!(query = %config.db_validate_query, "Running validation query");
+        match run_psql(config, endpoint, &scratch_db, &config.db_validate_query).await {
+            Ok(out) => Ok(out),
+            Err(e) => bail!("Validation query failed: {}", e),
+        }
+    }
+    .await;
+
+    info!(database = %scratch_db, "Dropping scratch database");
+    if let Err(e) = run_psql(
+        config,
+        endpoint,
+        &config.db_name,
+        &format!("DROP DATABASE IF EXISTS {}", quote_ident(&scratch_db)),
+    )
+    .await
+    {
+        warn!(error = %e, database = %scratch_db, "Failed to drop scratch database; remove it manually");
+    }
+
+    result
+}
+
+/// Run `pg_restore` of `dump_path` into `dbname`, with `extra_args` placed
+/// before the dump path. Returns what pg_restore wrote to stderr, which is
+/// its progress report under `--verbose`.
+pub(super) async fn pg_restore_into(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    dbname: &str,
+    dump_path: &Path,
+    extra_args: &[String],
+) -> anyhow::Result<String> {
+    info!(database = dbname, dump = %dump_path.display(), "Restoring dump");
+
+    let output = match tokio::process::Command::new("pg_restore")
+        .arg("--host")
+        .arg(&endpoint.host)
+        .arg("--port")
+        .arg(endpoint.port.to_string())
+        .arg("--username")
+        .arg(&config.db_username)
+        .arg("--dbname")
+        .arg(dbname)
+        .arg("--no-owner")
+        .arg("--no-privileges")
+        .arg("--exit-on-error")
+        .args(extra_args)
+        .arg(dump_path)
+        .env("PGPASSWORD", &config.db_password)
+        .envs(config.db_sslmode.as_deref().map(|mode| ("PGSSLMODE", mode)))
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => {
+            error!(error = %e, "Failed to spawn pg_restore");
+            bail!("Failed to spawn pg_restore: {}", e);
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(
+            exit_code = ?output.status.code(),
+            stderr = %stderr,
+            "pg_restore failed"
+        );
+        bail!(
+            "pg_restore exited with status {}: {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/// Run the plain SQL in the gzipped `dump_path` through `psql` against
+/// `dbname`, stopping at the first error. Returns psql's command tags
+/// (`CREATE TABLE`, `COPY 42`, ...).
+pub(super) async fn psql_restore_into(
+    config: &Config,
+    endpoint: &DbEndpoint,
+    dbname: &str,
+    dump_path: &Path,
+) -> anyhow::Result<String> {
+    info!(database = dbname, dump = %dump_path.display(), "Restoring plain SQL dump with psql");
+
+    let mut command = std::process::Command::new("psql");
+    command
+        .arg("--host")
+        .arg(&endpoint.host)
+        .arg("--port")
+        .arg(endpoint.port.to_string())
+        .arg("--username")
+        .arg(&config.db_username)
+        .arg("--dbname")
+        .arg(dbname)
+        .arg("--no-psqlrc")
+        .arg("-v")
+        .arg("ON_ERROR_STOP=1")
+        .arg("--file")
+        .arg("-")
+        .env("PGPASSWORD", &config.db_password)
+        .envs(config.db_sslmode.as_deref().map(|mode| ("PGSSLMODE", mode)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let path = dump_path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+        let mut child = match command.spawn() {
+            Ok(c) => c,
+            Err(e) => {
+                error!(error = %e, "Failed to spawn psql");
+                bail!("Failed to spawn psql: {}", e);
+            }
+        };
+        let Some(stdin) = child.stdin.take() else {
+            bail!("psql was spawned without a stdin pipe");
+        };
+        // Fed from its own thread so psql's output is drained meanwhile
+        let feeder = {
+            let path = path.clone();
+            std::thread::spawn(move || feed_sql_gz(&path, stdin))
+        };
+
+        let output = match child.wait_with_output() {
+            Ok(o) => o,
+            Err(e) => bail!("Failed to wait for psql: {}", e),
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!(exit_code = ?output.status.code(), stderr = %stderr, "psql restore failed");
+            bail!(
+                "psql exited with status {}: {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        match feeder.join() {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => bail!("Failed to decompress {}: {}", path.display(), e),
+            Err(_) => bail!("Feeding {} to psql panicked", path.display()),
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => bail!("psql restore task panicked: {}", e),
+    }
+}
+
+/// Write the SQL in the gzipped `path` to `stdin` inside a transaction that
+/// is only committed once the whole file decoded. A cut-off or corrupt dump
+/// leaves it open at end of input, and the server rolls it back.
+fn feed_sql_gz(path: &Path, mut stdin: ChildStdin) -> std::io::Result<u64> {
+    let file = File::open(path)?;
+    let mut decoder = flate2::read::GzDecoder::new(BufReader::with_capacity(512 * 1024, file));
+    stdin.write_all(b"BEGIN;\n")?;
+    let fed = crate::abort::copy(&mut decoder, &mut stdin)?;
+    stdin.write_all(b"\nCOMMIT;\n")?;
+    Ok(fed)
+}
+
+pub(super) async fn decompress(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    let src = src.to_path_buf();
+    let dest: PathBuf = dest.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let input = match File::open(&src) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to open {}: {}", src.display(), e),
+        };
+        let output = match File::create(&dest) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to create {}: {}", dest.display(), e),
+        };
+        let mut decoder =
+            match zstd::Decoder::with_buffer(BufReader::with_capacity(512 * 1024, input)) {
+                Ok(d) => d,
+                Err(e) => bail!("Failed to create zstd decoder: {}", e),
+            };
+        let mut writer = BufWriter::with_capacity(512 * 1024, output);
+        if let Err(e) = std::io::copy(&mut decoder, &mut writer) {
+            bail!("Failed to decompress {}: {}", src.display(), e);
+        }
+        if let Err(e) = writer.flush() {
+            bail!("Failed to flush {}: {}", dest.display(), e);
+        }
+        Ok(())
+    })
+    .await;
+
+    match result {
+        Ok(r) => r,
+        Err(e) => bail!("Decompression task panicked: {}", e),
+    }
+}
+
+/// Quote a Postgres identifier, doubling embedded quotes.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+pub(super) async fn remove_file(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!(error = %e, path = %path.display(), "Failed to remove temp file");
+    }
+}
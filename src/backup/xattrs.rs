@@ -0,0 +1,237 @@
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::bail;
+use tracing::{error, info, warn};
+
+/// PAX key prefix tar implementations use for extended attributes.
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// The only attribute namespace archived and restored. `security.*`,
+/// `trusted.*` and the `system.posix_acl_*` ACLs need privileges or filesystem
+/// support an unprivileged restore cannot count on.
+const USER_NAMESPACE: &str = "user.";
+
+/// Reads `user.*` extended attributes for each archived path
+/// (`TAR_PRESERVE_XATTRS`).
+/// On a platform or filesystem without xattr support it warns once and
+/// stops trying, so the archive is still written without them.
+#[derive(Debug)]
+pub struct XattrCapture {
+    enabled: bool,
+    entries: u64,
+}
+
+impl XattrCapture {
+    pub fn new(enabled: bool) -> Self {
+        if enabled && !xattr::SUPPORTED_PLATFORM {
+            warn!("TAR_PRESERVE_XATTRS is set but this platform has no xattr support, skipping");
+            return Self {
+                enabled: false,
+                entries: 0,
+            };
+        }
+        Self {
+            enabled,
+            entries: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Append a PAX header carrying the attributes of `path`, if it has any.
+    /// Must be called right before the entry for `path` itself.
+    pub fn append_for<W: Write>(
+        &mut self,
+        builder: &mut tar::Builder<W>,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let names = match xattr::list(path) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                warn!(path = %path.display(), "Filesystem does not support extended attributes, archiving without them");
+                self.enabled = false;
+                return Ok(());
+            }
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Failed to list extended attributes");
+                return Ok(());
+            }
+        };
+
+        let mut pax = Vec::new();
+        for name in names {
+            if !name.to_string_lossy().starts_with(USER_NAMESPACE) {
+                continue;
+            }
+            match xattr::get(path, &name) {
+                Ok(Some(value)) => pax.push((
+                    format!("{}{}", PAX_XATTR_PREFIX, name.to_string_lossy()),
+                    value,
+                )),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(error = %e, path = %path.display(), name = %name.to_string_lossy(), "Failed to read extended attribute");
+                }
+            }
+        }
+        if pax.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) =
+            builder.append_pax_extensions(pax.iter().map(|(k, v)| (k.as_str(), v.as_slice())))
+        {
+            error!(error = %e, path = %path.display(), "Failed to append extended attributes");
+            bail!(
+                "Failed to append extended attributes of {}: {}",
+                path.display(),
+                e
+            );
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    pub fn log_summary(&self) {
+        if self.enabled {
+            info!(entries = self.entries, "Archived extended attributes");
+        }
+    }
+}
+
+/// Whether `user.*` attributes can be set under `dir`. Restores probe the
+/// destination once so a filesystem without them logs one warning rather than
+/// one per file.
+pub fn unpack_supported(dir: &Path) -> bool {
+    if !xattr::SUPPORTED_PLATFORM {
+        warn!("This platform has no xattr support, restoring without extended attributes");
+        return false;
+    }
+
+    let probe = dir.join(".db-backup-goog.xattr-probe");
+    let supported = std::fs::File::create(&probe)
+        .and_then(|_| xattr::set(&probe, "user.db_backup_goog.probe", b"1"));
+    let _ = std::fs::remove_file(&probe);
+
+    match supported {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(error = %e, dir = %dir.display(), "Cannot set extended attributes here, restoring without them");
+            false
+        }
+    }
+}
+
+/// Unpack all of `archive` into `dest` the way [`tar::Archive::unpack`] does,
+/// directories last, then set the `user.*` attributes of each entry when
+/// `restore_xattrs` is set. tar's own xattr support aborts the whole unpack on
+/// the first attribute it cannot set; here that is only a warning.
+pub fn unpack<R: Read>(
+    archive: &mut tar::Archive<R>,
+    dest: &Path,
+    restore_xattrs: bool,
+) -> anyhow::Result<()> {
+    if let Err(e) = std::fs::create_dir_all(dest) {
+        bail!("Failed to create {}: {}", dest.display(), e);
+    }
+    let entries = match archive.entries() {
+        Ok(e) => e,
+        Err(e) => bail!("Failed to read archive entries: {}", e),
+    };
+
+    // Unpacked last and deepest first, so a read-only directory cannot block
+    // its own contents and directory mtimes survive
+    let mut directories = Vec::new();
+    for entry in entries {
+        crate::abort::check()?;
+        crate::systemd::progress();
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => bail!("Failed to read archive entry: {}", e),
+        };
+        if entry.header().entry_type() == tar::EntryType::Directory {
+            directories.push(entry);
+            continue;
+        }
+        unpack_entry(entry, dest, restore_xattrs)?;
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for dir in directories {
+        unpack_entry(dir, dest, restore_xattrs)?;
+    }
+    Ok(())
+}
+
+fn unpack_entry<R: Read>(
+    mut entry: tar::Entry<'_, R>,
+    dest: &Path,
+    restore_xattrs: bool,
+) -> anyhow::Result<()> {
+    let path = match entry.path() {
+        Ok(p) => p.into_owned(),
+        Err(e) => bail!("Invalid entry path: {}", e),
+    };
+    match entry.unpack_in(dest) {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!(path = %path.display(), "Skipping archive entry outside the target directory");
+            return Ok(());
+        }
+        Err(e) => bail!("Failed to extract {}: {}", path.display(), e),
+    }
+    if restore_xattrs {
+        restore_for(&mut entry, &target_path(dest, &path));
+    }
+    Ok(())
+}
+
+/// Where [`tar::Entry::unpack_in`] put an entry with `path`: its normal
+/// components joined under `dest`.
+pub fn target_path(dest: &Path, path: &Path) -> PathBuf {
+    let mut target = dest.to_path_buf();
+    for component in path.components() {
+        if let Component::Normal(part) = component {
+            target.push(part);
+        }
+    }
+    target
+}
+
+/// Set the `user.*` attributes `entry` carries on `target`, which it was just
+/// unpacked to. Attributes that cannot be set are logged and skipped.
+pub fn restore_for<R: Read>(entry: &mut tar::Entry<'_, R>, target: &Path) {
+    // Setting them on a link would land on whatever it points to
+    let kind = entry.header().entry_type();
+    if !kind.is_file() && !kind.is_dir() {
+        return;
+    }
+    let extensions = match entry.pax_extensions() {
+        Ok(Some(e)) => e,
+        Ok(None) => return,
+        Err(e) => {
+            warn!(error = %e, path = %target.display(), "Failed to read extended attributes from archive");
+            return;
+        }
+    };
+    for extension in extensions.flatten() {
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX) else {
+            continue;
+        };
+        if !name.starts_with(USER_NAMESPACE) {
+            continue;
+        }
+        if let Err(e) = xattr::set(target, name, extension.value_bytes()) {
+            warn!(error = %e, path = %target.display(), name = name, "Failed to restore extended attribute");
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, Timelike, Utc};
+
+/// What a backup command does when started inside a blackout window
+/// (`BACKUP_BLACKOUT_ACTION`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlackoutAction {
+    /// Exit successfully without backing up.
+    Skip,
+    /// Wait for the window to close, then back up.
+    Defer,
+}
+
+/// Clock the windows are written in (`BACKUP_BLACKOUT_TZ`).
+#[derive(Debug, Clone, Copy)]
+pub enum BlackoutZone {
+    Utc,
+    /// The host's local time zone, following its DST rules.
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl BlackoutZone {
+    /// Parse `utc`, `local` or a fixed offset such as `+09:00`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "utc" => Ok(BlackoutZone::Utc),
+            "local" => Ok(BlackoutZone::Local),
+            other => other
+                .parse::<FixedOffset>()
+                .map(BlackoutZone::Fixed)
+                .map_err(|_| "expected 'utc', 'local' or an offset like '+09:00'".to_string()),
+        }
+    }
+
+    fn wall_time(&self, now: DateTime<Utc>) -> NaiveTime {
+        match self {
+            BlackoutZone::Utc => now.time(),
+            BlackoutZone::Local => now.with_timezone(&Local).time(),
+            BlackoutZone::Fixed(offset) => now.with_timezone(offset).time(),
+        }
+    }
+}
+
+/// A daily `HH:MM-HH:MM` span; an end before the start wraps past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlackoutWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl BlackoutWindow {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((start, end)) = value.split_once('-') else {
+            return Err("expected HH:MM-HH:MM".to_string());
+        };
+        let parse_time = |s: &str| {
+            NaiveTime::parse_from_str(s.trim(), "%H:%M")
+                .map_err(|_| format!("'{}' is not a HH:MM time", s.trim()))
+        };
+        let window = BlackoutWindow {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        if window.start == window.end {
+            return Err("start and end are equal".to_string());
+        }
+        Ok(window)
+    }
+
+    fn contains(&self, t: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+
+    /// Time from `t` until the window closes, assuming `t` is inside it.
+    fn remaining(&self, t: NaiveTime) -> Duration {
+        let secs = |t: NaiveTime| i64::from(t.num_seconds_from_midnight());
+        let left = (secs(self.end) - secs(t)).rem_euclid(24 * 60 * 60);
+        Duration::from_secs(left as u64)
+    }
+}
+
+impl std::fmt::Display for BlackoutWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Hours during which backups must not run (`BACKUP_BLACKOUT_WINDOWS`).
+#[derive(Debug, Clone)]
+pub struct Blackout {
+    pub windows: Vec<BlackoutWindow>,
+    pub zone: BlackoutZone,
+    pub action: BlackoutAction,
+}
+
+impl Blackout {
+    /// The window `now` falls in and how long until it closes.
+    pub fn active(&self, now: DateTime<Utc>) -> Option<(BlackoutWindow, Duration)> {
+        let t = self.zone.wall_time(now);
+        self.windows
+            .iter()
+            .find(|w| w.contains(t))
+            .map(|w| (*w, w.remaining(t)))
+    }
+}
@@ -1,4 +1,12 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::backup::BackupKind;
+use crate::list::ListFormat;
+use crate::scrub::ScrubOrder;
+use crate::trend::TrendFormat;
 
 #[derive(Parser)]
 #[command(
@@ -7,17 +15,383 @@ use clap::{Parser, Subcommand};
 )]
 pub struct Cli {
     #[command(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
+
+    /// Validate configuration, external binaries and credentials, then exit
+    /// (0 when usable, 1 with a list of problems otherwise)
+    #[arg(long)]
+    pub config_check: bool,
+
+    /// Read settings from this TOML file (keys are the env var names in lower
+    /// case); anything it leaves out still comes from the environment
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Produce backups locally without authenticating to or uploading to Google Drive.
+    /// Artifacts are kept in BACKUP_TEMP_DIR.
+    #[arg(long, global = true)]
+    pub dump_only: bool,
+
+    /// For db, minecraft and all: take the real dump and archive, log the
+    /// upload and prune that would follow instead of doing them, then delete
+    /// the artifact. Storage is only listed, to show what prune would delete.
+    #[arg(long, global = true, conflicts_with = "dump_only")]
+    pub dry_run: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Command {
-    /// Backup PostgreSQL database and upload to Google Drive
+    #[command(flatten)]
+    Run(RunCommand),
+    #[command(flatten)]
+    Inspect(InspectCommand),
+    #[command(flatten)]
+    Setup(SetupCommand),
+}
+
+/// Commands that work on backups, with the temp directory in place and, apart
+/// from restores, under `MAX_RUNTIME_SECS`.
+#[derive(Subcommand)]
+pub enum RunCommand {
+    #[command(flatten)]
+    Backup(BackupCommand),
+    /// Prune old backups from Google Drive (keep N newest per type)
+    Prune {
+        /// Drive file id to never delete (repeatable, merged with PRUNE_KEEP_IDS)
+        #[arg(long = "keep-id")]
+        keep_ids: Vec<String>,
+        /// Write the deletions this prune would make, with reasons, to a JSON
+        /// plan file instead of deleting anything
+        #[arg(long, value_name = "FILE", conflicts_with = "apply_plan")]
+        emit_plan: Option<PathBuf>,
+        /// Delete exactly the files in a plan from --emit-plan, skipping any
+        /// that no longer exist under their planned name
+        #[arg(long, value_name = "FILE", conflicts_with = "keep_ids")]
+        apply_plan: Option<PathBuf>,
+    },
+    /// Stop the Minecraft server, keep a safety archive of the current world,
+    /// extract a full backup into place, and start the server again. With
+    /// --target-dir or --verify-only the live server is left alone
+    RestoreMinecraft {
+        /// `minecraft_*.tar.zst` backup to restore: a local path, a Drive file
+        /// id, or `latest`. A differential or incremental is restored on top of
+        /// its full backup. Omit it on a terminal to pick from a list
+        archive: Option<String>,
+        /// Required: confirms the live server directory may be replaced
+        #[arg(long)]
+        confirm: bool,
+        /// Extract into this directory instead of over the live server; the
+        /// server is neither stopped nor started
+        #[arg(long)]
+        target_dir: Option<PathBuf>,
+        /// Only check that the archive decompresses and reads end to end
+        #[arg(long, conflicts_with = "target_dir")]
+        verify_only: bool,
+        /// With --target-dir, extract only files with these extensions
+        /// (comma-separated or repeated, e.g. `dat,mca`)
+        #[arg(long, value_delimiter = ',', requires = "target_dir")]
+        include_ext: Vec<String>,
+        /// With --target-dir, skip files with these extensions
+        #[arg(long, value_delimiter = ',', requires = "target_dir")]
+        exclude_ext: Vec<String>,
+    },
+    /// Restore a db backup into DB_NAME with `pg_restore --clean`, dropping and
+    /// recreating the objects it contains. A `.sql.gz` is run through psql in
+    /// one transaction and needs DB_NAME to be without its objects
+    RestoreDb {
+        /// `.dump`, `.dump.zst` or `.sql.gz` backup to restore: a local path, a Drive
+        /// file id, or `latest`. Omit it on a terminal to pick from a list,
+        /// newest first; elsewhere it defaults to `latest`
+        archive: Option<String>,
+        /// Restore only this table (repeatable); it must be in the dump
+        #[arg(long = "table")]
+        tables: Vec<String>,
+        /// Required: confirms objects in DB_NAME may be replaced
+        #[arg(long)]
+        confirm: bool,
+    },
+    /// Restore the latest db backup from Google Drive into a scratch database,
+    /// run `DB_VALIDATE_QUERY` against it and drop it again
+    ValidateRestore,
+    /// Download a split archive from Google Drive by its index file, verify
+    /// every part, and join them back into a single archive
+    FetchArchive {
+        /// Drive file id of the `<archive>.index.json` index
+        index_id: String,
+        /// Type folder holding the parts
+        #[arg(long = "type", value_enum)]
+        backup_type: BackupKind,
+        /// Directory for the joined archive (defaults to BACKUP_TEMP_DIR)
+        #[arg(long)]
+        dest: Option<PathBuf>,
+        /// Also unpack a `.tar.zst` archive into this directory
+        #[arg(long)]
+        extract_to: Option<PathBuf>,
+    },
+    /// Download the newest backups of a type from the primary Google Drive
+    /// folder, checking each against Drive's size and its checksum sidecar
+    Download {
+        /// Backup type to download
+        #[arg(long = "type", value_enum)]
+        backup_type: BackupKind,
+        /// How many of the newest backups to fetch
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        latest: u32,
+        /// Target directory (defaults to BACKUP_TEMP_DIR)
+        #[arg(long)]
+        dest: Option<PathBuf>,
+    },
+    /// Probe stored backups with ranged downloads instead of fetching them:
+    /// check format headers, zstd frames and a sample of tar headers, and
+    /// compare Drive's md5 with the scrub manifest. Exits non-zero on any fail
+    Scrub {
+        /// Only scrub this backup type (defaults to all types)
+        #[arg(long = "type", value_enum)]
+        backup_type: Option<BackupKind>,
+        /// Backups probed at once
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: u32,
+        /// Start with the newest or the oldest backups
+        #[arg(long, value_enum, default_value = "newest")]
+        order: ScrubOrder,
+    },
+    /// Report backups with identical Drive md5 checksums in each type folder;
+    /// with --yes, delete all but the newest of each group
+    Dedup {
+        /// Only check this backup type (defaults to all types)
+        #[arg(long = "type", value_enum)]
+        backup_type: Option<BackupKind>,
+        /// Delete the duplicates instead of only reporting them
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Train a zstd dictionary from small files in MINECRAFT_SERVER_PATH for
+    /// use as ZSTD_DICT_PATH. Mainly helps trees of many small, similar files
+    TrainDict {
+        /// Where to write the dictionary (defaults to ZSTD_DICT_PATH)
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Maximum dictionary size in bytes
+        #[arg(long, default_value_t = 112_640)]
+        max_size: usize,
+        /// Maximum number of files sampled
+        #[arg(long, default_value_t = 10_000)]
+        samples: usize,
+        /// Replace an existing dictionary. The old one stays beside it under
+        /// its id, for the archives written with it
+        #[arg(long)]
+        force: bool,
+    },
+    /// List backups stored in the primary Google Drive folder, newest first
+    List {
+        /// Only list this backup type (defaults to all types)
+        #[arg(long = "type", value_enum)]
+        backup_type: Option<BackupKind>,
+        /// `csv` prints one row per backup, with the count and total size
+        /// on stderr
+        #[arg(long, alias = "output-format", value_enum, default_value = "table")]
+        format: ListFormat,
+    },
+}
+
+/// Commands that produce a backup; they honour blackout windows, retries and
+/// `--dry-run`.
+#[derive(Subcommand, Clone, Copy)]
+pub enum BackupCommand {
+    /// Backup the PostgreSQL database(s) in DB_NAMES (or DB_NAME) and upload to Google Drive
     Db,
     /// Backup Minecraft server and upload to Google Drive
-    Minecraft,
-    /// Run all backups (db + minecraft) and prune old Minecraft backups
+    Minecraft {
+        /// Archive only files modified since the last successful backup
+        /// (`minecraft_incr_<ts>.tar.zst`); restore applies the last full plus
+        /// every later incremental
+        #[arg(long)]
+        since_last: bool,
+    },
+    /// Run all backups (db + minecraft) concurrently and prune old Minecraft backups
     All,
-    /// Prune old Minecraft backups from Google Drive (keep N newest)
-    Prune,
+}
+
+/// Commands that only read the configuration and local state.
+#[derive(Subcommand)]
+pub enum InspectCommand {
+    /// Exit non-zero if the last successful backup is older than --max-age
+    CheckFreshness {
+        /// Maximum allowed age of the last successful backup (e.g. 90m, 26h, 7d)
+        #[arg(long, value_parser = parse_duration)]
+        max_age: Duration,
+        /// Only check this backup type (defaults to all types)
+        #[arg(long = "type", value_enum)]
+        backup_type: Option<BackupKind>,
+    },
+    /// List compiled-in storage backends, which one is active, and their capabilities
+    Backends,
+    /// Print recorded full-backup sizes over time with a growth projection;
+    /// reads the status file only, no backups run
+    Trend {
+        /// Only show this backup type (defaults to all types)
+        #[arg(long = "type", value_enum)]
+        backup_type: Option<BackupKind>,
+        #[arg(long, value_enum, default_value = "text")]
+        format: TrendFormat,
+        /// How far past the latest backup to project (e.g. 30d, 12w)
+        #[arg(long, value_parser = parse_duration, default_value = "90d")]
+        horizon: Duration,
+    },
+}
+
+/// Commands that need no configuration, so they work before one exists.
+#[derive(Subcommand)]
+pub enum SetupCommand {
+    /// Print a commented `.env` template of every supported environment
+    /// variable with its default
+    GenEnv,
+    /// Print a systemd `.service` and `.timer` unit pair that runs this binary
+    /// on a schedule
+    GenSystemd(GenSystemdArgs),
+    /// Load the configuration (from --config and the environment) and print
+    /// every resolved setting with secrets redacted; no backup runs
+    ValidateConfig,
+}
+
+/// Options of `gen-systemd`.
+#[derive(Args)]
+pub struct GenSystemdArgs {
+    /// Subcommand and flags the service runs
+    #[arg(long, default_value = "all")]
+    pub run: String,
+    /// When the timer fires, as a systemd calendar expression
+    #[arg(long, default_value = "daily")]
+    pub on_calendar: String,
+    /// Environment file holding the configuration
+    #[arg(long, default_value = "/etc/db-backup-goog.env")]
+    pub env_file: PathBuf,
+    /// Working directory, kept writable (defaults to the current directory)
+    #[arg(long)]
+    pub working_dir: Option<PathBuf>,
+    /// User the service runs as (defaults to root)
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Extra writable path, e.g. BACKUP_TEMP_DIR outside /tmp (repeatable)
+    #[arg(long = "read-write")]
+    pub read_write_paths: Vec<PathBuf>,
+    /// Unit name without suffix
+    #[arg(long, default_value = "db-backup-goog")]
+    pub name: String,
+}
+
+impl Command {
+    /// The subcommand as typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Run(command) => command.name(),
+            Command::Inspect(command) => command.name(),
+            Command::Setup(command) => command.name(),
+        }
+    }
+}
+
+impl RunCommand {
+    /// The subcommand as typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RunCommand::Backup(command) => command.name(),
+            RunCommand::Prune { .. } => "prune",
+            RunCommand::RestoreMinecraft { .. } => "restore-minecraft",
+            RunCommand::RestoreDb { .. } => "restore-db",
+            RunCommand::ValidateRestore => "validate-restore",
+            RunCommand::FetchArchive { .. } => "fetch-archive",
+            RunCommand::Download { .. } => "download",
+            RunCommand::Scrub { .. } => "scrub",
+            RunCommand::Dedup { .. } => "dedup",
+            RunCommand::TrainDict { .. } => "train-dict",
+            RunCommand::List { .. } => "list",
+        }
+    }
+
+    /// Whether the command restores a backup. Restores are exempt from
+    /// `MAX_RUNTIME_SECS`: cancelling one between stopping the server and
+    /// starting it again, or halfway through loading a dump, leaves things
+    /// worse than letting it finish.
+    pub fn is_restore(&self) -> bool {
+        matches!(
+            self,
+            RunCommand::RestoreMinecraft { .. } | RunCommand::RestoreDb { .. }
+        )
+    }
+}
+
+impl BackupCommand {
+    /// The subcommand as typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackupCommand::Db => "db",
+            BackupCommand::Minecraft { .. } => "minecraft",
+            BackupCommand::All => "all",
+        }
+    }
+}
+
+impl InspectCommand {
+    /// The subcommand as typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InspectCommand::CheckFreshness { .. } => "check-freshness",
+            InspectCommand::Backends => "backends",
+            InspectCommand::Trend { .. } => "trend",
+        }
+    }
+}
+
+impl SetupCommand {
+    /// The subcommand as typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SetupCommand::GenEnv => "gen-env",
+            SetupCommand::GenSystemd(_) => "gen-systemd",
+            SetupCommand::ValidateConfig => "validate-config",
+        }
+    }
+}
+
+/// Parse a human duration such as `45s`, `90m`, `26h`, `7d`, `2w` or `1h30m`.
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("duration must not be empty".to_string());
+    }
+
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total_secs: u64 = 0;
+    let mut digits = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit_secs = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(format!("invalid duration unit '{}' in '{}'", c, input)),
+        };
+        let value: u64 = match digits.parse() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("missing number before '{}' in '{}'", c, input)),
+        };
+        total_secs = total_secs.saturating_add(value.saturating_mul(unit_secs));
+        digits.clear();
+    }
+
+    if !digits.is_empty() {
+        return Err(format!("missing unit after '{}' in '{}'", digits, input));
+    }
+
+    Ok(Duration::from_secs(total_secs))
 }
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use super::config::Config;
+
+/// Validate what `Config::from_env` cannot: that required external binaries
+/// are on `PATH`, credentials files parse, and configured paths exist.
+/// Returns one line per problem; an empty list means the setup is usable.
+pub async fn check_environment(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut binaries = Vec::new();
+    if config.db_backup_enabled {
+        binaries.extend(["pg_dump", "psql"]);
+        if config.db_verify_roundtrip {
+            binaries.push("pg_restore");
+        }
+        if config.db_ssh.is_some() {
+            binaries.push("ssh");
+        }
+    }
+    if config.on_success_cmd.is_some()
+        || (config.minecraft_backup_enabled
+            && (config.mc_stop_command.is_some() || config.mc_start_command.is_some()))
+    {
+        binaries.push("sh");
+    }
+    for binary in binaries {
+        if find_in_path(binary).is_none() {
+            problems.push(format!("'{}' was not found on PATH", binary));
+        }
+    }
+
+    for path in &config.google_credentials_paths {
+        if let Err(e) = yup_oauth2::read_authorized_user_secret(path).await {
+            problems.push(format!(
+                "credentials file {} could not be parsed: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    if config.db_backup_enabled
+        && let Some(ssh) = &config.db_ssh
+        && !ssh.key_path.is_file()
+    {
+        problems.push(format!(
+            "DB_SSH_KEY {} does not exist",
+            ssh.key_path.display()
+        ));
+    }
+
+    if let Some(dict) = &config.zstd_dict_path
+        && !dict.is_file()
+    {
+        problems.push(format!("ZSTD_DICT_PATH {} does not exist", dict.display()));
+    }
+
+    if config.minecraft_backup_enabled && !config.minecraft_server_path.is_dir() {
+        problems.push(format!(
+            "MINECRAFT_SERVER_PATH {} is not a directory",
+            config.minecraft_server_path.display()
+        ));
+    }
+
+    if !config.backup_temp_dir.is_dir()
+        && !config
+            .backup_temp_dir
+            .parent()
+            .is_some_and(|parent| parent.as_os_str().is_empty() || parent.is_dir())
+    {
+        problems.push(format!(
+            "BACKUP_TEMP_DIR {} does not exist and cannot be created in a missing parent",
+            config.backup_temp_dir.display()
+        ));
+    }
+
+    problems
+}
+
+/// Resolve `name` against `PATH` the way a shell would.
+fn find_in_path(name: &str) -> Option<std::path::PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::metadata(path) {
+        Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::bail;
+use tracing::{error, warn};
+
+use super::env_template::ENV_SECTIONS;
+
+/// Read by the logger before any config file is loaded, so only the
+/// environment can set them.
+const ENV_ONLY: [&str; 2] = ["LOG_FORMAT", "LOG_REDACT_FIELDS"];
+
+/// Read a TOML config file into values keyed by env var name. Keys are the
+/// env var names in lower case (`db_host = "..."`); arrays become
+/// comma-separated lists. A string of the form `"$NAME"` is replaced by the
+/// env var `NAME`, so secrets can stay out of the file; `.env` is loaded
+/// first so it can supply them.
+pub fn read_values(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    super::config::load_dotenv();
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to read config file");
+            bail!("Failed to read config file {}: {}", path.display(), e);
+        }
+    };
+    let table: toml::Table = match toml::from_str(&contents) {
+        Ok(t) => t,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to parse config file");
+            bail!("Failed to parse config file {}: {}", path.display(), e);
+        }
+    };
+
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        let name = key.to_ascii_uppercase();
+        if ENV_ONLY.contains(&name.as_str()) {
+            warn!(key = %key, "Ignoring config file key; set it in the environment instead");
+            continue;
+        }
+        if !is_known(&name) {
+            bail!(
+                "Unknown key '{}' in {}; keys are the env var names in lower case",
+                key,
+                path.display()
+            );
+        }
+        let value = match value {
+            toml::Value::Array(items) => {
+                let items = items
+                    .into_iter()
+                    .map(|item| scalar(&key, item))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                items.join(",")
+            }
+            other => scalar(&key, other)?,
+        };
+        values.insert(name, value);
+    }
+    Ok(values)
+}
+
+/// Whether `name` is a variable listed in [`ENV_SECTIONS`].
+fn is_known(name: &str) -> bool {
+    ENV_SECTIONS
+        .iter()
+        .flat_map(|(_, vars)| vars.iter())
+        .any(|v| v.name == name)
+}
+
+fn scalar(key: &str, value: toml::Value) -> anyhow::Result<String> {
+    match value {
+        toml::Value::String(s) => match s.strip_prefix('$') {
+            Some(var) if !var.is_empty() => match std::env::var(var) {
+                Ok(resolved) => Ok(resolved),
+                Err(e) => bail!("'{}' refers to ${} which is not set: {}", key, var, e),
+            },
+            _ => Ok(s),
+        },
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        other => bail!(
+            "'{}' must be a string, number, boolean or array of those, not a {}",
+            key,
+            other.type_str()
+        ),
+    }
+}
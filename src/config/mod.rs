@@ -1 +1,4 @@
+pub mod check;
 pub mod config;
+pub mod env_template;
+pub mod file;
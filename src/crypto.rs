@@ -0,0 +1,348 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::bail;
+use tracing::{error, info};
+
+use crate::config::config::Config;
+
+/// Suffix added to the name of an encrypted backup.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+/// Marks a file written by the streaming format; files without it are the
+/// older single-shot format.
+const STREAM_MAGIC: &[u8; 8] = b"DBGSTRM1";
+
+/// Bytes of random nonce prefix after the magic. STREAM takes 5 of AES-GCM's
+/// 12 nonce bytes for the chunk counter and last-chunk flag.
+const STREAM_NONCE_LEN: usize = 7;
+
+/// Plaintext bytes sealed per chunk.
+const CHUNK_LEN: usize = 1024 * 1024;
+
+/// Bytes of authentication tag appended to every chunk.
+const TAG_LEN: usize = 16;
+
+/// Bytes of nonce ahead of the ciphertext in the single-shot format.
+const LEGACY_NONCE_LEN: usize = 12;
+
+/// Parse a 64-hex-char AES-256 key (`BACKUP_ENCRYPTION_KEY`).
+pub fn parse_key(hex_key: &str) -> anyhow::Result<[u8; 32]> {
+    let bytes = match hex::decode(hex_key.trim()) {
+        Ok(b) => b,
+        Err(e) => bail!("Encryption key is not valid hex: {}", e),
+    };
+    match <[u8; 32]>::try_from(bytes) {
+        Ok(key) => Ok(key),
+        Err(bytes) => bail!(
+            "Encryption key must be 32 bytes (64 hex chars), got {} bytes",
+            bytes.len()
+        ),
+    }
+}
+
+/// `name` without the encrypted suffix, when it has one.
+pub fn plaintext_name(name: &str) -> &str {
+    name.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(name)
+}
+
+/// Read up to `len` bytes, fewer only at end of file.
+fn read_chunk(reader: &mut impl Read, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(len);
+    reader.by_ref().take(len as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Encrypt `input` into `output` with AES-256-GCM in the STREAM construction:
+/// a magic, a random 7-byte nonce prefix, then 1 MiB chunks each sealed with
+/// its own tag. The last chunk is sealed as such, so truncation is detected.
+pub fn encrypt_file(input: &Path, output: &Path, key: &[u8; 32]) -> anyhow::Result<()> {
+    let mut reader = match File::open(input) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => bail!("Failed to open {}: {}", input.display(), e),
+    };
+    let mut writer = match File::create(output) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => bail!("Failed to create {}: {}", output.display(), e),
+    };
+
+    let mut nonce = [0u8; STREAM_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    if let Err(e) = writer
+        .write_all(STREAM_MAGIC)
+        .and_then(|()| writer.write_all(&nonce))
+    {
+        bail!("Failed to write {}: {}", output.display(), e);
+    }
+
+    let mut encryptor = EncryptorBE32::<Aes256Gcm>::new(key.into(), (&nonce).into());
+    let read = |reader: &mut BufReader<File>| match read_chunk(reader, CHUNK_LEN) {
+        Ok(c) => Ok(c),
+        Err(e) => bail!("Failed to read {}: {}", input.display(), e),
+    };
+    let seal = |sealed: Result<Vec<u8>, aes_gcm::aead::Error>| match sealed {
+        Ok(s) => Ok(s),
+        Err(_) => {
+            error!(path = %input.display(), "AES-GCM encryption failed");
+            bail!("Failed to encrypt {}", input.display());
+        }
+    };
+    let mut pending = read(&mut reader)?;
+    // A full chunk may still be the last one; that is only known once the
+    // next read comes back empty
+    while pending.len() == CHUNK_LEN {
+        crate::abort::check()?;
+        crate::systemd::progress();
+        let next = read(&mut reader)?;
+        if next.is_empty() {
+            break;
+        }
+        let sealed = seal(encryptor.encrypt_next(pending.as_slice()))?;
+        if let Err(e) = writer.write_all(&sealed) {
+            bail!("Failed to write {}: {}", output.display(), e);
+        }
+        pending = next;
+    }
+    let sealed = seal(encryptor.encrypt_last(pending.as_slice()))?;
+    if let Err(e) = writer.write_all(&sealed) {
+        bail!("Failed to write {}: {}", output.display(), e);
+    }
+    if let Err(e) = writer.flush() {
+        bail!("Failed to write {}: {}", output.display(), e);
+    }
+    Ok(())
+}
+
+/// Reverse [`encrypt_file`], also accepting the older single-shot format.
+/// Never replaces an existing `output`, and removes it again when the key is
+/// wrong or any chunk was altered, reordered or cut off.
+pub fn decrypt_file(input: &Path, output: &Path, key: &[u8; 32]) -> anyhow::Result<()> {
+    let mut reader = match File::open(input) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => bail!("Failed to open {}: {}", input.display(), e),
+    };
+    let file = match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output)
+    {
+        Ok(f) => f,
+        Err(e) => bail!("Failed to create {}: {}", output.display(), e),
+    };
+
+    let result = decrypt_into(input, &mut reader, &mut BufWriter::new(file), key);
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result
+}
+
+fn decrypt_into(
+    input: &Path,
+    reader: &mut BufReader<File>,
+    writer: &mut BufWriter<File>,
+    key: &[u8; 32],
+) -> anyhow::Result<()> {
+    let corrupted = || {
+        error!(path = %input.display(), "AES-GCM decryption failed");
+        anyhow::anyhow!(
+            "Failed to decrypt {}: wrong BACKUP_ENCRYPTION_KEY or corrupted file",
+            input.display()
+        )
+    };
+    let read = |reader: &mut BufReader<File>, len: usize| match read_chunk(reader, len) {
+        Ok(c) => Ok(c),
+        Err(e) => bail!("Failed to read {}: {}", input.display(), e),
+    };
+    let write = |writer: &mut BufWriter<File>, plain: &[u8]| match writer.write_all(plain) {
+        Ok(()) => Ok(()),
+        Err(e) => bail!("Failed to write decrypted {}: {}", input.display(), e),
+    };
+
+    let header = read(reader, STREAM_MAGIC.len() + STREAM_NONCE_LEN)?;
+    let Some(nonce) = header.strip_prefix(STREAM_MAGIC.as_slice()) else {
+        let mut sealed = header;
+        if let Err(e) = reader.read_to_end(&mut sealed) {
+            bail!("Failed to read {}: {}", input.display(), e);
+        }
+        write(writer, &decrypt_legacy(input, &sealed, key, corrupted)?)?;
+        return flush(writer, input);
+    };
+    if nonce.len() < STREAM_NONCE_LEN {
+        bail!("{} is too short to be an encrypted backup", input.display());
+    }
+
+    let mut decryptor = DecryptorBE32::<Aes256Gcm>::new(key.into(), nonce.into());
+    let mut pending = read(reader, CHUNK_LEN + TAG_LEN)?;
+    while pending.len() == CHUNK_LEN + TAG_LEN {
+        crate::abort::check()?;
+        crate::systemd::progress();
+        let next = read(reader, CHUNK_LEN + TAG_LEN)?;
+        if next.is_empty() {
+            break;
+        }
+        let plain = decryptor.decrypt_next(pending.as_slice());
+        write(writer, &plain.map_err(|_| corrupted())?)?;
+        pending = next;
+    }
+    let plain = decryptor.decrypt_last(pending.as_slice());
+    write(writer, &plain.map_err(|_| corrupted())?)?;
+    flush(writer, input)
+}
+
+/// Open the single-shot format: a 12-byte nonce, then one ciphertext and tag.
+fn decrypt_legacy(
+    input: &Path,
+    sealed: &[u8],
+    key: &[u8; 32],
+    corrupted: impl Fn() -> anyhow::Error,
+) -> anyhow::Result<Vec<u8>> {
+    if sealed.len() < LEGACY_NONCE_LEN {
+        bail!("{} is too short to be an encrypted backup", input.display());
+    }
+    let (nonce, ciphertext) = sealed.split_at(LEGACY_NONCE_LEN);
+    let cipher = Aes256Gcm::new(key.into());
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| corrupted())
+}
+
+fn flush(writer: &mut BufWriter<File>, input: &Path) -> anyhow::Result<()> {
+    match writer.flush() {
+        Ok(()) => Ok(()),
+        Err(e) => bail!("Failed to write decrypted {}: {}", input.display(), e),
+    }
+}
+
+/// Decrypt `archive` into `BACKUP_TEMP_DIR` when its name ends in `.enc`,
+/// returning the plaintext copy for the caller to remove.
+pub async fn decrypt_if_encrypted(
+    config: &Config,
+    archive: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    let name = match archive.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => return Ok(None),
+    };
+    if !name.ends_with(ENCRYPTED_SUFFIX) {
+        return Ok(None);
+    }
+    let Some(key) = config.encryption_key else {
+        bail!(
+            "{} is encrypted; set BACKUP_ENCRYPTION_KEY to restore it",
+            archive.display()
+        );
+    };
+
+    let decrypted = config.backup_temp_dir.join(plaintext_name(&name));
+    info!(archive = %archive.display(), dest = %decrypted.display(), "Decrypting backup");
+    let (input, output) = (archive.to_path_buf(), decrypted.clone());
+    match tokio::task::spawn_blocking(move || decrypt_file(&input, &output, &key)).await {
+        Ok(Ok(())) => Ok(Some(decrypted)),
+        Ok(Err(e)) => Err(e),
+        Err(e) => bail!("Decryption task panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// Scratch directory removed again when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("crypto-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn round_trip(name: &str, plain: &[u8]) {
+        let dir = Scratch::new(name);
+        let (input, sealed, output) = (dir.0.join("in"), dir.0.join("in.enc"), dir.0.join("out"));
+        std::fs::write(&input, plain).unwrap();
+        encrypt_file(&input, &sealed, &KEY).unwrap();
+        let chunks = plain.len().div_ceil(CHUNK_LEN).max(1);
+        assert_eq!(
+            std::fs::metadata(&sealed).unwrap().len() as usize,
+            STREAM_MAGIC.len() + STREAM_NONCE_LEN + plain.len() + chunks * TAG_LEN
+        );
+        decrypt_file(&sealed, &output, &KEY).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trips_empty_file() {
+        round_trip("empty", &[]);
+    }
+
+    #[test]
+    fn round_trips_across_chunks() {
+        round_trip("partial", &sample(2 * CHUNK_LEN + 17));
+    }
+
+    #[test]
+    fn round_trips_exact_chunk_multiple() {
+        round_trip("exact", &sample(2 * CHUNK_LEN));
+    }
+
+    #[test]
+    fn rejects_wrong_key_and_truncation_without_leaving_output() {
+        let dir = Scratch::new("reject");
+        let (input, sealed, output) = (dir.0.join("in"), dir.0.join("in.enc"), dir.0.join("out"));
+        std::fs::write(&input, sample(CHUNK_LEN + 5)).unwrap();
+        encrypt_file(&input, &sealed, &KEY).unwrap();
+
+        assert!(decrypt_file(&sealed, &output, &[8; 32]).is_err());
+        assert!(!output.exists());
+
+        // Dropping the last chunk leaves a full chunk not sealed as the last
+        let full = STREAM_MAGIC.len() + STREAM_NONCE_LEN + CHUNK_LEN + TAG_LEN;
+        let bytes = std::fs::read(&sealed).unwrap();
+        std::fs::write(&sealed, &bytes[..full]).unwrap();
+        assert!(decrypt_file(&sealed, &output, &KEY).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn decrypts_legacy_single_shot_format() {
+        let dir = Scratch::new("legacy");
+        let (sealed, output) = (dir.0.join("in.enc"), dir.0.join("out"));
+        let nonce = [3u8; LEGACY_NONCE_LEN];
+        let mut bytes = nonce.to_vec();
+        let cipher = Aes256Gcm::new((&KEY).into());
+        bytes.extend(
+            cipher
+                .encrypt(Nonce::from_slice(&nonce), b"legacy".as_slice())
+                .unwrap(),
+        );
+        std::fs::write(&sealed, bytes).unwrap();
+
+        decrypt_file(&sealed, &output, &KEY).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"legacy");
+    }
+
+    #[test]
+    fn plaintext_name_strips_only_the_suffix() {
+        assert_eq!(plaintext_name("db_app.dump.enc"), "db_app.dump");
+        assert_eq!(plaintext_name("db_app.dump"), "db_app.dump");
+    }
+}
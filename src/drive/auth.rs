@@ -1,22 +1,34 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use anyhow::bail;
-use tracing::{error, info};
+use google_drive3::api::Scope;
+use rustls::crypto::CryptoProvider;
+use tracing::{error, info, warn};
 
-pub type DriveHub = google_drive3::DriveHub<
-    hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>,
->;
+pub type Connector =
+    hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>;
 
-pub async fn build_hub(credentials_path: &Path) -> anyhow::Result<DriveHub> {
+pub type DriveHub = google_drive3::DriveHub<Connector>;
+
+/// Bounded retry for the initial token fetch, so starting before networking
+/// has settled (e.g. at boot) doesn't fail the run outright.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthRetry {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+pub async fn build_hub(credentials_path: &Path, retry: AuthRetry) -> anyhow::Result<DriveHub> {
     info!(
         path = %credentials_path.display(),
         "Authenticating with Google Drive"
     );
 
     // Install the rustls crypto provider before any TLS operations.
-    if let Err(e) = rustls::crypto::ring::default_provider().install_default() {
-        tracing::debug!(error = ?e, "CryptoProvider already installed, continuing");
-    }
+    let provider = ensure_crypto_provider();
 
     let secret = match yup_oauth2::read_authorized_user_secret(credentials_path).await {
         Ok(s) => s,
@@ -45,13 +57,39 @@ pub async fn build_hub(credentials_path: &Path) -> anyhow::Result<DriveHub> {
         }
     };
 
-    let connector = match hyper_rustls::HttpsConnectorBuilder::new().with_native_roots() {
-        Ok(builder) => builder.https_only().enable_http2().build(),
-        Err(e) => {
-            error!(error = %e, "Failed to load native TLS root certificates");
-            bail!("Failed to load native TLS root certificates: {}", e);
+    // Fetch a token up front: this is the first network round-trip, so DNS and
+    // connection failures surface here where they can be retried.
+    let mut attempt: u32 = 0;
+    loop {
+        match auth.token(&[Scope::Full]).await {
+            Ok(_) => break,
+            Err(e) if is_transient_auth_error(&e) && attempt < retry.retries => {
+                let delay = retry.delay.saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                warn!(
+                    error = %e,
+                    attempt = attempt,
+                    max_retries = retry.retries,
+                    delay = ?delay,
+                    "Transient network error during Google authentication, retrying"
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                error!(error = %e, attempts = attempt + 1, "Failed to obtain Google access token");
+                bail!("Failed to obtain Google access token: {}", e);
+            }
         }
-    };
+    }
+
+    let connector =
+        match hyper_rustls::HttpsConnectorBuilder::new().with_provider_and_native_roots(provider) {
+            Ok(builder) => builder.https_only().enable_http2().build(),
+            Err(e) => {
+                error!(error = %e, "Failed to load native TLS root certificates");
+                bail!("Failed to load native TLS root certificates: {}", e);
+            }
+        };
 
     let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
         .build(connector);
@@ -62,3 +100,149 @@ pub async fn build_hub(credentials_path: &Path) -> anyhow::Result<DriveHub> {
 
     Ok(hub)
 }
+
+/// Install ring as the process-wide rustls provider, or verify that the one
+/// already installed is ring. If another provider (typically aws-lc-rs pulled
+/// in by a different dependency) won the race, warn with the fix and pin ring
+/// explicitly for the Drive connector instead of relying on the default.
+fn ensure_crypto_provider() -> Arc<CryptoProvider> {
+    let expected = Arc::new(rustls::crypto::ring::default_provider());
+
+    if rustls::crypto::ring::default_provider()
+        .install_default()
+        .is_ok()
+    {
+        return expected;
+    }
+
+    let Some(installed) = CryptoProvider::get_default() else {
+        return expected;
+    };
+
+    let same_suites = installed
+        .cipher_suites
+        .iter()
+        .map(|s| s.suite())
+        .eq(expected.cipher_suites.iter().map(|s| s.suite()));
+    let same_kx = installed
+        .kx_groups
+        .iter()
+        .map(|g| g.name())
+        .eq(expected.kx_groups.iter().map(|g| g.name()));
+
+    if same_suites && same_kx {
+        tracing::debug!("ring CryptoProvider already installed, continuing");
+        return Arc::clone(installed);
+    }
+
+    warn!(
+        installed_cipher_suites = ?installed.cipher_suites.iter().map(|s| s.suite()).collect::<Vec<_>>(),
+        installed_kx_groups = ?installed.kx_groups.iter().map(|g| g.name()).collect::<Vec<_>>(),
+        "A rustls CryptoProvider other than ring was installed as the process default before \
+         Drive setup; using ring explicitly for Drive. If TLS handshakes still fail, ensure every \
+         rustls-based dependency enables the 'ring' feature (not 'aws-lc-rs'), or install ring \
+         with CryptoProvider::install_default before anything else uses TLS"
+    );
+    expected
+}
+
+/// One authenticated Drive account, labelled by its credentials file.
+pub struct DriveAccount {
+    pub credentials_path: PathBuf,
+    pub hub: DriveHub,
+}
+
+/// Every configured account, authenticated up front, with the one currently
+/// in use. Uploads fail over to the next account when the active one is
+/// rejected for quota or authorization reasons.
+pub struct DriveAccounts {
+    accounts: Vec<DriveAccount>,
+    active: AtomicUsize,
+}
+
+impl DriveAccounts {
+    /// Authenticate every credentials file in order. Accounts that fail to
+    /// authenticate are skipped with an error; at least one must succeed.
+    pub async fn build(credentials_paths: &[PathBuf], retry: AuthRetry) -> anyhow::Result<Self> {
+        let mut accounts = Vec::with_capacity(credentials_paths.len());
+        let mut last_error = None;
+
+        for path in credentials_paths {
+            match build_hub(path, retry).await {
+                Ok(hub) => accounts.push(DriveAccount {
+                    credentials_path: path.clone(),
+                    hub,
+                }),
+                Err(e) => {
+                    error!(error = %e, path = %path.display(), "Skipping Google account that failed to authenticate");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if accounts.is_empty() {
+            match last_error {
+                Some(e) => return Err(e),
+                None => bail!("No Google credentials configured"),
+            }
+        }
+
+        info!(
+            accounts = accounts.len(),
+            active = %accounts[0].credentials_path.display(),
+            "Google Drive accounts ready"
+        );
+
+        Ok(DriveAccounts {
+            accounts,
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    /// Index of the account currently in use.
+    pub fn active_index(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn hub(&self) -> &DriveHub {
+        &self.accounts[self.active_index()].hub
+    }
+
+    pub fn hub_at(&self, index: usize) -> &DriveHub {
+        &self.accounts[index].hub
+    }
+
+    /// Move off the account at `failed`, unless a concurrent upload already
+    /// did. Returns the index to use next, or `None` when every account has
+    /// been tried.
+    pub fn fail_over(&self, failed: usize) -> Option<usize> {
+        let next = failed + 1;
+        if next >= self.accounts.len() {
+            return None;
+        }
+
+        match self
+            .active
+            .compare_exchange(failed, next, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => {
+                warn!(
+                    from = %self.accounts[failed].credentials_path.display(),
+                    to = %self.accounts[next].credentials_path.display(),
+                    "Failing over to next Google account"
+                );
+                Some(next)
+            }
+            Err(current) => Some(current),
+        }
+    }
+}
+
+/// Credential problems (revoked/invalid grants, bad secrets) will not fix
+/// themselves; everything else (DNS, refused connections, I/O) is retried.
+fn is_transient_auth_error(e: &yup_oauth2::Error) -> bool {
+    !matches!(
+        e,
+        yup_oauth2::Error::AuthError(_) | yup_oauth2::Error::UserError(_)
+    )
+}
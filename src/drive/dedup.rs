@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use google_drive3::api::{File as DriveFile, Scope};
+use tracing::{error, info, warn};
+
+use super::auth::DriveHub;
+use super::prune::delete_sidecar_of;
+use super::retry::RetryAfter;
+use super::shared::SharedDrive;
+use crate::backup::checksum::is_sidecar_name;
+use crate::util::format::humanize_bytes;
+
+/// Backups in one folder sharing an `md5Checksum`. The newest is kept.
+#[derive(Debug)]
+pub struct DuplicateGroup {
+    pub md5: String,
+    pub keep: DriveFile,
+    /// Newest first, like the listing they came from.
+    pub duplicates: Vec<DriveFile>,
+}
+
+impl DuplicateGroup {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.duplicates
+            .iter()
+            .filter_map(|f| f.size.and_then(|s| u64::try_from(s).ok()))
+            .sum()
+    }
+}
+
+/// Group the backups named with `prefix` by checksum. `files` must be newest
+/// first, as [`super::prune::list_all_files_in_folder`] returns them.
+/// Checksum sidecars and files Drive reports no md5 for are left out.
+pub fn find_duplicates(files: &[DriveFile], prefix: &str) -> Vec<DuplicateGroup> {
+    let mut order: Vec<String> = Vec::new();
+    let mut by_md5: HashMap<String, Vec<DriveFile>> = HashMap::new();
+
+    for file in files {
+        let (Some(name), Some(md5)) = (&file.name, &file.md5_checksum) else {
+            continue;
+        };
+        if !name.starts_with(prefix) || is_sidecar_name(name) {
+            continue;
+        }
+        let group = by_md5.entry(md5.clone()).or_default();
+        if group.is_empty() {
+            order.push(md5.clone());
+        }
+        group.push(file.clone());
+    }
+
+    order
+        .into_iter()
+        .filter_map(|md5| {
+            let mut files = by_md5.remove(&md5)?;
+            if files.len() < 2 {
+                return None;
+            }
+            let keep = files.remove(0);
+            Some(DuplicateGroup {
+                md5,
+                keep,
+                duplicates: files,
+            })
+        })
+        .collect()
+}
+
+/// Delete every duplicate in `groups` together with its checksum sidecar,
+/// skipping pinned ids. Returns the number of backups deleted.
+pub async fn delete_duplicates(
+    hub: &DriveHub,
+    groups: &[DuplicateGroup],
+    files: &[DriveFile],
+    pinned_ids: &[String],
+) -> u32 {
+    let sidecars: Vec<DriveFile> = files
+        .iter()
+        .filter(|f| f.name.as_deref().is_some_and(is_sidecar_name))
+        .cloned()
+        .collect();
+    let mut deleted: u32 = 0;
+
+    for file in groups.iter().flat_map(|g| &g.duplicates) {
+        let Some(file_id) = file.id.as_deref() else {
+            warn!("Skipping duplicate with no ID");
+            continue;
+        };
+        let file_name = file.name.as_deref().unwrap_or("unknown");
+
+        if pinned_ids.iter().any(|pinned| pinned == file_id) {
+            info!(
+                file_name = file_name,
+                file_id = file_id,
+                "Preserving pinned duplicate"
+            );
+            continue;
+        }
+
+        match hub
+            .files()
+            .delete(file_id)
+            .shared_drive()
+            .add_scope(Scope::Full)
+            .delegate(&mut RetryAfter::default())
+            .doit()
+            .await
+        {
+            Ok(_) => {
+                info!(
+                    file_name = file_name,
+                    file_id = file_id,
+                    "Deleted duplicate backup"
+                );
+                deleted += 1;
+                delete_sidecar_of(hub, &sidecars, file_name).await;
+            }
+            Err(e) => {
+                error!(
+                    error = %e,
+                    file_name = file_name,
+                    file_id = file_id,
+                    "Failed to delete duplicate backup"
+                );
+            }
+        }
+    }
+
+    deleted
+}
+
+pub fn print(groups: &[DuplicateGroup]) {
+    if groups.is_empty() {
+        println!("No duplicate backups found");
+        return;
+    }
+
+    for group in groups {
+        println!(
+            "{}  keep {}",
+            group.md5,
+            group.keep.name.as_deref().unwrap_or("unknown")
+        );
+        for dup in &group.duplicates {
+            println!(
+                "{:32}  dup  {} ({})",
+                "",
+                dup.name.as_deref().unwrap_or("unknown"),
+                dup.id.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+
+    let count: usize = groups.iter().map(|g| g.duplicates.len()).sum();
+    let wasted: u64 = groups.iter().map(DuplicateGroup::wasted_bytes).sum();
+    println!(
+        "{} duplicate(s) in {} group(s), {} reclaimable",
+        count,
+        groups.len(),
+        humanize_bytes(wasted as f64)
+    );
+}
@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::bail;
+use google_drive3::api::{File as DriveFile, Scope};
+use http_body_util::BodyExt;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+use super::auth::DriveHub;
+use super::retry::RetryAfter;
+use super::shared::SharedDrive;
+use crate::backup::checksum::sha256_file;
+
+/// Download a Drive file's content to `dest`, streaming the response body to
+/// disk chunk by chunk. A partially written file is removed on failure.
+pub async fn download_file(hub: &DriveHub, file_id: &str, dest: &Path) -> anyhow::Result<PathBuf> {
+    info!(
+        file_id = file_id,
+        dest = %dest.display(),
+        "Starting download from Google Drive"
+    );
+
+    let response = match hub
+        .files()
+        .get(file_id)
+        .shared_drive()
+        .param("alt", "media")
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        Ok((response, _)) => response,
+        Err(e) => {
+            error!(error = %e, file_id = file_id, "Failed to start download");
+            bail!("Failed to download Drive file {}: {}", file_id, e);
+        }
+    };
+
+    let result = write_body(response.into_body(), dest).await;
+    match result {
+        Ok(bytes) => {
+            info!(
+                file_id = file_id,
+                dest = %dest.display(),
+                size_bytes = bytes,
+                "Download completed"
+            );
+            Ok(dest.to_path_buf())
+        }
+        Err(e) => {
+            error!(error = %e, file_id = file_id, "Download failed");
+            if let Err(rm) = tokio::fs::remove_file(dest).await
+                && rm.kind() != std::io::ErrorKind::NotFound
+            {
+                error!(error = %rm, path = %dest.display(), "Failed to remove partial download");
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn write_body(mut body: google_drive3::common::Body, dest: &Path) -> anyhow::Result<u64> {
+    let mut file = match tokio::fs::File::create(dest).await {
+        Ok(f) => f,
+        Err(e) => bail!("Failed to create {}: {}", dest.display(), e),
+    };
+
+    let mut written: u64 = 0;
+    while let Some(frame) = body.frame().await {
+        let frame = match frame {
+            Ok(f) => f,
+            Err(e) => bail!("Download stream failed after {} bytes: {}", written, e),
+        };
+        if let Ok(chunk) = frame.into_data() {
+            if let Err(e) = file.write_all(&chunk).await {
+                bail!("Failed to write {}: {}", dest.display(), e);
+            }
+            written += chunk.len() as u64;
+            crate::systemd::progress();
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        bail!("Failed to flush {}: {}", dest.display(), e);
+    }
+
+    Ok(written)
+}
+
+/// Where a Drive file called `name` is downloaded to in `dest_dir`. Names
+/// come from Drive, so anything but a plain file name (a path separator,
+/// `..`) is refused rather than joined.
+pub fn local_path(dest_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name) {
+        bail!("Refusing to download '{}': not a plain file name", name);
+    }
+    Ok(dest_dir.join(name))
+}
+
+/// Download a backup listed in a Drive folder into `dest_dir` under its own
+/// name and check it: its size must match Drive's and, when `sidecar` (its
+/// `.sha256` file) is given, so must its SHA-256. The sidecar is kept next to
+/// it. A copy already in `dest_dir` that passes the same checks is kept
+/// instead of downloaded again. Returns the path and size.
+pub async fn download_verified(
+    hub: &DriveHub,
+    file: &DriveFile,
+    sidecar: Option<&DriveFile>,
+    dest_dir: &Path,
+) -> anyhow::Result<(PathBuf, u64)> {
+    let (Some(file_id), Some(name)) = (file.id.as_deref(), file.name.as_deref()) else {
+        bail!("Drive file has no id or name");
+    };
+    let path = local_path(dest_dir, name)?;
+    let expected_size = file.size.and_then(|s| u64::try_from(s).ok());
+
+    let expected_sha256 = match sidecar.and_then(|s| s.id.as_deref()) {
+        Some(sidecar_id) => {
+            let sidecar_path = dest_dir.join(format!(
+                "{}.{}",
+                name,
+                crate::backup::checksum::SIDECAR_EXTENSION
+            ));
+            download_file(hub, sidecar_id, &sidecar_path).await?;
+            let contents = match tokio::fs::read_to_string(&sidecar_path).await {
+                Ok(c) => c,
+                Err(e) => bail!("Failed to read {}: {}", sidecar_path.display(), e),
+            };
+            // `<hex>  <name>`, as written by `sha256sum`
+            match contents.split_whitespace().next() {
+                Some(hex) if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    Some(hex.to_ascii_lowercase())
+                }
+                _ => bail!("Checksum sidecar of '{}' holds no sha256 digest", name),
+            }
+        }
+        None => None,
+    };
+
+    if path.is_file()
+        && let Ok(size) = check_download(&path, expected_size, expected_sha256.as_deref()).await
+    {
+        info!(path = %path.display(), "Backup already downloaded and verified, skipping");
+        return Ok((path, size));
+    }
+
+    download_file(hub, file_id, &path).await?;
+    match check_download(&path, expected_size, expected_sha256.as_deref()).await {
+        Ok(size) => Ok((path, size)),
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Downloaded backup failed verification");
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(e)
+        }
+    }
+}
+
+/// Size of `path`, after checking it against the expected size and digest.
+async fn check_download(
+    path: &Path,
+    expected_size: Option<u64>,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<u64> {
+    let size = match tokio::fs::metadata(path).await {
+        Ok(m) => m.len(),
+        Err(e) => bail!("Failed to stat {}: {}", path.display(), e),
+    };
+    if let Some(expected) = expected_size
+        && size != expected
+    {
+        bail!(
+            "{} is {} bytes but Drive reports {}",
+            path.display(),
+            size,
+            expected
+        );
+    }
+    if let Some(expected) = expected_sha256 {
+        let digest = sha256_file(path).await?;
+        if digest != expected {
+            bail!(
+                "{} has sha256 {} but its sidecar says {}",
+                path.display(),
+                digest,
+                expected
+            );
+        }
+    }
+    Ok(size)
+}
+
+const RANGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Part of a file to fetch with a ranged download.
+#[derive(Debug, Clone, Copy)]
+pub enum ByteRange {
+    /// The first `n` bytes.
+    Head(u64),
+    /// The last `n` bytes.
+    Tail(u64),
+}
+
+impl ByteRange {
+    fn header_value(&self) -> String {
+        match self {
+            ByteRange::Head(n) => format!("bytes=0-{}", n.saturating_sub(1)),
+            ByteRange::Tail(n) => format!("bytes=-{}", n),
+        }
+    }
+}
+
+/// Bytes returned by [`download_range`].
+#[derive(Debug)]
+pub struct RangedBytes {
+    pub bytes: Vec<u8>,
+    /// Full size of the file as reported by `Content-Range`.
+    pub total_size: Option<u64>,
+}
+
+/// Fetch only `range` of a Drive file. The generated client cannot send a
+/// `Range` header, so this issues the media request directly with the hub's
+/// access token. Fails if Drive answers with the whole file instead.
+pub async fn download_range(
+    hub: &DriveHub,
+    file_id: &str,
+    range: ByteRange,
+) -> anyhow::Result<RangedBytes> {
+    let token = match hub.auth.get_token(&[Scope::Full.as_ref()]).await {
+        Ok(Some(t)) => t,
+        Ok(None) => bail!("No access token available for a ranged download"),
+        Err(e) => bail!("Failed to obtain access token: {}", e),
+    };
+
+    let client = match reqwest::Client::builder().timeout(RANGE_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(e) => bail!("Failed to build HTTP client: {}", e),
+    };
+    let url = format!(
+        "https://www.googleapis.com/drive/v3/files/{}?alt=media&supportsAllDrives=true",
+        file_id
+    );
+    let response = match client
+        .get(&url)
+        .bearer_auth(token)
+        .header(reqwest::header::RANGE, range.header_value())
+        .send()
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            error!(error = %e, file_id = file_id, "Ranged download request failed");
+            bail!("Ranged download of {} failed: {}", file_id, e);
+        }
+    };
+
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        bail!(
+            "Ranged download of {} returned {} instead of 206 Partial Content",
+            file_id,
+            response.status()
+        );
+    }
+
+    // Content-Range: bytes <start>-<end>/<total>
+    let total_size = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit_once('/'))
+        .and_then(|(_, total)| total.parse::<u64>().ok());
+
+    let bytes = match response.bytes().await {
+        Ok(b) => b.to_vec(),
+        Err(e) => bail!("Ranged download of {} failed mid-body: {}", file_id, e),
+    };
+
+    Ok(RangedBytes { bytes, total_size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_file_names_are_downloaded() {
+        let dest = Path::new("/var/tmp/backups");
+        assert_eq!(
+            local_path(dest, "world.tar.zst.part001").unwrap(),
+            dest.join("world.tar.zst.part001")
+        );
+        for name in ["", "..", "../etc/passwd", "/etc/passwd", "a/b", "part/"] {
+            assert!(local_path(dest, name).is_err(), "{name}");
+        }
+    }
+}
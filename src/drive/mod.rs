@@ -1,3 +1,7 @@
 pub mod auth;
+pub mod dedup;
+pub mod download;
 pub mod prune;
+pub mod retry;
+pub mod shared;
 pub mod upload;
@@ -1,12 +1,24 @@
 use anyhow::bail;
+use chrono::{DateTime, Utc};
 use google_drive3::api::{File as DriveFile, Scope};
+use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use super::auth::DriveHub;
+use super::retry::RetryAfter;
+use super::shared::SharedDrive;
+use super::upload::SIZE_PROPERTY;
+use crate::backup::BackupKind;
+use crate::backup::chain::restore_chains;
+use crate::backup::checksum::{SIDECAR_EXTENSION, is_sidecar_name};
+use crate::config::config::Config;
+use crate::storage::StoredObject;
 
 /// List all non-folder files in a Drive folder, handling pagination.
-/// Returns files sorted by createdTime descending (newest first).
-async fn list_all_files_in_folder(
+/// Returns files sorted by createdTime descending (newest first). createdTime
+/// is set by Drive, so a local clock step cannot reorder it; ties fall back
+/// to the name, whose timestamp only ever increases.
+pub async fn list_all_files_in_folder(
     hub: &DriveHub,
     folder_id: &str,
 ) -> anyhow::Result<Vec<DriveFile>> {
@@ -22,10 +34,14 @@ async fn list_all_files_in_folder(
         let mut request = hub
             .files()
             .list()
+            .shared_drive()
             .q(&query)
             .spaces("drive")
-            .order_by("createdTime desc")
-            .param("fields", "nextPageToken, files(id, name, createdTime)")
+            .order_by("createdTime desc,name desc")
+            .param(
+                "fields",
+                "nextPageToken, files(id, name, size, createdTime, md5Checksum, appProperties)",
+            )
             .page_size(1000)
             .add_scope(Scope::Full);
 
@@ -33,7 +49,8 @@ async fn list_all_files_in_folder(
             request = request.page_token(token);
         }
 
-        let (_, file_list) = match request.doit().await {
+        let mut throttle = RetryAfter::default();
+        let (_, file_list) = match request.delegate(&mut throttle).doit().await {
             Ok(r) => r,
             Err(e) => {
                 error!(error = %e, folder_id = folder_id, "Failed to list files for pruning");
@@ -56,76 +73,581 @@ async fn list_all_files_in_folder(
     Ok(all_files)
 }
 
-/// Delete all but the `keep` newest files in the given Google Drive folder.
-/// Returns the number of files deleted.
+/// The local size recorded at upload and the size Drive reports, when they
+/// differ: the upload was cut short or the stored copy is damaged. Files
+/// uploaded before sizes were recorded are never suspect.
+pub fn size_mismatch(file: &DriveFile) -> Option<(u64, u64)> {
+    let recorded = file
+        .app_properties
+        .as_ref()?
+        .get(SIZE_PROPERTY)?
+        .parse::<u64>()
+        .ok()?;
+    let stored = u64::try_from(file.size?).ok()?;
+    (recorded != stored).then_some((recorded, stored))
+}
+
+/// Retention rules applied by [`prune_old_backups`].
+pub struct PrunePolicy<'a> {
+    /// Only files whose name starts with this prefix are counted or deleted.
+    pub name_prefix: String,
+    /// Number of newest full backups to keep, each with the differential and
+    /// incremental archives built on it.
+    pub keep: usize,
+    /// Drive file ids that are never deleted, regardless of age or count.
+    pub pinned_ids: &'a [String],
+    /// Files younger than this are kept even beyond `keep` (`PRUNE_MIN_AGE_SECS`).
+    pub min_age: Option<std::time::Duration>,
+}
+
+impl PrunePolicy<'_> {
+    /// Whether a file created at `created` is still inside the grace window.
+    /// A file of unknown age is treated as new.
+    pub fn within_grace(&self, created: Option<DateTime<Utc>>) -> bool {
+        let Some(min_age) = self.min_age else {
+            return false;
+        };
+        let Some(created) = created else {
+            return true;
+        };
+        let min_age = chrono::Duration::from_std(min_age).unwrap_or(chrono::Duration::MAX);
+        Utc::now().signed_duration_since(created) < min_age
+    }
+
+    /// Choose which of `candidates` (newest first) to delete, each with the
+    /// reason. Retention counts restore chains: a full backup plus the
+    /// differentials and incrementals built on it. The `keep` newest chains
+    /// survive and older ones are dropped whole, so a kept archive never
+    /// loses its base. A chain with a pinned member, or one inside the grace
+    /// window, is kept entirely.
+    pub fn select(&self, candidates: &[RetentionCandidate<'_>]) -> Vec<(usize, String)> {
+        let prefix = self.name_prefix.as_str();
+        let chains = restore_chains(candidates.iter().map(|c| c.name), prefix);
+        let total_full = chains.iter().filter(|c| c.full.is_some()).count();
+
+        let mut selected = Vec::new();
+        let mut fulls_seen = 0;
+        for chain in &chains {
+            let beyond = match chain.full {
+                Some(_) => {
+                    fulls_seen += 1;
+                    fulls_seen > self.keep
+                }
+                None => fulls_seen >= self.keep,
+            };
+            if !beyond {
+                continue;
+            }
+
+            let mut members = chain.members.iter().map(|&i| &candidates[i]);
+            if let Some(pinned) = members
+                .clone()
+                .find(|c| self.pinned_ids.iter().any(|id| id == c.id))
+            {
+                info!(
+                    file_name = pinned.name,
+                    file_id = pinned.id,
+                    chain_len = chain.members.len(),
+                    "Preserving pinned backup and the chain it belongs to"
+                );
+                continue;
+            }
+            if let Some(young) = members.find(|c| self.within_grace(c.created)) {
+                info!(
+                    file_name = young.name,
+                    file_id = young.id,
+                    chain_len = chain.members.len(),
+                    min_age = ?self.min_age,
+                    "Keeping backup chain beyond retention count; younger than PRUNE_MIN_AGE_SECS"
+                );
+                continue;
+            }
+
+            for &index in &chain.members {
+                let reason = match chain.full {
+                    Some(full) if full == index => format!(
+                        "full backup number {} of {} {}* full backups, newest first; retention keeps {}",
+                        fulls_seen, total_full, prefix, self.keep
+                    ),
+                    Some(full) => format!(
+                        "built on full backup {}, which retention drops",
+                        candidates[full].name
+                    ),
+                    None => format!(
+                        "older than every full {}* backup, so it cannot be restored",
+                        prefix
+                    ),
+                };
+                selected.push((index, reason));
+            }
+        }
+        selected
+    }
+}
+
+/// The configured retention for `kind`, or `None` if that type is never pruned.
+pub fn retention_policy<'a>(
+    config: &Config,
+    kind: BackupKind,
+    pinned_ids: &'a [String],
+) -> Option<PrunePolicy<'a>> {
+    let keep = match kind {
+        BackupKind::Db => config.db_retention_count?,
+        BackupKind::Minecraft => config.mc_retention_count,
+    };
+    Some(PrunePolicy {
+        name_prefix: kind.artifact_prefix(config.host_tag.as_deref()),
+        keep,
+        pinned_ids,
+        min_age: config.prune_min_age,
+    })
+}
+
+/// A stored backup as [`PrunePolicy::select`] sees it.
+pub struct RetentionCandidate<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// One file a prune would delete, as written to and read back from a plan
+/// file by `prune --emit-plan` and `prune --apply-plan`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedDeletion {
+    pub folder_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub size_bytes: Option<u64>,
+    /// Why retention selected this file.
+    pub reason: String,
+    /// Checksum sidecar deleted along with the file.
+    pub sidecar_id: Option<String>,
+}
+
+/// A reviewed list of deletions, applied as-is by `prune --apply-plan`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrunePlan {
+    pub created_at: DateTime<Utc>,
+    pub deletions: Vec<PlannedDeletion>,
+}
+
+/// Delete all but the `keep` newest backup chains whose names start with the
+/// policy's prefix in the given Google Drive folder (see
+/// [`PrunePolicy::select`]). Files of other types sharing the folder are never
+/// counted or deleted, and pinned ids are always preserved. Files whose size differs from the one recorded at upload are reported and
+/// left alone, but don't count towards `keep`, so a truncated upload never
+/// pushes out a good older backup. Returns the number of files deleted.
 pub async fn prune_old_backups(
     hub: &DriveHub,
     folder_id: &str,
-    keep: usize,
+    policy: &PrunePolicy<'_>,
 ) -> anyhow::Result<u32> {
-    let files = list_all_files_in_folder(hub, folder_id).await?;
+    let planned = plan_prune(hub, folder_id, policy).await?;
+    let mut deleted_count: u32 = 0;
+    for deletion in &planned {
+        if delete_planned(hub, deletion).await {
+            deleted_count += 1;
+        }
+    }
 
-    let total = files.len();
-    if total <= keep {
+    if !planned.is_empty() {
         info!(
             folder_id = folder_id,
-            total_files = total,
-            keep = keep,
-            "No files to prune"
+            name_prefix = %policy.name_prefix,
+            deleted = deleted_count,
+            kept = policy.keep,
+            "Pruning completed"
         );
-        return Ok(0);
     }
+    Ok(deleted_count)
+}
 
-    let to_delete = &files[keep..];
-    let mut deleted_count: u32 = 0;
+/// The files [`prune_old_backups`] would delete from `folder_id`, without
+/// deleting anything.
+pub async fn plan_prune(
+    hub: &DriveHub,
+    folder_id: &str,
+    policy: &PrunePolicy<'_>,
+) -> anyhow::Result<Vec<PlannedDeletion>> {
+    let name_prefix = policy.name_prefix.as_str();
+    let keep = policy.keep;
 
-    for file in to_delete {
-        let file_id = match &file.id {
-            Some(id) => id,
-            None => {
+    // Checksum sidecars share their data file's prefix but are neither counted
+    // nor selected on their own; they are removed together with their data file.
+    let (sidecars, files): (Vec<DriveFile>, Vec<DriveFile>) =
+        list_all_files_in_folder(hub, folder_id)
+            .await?
+            .into_iter()
+            .filter(|f| match &f.name {
+                Some(name) => name.starts_with(name_prefix),
+                None => false,
+            })
+            .partition(|f| match &f.name {
+                Some(name) => is_sidecar_name(name),
+                None => false,
+            });
+    let (suspect, files): (Vec<DriveFile>, Vec<DriveFile>) =
+        files.into_iter().partition(|f| size_mismatch(f).is_some());
+    for file in &suspect {
+        if let Some((recorded, stored)) = size_mismatch(file) {
+            warn!(
+                file_name = file.name.as_deref().unwrap_or("unknown"),
+                file_id = file.id.as_deref().unwrap_or("unknown"),
+                recorded_bytes = recorded,
+                stored_bytes = stored,
+                "Suspect backup: Drive size differs from the uploaded size; not counted as kept"
+            );
+        }
+    }
+
+    let files: Vec<DriveFile> = files
+        .into_iter()
+        .filter(|f| {
+            if f.id.is_none() {
                 warn!("Skipping file with no ID during pruning");
-                continue;
             }
-        };
-        let file_name = match &file.name {
-            Some(name) => name.as_str(),
-            None => "unknown",
-        };
+            f.id.is_some()
+        })
+        .collect();
+    let candidates: Vec<RetentionCandidate<'_>> = files
+        .iter()
+        .map(|f| RetentionCandidate {
+            id: f.id.as_deref().unwrap_or_default(),
+            name: f.name.as_deref().unwrap_or("unknown"),
+            created: f.created_time,
+        })
+        .collect();
 
+    let selected = policy.select(&candidates);
+    if selected.is_empty() {
         info!(
-            file_name = file_name,
-            file_id = %file_id,
-            "Deleting old backup"
+            folder_id = folder_id,
+            name_prefix = name_prefix,
+            total_files = files.len(),
+            keep = keep,
+            "No files to prune"
         );
+        return Ok(Vec::new());
+    }
 
-        match hub
+    let planned = selected
+        .into_iter()
+        .map(|(index, reason)| {
+            let file = &files[index];
+            let candidate = &candidates[index];
+            let sidecar_name = format!("{}.{}", candidate.name, SIDECAR_EXTENSION);
+            PlannedDeletion {
+                folder_id: folder_id.to_string(),
+                file_id: candidate.id.to_string(),
+                file_name: candidate.name.to_string(),
+                created_at: file.created_time,
+                size_bytes: file.size.and_then(|s| u64::try_from(s).ok()),
+                reason,
+                sidecar_id: sidecars
+                    .iter()
+                    .find(|f| f.name.as_deref() == Some(sidecar_name.as_str()))
+                    .and_then(|f| f.id.clone()),
+            }
+        })
+        .collect();
+    Ok(planned)
+}
+
+/// Indices into `objects`, as [`crate::storage::Storage::list`] returns them,
+/// of the backups `policy` would delete, each with the reason. Suspect
+/// objects are neither counted nor selected, as in [`plan_prune`].
+pub fn select_stored(policy: &PrunePolicy<'_>, objects: &[StoredObject]) -> Vec<(usize, String)> {
+    let counted: Vec<usize> = (0..objects.len())
+        .filter(|&i| !objects[i].suspect)
+        .collect();
+    let candidates: Vec<RetentionCandidate<'_>> = counted
+        .iter()
+        .map(|&i| RetentionCandidate {
+            id: &objects[i].id,
+            name: &objects[i].name,
+            created: objects[i].created_at,
+        })
+        .collect();
+    policy
+        .select(&candidates)
+        .into_iter()
+        .map(|(index, reason)| (counted[index], reason))
+        .collect()
+}
+
+/// What [`apply_plan`] did with each planned deletion.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlanOutcome {
+    pub deleted: u32,
+    /// Gone, trashed or renamed since the plan was made.
+    pub skipped: u32,
+    /// Drive refused the delete, or could not say whether the file is still
+    /// there.
+    pub failed: u32,
+}
+
+/// Delete each file in `plan` that still exists, untrashed, under its planned
+/// name, and skip the rest.
+pub async fn apply_plan(hub: &DriveHub, plan: &PrunePlan) -> PlanOutcome {
+    let mut outcome = PlanOutcome::default();
+    for deletion in &plan.deletions {
+        let current = match hub
             .files()
-            .delete(file_id)
+            .get(&deletion.file_id)
+            .shared_drive()
+            .param("fields", "id, name, trashed")
             .add_scope(Scope::Full)
+            .delegate(&mut RetryAfter::default())
             .doit()
             .await
         {
-            Ok(_) => {
-                deleted_count += 1;
+            Ok((_, file)) => file,
+            Err(e) if super::upload::is_not_found(&e) => {
+                info!(
+                    file_name = %deletion.file_name,
+                    file_id = %deletion.file_id,
+                    "Planned file no longer exists; skipping it"
+                );
+                outcome.skipped += 1;
+                continue;
             }
             Err(e) => {
                 error!(
                     error = %e,
-                    file_name = file_name,
-                    file_id = %file_id,
-                    "Failed to delete file during pruning"
+                    file_name = %deletion.file_name,
+                    file_id = %deletion.file_id,
+                    "Failed to look up planned file"
                 );
+                outcome.failed += 1;
+                continue;
             }
+        };
+        if current.trashed == Some(true) {
+            info!(
+                file_name = %deletion.file_name,
+                file_id = %deletion.file_id,
+                "Planned file is already in the trash; skipping it"
+            );
+            outcome.skipped += 1;
+            continue;
+        }
+        if current.name.as_deref() != Some(deletion.file_name.as_str()) {
+            warn!(
+                file_id = %deletion.file_id,
+                planned_name = %deletion.file_name,
+                current_name = current.name.as_deref().unwrap_or("unknown"),
+                "Planned file was renamed since the plan was made; skipping it"
+            );
+            outcome.skipped += 1;
+            continue;
+        }
+
+        if delete_planned(hub, deletion).await {
+            outcome.deleted += 1;
+        } else {
+            outcome.failed += 1;
         }
     }
+    outcome
+}
 
+/// Delete one planned file and then its sidecar. Failures are logged;
+/// returns whether the file itself was deleted.
+async fn delete_planned(hub: &DriveHub, deletion: &PlannedDeletion) -> bool {
     info!(
-        folder_id = folder_id,
-        deleted = deleted_count,
-        kept = keep,
-        total_before = total,
-        "Pruning completed"
+        file_name = %deletion.file_name,
+        file_id = %deletion.file_id,
+        reason = %deletion.reason,
+        "Deleting old backup"
     );
 
-    Ok(deleted_count)
+    if let Err(e) = hub
+        .files()
+        .delete(&deletion.file_id)
+        .shared_drive()
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        error!(
+            error = %e,
+            file_name = %deletion.file_name,
+            file_id = %deletion.file_id,
+            "Failed to delete file during pruning"
+        );
+        return false;
+    }
+
+    if let Some(sidecar_id) = &deletion.sidecar_id
+        && sidecar_matches(hub, deletion, sidecar_id).await
+    {
+        match hub
+            .files()
+            .delete(sidecar_id)
+            .shared_drive()
+            .add_scope(Scope::Full)
+            .delegate(&mut RetryAfter::default())
+            .doit()
+            .await
+        {
+            Ok(_) => info!(
+                file_id = %sidecar_id,
+                "Deleted checksum sidecar of pruned backup"
+            ),
+            Err(e) => error!(
+                error = %e,
+                file_id = %sidecar_id,
+                "Failed to delete checksum sidecar during pruning"
+            ),
+        }
+    }
+    true
+}
+
+/// Whether `sidecar_id` is still the untrashed sidecar of the planned file.
+/// A plan can be applied long after it was made, by which time the id may
+/// name something else.
+async fn sidecar_matches(hub: &DriveHub, deletion: &PlannedDeletion, sidecar_id: &str) -> bool {
+    let expected = format!("{}.{}", deletion.file_name, SIDECAR_EXTENSION);
+    match hub
+        .files()
+        .get(sidecar_id)
+        .shared_drive()
+        .param("fields", "id, name, trashed")
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        Ok((_, file)) if file.trashed != Some(true) && file.name.as_deref() == Some(&expected) => {
+            true
+        }
+        Ok((_, file)) => {
+            warn!(
+                file_id = %sidecar_id,
+                expected_name = %expected,
+                current_name = file.name.as_deref().unwrap_or("unknown"),
+                trashed = file.trashed.unwrap_or(false),
+                "Planned checksum sidecar changed since the plan was made; leaving it"
+            );
+            false
+        }
+        Err(e) if super::upload::is_not_found(&e) => false,
+        Err(e) => {
+            error!(error = %e, file_id = %sidecar_id, "Failed to look up planned checksum sidecar");
+            false
+        }
+    }
+}
+
+/// Delete the checksum sidecar belonging to `data_file_name`, if one exists.
+pub(super) async fn delete_sidecar_of(
+    hub: &DriveHub,
+    sidecars: &[DriveFile],
+    data_file_name: &str,
+) {
+    let sidecar_name = format!("{}.{}", data_file_name, SIDECAR_EXTENSION);
+    let Some(sidecar_id) = sidecars
+        .iter()
+        .find(|f| f.name.as_deref() == Some(sidecar_name.as_str()))
+        .and_then(|f| f.id.as_deref())
+    else {
+        return;
+    };
+
+    match hub
+        .files()
+        .delete(sidecar_id)
+        .shared_drive()
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        Ok(_) => {
+            info!(
+                file_name = %sidecar_name,
+                file_id = sidecar_id,
+                "Deleted checksum sidecar of pruned backup"
+            );
+        }
+        Err(e) => {
+            error!(
+                error = %e,
+                file_name = %sidecar_name,
+                file_id = sidecar_id,
+                "Failed to delete checksum sidecar during pruning"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(keep: usize, pinned_ids: &[String]) -> PrunePolicy<'_> {
+        PrunePolicy {
+            name_prefix: "minecraft_".to_string(),
+            keep,
+            pinned_ids,
+            min_age: None,
+        }
+    }
+
+    fn candidates<'a>(names: &[&'a str]) -> Vec<RetentionCandidate<'a>> {
+        names
+            .iter()
+            .map(|&name| RetentionCandidate {
+                id: name,
+                name,
+                created: None,
+            })
+            .collect()
+    }
+
+    fn selected_names<'a>(policy: &PrunePolicy<'_>, names: &[&'a str]) -> Vec<&'a str> {
+        policy
+            .select(&candidates(names))
+            .into_iter()
+            .map(|(index, _)| names[index])
+            .collect()
+    }
+
+    const LISTING: [&str; 6] = [
+        "minecraft_diff_5.tar.zst",
+        "minecraft_diff_4.tar.zst",
+        "minecraft_3.tar.zst",
+        "minecraft_incr_2.tar.zst",
+        "minecraft_1.tar.zst",
+        "minecraft_diff_0.tar.zst",
+    ];
+
+    #[test]
+    fn keeps_differentials_with_their_full() {
+        assert_eq!(
+            selected_names(&policy(1, &[]), &LISTING),
+            [
+                "minecraft_incr_2.tar.zst",
+                "minecraft_1.tar.zst",
+                "minecraft_diff_0.tar.zst"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_everything_within_the_full_count() {
+        assert!(selected_names(&policy(2, &[]), &LISTING[..5]).is_empty());
+    }
+
+    #[test]
+    fn pinned_member_keeps_its_whole_chain() {
+        let pinned = ["minecraft_incr_2.tar.zst".to_string()];
+        assert_eq!(
+            selected_names(&policy(1, &pinned), &LISTING),
+            ["minecraft_diff_0.tar.zst"]
+        );
+    }
 }
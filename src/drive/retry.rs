@@ -0,0 +1,132 @@
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use google_drive3::common::{Delegate, Response, Retry};
+use google_drive3::hyper::header::{HeaderMap, RETRY_AFTER};
+use tracing::warn;
+
+/// Longest `Retry-After` worth waiting for; beyond this the call fails
+/// instead of stalling the run.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// How often and how patiently Drive calls are retried
+/// (`DRIVE_MAX_RETRIES`, `DRIVE_BASE_DELAY_MS`).
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries of one Drive call before its error is returned.
+    pub max_retries: u32,
+    /// First backoff delay when Drive does not send `Retry-After`; doubled
+    /// per retry, plus up to half again as jitter.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Set the policy every [`RetryAfter`] created from now on uses. Only the
+/// first call takes effect.
+pub fn configure(policy: RetryPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+/// Delegate for Drive calls that retries `429 Too Many Requests`,
+/// `503 Service Unavailable` and connection errors, waiting exactly as long
+/// as `Retry-After` asks and backing off exponentially with jitter when it
+/// is absent. Attach a fresh one to each call with `.delegate(&mut throttle)`.
+#[derive(Debug)]
+pub struct RetryAfter {
+    policy: RetryPolicy,
+    retries: u32,
+}
+
+impl Default for RetryAfter {
+    fn default() -> Self {
+        Self {
+            policy: POLICY.get().copied().unwrap_or_default(),
+            retries: 0,
+        }
+    }
+}
+
+impl RetryAfter {
+    /// Exponential delay for the next retry with up to 50% jitter, so
+    /// parallel uploads that failed together don't retry in lockstep.
+    fn backoff(&self) -> Duration {
+        let delay = self
+            .policy
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(self.retries));
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        delay.saturating_add(delay.mul_f64(f64::from(nanos % 1000) / 2000.0))
+    }
+}
+
+impl Delegate for RetryAfter {
+    fn http_error(&mut self, err: &hyper_util::client::legacy::Error) -> Retry {
+        if self.retries >= self.policy.max_retries {
+            return Retry::Abort;
+        }
+        let delay = self.backoff();
+        self.retries += 1;
+        warn!(
+            error = %err,
+            delay = ?delay,
+            retry = self.retries,
+            max_retries = self.policy.max_retries,
+            "Drive request failed to connect, retrying"
+        );
+        Retry::After(delay)
+    }
+
+    fn http_failure(&mut self, response: &Response, _err: Option<&serde_json::Value>) -> Retry {
+        let status = response.status().as_u16();
+        if !matches!(status, 429 | 503) || self.retries >= self.policy.max_retries {
+            return Retry::Abort;
+        }
+
+        let delay = match retry_after(response.headers()) {
+            Some(delay) if delay > MAX_RETRY_AFTER => {
+                warn!(
+                    status = status,
+                    retry_after = ?delay,
+                    "Drive asked to retry later than we are willing to wait, giving up"
+                );
+                return Retry::Abort;
+            }
+            Some(delay) => delay,
+            None => self.backoff(),
+        };
+        self.retries += 1;
+        warn!(
+            status = status,
+            delay = ?delay,
+            retry = self.retries,
+            max_retries = self.policy.max_retries,
+            "Drive throttled the request, retrying"
+        );
+        Retry::After(delay)
+    }
+}
+
+/// `Retry-After` as either delay-seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    // IMF-fixdate (`Wed, 21 Oct 2015 07:28:00 GMT`) is valid RFC 2822
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
@@ -1,17 +1,140 @@
-use std::io::BufReader;
+use std::collections::HashMap;
+use std::io::{BufReader, IsTerminal};
 use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Instant;
 
 use anyhow::bail;
-use google_drive3::api::{File as DriveFile, Scope};
-use tracing::{error, info};
+use google_drive3::api::{File as DriveFile, Permission, Scope};
+use google_drive3::common::{ContentRange, Delegate, Response, Retry};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tracing::{debug, error, info, warn};
 
 use super::auth::DriveHub;
+use super::retry::RetryAfter;
+use super::shared::SharedDrive;
+use crate::backup::checksum::md5_file_blocking;
+use crate::metrics::UploadStream;
+
+/// `appProperties` key holding the local size of every uploaded file, so a
+/// truncated copy can be told apart later.
+pub const SIZE_PROPERTY: &str = "size_bytes";
+
+/// Terminal progress bars of the uploads in flight; concurrent fan-out
+/// uploads each get their own line.
+static PROGRESS_BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// What Drive reports back for a completed upload.
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    pub id: Option<String>,
+    pub web_view_link: Option<String>,
+}
+
+/// Extra metadata set on folders this tool creates. Existing folders are
+/// left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct FolderMetadata {
+    pub description: Option<String>,
+    pub app_properties: HashMap<String, String>,
+}
+
+/// Who `DRIVE_GRANT_READER` gives read access to uploaded backups.
+#[derive(Debug, Clone)]
+pub enum ReaderGrant {
+    /// Anyone holding the link.
+    AnyoneWithLink,
+    User(String),
+}
+
+/// Returned inside `anyhow::Error` when Drive rejects an upload because the
+/// account's storage quota is exhausted, so callers can downcast and react.
+#[derive(Debug)]
+pub struct StorageQuotaExceeded {
+    pub file_name: String,
+}
+
+impl std::fmt::Display for StorageQuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Google Drive storage quota exceeded while uploading '{}': empty the Drive trash, \
+             delete old backups, or set PRUNE_ON_QUOTA=true to prune automatically",
+            self.file_name
+        )
+    }
+}
+
+impl std::error::Error for StorageQuotaExceeded {}
+
+/// Returned inside `anyhow::Error` when Drive refuses an upload because of the
+/// account itself (missing/revoked token, per-user rate or daily limits), so
+/// another account may succeed.
+#[derive(Debug)]
+pub struct AccountRejected {
+    pub file_name: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for AccountRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Google account rejected upload of '{}': {}",
+            self.file_name, self.reason
+        )
+    }
+}
+
+impl std::error::Error for AccountRejected {}
+
+/// Whether a Drive API error is tied to the authenticated account rather than
+/// the request: auth failures and per-user/daily usage limits.
+pub fn is_account_rejected(e: &google_drive3::Error) -> bool {
+    match e {
+        google_drive3::Error::MissingToken(_) => true,
+        google_drive3::Error::BadRequest(body) => {
+            let body = body.to_string();
+            ["authError", "dailyLimitExceeded", "userRateLimitExceeded"]
+                .iter()
+                .any(|reason| body.contains(reason))
+        }
+        google_drive3::Error::Failure(response) => {
+            matches!(response.status().as_u16(), 401 | 403)
+        }
+        _ => false,
+    }
+}
+
+/// Whether an upload error may succeed when retried with another account.
+pub fn is_account_failover_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<StorageQuotaExceeded>().is_some()
+        || e.downcast_ref::<AccountRejected>().is_some()
+}
+
+/// Whether a Drive API error says the file does not exist (404).
+pub fn is_not_found(e: &google_drive3::Error) -> bool {
+    match e {
+        google_drive3::Error::BadRequest(body) => body["error"]["code"].as_u64() == Some(404),
+        google_drive3::Error::Failure(response) => response.status().as_u16() == 404,
+        _ => false,
+    }
+}
+
+/// Whether a Drive API error is the `storageQuotaExceeded` rejection.
+pub fn is_storage_quota_exceeded(e: &google_drive3::Error) -> bool {
+    match e {
+        google_drive3::Error::BadRequest(body) => body.to_string().contains("storageQuotaExceeded"),
+        _ => false,
+    }
+}
 
 /// Find an existing subfolder by name under `parent_id`, or create it if missing.
 pub async fn find_or_create_folder(
     hub: &DriveHub,
     parent_id: &str,
     name: &str,
+    metadata: &FolderMetadata,
 ) -> anyhow::Result<String> {
     // Escape single quotes in folder name to prevent Drive API query injection
     let escaped_name = name.replace('\\', "\\\\").replace('\'', "\\'");
@@ -23,10 +146,12 @@ pub async fn find_or_create_folder(
     let result = hub
         .files()
         .list()
+        .shared_drive()
         .q(&query)
         .spaces("drive")
         .param("fields", "files(id, name)")
         .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
         .doit()
         .await;
 
@@ -57,6 +182,12 @@ pub async fn find_or_create_folder(
         name: Some(name.to_string()),
         mime_type: Some("application/vnd.google-apps.folder".to_string()),
         parents: Some(vec![parent_id.to_string()]),
+        description: metadata.description.clone(),
+        app_properties: if metadata.app_properties.is_empty() {
+            None
+        } else {
+            Some(metadata.app_properties.clone())
+        },
         ..Default::default()
     };
 
@@ -71,8 +202,10 @@ pub async fn find_or_create_folder(
     let result = hub
         .files()
         .create(folder_metadata)
+        .shared_drive()
         .param("fields", "id, name")
         .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
         .upload(std::io::empty(), folder_mime)
         .await;
 
@@ -99,8 +232,82 @@ pub async fn find_or_create_folder(
     }
 }
 
+/// Walk `segments` down from `root_id`, creating missing folders, and return
+/// the id of the last one. Fails if a segment matches more than one folder,
+/// since either choice could silently split backups across two trees.
+pub async fn resolve_folder_path(
+    hub: &DriveHub,
+    root_id: &str,
+    segments: &[String],
+    metadata: &FolderMetadata,
+) -> anyhow::Result<String> {
+    let mut parent_id = root_id.to_string();
+    for (depth, name) in segments.iter().enumerate() {
+        let escaped_name = name.replace('\\', "\\\\").replace('\'', "\\'");
+        let query = format!(
+            "name = '{}' and '{}' in parents and mimeType = 'application/vnd.google-apps.folder' and trashed = false",
+            escaped_name, parent_id
+        );
+        let (_, file_list) = match hub
+            .files()
+            .list()
+            .shared_drive()
+            .q(&query)
+            .spaces("drive")
+            .param("fields", "files(id, name)")
+            .add_scope(Scope::Full)
+            .delegate(&mut RetryAfter::default())
+            .doit()
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                error!(error = %e, folder_name = %name, "Failed to search for folder on Google Drive");
+                bail!("Failed to search for folder '{}': {}", name, e);
+            }
+        };
+
+        let ids: Vec<String> = file_list
+            .files
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|f| f.id)
+            .collect();
+        parent_id = match ids.as_slice() {
+            [] => find_or_create_folder(hub, &parent_id, name, metadata).await?,
+            [id] => id.clone(),
+            _ => {
+                let path = segments[..=depth].join("/");
+                error!(path = %path, folder_ids = ?ids, "Drive folder path is ambiguous");
+                bail!(
+                    "GOOGLE_DRIVE_FOLDER_PATH segment '{}' matches {} folders ({}); rename or remove the duplicates",
+                    path,
+                    ids.len(),
+                    ids.join(", ")
+                );
+            }
+        };
+    }
+
+    info!(path = %segments.join("/"), folder_id = %parent_id, "Resolved Drive folder path");
+    Ok(parent_id)
+}
+
 /// Upload a local file to a specific Google Drive folder using resumable upload.
-pub async fn upload_file(hub: &DriveHub, folder_id: &str, file_path: &Path) -> anyhow::Result<()> {
+/// `app_properties` are attached to the Drive file along with its local size
+/// under [`SIZE_PROPERTY`], and `description` is shown for it in the Drive UI. Drive does not checksum
+/// individual chunks, so progress is logged per chunk and the whole file's
+/// md5, hashed locally alongside the upload, is compared with Drive's once it
+/// completes; a mismatching copy is deleted and the upload fails. Progress of
+/// a backup `stream` is exported to the textfile collector.
+pub async fn upload_file(
+    hub: &DriveHub,
+    folder_id: &str,
+    file_path: &Path,
+    app_properties: &HashMap<String, String>,
+    description: Option<&str>,
+    stream: Option<&UploadStream>,
+) -> anyhow::Result<UploadedFile> {
     let file_name = match file_path.file_name() {
         Some(name) => match name.to_str() {
             Some(s) => s.to_string(),
@@ -133,9 +340,13 @@ pub async fn upload_file(hub: &DriveHub, folder_id: &str, file_path: &Path) -> a
         "Starting resumable upload to Google Drive"
     );
 
+    let mut app_properties = app_properties.clone();
+    app_properties.insert(SIZE_PROPERTY.to_string(), file_size.to_string());
     let file_metadata = DriveFile {
         name: Some(file_name.clone()),
         parents: Some(vec![folder_id.to_string()]),
+        description: description.map(str::to_string),
+        app_properties: Some(app_properties),
         ..Default::default()
     };
 
@@ -156,13 +367,24 @@ pub async fn upload_file(hub: &DriveHub, folder_id: &str, file_path: &Path) -> a
         }
     };
 
+    let hash_path = file_path.to_path_buf();
+    let local_md5 = tokio::task::spawn_blocking(move || md5_file_blocking(&hash_path));
+
+    let mut progress = ChunkProgress::new(&file_name, file_size, stream.cloned());
     let result = hub
         .files()
         .create(file_metadata)
-        .param("fields", "id, name, size")
+        .shared_drive()
+        .param(
+            "fields",
+            "id, name, size, md5Checksum, webViewLink, parents",
+        )
         .add_scope(Scope::Full)
+        .delegate(&mut progress)
         .upload_resumable(reader, mime_type)
         .await;
+    progress.finish(result.is_ok());
+    let resumes = progress.resumes;
 
     match result {
         Ok((_, uploaded)) => {
@@ -170,13 +392,51 @@ pub async fn upload_file(hub: &DriveHub, folder_id: &str, file_path: &Path) -> a
                 Some(id) => id,
                 None => "unknown",
             };
+            let local_md5 = match local_md5.await {
+                Ok(r) => r?,
+                Err(e) => bail!("Hashing task panicked: {}", e),
+            };
+            verify_md5(
+                hub,
+                &file_name,
+                id,
+                &local_md5,
+                uploaded.md5_checksum.as_deref(),
+            )
+            .await?;
             info!(
                 file_name = %file_name,
                 drive_file_id = id,
                 file_size_bytes = file_size,
+                resumes = resumes,
                 "Upload completed"
             );
-            Ok(())
+            check_parents(&file_name, id, folder_id, uploaded.parents.as_deref());
+            Ok(UploadedFile {
+                id: uploaded.id,
+                web_view_link: uploaded.web_view_link,
+            })
+        }
+        Err(e) if is_storage_quota_exceeded(&e) => {
+            error!(
+                error = %e,
+                file_name = %file_name,
+                file_size_bytes = file_size,
+                "Google Drive storage quota exceeded - free space in the account or enable PRUNE_ON_QUOTA"
+            );
+            Err(StorageQuotaExceeded { file_name }.into())
+        }
+        Err(e) if is_account_rejected(&e) => {
+            error!(
+                error = %e,
+                file_name = %file_name,
+                "Google account rejected the upload"
+            );
+            Err(AccountRejected {
+                file_name,
+                reason: e.to_string(),
+            }
+            .into())
         }
         Err(e) => {
             error!(
@@ -188,3 +448,325 @@ pub async fn upload_file(hub: &DriveHub, folder_id: &str, file_path: &Path) -> a
         }
     }
 }
+
+/// Fail when Drive's md5 of the uploaded file differs from the local one,
+/// deleting the corrupt copy so retention never counts it.
+async fn verify_md5(
+    hub: &DriveHub,
+    file_name: &str,
+    file_id: &str,
+    local_md5: &str,
+    remote_md5: Option<&str>,
+) -> anyhow::Result<()> {
+    let Some(remote_md5) = remote_md5 else {
+        warn!(
+            file_name = %file_name,
+            drive_file_id = file_id,
+            "Drive returned no md5Checksum; upload integrity not verified"
+        );
+        return Ok(());
+    };
+    if remote_md5.eq_ignore_ascii_case(local_md5) {
+        info!(file_name = %file_name, md5 = local_md5, "Upload md5 verified");
+        return Ok(());
+    }
+
+    error!(
+        file_name = %file_name,
+        drive_file_id = file_id,
+        local_md5 = local_md5,
+        remote_md5 = remote_md5,
+        "Uploaded file does not match the local file, deleting it"
+    );
+    if let Err(e) = hub
+        .files()
+        .delete(file_id)
+        .shared_drive()
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        error!(error = %e, drive_file_id = file_id, "Failed to delete corrupt upload");
+    }
+    bail!(
+        "Upload of '{}' was corrupted in transit: Drive has md5 {} but the local file has {}",
+        file_name,
+        remote_md5,
+        local_md5
+    );
+}
+
+/// Upload delegate that logs each chunk as the previous ones are
+/// acknowledged (every chunk at debug, every 10% at info) and retries
+/// throttling like [`RetryAfter`]. On a terminal it also draws a progress bar
+/// to stderr. Progress and resumes of a backup stream go to the textfile
+/// collector when `TEXTFILE_COLLECTOR_DIR` is set.
+struct ChunkProgress {
+    throttle: RetryAfter,
+    file_name: String,
+    stream: Option<UploadStream>,
+    total: u64,
+    started: Instant,
+    next_percent: u64,
+    bar: Option<ProgressBar>,
+    /// Start of the chunk last offered; a chunk starting at or before it is
+    /// being re-sent from Drive's last acknowledged byte.
+    last_first: Option<u64>,
+    resumes: u64,
+}
+
+impl ChunkProgress {
+    fn new(file_name: &str, total: u64, stream: Option<UploadStream>) -> Self {
+        // Logs go to stdout, so a redirected run gets no bar to interleave
+        let bar = std::io::stdout().is_terminal().then(|| {
+            let style = ProgressStyle::with_template(
+                "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar());
+            let bar = PROGRESS_BARS.add(ProgressBar::new(total));
+            bar.set_style(style.progress_chars("=> "));
+            bar.set_message(file_name.to_string());
+            bar
+        });
+        Self {
+            throttle: RetryAfter::default(),
+            file_name: file_name.to_string(),
+            stream,
+            total,
+            started: Instant::now(),
+            next_percent: 10,
+            bar,
+            last_first: None,
+            resumes: 0,
+        }
+    }
+
+    /// Remove the progress bar once the upload has finished or failed, and
+    /// record a completed upload as fully acknowledged.
+    fn finish(&self, completed: bool) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            PROGRESS_BARS.remove(bar);
+        }
+        if completed && let Some(stream) = &self.stream {
+            crate::metrics::write_upload_progress(stream, self.total, self.total);
+        }
+    }
+}
+
+impl Delegate for ChunkProgress {
+    fn http_error(&mut self, err: &hyper_util::client::legacy::Error) -> Retry {
+        self.throttle.http_error(err)
+    }
+
+    fn http_failure(&mut self, response: &Response, err: Option<&serde_json::Value>) -> Retry {
+        self.throttle.http_failure(response, err)
+    }
+
+    fn cancel_chunk_upload(&mut self, chunk: &ContentRange) -> bool {
+        let Some(range) = &chunk.range else {
+            return false;
+        };
+        // Called before each chunk is sent, so everything below it is stored
+        let acknowledged = range.first;
+        let total = chunk.total_length.max(self.total).max(1);
+        let percent = acknowledged * 100 / total;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 {
+            acknowledged as f64 / elapsed
+        } else {
+            0.0
+        };
+        debug!(
+            file_name = %self.file_name,
+            chunk_first = range.first,
+            chunk_last = range.last,
+            acknowledged_bytes = acknowledged,
+            total_bytes = total,
+            "Uploading chunk"
+        );
+        if let Some(prev) = self.last_first
+            && range.first <= prev
+        {
+            self.resumes += 1;
+            warn!(
+                file_name = %self.file_name,
+                offset = range.first,
+                resumes = self.resumes,
+                "Resuming upload from the last acknowledged byte"
+            );
+            if let Some(stream) = &self.stream {
+                crate::metrics::record_upload_resume(stream);
+            }
+        }
+        self.last_first = Some(range.first);
+        crate::systemd::progress();
+        if let Some(stream) = &self.stream {
+            crate::metrics::write_upload_progress(stream, acknowledged, total);
+        }
+        if let Some(bar) = &self.bar {
+            bar.set_position(acknowledged);
+        }
+        if percent >= self.next_percent {
+            info!(
+                file_name = %self.file_name,
+                acknowledged_bytes = acknowledged,
+                total_bytes = total,
+                percent = percent,
+                bytes_per_sec = bytes_per_sec as u64,
+                "Upload progress"
+            );
+            self.next_percent = (percent / 10 + 1) * 10;
+        }
+        false
+    }
+}
+
+/// Warn when Drive reports the uploaded file outside `folder_id`. Retention
+/// lists backups by folder, so a misrouted file would never be pruned and
+/// wouldn't count towards the kept backups.
+fn check_parents(file_name: &str, file_id: &str, folder_id: &str, parents: Option<&[String]>) {
+    match parents {
+        Some(parents) if parents.iter().any(|p| p == folder_id) => {}
+        Some(parents) => warn!(
+            file_name = %file_name,
+            drive_file_id = file_id,
+            expected_folder_id = folder_id,
+            actual_parents = ?parents,
+            "Uploaded file landed outside the intended Drive folder; retention will not see it"
+        ),
+        None => warn!(
+            file_name = %file_name,
+            drive_file_id = file_id,
+            expected_folder_id = folder_id,
+            "Drive returned no parents for the uploaded file; cannot confirm its folder"
+        ),
+    }
+}
+
+/// Bytes still free in the account's Drive quota, or `None` when the account
+/// has no limit.
+pub async fn available_quota(hub: &DriveHub) -> anyhow::Result<Option<u64>> {
+    let about = match hub
+        .about()
+        .get()
+        .param("fields", "storageQuota")
+        .add_scope(Scope::Full)
+        .delegate(&mut RetryAfter::default())
+        .doit()
+        .await
+    {
+        Ok((_, about)) => about,
+        Err(e) => bail!("Failed to query Drive storage quota: {}", e),
+    };
+
+    let Some(quota) = about.storage_quota else {
+        bail!("Drive did not report a storage quota");
+    };
+    match quota.limit {
+        Some(limit) => {
+            let usage = quota.usage.unwrap_or(0);
+            Ok(Some(limit.saturating_sub(usage).max(0) as u64))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Times `files().get` is tried by [`confirm_visible`], doubling the wait
+/// between attempts from [`CONFIRM_INITIAL_DELAY`].
+const CONFIRM_ATTEMPTS: u32 = 6;
+const CONFIRM_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Poll Drive until the just-uploaded `file_id` can be fetched, so a prune
+/// that runs right after the upload counts it (`CONFIRM_UPLOAD_VISIBLE`).
+pub async fn confirm_visible(hub: &DriveHub, file_id: &str) -> anyhow::Result<()> {
+    let mut delay = CONFIRM_INITIAL_DELAY;
+    for attempt in 1..=CONFIRM_ATTEMPTS {
+        match hub
+            .files()
+            .get(file_id)
+            .shared_drive()
+            .param("fields", "id")
+            .add_scope(Scope::Full)
+            .delegate(&mut RetryAfter::default())
+            .doit()
+            .await
+        {
+            Ok((_, file)) if file.id.is_some() => {
+                info!(
+                    file_id = file_id,
+                    attempt = attempt,
+                    "Uploaded file is visible"
+                );
+                return Ok(());
+            }
+            Ok(_) => warn!(
+                file_id = file_id,
+                attempt = attempt,
+                "Uploaded file not visible yet"
+            ),
+            Err(e) => {
+                warn!(error = %e, file_id = file_id, attempt = attempt, "Uploaded file not visible yet")
+            }
+        }
+        if attempt < CONFIRM_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    error!(
+        file_id = file_id,
+        attempts = CONFIRM_ATTEMPTS,
+        "Uploaded file never became visible"
+    );
+    bail!(
+        "Uploaded file {} was not visible on Google Drive after {} attempts",
+        file_id,
+        CONFIRM_ATTEMPTS
+    );
+}
+
+/// Give `grant` read access to an uploaded file so its link can be shared.
+pub async fn grant_reader(
+    hub: &DriveHub,
+    file_id: &str,
+    grant: &ReaderGrant,
+) -> anyhow::Result<()> {
+    let permission = match grant {
+        ReaderGrant::AnyoneWithLink => Permission {
+            role: Some("reader".to_string()),
+            type_: Some("anyone".to_string()),
+            allow_file_discovery: Some(false),
+            ..Default::default()
+        },
+        ReaderGrant::User(email) => Permission {
+            role: Some("reader".to_string()),
+            type_: Some("user".to_string()),
+            email_address: Some(email.clone()),
+            ..Default::default()
+        },
+    };
+
+    let mut request = hub
+        .permissions()
+        .create(permission, file_id)
+        .shared_drive()
+        .add_scope(Scope::Full);
+    if matches!(grant, ReaderGrant::User(_)) {
+        request = request.send_notification_email(false);
+    }
+
+    let mut throttle = RetryAfter::default();
+    match request.delegate(&mut throttle).doit().await {
+        Ok(_) => {
+            info!(file_id = file_id, grant = ?grant, "Granted read access to uploaded backup");
+            Ok(())
+        }
+        Err(e) => {
+            error!(error = %e, file_id = file_id, "Failed to grant read access");
+            bail!("Failed to grant read access to {}: {}", file_id, e);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::backup::BackupKind;
+use crate::storage::StoredObject;
+use crate::util::format::humanize_bytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// One stored backup as shown by `list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupListing {
+    pub backup_type: BackupKind,
+    pub name: String,
+    pub id: String,
+    pub size_bytes: Option<u64>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Whether a prune under the current retention would keep it; `None`
+    /// when the type is never pruned.
+    pub survives_prune: Option<bool>,
+    /// Drive's size differs from the size recorded at upload.
+    pub suspect: bool,
+}
+
+impl BackupListing {
+    /// A listing of `object`, a backup of `backup_type`, not yet checked
+    /// against retention.
+    pub fn stored(backup_type: BackupKind, object: StoredObject) -> Self {
+        Self {
+            backup_type,
+            name: object.name,
+            id: object.id,
+            size_bytes: object.size_bytes,
+            created_at: object.created_at,
+            survives_prune: None,
+            suspect: object.suspect,
+        }
+    }
+}
+
+/// How many backups a listing holds and their combined size.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ListSummary {
+    pub count: usize,
+    pub total_bytes: u64,
+}
+
+impl ListSummary {
+    pub fn of(listings: &[BackupListing]) -> Self {
+        Self {
+            count: listings.len(),
+            total_bytes: listings.iter().filter_map(|l| l.size_bytes).sum(),
+        }
+    }
+}
+
+/// The JSON document `list --format json` prints.
+#[derive(Serialize)]
+struct JsonListing<'a> {
+    #[serde(flatten)]
+    summary: ListSummary,
+    backups: &'a [BackupListing],
+}
+
+pub fn print(listings: &[BackupListing], format: ListFormat) -> anyhow::Result<()> {
+    let summary = ListSummary::of(listings);
+    match format {
+        ListFormat::Table => print_table(listings, summary),
+        ListFormat::Json => {
+            let document = JsonListing {
+                summary,
+                backups: listings,
+            };
+            let json = match serde_json::to_string_pretty(&document) {
+                Ok(j) => j,
+                Err(e) => anyhow::bail!("Failed to serialize listing: {}", e),
+            };
+            println!("{}", json);
+        }
+        ListFormat::Csv => {
+            println!("backup_type,name,id,size_bytes,created_at,survives_prune,suspect");
+            for l in listings {
+                println!(
+                    "{},{},{},{},{},{},{}",
+                    l.backup_type,
+                    csv_field(&l.name),
+                    csv_field(&l.id),
+                    l.size_bytes.map(|s| s.to_string()).unwrap_or_default(),
+                    l.created_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                    l.survives_prune.map(|k| k.to_string()).unwrap_or_default(),
+                    l.suspect
+                );
+            }
+            // Kept off stdout so every CSV row is a backup
+            eprintln!(
+                "{} backup(s), {} bytes total",
+                summary.count, summary.total_bytes
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_table(listings: &[BackupListing], summary: ListSummary) {
+    let name_width = listings
+        .iter()
+        .map(|l| l.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len());
+
+    println!(
+        "{:<10} {:<name_width$} {:>10}  {:<16}  {:<7}  ID",
+        "TYPE", "NAME", "SIZE", "CREATED", "PRUNE"
+    );
+    for l in listings {
+        println!(
+            "{:<10} {:<name_width$} {:>10}  {:<16}  {:<7}  {}",
+            l.backup_type.as_str(),
+            l.name,
+            l.size_bytes
+                .map(|s| humanize_bytes(s as f64))
+                .unwrap_or_else(|| "-".to_string()),
+            l.created_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            match l.survives_prune {
+                _ if l.suspect => "suspect",
+                Some(true) => "keep",
+                Some(false) => "drop",
+                None => "-",
+            },
+            l.id
+        );
+    }
+
+    println!(
+        "\n{} backup(s), {} total",
+        summary.count,
+        humanize_bytes(summary.total_bytes as f64)
+    );
+    let suspect = listings.iter().filter(|l| l.suspect).count();
+    if suspect > 0 {
+        println!(
+            "{} suspect backup(s) whose Drive size differs from the uploaded size; \
+             prune leaves them alone and does not count them as kept",
+            suspect
+        );
+    }
+}
+
+/// Show `listings` as a numbered table and ask which one to use. Reads the
+/// answer from stdin; an empty answer or `q` cancels.
+pub fn pick(listings: &[BackupListing]) -> anyhow::Result<&BackupListing> {
+    if listings.is_empty() {
+        anyhow::bail!("No backups available to choose from");
+    }
+
+    for (i, l) in listings.iter().enumerate() {
+        println!(
+            "{:>3}) {}  {:>10}  {}",
+            i + 1,
+            l.created_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            l.size_bytes
+                .map(|s| humanize_bytes(s as f64))
+                .unwrap_or_else(|| "-".to_string()),
+            l.name
+        );
+    }
+
+    let stdin = std::io::stdin();
+    loop {
+        print!(
+            "Restore which backup? [1-{}, q to cancel]: ",
+            listings.len()
+        );
+        if let Err(e) = std::io::stdout().flush() {
+            anyhow::bail!("Failed to write prompt: {}", e);
+        }
+
+        let mut answer = String::new();
+        match stdin.read_line(&mut answer) {
+            Ok(0) => anyhow::bail!("Restore cancelled"),
+            Ok(_) => {}
+            Err(e) => anyhow::bail!("Failed to read selection: {}", e),
+        }
+        let answer = answer.trim();
+        if answer.is_empty() || answer.eq_ignore_ascii_case("q") {
+            anyhow::bail!("Restore cancelled");
+        }
+
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=listings.len()).contains(&n) => return Ok(&listings[n - 1]),
+            _ => println!("Enter a number between 1 and {}", listings.len()),
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
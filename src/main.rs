@@ -1,20 +1,49 @@
 #![feature(const_type_name)]
 
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::Duration;
 
-use clap::Parser;
-use tracing::{error, info};
+use anyhow::bail;
+use clap::{CommandFactory, Parser};
+use futures::stream::{self, StreamExt};
+use tracing::{error, info, warn};
 
-use crate::cli::{Cli, Command};
+use crate::backup::chain::{self, ArchiveScope};
+use crate::backup::{BackupArtifact, BackupKind};
+use crate::blackout::BlackoutAction;
+use crate::cli::{
+    BackupCommand, Cli, Command, GenSystemdArgs, InspectCommand, RunCommand, SetupCommand,
+};
 use crate::config::config::Config;
+use crate::drive::auth::DriveAccounts;
+use crate::drive::prune::retention_policy;
+use crate::notify::{ArtifactDetails, ArtifactResult, BackupEvent};
 use crate::setup_logger::setup_logger;
+use crate::storage::drive::{folder_metadata, primary_root, resolve_folders};
+use crate::util::fs::{artifact_name, remove_temp_file};
 
+pub mod abort;
 pub mod backup;
+pub mod blackout;
 pub mod build_info;
 pub mod cli;
 pub mod config;
+pub mod crypto;
 pub mod drive;
+pub mod list;
+pub mod metrics;
+pub mod notify;
+pub mod report;
+pub mod scrub;
 pub mod setup_logger;
+pub mod status;
+pub mod storage;
+pub mod systemd;
+pub mod trend;
+pub mod util;
 
 use mimalloc::MiMalloc;
 
@@ -25,7 +54,7 @@ static GLOBAL: MiMalloc = MiMalloc;
 async fn main() -> ExitCode {
     let app_start_time = tokio::time::Instant::now();
 
-    let (_log_guard, _stdout_guard) = setup_logger().await;
+    let (log_guard, _stdout_guard, log_path) = setup_logger().await;
     let _span_entered = tracing::info_span!(std::any::type_name_of_val(&main)).entered();
 
     // Log panics via tracing before the process aborts
@@ -52,12 +81,33 @@ async fn main() -> ExitCode {
 
     let cli = Cli::parse();
 
-    let config = match Config::from_env() {
-        Ok(c) => c,
-        Err(e) => {
-            error!(error = %e, "Failed to load configuration");
-            return ExitCode::FAILURE;
-        }
+    if cli.config_check {
+        return run_config_check(cli.config.as_deref()).await;
+    }
+    let Some(command) = cli.command else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required unless --config-check is given",
+            )
+            .exit();
+    };
+
+    let command = match command {
+        Command::Setup(command) => return run_setup(command, cli.config.as_deref()),
+        Command::Inspect(command) => return run_inspect(command, cli.config.as_deref()).await,
+        Command::Run(command) => command,
+    };
+
+    let Some(config) = load_configured(cli.config.as_deref()) else {
+        return ExitCode::FAILURE;
+    };
+
+    let command_name = command.name();
+    let started_at = chrono::Utc::now();
+    let backup_command = match &command {
+        RunCommand::Backup(command) => Some(*command),
+        _ => None,
     };
 
     // Ensure temp directory exists
@@ -67,130 +117,2449 @@ async fn main() -> ExitCode {
             path = %config.backup_temp_dir.display(),
             "Failed to create backup temp directory"
         );
-        return ExitCode::FAILURE;
+        let error = format!("Failed to create backup temp directory: {}", e);
+        if !cli.dry_run {
+            notify_run_failure(&config, backup_command, started_at, &error).await;
+        }
+        return finish(
+            &config,
+            command_name,
+            started_at,
+            ExitCode::FAILURE,
+            Some(&error),
+        )
+        .await;
+    }
+    // Logged for diagnostics; backup commands enforce the minimums later
+    match util::fs::free_space(&config.backup_temp_dir) {
+        Ok(space) => info!(
+            path = %config.backup_temp_dir.display(),
+            available_bytes = space.available_bytes,
+            available_inodes = ?space.available_inodes,
+            "Backup temp directory free space"
+        ),
+        Err(e) => warn!(error = %e, "Could not read free space of the backup temp directory"),
+    }
+
+    // Under systemd (Type=notify), report readiness; the watchdog is fed by
+    // progress in the archive, dump and upload loops
+    systemd::init_watchdog();
+    systemd::ready();
+
+    let options = RunOptions {
+        dump_only: cli.dump_only,
+        dry_run: cli.dry_run,
+        since_last: false,
+    };
+    // Deferring for a blackout window does not count against MAX_RUNTIME_SECS
+    if matches!(command, RunCommand::Backup(_)) && !wait_out_blackout(&config).await {
+        return finish(&config, command_name, started_at, ExitCode::SUCCESS, None).await;
     }
 
-    let result = match cli.command {
-        Command::Db => run_db_backup(&config).await,
-        Command::Minecraft => run_minecraft_backup(&config).await,
-        Command::All => run_all(&config).await,
-        Command::Prune => run_prune(&config).await,
+    let max_runtime = match config.max_runtime {
+        Some(limit) if command.is_restore() => {
+            info!(limit = ?limit, "MAX_RUNTIME_SECS does not apply to restores");
+            None
+        }
+        limit => limit,
+    };
+    let result = match max_runtime {
+        Some(limit) => {
+            let preexisting = temp_dir_entries(&config.backup_temp_dir).await;
+            match tokio::time::timeout(limit, run_command(&config, command, options)).await {
+                Ok(r) => r,
+                Err(_) => {
+                    let code = abort_run(&config, limit, &preexisting, app_start_time).await;
+                    if !cli.dry_run {
+                        let error = "MAX_RUNTIME_SECS exceeded";
+                        notify_run_failure(&config, backup_command, started_at, error).await;
+                    }
+                    report::write(
+                        &config,
+                        command_name,
+                        started_at,
+                        report::ReportStatus::Aborted,
+                        Some("MAX_RUNTIME_SECS exceeded"),
+                    )
+                    .await;
+                    return code;
+                }
+            }
+        }
+        None => run_command(&config, command, options).await,
     };
 
     match result {
         Ok(()) => {
             info!(duration = ?app_start_time.elapsed(), "All operations completed successfully");
-            ExitCode::SUCCESS
+            systemd::status("Completed successfully");
+            finish(&config, command_name, started_at, ExitCode::SUCCESS, None).await
         }
         Err(e) => {
             error!(error = %e, duration = ?app_start_time.elapsed(), "Operation failed");
-            ExitCode::FAILURE
+            systemd::status(&format!("Failed: {}", e));
+            if config.upload_logs_on_failure
+                && !cli.dump_only
+                && !cli.dry_run
+                && config.storage_backend != "b2"
+            {
+                // Dropping the guard flushes the file writer; anything logged
+                // after this only reaches stdout
+                drop(log_guard);
+                upload_session_log(&config, &log_path).await;
+            }
+            let error = format!("{:#}", e);
+            if !cli.dry_run {
+                notify_run_failure(&config, backup_command, started_at, &error).await;
+            }
+            finish(
+                &config,
+                command_name,
+                started_at,
+                ExitCode::FAILURE,
+                Some(&error),
+            )
+            .await
         }
     }
 }
 
-async fn run_db_backup(config: &Config) -> anyhow::Result<()> {
-    let hub = drive::auth::build_hub(&config.google_credentials_path).await?;
-    let folder_id =
-        drive::upload::find_or_create_folder(&hub, &config.google_drive_folder_id, "DB_Backups")
-            .await?;
+/// Email the failure of a backup command that ended without `run_backups`
+/// reporting it: before any artifact was made, or cut short by
+/// `MAX_RUNTIME_SECS`. Every artifact the command covers is marked failed
+/// with `error`. Other commands send nothing.
+async fn notify_run_failure(
+    config: &Config,
+    command: Option<BackupCommand>,
+    started_at: chrono::DateTime<chrono::Utc>,
+    error: &str,
+) {
+    let Some(command) = command else {
+        return;
+    };
+    let kinds: Vec<BackupKind> = match command {
+        BackupCommand::Db => vec![BackupKind::Db],
+        BackupCommand::Minecraft { .. } => vec![BackupKind::Minecraft],
+        BackupCommand::All => BackupKind::ALL
+            .into_iter()
+            .filter(|&kind| config.backup_enabled(kind))
+            .collect(),
+    };
+    let artifacts = backup_targets(config, &kinds)
+        .into_iter()
+        .map(|target| ArtifactResult {
+            kind: target.kind,
+            database: target.database(config).map(str::to_string),
+            file_name: None,
+            size_bytes: None,
+            link: None,
+            error: Some(error.to_string()),
+            duration: None,
+            details: None,
+        })
+        .collect();
+    let event = BackupEvent {
+        command: command.name().to_string(),
+        started_at,
+        finished_at: chrono::Utc::now(),
+        artifacts,
+    };
+    notify::email_run_failure(config, &event).await;
+}
 
-    let dump_path = backup::db::backup_db(config).await?;
-    drive::upload::upload_file(&hub, &folder_id, &dump_path).await?;
+/// Drive folder under the primary root that receives session logs.
+const LOGS_FOLDER: &str = "Logs";
 
-    // Clean up temp file after successful upload
-    if let Err(e) = tokio::fs::remove_file(&dump_path).await {
-        error!(
+/// Upload this session's log to the `Logs` folder under the primary Drive
+/// root, as `.log.zst` unless `COMPRESS_UPLOADED_LOGS=false`. The local log
+/// is left uncompressed. Failures are logged only.
+async fn upload_session_log(config: &Config, log_path: &Path) {
+    let result: anyhow::Result<()> = async {
+        let accounts =
+            DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+        let hub = accounts.hub();
+        let root = primary_root(config, hub).await?;
+        let folder_id =
+            drive::upload::find_or_create_folder(hub, &root, LOGS_FOLDER, &folder_metadata(config))
+                .await?;
+        let properties = HashMap::new();
+
+        if !config.compress_uploaded_logs {
+            drive::upload::upload_file(hub, &folder_id, log_path, &properties, None, None).await?;
+            return Ok(());
+        }
+
+        let compressed = config
+            .backup_temp_dir
+            .join(format!("{}.zst", artifact_name(log_path)));
+        util::fs::zstd_compress(log_path, &compressed).await?;
+        let uploaded =
+            drive::upload::upload_file(hub, &folder_id, &compressed, &properties, None, None).await;
+        remove_temp_file(&compressed).await;
+        uploaded?;
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => info!(path = %log_path.display(), "Uploaded session log to Google Drive"),
+        Err(e) => warn!(error = %e, path = %log_path.display(), "Failed to upload session log"),
+    }
+}
+
+/// Write the exit report for a command that ended with `code`, then pass the
+/// code through.
+async fn finish(
+    config: &Config,
+    command: &str,
+    started_at: chrono::DateTime<chrono::Utc>,
+    code: ExitCode,
+    error: Option<&str>,
+) -> ExitCode {
+    let status = if code == ExitCode::SUCCESS {
+        report::ReportStatus::Success
+    } else {
+        report::ReportStatus::Failure
+    };
+    report::write(config, command, started_at, status, error).await;
+    code
+}
+
+/// Dispatch a command that needs the temp directory.
+async fn run_command(
+    config: &Config,
+    command: RunCommand,
+    options: RunOptions,
+) -> anyhow::Result<()> {
+    let dump_only = options.dump_only;
+    if options.dry_run && !matches!(command, RunCommand::Backup(_)) {
+        bail!("--dry-run only applies to db, minecraft and all");
+    }
+    match command {
+        RunCommand::Backup(BackupCommand::Db) if !config.db_backup_enabled => Err(anyhow::anyhow!(
+            "db backups are disabled by DB_BACKUP_ENABLED=false"
+        )),
+        RunCommand::Backup(BackupCommand::Minecraft { .. }) if !config.minecraft_backup_enabled => {
+            Err(anyhow::anyhow!(
+                "minecraft backups are disabled by MINECRAFT_BACKUP_ENABLED=false"
+            ))
+        }
+        RunCommand::Backup(command) => run_with_retries(config, command, options).await,
+        RunCommand::Prune { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with prune, which requires Google Drive"
+        )),
+        RunCommand::Prune {
+            apply_plan: Some(path),
+            ..
+        } => run_apply_prune_plan(config, &path).await,
+        RunCommand::Prune {
+            keep_ids,
+            emit_plan,
+            ..
+        } => run_prune(config, keep_ids, emit_plan.as_deref()).await,
+        RunCommand::RestoreMinecraft {
+            confirm: false,
+            target_dir: None,
+            verify_only: false,
+            ..
+        } => Err(anyhow::anyhow!(
+            "restore-minecraft replaces the live server directory; re-run with --confirm"
+        )),
+        RunCommand::RestoreMinecraft {
+            archive,
+            target_dir,
+            verify_only,
+            include_ext,
+            exclude_ext,
+            ..
+        } => {
+            let target = match (verify_only, target_dir) {
+                (true, _) => RestoreTarget::VerifyOnly,
+                (false, Some(dir)) => {
+                    let filter = backup::restore::ExtFilter::new(&include_ext, &exclude_ext);
+                    RestoreTarget::Dir(dir, filter)
+                }
+                (false, None) => RestoreTarget::Live,
+            };
+            run_restore_minecraft(config, archive, &target, dump_only).await
+        }
+        RunCommand::RestoreDb { confirm: false, .. } => Err(anyhow::anyhow!(
+            "restore-db replaces objects in DB_NAME; re-run with --confirm"
+        )),
+        RunCommand::RestoreDb {
+            archive, tables, ..
+        } => run_restore_db(config, archive, &tables, dump_only).await,
+        RunCommand::ValidateRestore if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with validate-restore, which requires Google Drive"
+        )),
+        RunCommand::ValidateRestore => run_validate_restore(config).await,
+        RunCommand::FetchArchive { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with fetch-archive, which requires Google Drive"
+        )),
+        RunCommand::FetchArchive {
+            index_id,
+            backup_type,
+            dest,
+            extract_to,
+        } => run_fetch_archive(config, &index_id, backup_type, dest, extract_to).await,
+        RunCommand::Scrub { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with scrub, which requires Google Drive"
+        )),
+        RunCommand::Scrub {
+            backup_type,
+            concurrency,
+            order,
+        } => run_scrub(config, backup_type, concurrency as usize, order).await,
+        RunCommand::Dedup { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with dedup, which requires Google Drive"
+        )),
+        RunCommand::Dedup { backup_type, yes } => run_dedup(config, backup_type, yes).await,
+        RunCommand::Download { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with download, which requires Google Drive"
+        )),
+        RunCommand::Download {
+            backup_type,
+            latest,
+            dest,
+        } => run_download(config, backup_type, latest as usize, dest).await,
+        RunCommand::TrainDict {
+            output,
+            max_size,
+            samples,
+            force,
+        } => run_train_dict(config, output, max_size, samples, force).await,
+        RunCommand::List { .. } if dump_only => Err(anyhow::anyhow!(
+            "--dump-only cannot be combined with list, which requires Google Drive"
+        )),
+        RunCommand::List {
+            backup_type,
+            format,
+        } => run_list(config, backup_type, format).await,
+    }
+}
+
+/// Check `BACKUP_BLACKOUT_WINDOWS` before a backup command. Returns false when
+/// the backup should be skipped; with `BACKUP_BLACKOUT_ACTION=defer` it sleeps
+/// until no window is active instead.
+async fn wait_out_blackout(config: &Config) -> bool {
+    let Some(blackout) = &config.backup_blackout else {
+        return true;
+    };
+
+    while let Some((window, remaining)) = blackout.active(chrono::Utc::now()) {
+        match blackout.action {
+            BlackoutAction::Skip => {
+                info!(window = %window, "Inside a backup blackout window, skipping");
+                return false;
+            }
+            BlackoutAction::Defer => {
+                info!(window = %window, wait = ?remaining, "Inside a backup blackout window, deferring");
+                systemd::status(&format!("Deferred by blackout window {}", window));
+                // Sleep a second past the close so the next check is outside it
+                systemd::sleep(remaining + Duration::from_secs(1)).await;
+            }
+        }
+    }
+    true
+}
+
+/// Exit code when `MAX_RUNTIME_SECS` cuts a run short, as timeout(1) uses.
+const EXIT_MAX_RUNTIME: u8 = 124;
+
+/// Blocking archive and dump tasks stop at their next check of the abort
+/// flag; this is how long they get before their files are removed.
+const ABORT_GRACE: Duration = Duration::from_secs(2);
+
+/// Wind down after `MAX_RUNTIME_SECS`. Dropping the command's future has
+/// already killed its child processes and released its dump slots; this stops
+/// blocking tasks and removes the files the run created.
+async fn abort_run(
+    config: &Config,
+    limit: Duration,
+    preexisting: &HashSet<PathBuf>,
+    app_start_time: tokio::time::Instant,
+) -> ExitCode {
+    error!(
+        max_runtime = ?limit,
+        duration = ?app_start_time.elapsed(),
+        "MAX_RUNTIME_SECS exceeded, aborting"
+    );
+    systemd::status("Aborted: MAX_RUNTIME_SECS exceeded");
+    abort::trigger();
+    tokio::time::sleep(ABORT_GRACE).await;
+    remove_new_temp_files(&config.backup_temp_dir, preexisting).await;
+    ExitCode::from(EXIT_MAX_RUNTIME)
+}
+
+/// Run a backup command, re-running it from scratch up to `COMMAND_RETRIES`
+/// times on failure. Files a failed attempt left in the temp directory are
+/// removed before the next one, and no attempt runs past `COMMAND_DEADLINE_SECS`.
+async fn run_with_retries(
+    config: &Config,
+    command: BackupCommand,
+    options: RunOptions,
+) -> anyhow::Result<()> {
+    let retry = config.command_retry;
+    let deadline = retry.deadline.map(|d| tokio::time::Instant::now() + d);
+    let attempts = retry.retries + 1;
+    let preexisting = temp_dir_entries(&config.backup_temp_dir).await;
+
+    let mut attempt = 1;
+    loop {
+        if attempts > 1 {
+            info!(
+                attempt = attempt,
+                max_attempts = attempts,
+                "Starting command attempt"
+            );
+        }
+
+        let run = run_backup_command(config, command, options);
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, run).await {
+                Ok(r) => r,
+                Err(_) => Err(anyhow::anyhow!(
+                    "Command exceeded COMMAND_DEADLINE_SECS ({:?})",
+                    retry.deadline.unwrap_or_default()
+                )),
+            },
+            None => run.await,
+        };
+
+        let e = match result {
+            Ok(()) => {
+                if attempt > 1 {
+                    info!(attempt = attempt, "Command succeeded after retry");
+                }
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        if attempt >= attempts {
+            if attempts > 1 {
+                error!(error = %e, attempts = attempt, "Command failed on every attempt");
+            }
+            return Err(e);
+        }
+        if let Some(deadline) = deadline
+            && tokio::time::Instant::now() + retry.delay >= deadline
+        {
+            error!(error = %e, attempt = attempt, "No time left before COMMAND_DEADLINE_SECS, not retrying");
+            return Err(e);
+        }
+
+        warn!(
             error = %e,
-            path = %dump_path.display(),
-            "Failed to remove temp file after upload"
+            attempt = attempt,
+            max_attempts = attempts,
+            delay = ?retry.delay,
+            "Command failed, retrying from scratch"
         );
+        systemd::status(&format!("Attempt {} failed, retrying", attempt));
+        remove_new_temp_files(&config.backup_temp_dir, &preexisting).await;
+        systemd::sleep(retry.delay).await;
+        attempt += 1;
     }
+}
 
-    Ok(())
+async fn run_backup_command(
+    config: &Config,
+    command: BackupCommand,
+    options: RunOptions,
+) -> anyhow::Result<()> {
+    match command {
+        BackupCommand::Db => run_backups(config, "db", &[BackupKind::Db], options).await,
+        BackupCommand::Minecraft { since_last } => {
+            let options = RunOptions {
+                since_last,
+                ..options
+            };
+            run_backups(config, "minecraft", &[BackupKind::Minecraft], options).await
+        }
+        BackupCommand::All => {
+            let mut kinds = Vec::new();
+            for kind in BackupKind::ALL {
+                if config.backup_enabled(kind) {
+                    kinds.push(kind);
+                } else {
+                    info!(
+                        backup_type = kind.as_str(),
+                        "Backup type disabled, skipping"
+                    );
+                }
+            }
+            if kinds.is_empty() {
+                warn!("Every backup type is disabled, nothing to back up");
+                return Ok(());
+            }
+            run_backups(config, "all", &kinds, options).await
+        }
+    }
 }
 
-async fn run_minecraft_backup(config: &Config) -> anyhow::Result<()> {
-    let hub = drive::auth::build_hub(&config.google_credentials_path).await?;
-    let folder_id = drive::upload::find_or_create_folder(
-        &hub,
-        &config.google_drive_folder_id,
-        "Minecraft_Backups",
-    )
-    .await?;
+async fn temp_dir_entries(dir: &Path) -> HashSet<PathBuf> {
+    let mut entries = HashSet::new();
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(r) => r,
+        Err(e) => {
+            warn!(error = %e, path = %dir.display(), "Failed to list temp directory");
+            return entries;
+        }
+    };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        entries.insert(entry.path());
+    }
+    entries
+}
 
-    let archive_path = backup::minecraft::backup_minecraft(config).await?;
-    drive::upload::upload_file(&hub, &folder_id, &archive_path).await?;
+/// Remove files in `dir` that were not there before the first attempt, i.e.
+/// leftovers of a failed attempt. Anything that predates the run is kept.
+async fn remove_new_temp_files(dir: &Path, preexisting: &HashSet<PathBuf>) {
+    for path in temp_dir_entries(dir).await {
+        if preexisting.contains(&path) {
+            continue;
+        }
+        let removed = if path.is_dir() {
+            tokio::fs::remove_dir_all(&path).await
+        } else {
+            tokio::fs::remove_file(&path).await
+        };
+        match removed {
+            Ok(()) => info!(path = %path.display(), "Removed leftover from failed attempt"),
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Failed to remove leftover from failed attempt")
+            }
+        }
+    }
+}
 
-    // Prune old backups after successful upload
-    drive::prune::prune_old_backups(&hub, &folder_id, config.mc_retention_count).await?;
+/// Command-line switches that shape a backup run.
+#[derive(Debug, Clone, Copy)]
+struct RunOptions {
+    /// Keep artifacts in the temp directory and never touch Google Drive.
+    dump_only: bool,
+    /// Log the upload and prune instead of doing them, then delete artifacts.
+    dry_run: bool,
+    /// Archive only Minecraft files modified since the last successful backup.
+    since_last: bool,
+}
 
-    // Clean up temp file
-    if let Err(e) = tokio::fs::remove_file(&archive_path).await {
+/// One artifact a backup run produces.
+#[derive(Debug, Clone, Copy)]
+struct BackupTarget<'a> {
+    kind: BackupKind,
+    /// The database a db target dumps; `None` for other types and for the
+    /// `DB_BUNDLE` target, which dumps every database into one bundle.
+    db_name: Option<&'a str>,
+}
+
+impl<'a> BackupTarget<'a> {
+    /// The database the target is labelled with in status, metrics and
+    /// notifications: its name when `DB_NAMES` is set, else none.
+    fn database(self, config: &Config) -> Option<&'a str> {
+        self.db_name.filter(|_| config.db_subfolders)
+    }
+}
+
+/// Each of `kinds` as a backup target, with db expanded to one target per
+/// database in `DB_NAMES`, or to a single bundle target with `DB_BUNDLE`.
+fn backup_targets<'a>(config: &'a Config, kinds: &[BackupKind]) -> Vec<BackupTarget<'a>> {
+    let mut targets = Vec::with_capacity(kinds.len());
+    for &kind in kinds {
+        match kind {
+            BackupKind::Db if config.db_bundle => targets.push(BackupTarget {
+                kind,
+                db_name: None,
+            }),
+            BackupKind::Db => targets.extend(config.db_names.iter().map(|name| BackupTarget {
+                kind,
+                db_name: Some(name.as_str()),
+            })),
+            BackupKind::Minecraft => targets.push(BackupTarget {
+                kind,
+                db_name: None,
+            }),
+        }
+    }
+    targets
+}
+
+/// Back up every target of `kinds` concurrently, continuing past individual
+/// failures, then send a notification carrying every artifact's outcome.
+/// With `dump_only`, Google Drive is never touched and artifacts stay in the
+/// temp directory; with `dry_run`, storage is only listed, artifacts are
+/// deleted and nothing is notified or exported.
+async fn run_backups(
+    config: &Config,
+    command: &str,
+    kinds: &[BackupKind],
+    options: RunOptions,
+) -> anyhow::Result<()> {
+    let started_at = chrono::Utc::now();
+
+    let targets = backup_targets(config, kinds);
+    if targets.len() > config.max_artifacts_per_run {
         error!(
-            error = %e,
-            path = %archive_path.display(),
-            "Failed to remove temp file after upload"
+            planned = targets.len(),
+            limit = config.max_artifacts_per_run,
+            "Backup run would exceed MAX_ARTIFACTS_PER_RUN"
+        );
+        bail!(
+            "{} would produce {} artifacts, more than MAX_ARTIFACTS_PER_RUN={}",
+            command,
+            targets.len(),
+            config.max_artifacts_per_run
+        );
+    }
+    check_temp_space(config)?;
+
+    let mut artifacts = if options.dry_run {
+        // Only read, to show what retention would delete
+        let storage = match storage::connect(config).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!(error = %e, "Dry run: could not connect to storage; retention will not be simulated");
+                None
+            }
+        };
+        let storage = storage.as_deref();
+        futures::future::join_all(
+            targets
+                .iter()
+                .map(|&target| timed(backup_dry_run(config, storage, target, options))),
+        )
+        .await
+    } else if options.dump_only {
+        futures::future::join_all(
+            targets
+                .iter()
+                .map(|&target| timed(backup_local(config, target, options))),
+        )
+        .await
+    } else {
+        backup_all_to_storage(config, &targets, options).await
+    };
+    // Every path yields one result per target, in order
+    for (artifact, target) in artifacts.iter_mut().zip(&targets) {
+        artifact.database = target.database(config).map(str::to_string);
+    }
+
+    let event = BackupEvent {
+        command: command.to_string(),
+        started_at,
+        finished_at: chrono::Utc::now(),
+        artifacts,
+    };
+    // A dry run stored nothing, so nothing is announced or exported
+    if !options.dry_run {
+        notify::dispatch(config, &event).await;
+        notify::on_success::run(config, &event).await;
+    }
+    report::record_artifacts(&event.artifacts);
+    if !options.dry_run
+        && let Some(dir) = &config.textfile_collector_dir
+    {
+        metrics::write_textfiles(config, dir, &event.artifacts).await;
+    }
+    if config.db_subfolders {
+        log_database_summary(&event.artifacts);
+    }
+
+    event.into_result()
+}
+
+/// Log the outcome of each database's backup, then how many succeeded.
+fn log_database_summary(artifacts: &[ArtifactResult]) {
+    let mut attempted = 0;
+    let mut succeeded = 0;
+    for artifact in artifacts {
+        let Some(db_name) = &artifact.database else {
+            continue;
+        };
+        attempted += 1;
+        match &artifact.error {
+            None => {
+                succeeded += 1;
+                info!(
+                    db_name = %db_name,
+                    file_name = artifact.file_name.as_deref().unwrap_or("-"),
+                    "Database backup succeeded"
+                );
+            }
+            Some(e) => error!(db_name = %db_name, error = %e, "Database backup failed"),
+        }
+    }
+    if attempted > 0 {
+        info!(
+            succeeded = succeeded,
+            failed = attempted - succeeded,
+            "Backed up {} of {} databases",
+            succeeded,
+            attempted
         );
     }
+}
+
+/// Await one kind's backup, stamping the result with how long it took.
+async fn timed(backup: impl Future<Output = ArtifactResult>) -> ArtifactResult {
+    let started = std::time::Instant::now();
+    let mut result = backup.await;
+    result.duration = Some(started.elapsed());
+    result
+}
+
+/// Produce a backup of `target` and leave it in the temp directory.
+async fn backup_local(
+    config: &Config,
+    target: BackupTarget<'_>,
+    options: RunOptions,
+) -> ArtifactResult {
+    let kind = target.kind;
+    let result: anyhow::Result<(PathBuf, u64, Option<String>)> = async {
+        systemd::status(&format!("Creating {} backup", kind));
+        let (artifact, _) = create_artifact(config, target, options).await?;
+        let size_bytes = tokio::fs::metadata(&artifact.path).await?.len();
+        Ok((artifact.path, size_bytes, artifact.sha256))
+    }
+    .await;
 
+    match result {
+        Ok((path, size_bytes, sha256)) => {
+            info!(
+                backup_type = %kind,
+                path = %path.display(),
+                size_bytes = size_bytes,
+                "Dump-only mode: backup kept locally, skipping upload"
+            );
+            ArtifactResult {
+                kind,
+                database: None,
+                file_name: Some(artifact_name(&path)),
+                size_bytes: Some(size_bytes),
+                link: None,
+                error: None,
+                duration: None,
+                details: Some(ArtifactDetails {
+                    path,
+                    remote_id: None,
+                    sha256,
+                    web_view_link: None,
+                }),
+            }
+        }
+        Err(e) if e.downcast_ref::<backup::db::DbUnchanged>().is_some() => unchanged_result(kind),
+        Err(e) => {
+            error!(error = %e, backup_type = %kind, "Backup failed");
+            ArtifactResult {
+                kind,
+                database: None,
+                file_name: None,
+                size_bytes: None,
+                link: None,
+                error: Some(format!("{:#}", e)),
+                duration: None,
+                details: None,
+            }
+        }
+    }
+}
+
+/// Produce a backup of `target`, log the upload and prune a real run would do
+/// with it, then delete it. Storage and the status file are left untouched.
+async fn backup_dry_run(
+    config: &Config,
+    storage: Option<&dyn storage::Storage>,
+    target: BackupTarget<'_>,
+    options: RunOptions,
+) -> ArtifactResult {
+    let kind = target.kind;
+    let result: anyhow::Result<(String, u64)> = async {
+        systemd::status(&format!("Creating {} backup (dry run)", kind));
+        let (artifact, scope) = create_artifact(config, target, options).await?;
+        let file_name = artifact_name(&artifact.path);
+        let metadata = tokio::fs::metadata(&artifact.path).await;
+        remove_temp_file(&artifact.path).await;
+        let size_bytes = metadata?.len();
+        log_simulated_store(config, storage, target, scope, &file_name, size_bytes).await;
+        Ok((file_name, size_bytes))
+    }
+    .await;
+
+    match result {
+        Ok((file_name, size_bytes)) => ArtifactResult {
+            kind,
+            database: None,
+            file_name: Some(file_name),
+            size_bytes: Some(size_bytes),
+            link: None,
+            error: None,
+            duration: None,
+            details: None,
+        },
+        Err(e) if e.downcast_ref::<backup::db::DbUnchanged>().is_some() => unchanged_result(kind),
+        Err(e) => {
+            error!(error = %e, backup_type = %kind, "Backup failed");
+            ArtifactResult {
+                kind,
+                database: None,
+                file_name: None,
+                size_bytes: None,
+                link: None,
+                error: Some(format!("{:#}", e)),
+                duration: None,
+                details: None,
+            }
+        }
+    }
+}
+
+/// Log each step [`backup_to_storage`] would take after producing
+/// `file_name`, listing what retention would delete when `storage` is open.
+async fn log_simulated_store(
+    config: &Config,
+    storage: Option<&dyn storage::Storage>,
+    target: BackupTarget<'_>,
+    scope: ArchiveScope,
+    file_name: &str,
+    size_bytes: u64,
+) {
+    let kind = target.kind;
+    let size = util::format::humanize_bytes(size_bytes as f64);
+    let folder = storage_folder(config, target).join("/");
+    if config.storage_backend == "b2" {
+        info!(
+            backup_type = %kind,
+            key = %format!("{}/{}", folder, file_name),
+            size_bytes = size_bytes,
+            "Dry run: would upload {} ({}) to the B2 bucket",
+            file_name,
+            size
+        );
+    } else {
+        info!(
+            backup_type = %kind,
+            root_folder_id = %config.google_drive_folder_id,
+            folder_path = ?config.google_drive_folder_path,
+            mirror_folder_ids = ?config.google_drive_mirror_folder_ids,
+            size_bytes = size_bytes,
+            "Dry run: would upload {} ({}) to the '{}' folder of {} Drive root(s)",
+            file_name,
+            size,
+            folder,
+            1 + config.google_drive_mirror_folder_ids.len()
+        );
+        if config.upload_checksum_sidecar {
+            info!(
+                "Dry run: would upload checksum sidecar {}.{} next to it",
+                file_name,
+                backup::checksum::SIDECAR_EXTENSION
+            );
+        }
+        if let Some(grant) = &config.drive_grant_reader {
+            info!(grant = ?grant, "Dry run: would share {} with the reader grant", file_name);
+        }
+    }
+
+    match retention_policy(config, kind, &config.prune_keep_ids) {
+        Some(policy) if config.prune_after_upload => {
+            info!(
+                backup_type = %kind,
+                pinned_ids = ?policy.pinned_ids,
+                min_age = ?policy.min_age,
+                "Dry run: would delete all but the {} newest {}* backups",
+                policy.keep,
+                policy.name_prefix
+            );
+            if let Some(storage) = storage {
+                let folder = storage_folder(config, target);
+                log_simulated_prune(storage, &folder, &policy, file_name).await;
+            }
+        }
+        Some(_) => {
+            info!(backup_type = %kind, "Dry run: would not prune; PRUNE_AFTER_UPLOAD is off")
+        }
+        None => info!(backup_type = %kind, "Dry run: would not prune; no retention is configured"),
+    }
+    info!(
+        backup_type = %kind,
+        full = scope.is_full(),
+        "Dry run: would record the run in the status file; it was left unchanged"
+    );
+}
+
+/// Log the backups at the primary destination `policy` would delete once
+/// `file_name` was uploaded next to them.
+async fn log_simulated_prune(
+    storage: &dyn storage::Storage,
+    folder: &[&str],
+    policy: &drive::prune::PrunePolicy<'_>,
+    file_name: &str,
+) {
+    let mut objects = match storage.list(folder, &policy.name_prefix).await {
+        Ok(o) => o,
+        Err(e) => {
+            warn!(error = %e, "Dry run: could not list stored backups to simulate retention");
+            return;
+        }
+    };
+    // The new backup is the newest, and has no id a pin could match
+    objects.insert(
+        0,
+        storage::StoredObject {
+            id: String::new(),
+            name: file_name.to_string(),
+            size_bytes: None,
+            created_at: Some(chrono::Utc::now()),
+            suspect: false,
+        },
+    );
+
+    let selected = drive::prune::select_stored(policy, &objects);
+    if selected.is_empty() {
+        info!(
+            stored = objects.len() - 1,
+            "Dry run: retention would delete nothing"
+        );
+    }
+    for (index, reason) in selected {
+        let object = &objects[index];
+        info!(
+            file_name = %object.name,
+            file_id = %object.id,
+            created_at = ?object.created_at,
+            reason = %reason,
+            "Dry run: would delete"
+        );
+    }
+}
+
+/// Apply retention to every type folder at every destination of the
+/// backend, or with `emit_plan`, write the deletions it would make to that
+/// file and delete nothing.
+async fn run_prune(
+    config: &Config,
+    extra_keep_ids: Vec<String>,
+    emit_plan: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut pinned_ids = config.prune_keep_ids.clone();
+    pinned_ids.extend(extra_keep_ids);
+
+    if let Some(path) = emit_plan {
+        return write_prune_plan(config, &pinned_ids, path).await;
+    }
+
+    let storage = storage::connect(config).await?;
+    for target in backup_targets(config, &[BackupKind::Minecraft, BackupKind::Db]) {
+        let Some(policy) = retention_policy(config, target.kind, &pinned_ids) else {
+            continue;
+        };
+        for destination in storage
+            .destinations(&storage_folder(config, target))
+            .await?
+        {
+            let deleted = storage.prune(&destination, &policy).await?;
+            report::record_prune(target.kind, &destination, deleted);
+        }
+    }
+    Ok(())
+}
+
+/// Write the deletions retention would make in every Drive type folder to
+/// the plan file at `path`, deleting nothing.
+async fn write_prune_plan(
+    config: &Config,
+    pinned_ids: &[String],
+    path: &Path,
+) -> anyhow::Result<()> {
+    if config.storage_backend == "b2" {
+        bail!("--emit-plan is only supported with Google Drive storage");
+    }
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let mut deletions = Vec::new();
+    for target in backup_targets(config, &[BackupKind::Minecraft, BackupKind::Db]) {
+        let Some(policy) = retention_policy(config, target.kind, pinned_ids) else {
+            continue;
+        };
+        for folder_id in resolve_folders(config, hub, &storage_folder(config, target)).await? {
+            deletions.extend(drive::prune::plan_prune(hub, &folder_id, &policy).await?);
+        }
+    }
+
+    let plan = drive::prune::PrunePlan {
+        created_at: chrono::Utc::now(),
+        deletions,
+    };
+    let json = serde_json::to_vec_pretty(&plan)?;
+    util::fs::write_atomic(path, &json).await?;
+    info!(
+        path = %path.display(),
+        deletions = plan.deletions.len(),
+        "Wrote prune plan; nothing was deleted"
+    );
+    println!(
+        "Wrote {} planned deletion(s) to {}",
+        plan.deletions.len(),
+        path.display()
+    );
     Ok(())
 }
 
-async fn run_all(config: &Config) -> anyhow::Result<()> {
-    let hub = drive::auth::build_hub(&config.google_credentials_path).await?;
+/// Delete exactly the files listed in the prune plan at `path`, re-checking
+/// each one first. Fails if Drive refused any deletion.
+async fn run_apply_prune_plan(config: &Config, path: &Path) -> anyhow::Result<()> {
+    if config.storage_backend == "b2" {
+        bail!("--apply-plan is only supported with Google Drive storage");
+    }
+    let bytes = match tokio::fs::read(path).await {
+        Ok(b) => b,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Failed to read prune plan");
+            bail!("Failed to read prune plan {}: {}", path.display(), e);
+        }
+    };
+    let plan: drive::prune::PrunePlan = match serde_json::from_slice(&bytes) {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "Prune plan is not valid JSON");
+            bail!("Prune plan {} is invalid: {}", path.display(), e);
+        }
+    };
+    info!(
+        path = %path.display(),
+        planned_at = %plan.created_at,
+        deletions = plan.deletions.len(),
+        "Applying prune plan"
+    );
 
-    // --- DB backup ---
-    let db_folder_id =
-        drive::upload::find_or_create_folder(&hub, &config.google_drive_folder_id, "DB_Backups")
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let outcome = drive::prune::apply_plan(accounts.hub(), &plan).await;
+    info!(
+        deleted = outcome.deleted,
+        skipped = outcome.skipped,
+        failed = outcome.failed,
+        "Prune plan applied"
+    );
+    println!(
+        "Deleted {}, skipped {} no longer matching, failed {}",
+        outcome.deleted, outcome.skipped, outcome.failed
+    );
+    if outcome.failed > 0 {
+        bail!(
+            "{} of {} planned deletions failed",
+            outcome.failed,
+            plan.deletions.len()
+        );
+    }
+    Ok(())
+}
+
+/// Rebuild a split archive from the primary Drive folder of `kind`, then
+/// optionally unpack it.
+async fn run_fetch_archive(
+    config: &Config,
+    index_id: &str,
+    kind: BackupKind,
+    dest: Option<PathBuf>,
+    extract_to: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let folder_ids = resolve_type_folders(config, hub, kind).await?;
+    let Some(folder_id) = folder_ids.first() else {
+        bail!("No Drive folder configured for {} backups", kind);
+    };
+
+    let dest = dest.unwrap_or_else(|| config.backup_temp_dir.clone());
+    let archive = backup::split::fetch_split_archive(hub, folder_id, index_id, &dest).await?;
+
+    if let Some(dir) = extract_to {
+        if !archive.to_string_lossy().ends_with(".tar.zst") {
+            bail!(
+                "--extract-to only applies to .tar.zst archives; {} was kept as is",
+                archive.display()
+            );
+        }
+        info!(archive = %archive.display(), dest = %dir.display(), "Extracting joined archive");
+        backup::split::extract_archive(
+            &archive,
+            &dir,
+            config.tar_preserve_xattrs,
+            config.zstd_dict_path.as_deref(),
+        )
+        .await?;
+        backup::restore_log::record_tree(config, &archive, &dir).await?;
+    }
+
+    println!("{}", archive.display());
+    Ok(())
+}
+
+/// Download the `latest` newest backups of `kind` from its primary Drive
+/// folder into `dest`, verifying each. Every backup is attempted; the command
+/// fails if any of them did.
+async fn run_download(
+    config: &Config,
+    kind: BackupKind,
+    latest: usize,
+    dest: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let folder_ids = resolve_type_folders(config, hub, kind).await?;
+    let Some(folder_id) = folder_ids.first() else {
+        bail!("No Drive folder configured for {} backups", kind);
+    };
+    let dest = dest.unwrap_or_else(|| config.backup_temp_dir.clone());
+    if let Err(e) = tokio::fs::create_dir_all(&dest).await {
+        bail!("Failed to create {}: {}", dest.display(), e);
+    }
+
+    // Newest first, so the first `latest` backups are the ones wanted
+    let prefix = kind.artifact_prefix(config.host_tag.as_deref());
+    let (sidecars, backups): (Vec<_>, Vec<_>) =
+        drive::prune::list_all_files_in_folder(hub, folder_id)
+            .await?
+            .into_iter()
+            .filter(|f| f.name.as_deref().is_some_and(|n| n.starts_with(&prefix)))
+            .partition(|f| {
+                f.name
+                    .as_deref()
+                    .is_some_and(backup::checksum::is_sidecar_name)
+            });
+    let chosen: Vec<_> = backups.into_iter().take(latest).collect();
+    if chosen.len() < latest {
+        warn!(
+            requested = latest,
+            available = chosen.len(),
+            "Fewer backups on Google Drive than requested"
+        );
+    }
+
+    let mut downloaded = 0;
+    let mut total_bytes: u64 = 0;
+    for file in &chosen {
+        let name = file.name.as_deref().unwrap_or("unknown");
+        let sidecar_name = format!("{}.{}", name, backup::checksum::SIDECAR_EXTENSION);
+        let sidecar = sidecars
+            .iter()
+            .find(|s| s.name.as_deref() == Some(sidecar_name.as_str()));
+        match drive::download::download_verified(hub, file, sidecar, &dest).await {
+            Ok((path, size)) => {
+                println!(
+                    "ok      {} ({}{})",
+                    path.display(),
+                    util::format::humanize_bytes(size as f64),
+                    if sidecar.is_some() {
+                        ", sha256 verified"
+                    } else {
+                        ""
+                    }
+                );
+                downloaded += 1;
+                total_bytes += size;
+            }
+            Err(e) => {
+                error!(error = %e, name = name, "Failed to download backup");
+                println!("FAILED  {}: {}", name, e);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} backup(s) downloaded to {}, {} total",
+        downloaded,
+        chosen.len(),
+        dest.display(),
+        util::format::humanize_bytes(total_bytes as f64)
+    );
+    if downloaded < chosen.len() {
+        bail!(
+            "{} of {} downloads failed",
+            chosen.len() - downloaded,
+            chosen.len()
+        );
+    }
+    Ok(())
+}
+
+/// Print the backups in the primary Drive folder of each type, newest first,
+/// marking which ones the configured retention would keep. Checksum sidecars
+/// are left out.
+async fn run_list(
+    config: &Config,
+    backup_type: Option<BackupKind>,
+    format: list::ListFormat,
+) -> anyhow::Result<()> {
+    let storage = storage::connect(config).await?;
+
+    let kinds: Vec<BackupKind> = match backup_type {
+        Some(kind) => vec![kind],
+        None => BackupKind::ALL.to_vec(),
+    };
+
+    // Retention applies per folder, so each database's backups are ranked alone
+    let mut listings = Vec::new();
+    for target in backup_targets(config, &kinds) {
+        let kind = target.kind;
+        let prefix = kind.artifact_prefix(config.host_tag.as_deref());
+        let objects = storage
+            .list(&storage_folder(config, target), &prefix)
             .await?;
+        // The same selection a prune makes; suspect backups are left alone
+        let selected: Option<HashSet<usize>> =
+            retention_policy(config, kind, &config.prune_keep_ids).map(|policy| {
+                drive::prune::select_stored(&policy, &objects)
+                    .into_iter()
+                    .map(|(index, _)| index)
+                    .collect()
+            });
+        for (index, object) in objects.into_iter().enumerate() {
+            let mut listing = list::BackupListing::stored(kind, object);
+            listing.survives_prune = selected.as_ref().map(|s| !s.contains(&index));
+            listings.push(listing);
+        }
+    }
+
+    list::print(&listings, format)
+}
 
-    let dump_path = backup::db::backup_db(config).await?;
-    drive::upload::upload_file(&hub, &db_folder_id, &dump_path).await?;
+/// Scrub every backup in the primary Drive folder of each type, `concurrency`
+/// at a time and started in `order`, and print a pass/warn/fail line per file.
+async fn run_scrub(
+    config: &Config,
+    backup_type: Option<BackupKind>,
+    concurrency: usize,
+    order: scrub::ScrubOrder,
+) -> anyhow::Result<()> {
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
 
-    if let Err(e) = tokio::fs::remove_file(&dump_path).await {
-        error!(
-            error = %e,
-            path = %dump_path.display(),
-            "Failed to remove temp DB dump after upload"
+    let kinds: Vec<BackupKind> = match backup_type {
+        Some(kind) => vec![kind],
+        None => BackupKind::ALL.to_vec(),
+    };
+
+    // Checked up front so a missing dictionary fails before any download
+    backup::dict::load(config.zstd_dict_path.as_deref())?;
+    let mut manifest = scrub::ScrubManifest::load(&config.scrub_manifest_path).await?;
+    let mut files = Vec::new();
+    for kind in kinds {
+        let folder_ids = resolve_type_folders(config, hub, kind).await?;
+        let Some(folder_id) = folder_ids.first() else {
+            continue;
+        };
+
+        let prefix = kind.artifact_prefix(config.host_tag.as_deref());
+        let mut present = HashSet::new();
+        for file in drive::prune::list_all_files_in_folder(hub, folder_id).await? {
+            let (Some(id), Some(name)) = (&file.id, &file.name) else {
+                continue;
+            };
+            if !name.starts_with(&prefix) || backup::checksum::is_sidecar_name(name) {
+                continue;
+            }
+            present.insert(id.clone());
+            files.push((kind, file));
+        }
+        manifest.forget_missing(&prefix, &present);
+    }
+
+    // Each folder lists newest first; merge the types by age
+    match order {
+        scrub::ScrubOrder::Newest => files.sort_by_key(|(_, f)| std::cmp::Reverse(f.created_time)),
+        scrub::ScrubOrder::Oldest => files.sort_by_key(|(_, f)| f.created_time),
+    }
+    let checked: Vec<_> = files
+        .iter()
+        .map(|(kind, file)| (file, scrub::check_manifest(*kind, file, &mut manifest)))
+        .collect();
+    manifest.save(&config.scrub_manifest_path).await?;
+
+    // Started in order, collected as they finish, printed in start order
+    let dict_path = config.zstd_dict_path.as_deref();
+    let mut indexed: Vec<(usize, scrub::ScrubReport)> =
+        stream::iter(checked.into_iter().enumerate())
+            .map(|(i, (file, report))| async move {
+                (i, scrub::probe_file(hub, file, report, dict_path).await)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+    indexed.sort_by_key(|(i, _)| *i);
+    let reports: Vec<scrub::ScrubReport> = indexed.into_iter().map(|(_, r)| r).collect();
+
+    scrub::print(&reports);
+
+    let failed = reports
+        .iter()
+        .filter(|r| r.status == scrub::ScrubStatus::Fail)
+        .count();
+    let warned = reports
+        .iter()
+        .filter(|r| r.status == scrub::ScrubStatus::Warn)
+        .count();
+    info!(
+        scrubbed = reports.len(),
+        failed = failed,
+        warned = warned,
+        "Scrub completed"
+    );
+    if failed > 0 {
+        bail!("{} of {} backups failed scrub", failed, reports.len());
+    }
+    Ok(())
+}
+
+/// Train a zstd dictionary on the Minecraft server directory and write it to
+/// `output` or `ZSTD_DICT_PATH`, plus a copy named by its id. An existing
+/// dictionary is only replaced with `force`, and is kept under its own id
+/// first so archives written with it still restore.
+async fn run_train_dict(
+    config: &Config,
+    output: Option<PathBuf>,
+    max_size: usize,
+    samples: usize,
+    force: bool,
+) -> anyhow::Result<()> {
+    let Some(output) = output.or_else(|| config.zstd_dict_path.clone()) else {
+        bail!("train-dict needs --output or ZSTD_DICT_PATH");
+    };
+    if output.exists() {
+        if !force {
+            bail!(
+                "{} already exists; archives written with it need it to restore. Pass --force to replace it",
+                output.display()
+            );
+        }
+        keep_dict_by_id(&output).await?;
+    }
+
+    let source = config.minecraft_server_path.clone();
+    let dict =
+        match tokio::task::spawn_blocking(move || backup::dict::train(&source, max_size, samples))
+            .await
+        {
+            Ok(r) => r?,
+            Err(e) => bail!("Dictionary training task panicked: {}", e),
+        };
+
+    if let Some(id) = backup::dict::dict_id(&dict) {
+        util::fs::write_atomic(&backup::dict::by_id_path(&output, id), &dict).await?;
+    }
+    util::fs::write_atomic(&output, &dict).await?;
+    info!(
+        path = %output.display(),
+        size_bytes = dict.len(),
+        dict_id = ?backup::dict::dict_id(&dict),
+        "Wrote zstd dictionary; archives made with it need the same file to restore"
+    );
+    Ok(())
+}
+
+/// Copy the dictionary at `path` to its by-id name before it is replaced.
+async fn keep_dict_by_id(path: &Path) -> anyhow::Result<()> {
+    let Some(dict) = backup::dict::load(Some(path))? else {
+        return Ok(());
+    };
+    let Some(id) = backup::dict::dict_id(&dict) else {
+        bail!(
+            "{} is a raw dictionary without an id and cannot be kept beside its replacement; move it aside first",
+            path.display()
         );
+    };
+    let kept = backup::dict::by_id_path(path, id);
+    if kept.is_file() {
+        return Ok(());
     }
+    util::fs::write_atomic(&kept, &dict).await?;
+    info!(path = %kept.display(), dict_id = id, "Kept the previous zstd dictionary");
+    Ok(())
+}
 
-    // --- Minecraft backup ---
-    let mc_folder_id = drive::upload::find_or_create_folder(
-        &hub,
-        &config.google_drive_folder_id,
-        "Minecraft_Backups",
-    )
-    .await?;
+/// Find backups with matching md5 checksums in every Drive folder of each
+/// type and, with `delete`, remove all but the newest copy.
+async fn run_dedup(
+    config: &Config,
+    backup_type: Option<BackupKind>,
+    delete: bool,
+) -> anyhow::Result<()> {
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let kinds: Vec<BackupKind> = match backup_type {
+        Some(kind) => vec![kind],
+        None => BackupKind::ALL.to_vec(),
+    };
 
-    let archive_path = backup::minecraft::backup_minecraft(config).await?;
-    drive::upload::upload_file(&hub, &mc_folder_id, &archive_path).await?;
+    let mut all_groups = Vec::new();
+    for kind in kinds {
+        let prefix = kind.artifact_prefix(config.host_tag.as_deref());
+        for folder_id in resolve_type_folders(config, hub, kind).await? {
+            let files = drive::prune::list_all_files_in_folder(hub, &folder_id).await?;
+            let groups = drive::dedup::find_duplicates(&files, &prefix);
+            if delete && !groups.is_empty() {
+                let deleted =
+                    drive::dedup::delete_duplicates(hub, &groups, &files, &config.prune_keep_ids)
+                        .await;
+                report::record_prune(kind, &folder_id, deleted);
+            }
+            all_groups.extend(groups);
+        }
+    }
 
-    // Prune old Minecraft backups
-    drive::prune::prune_old_backups(&hub, &mc_folder_id, config.mc_retention_count).await?;
+    drive::dedup::print(&all_groups);
+    if !delete && !all_groups.is_empty() {
+        println!("Dry run; re-run with --yes to delete the duplicates");
+    }
+    Ok(())
+}
 
-    if let Err(e) = tokio::fs::remove_file(&archive_path).await {
-        error!(
-            error = %e,
-            path = %archive_path.display(),
-            "Failed to remove temp Minecraft archive after upload"
+/// Backups of `kind` in its primary Drive folder (`DB_NAME`'s for db),
+/// newest first.
+async fn list_backups(
+    config: &Config,
+    hub: &drive::auth::DriveHub,
+    kind: BackupKind,
+) -> anyhow::Result<Vec<list::BackupListing>> {
+    list_target_backups(config, hub, primary_target(config, kind)).await
+}
+
+/// Backups of `target` in its primary Drive folder, newest first.
+async fn list_target_backups(
+    config: &Config,
+    hub: &drive::auth::DriveHub,
+    target: BackupTarget<'_>,
+) -> anyhow::Result<Vec<list::BackupListing>> {
+    let kind = target.kind;
+    let folder_ids = resolve_folders(config, hub, &storage_folder(config, target)).await?;
+    let Some(folder_id) = folder_ids.first() else {
+        return Ok(Vec::new());
+    };
+
+    let prefix = kind.artifact_prefix(config.host_tag.as_deref());
+    let objects = storage::drive::list_folder(hub, folder_id, &prefix).await?;
+    Ok(objects
+        .into_iter()
+        .map(|object| list::BackupListing::stored(kind, object))
+        .collect())
+}
+
+/// Where `restore-minecraft` puts the backup.
+enum RestoreTarget {
+    /// Over the live server directory, stopping and restarting the server.
+    Live,
+    /// Into a separate directory, keeping only files the filter lets through.
+    Dir(PathBuf, backup::restore::ExtFilter),
+    /// Nowhere; the archive is only read end to end.
+    VerifyOnly,
+}
+
+/// Restore, extract or verify the Minecraft backup chain `chain` (a full
+/// backup, then the archives built on it, oldest first), decrypting each
+/// archive first when it is encrypted.
+async fn restore_minecraft_to(
+    config: &Config,
+    chain: &[PathBuf],
+    target: &RestoreTarget,
+) -> anyhow::Result<()> {
+    let mut decrypted = Vec::new();
+    let result = async {
+        let mut plain = Vec::with_capacity(chain.len());
+        for archive in chain {
+            match crypto::decrypt_if_encrypted(config, archive).await? {
+                Some(path) => {
+                    decrypted.push(path.clone());
+                    plain.push(path);
+                }
+                None => plain.push(archive.clone()),
+            }
+        }
+        restore_plain_minecraft_to(config, &plain, target).await
+    }
+    .await;
+    for path in &decrypted {
+        remove_temp_file(path).await;
+    }
+    result
+}
+
+/// [`restore_minecraft_to`] for archives that are not encrypted.
+async fn restore_plain_minecraft_to(
+    config: &Config,
+    chain: &[PathBuf],
+    target: &RestoreTarget,
+) -> anyhow::Result<()> {
+    match target {
+        RestoreTarget::Live => backup::restore::restore_minecraft(config, chain)
+            .await
+            .map(|_| ()),
+        RestoreTarget::Dir(dir, filter) => {
+            backup::restore::extract_into(config, chain, dir, filter).await
+        }
+        RestoreTarget::VerifyOnly => {
+            backup::restore::verify_chain(chain, config.zstd_dict_path.as_deref())
+                .await
+                .map(|_| ())
+        }
+    }
+}
+
+/// Restore a Minecraft backup from a local path, or download it from Drive by
+/// file id or `latest`, into `target`. A differential or incremental from
+/// Drive is restored on top of its full backup and the archives between
+/// them. Without `source`, a terminal session picks the backup from a
+/// numbered list; anything else must name one explicitly.
+async fn run_restore_minecraft(
+    config: &Config,
+    source: Option<String>,
+    target: &RestoreTarget,
+    dump_only: bool,
+) -> anyhow::Result<()> {
+    if let Some(path) = source.as_deref().map(Path::new)
+        && path.is_file()
+    {
+        return restore_minecraft_to(config, &[path.to_path_buf()], target).await;
+    }
+
+    if source.is_none() && !(std::io::stdin().is_terminal() && std::io::stdout().is_terminal()) {
+        bail!(
+            "restore-minecraft needs an archive path, a Drive file id or `latest` when not run from a terminal"
         );
     }
+    if dump_only {
+        bail!("--dump-only cannot be combined with restoring from Google Drive");
+    }
+
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let archives: Vec<list::BackupListing> = list_backups(config, hub, BackupKind::Minecraft)
+        .await?
+        .into_iter()
+        .filter(|l| crypto::plaintext_name(&l.name).ends_with(".tar.zst"))
+        .collect();
+    // Archives older than every full backup have nothing to apply them to
+    let prefix = BackupKind::Minecraft.artifact_prefix(config.host_tag.as_deref());
+    let chains: Vec<chain::RestoreChain> =
+        chain::restore_chains(archives.iter().map(|l| l.name.as_str()), &prefix)
+            .into_iter()
+            .filter(|c| c.full.is_some())
+            .collect();
+    let restorable: Vec<list::BackupListing> = chains
+        .iter()
+        .flat_map(|c| c.members.iter().map(|&i| archives[i].clone()))
+        .collect();
+
+    let chosen = match source.as_deref() {
+        None => list::pick(&restorable)?,
+        Some("latest") => match restorable.first() {
+            Some(l) => l,
+            None => bail!("No restorable Minecraft backups found on Google Drive"),
+        },
+        Some(id) => match restorable.iter().find(|l| l.id == id) {
+            Some(l) => l,
+            None => bail!(
+                "'{}' is neither a local file nor the Drive file id of a restorable Minecraft backup",
+                id
+            ),
+        },
+    };
+
+    // The chosen archive, its full backup and everything between, oldest first
+    let Some((position, chosen_chain)) =
+        archives
+            .iter()
+            .position(|l| l.id == chosen.id)
+            .and_then(|p| {
+                chains
+                    .iter()
+                    .find(|c| c.members.contains(&p))
+                    .map(|c| (p, c))
+            })
+    else {
+        bail!("'{}' belongs to no restorable backup chain", chosen.name);
+    };
+    let needed: Vec<&list::BackupListing> = chosen_chain
+        .members
+        .iter()
+        .rev()
+        .filter(|&&i| i >= position)
+        .map(|&i| &archives[i])
+        .collect();
+
+    let mut downloaded = Vec::with_capacity(needed.len());
+    let result = async {
+        for listing in &needed {
+            info!(name = %listing.name, file_id = %listing.id, "Downloading Minecraft backup from Google Drive");
+            let path = drive::download::local_path(&config.backup_temp_dir, &listing.name)?;
+            downloaded.push(path.clone());
+            drive::download::download_file(hub, &listing.id, &path).await?;
+        }
+        info!(name = %chosen.name, chain_len = needed.len(), "Restoring Minecraft backup");
+        restore_minecraft_to(config, &downloaded, target).await
+    }
+    .await;
+    for path in &downloaded {
+        remove_temp_file(path).await;
+    }
+    result
+}
+
+/// Restore a db backup from a local path or the primary Drive folder into
+/// `DB_NAME`, optionally limited to `tables`.
+async fn run_restore_db(
+    config: &Config,
+    source: Option<String>,
+    tables: &[String],
+    dump_only: bool,
+) -> anyhow::Result<()> {
+    if let Some(path) = source.as_deref().map(Path::new)
+        && path.is_file()
+    {
+        return restore_db_from(config, path, tables).await;
+    }
+    if dump_only {
+        bail!("--dump-only cannot be combined with restoring from Google Drive");
+    }
+
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
 
+    let dumps: Vec<list::BackupListing> = list_backups(config, hub, BackupKind::Db)
+        .await?
+        .into_iter()
+        .filter(|l| backup::db_restore::is_db_dump_name(crypto::plaintext_name(&l.name)))
+        .collect();
+
+    let chosen = match source.as_deref() {
+        None if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() => {
+            list::pick(&dumps)?
+        }
+        None | Some("latest") => match dumps.first() {
+            Some(l) => l,
+            None => bail!("No db dumps found on Google Drive"),
+        },
+        Some(id) => match dumps.iter().find(|l| l.id == id) {
+            Some(l) => l,
+            None => bail!(
+                "'{}' is neither a local file nor the Drive file id of a db dump",
+                id
+            ),
+        },
+    };
+
+    info!(name = %chosen.name, file_id = %chosen.id, "Restoring db backup from Google Drive");
+    let downloaded = drive::download::local_path(&config.backup_temp_dir, &chosen.name)?;
+    drive::download::download_file(hub, &chosen.id, &downloaded).await?;
+
+    let result = restore_db_from(config, &downloaded, tables).await;
+    remove_temp_file(&downloaded).await;
+    result
+}
+
+/// [`backup::db_restore::restore_db`], decrypting `archive` first when needed.
+async fn restore_db_from(config: &Config, archive: &Path, tables: &[String]) -> anyhow::Result<()> {
+    let decrypted = crypto::decrypt_if_encrypted(config, archive).await?;
+    let result =
+        backup::db_restore::restore_db(config, decrypted.as_deref().unwrap_or(archive), tables)
+            .await;
+    if let Some(path) = &decrypted {
+        remove_temp_file(path).await;
+    }
+    result
+}
+
+/// Validate the newest db backup in the primary Drive folder by restoring it
+/// into a scratch database.
+async fn run_validate_restore(config: &Config) -> anyhow::Result<()> {
+    let accounts =
+        DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+    let hub = accounts.hub();
+
+    let folder_ids = resolve_type_folders(config, hub, BackupKind::Db).await?;
+    let Some(folder_id) = folder_ids.first() else {
+        bail!("No Drive folder configured for db backups");
+    };
+
+    systemd::status("Validating latest db backup");
+    let output = backup::validate::validate_latest_db_backup(config, hub, folder_id).await?;
+    info!(result = %output, "Restore validation passed");
+    println!("{}", output);
+
+    Ok(())
+}
+
+/// Connect to the configured backend once and back up all of `targets`
+/// through it concurrently; one failing never cancels the others. A failure
+/// to connect fails every artifact.
+///
+/// The targets are joined on this task rather than spawned, so that the
+/// MAX_RUNTIME_SECS timeout, which drops this future, also stops every
+/// upload still in flight. They still run in parallel: archiving and
+/// dumping run on blocking threads, and each HTTP connection runs as its
+/// own task.
+async fn backup_all_to_storage(
+    config: &Config,
+    targets: &[BackupTarget<'_>],
+    options: RunOptions,
+) -> Vec<ArtifactResult> {
+    match storage::connect(config).await {
+        Ok(storage) => {
+            futures::future::join_all(
+                targets
+                    .iter()
+                    .map(|&target| timed(backup_to_storage(config, &*storage, target, options))),
+            )
+            .await
+        }
+        Err(e) => targets
+            .iter()
+            .map(|&BackupTarget { kind, .. }| ArtifactResult {
+                kind,
+                database: None,
+                file_name: None,
+                size_bytes: None,
+                link: None,
+                error: Some(format!("{:#}", e)),
+                duration: None,
+                details: None,
+            })
+            .collect(),
+    }
+}
+
+/// Produce a backup of `target`, upload it to every destination of the
+/// backend, prune old backups there and record the outcome in the status
+/// file.
+async fn backup_to_storage(
+    config: &Config,
+    storage: &dyn storage::Storage,
+    target: BackupTarget<'_>,
+    options: RunOptions,
+) -> ArtifactResult {
+    let kind = target.kind;
+    let started_at = chrono::Utc::now();
+    let result: anyhow::Result<(String, u64, ArtifactDetails)> = async {
+        let folder = storage_folder(config, target);
+
+        systemd::status(&format!("Creating {} backup", kind));
+        let (artifact, scope) = create_artifact(config, target, options).await?;
+        let artifact_path = artifact.path.clone();
+        let size_bytes = tokio::fs::metadata(&artifact_path).await?.len();
+        systemd::status(&format!("Uploading {} backup", kind));
+        let uploaded = match upload_dictionary(config, storage, &folder, kind).await {
+            Ok(()) => storage.upload(&folder, &artifact, kind).await,
+            Err(e) => Err(e),
+        };
+
+        // The artifact is not kept around for a retry, uploaded or not
+        remove_temp_file(&artifact_path).await;
+        let stored = uploaded?;
+
+        // Prune old backups after successful upload
+        if config.prune_after_upload
+            && let Some(policy) = retention_policy(config, kind, &config.prune_keep_ids)
+        {
+            systemd::status(&format!("Pruning old {} backups", kind));
+            for destination in &stored.destinations {
+                let deleted = storage.prune(destination, &policy).await?;
+                report::record_prune(kind, destination, deleted);
+            }
+        }
+
+        record_uploaded(
+            config,
+            target,
+            scope,
+            started_at,
+            size_bytes,
+            &artifact.db_writes,
+        )
+        .await;
+
+        let details = ArtifactDetails {
+            remote_id: stored.id,
+            web_view_link: stored.link,
+            sha256: artifact.sha256,
+            path: artifact_path,
+        };
+        Ok((artifact_name(&details.path), size_bytes, details))
+    }
+    .await;
+
+    uploaded_result(config, target, started_at, result).await
+}
+
+/// Upload the `ZSTD_DICT_PATH` dictionary a Minecraft archive is compressed
+/// with into `folder`, under its id, unless it is there already. Without it
+/// the archive cannot be restored on another machine.
+async fn upload_dictionary(
+    config: &Config,
+    storage: &dyn storage::Storage,
+    folder: &[&str],
+    kind: BackupKind,
+) -> anyhow::Result<()> {
+    if kind != BackupKind::Minecraft {
+        return Ok(());
+    }
+    let Some(dict) = backup::dict::load(config.zstd_dict_path.as_deref())? else {
+        return Ok(());
+    };
+    let Some(id) = backup::dict::dict_id(&dict) else {
+        warn!("ZSTD_DICT_PATH is a raw dictionary without an id, not uploading it");
+        return Ok(());
+    };
+    let name = backup::dict::id_file_name(id);
+    if storage
+        .list(folder, &name)
+        .await?
+        .iter()
+        .any(|o| o.name == name)
+    {
+        return Ok(());
+    }
+
+    let path = config.backup_temp_dir.join(&name);
+    util::fs::write_atomic(&path, &dict).await?;
+    let dictionary = backup::BackupArtifact {
+        path: path.clone(),
+        sha256: None,
+        db_writes: Vec::new(),
+        database: None,
+    };
+    let uploaded = storage.upload(folder, &dictionary, kind).await;
+    remove_temp_file(&path).await;
+    uploaded?;
+    info!(name = %name, dict_id = id, "Uploaded zstd dictionary next to the archives that need it");
     Ok(())
 }
 
-async fn run_prune(config: &Config) -> anyhow::Result<()> {
-    let hub = drive::auth::build_hub(&config.google_credentials_path).await?;
-    let folder_id = drive::upload::find_or_create_folder(
-        &hub,
-        &config.google_drive_folder_id,
-        "Minecraft_Backups",
+/// Persist chain position, size history and db write counters for an
+/// uploaded artifact.
+async fn record_uploaded(
+    config: &Config,
+    target: BackupTarget<'_>,
+    scope: ArchiveScope,
+    started_at: chrono::DateTime<chrono::Utc>,
+    size_bytes: u64,
+    db_writes: &[(String, status::DbWriteMark)],
+) {
+    let kind = target.kind;
+    // Incrementals sit outside the differential chain and leave it untouched
+    if kind == BackupKind::Minecraft && !matches!(scope, ArchiveScope::Incremental { .. }) {
+        status::record_chain(&config.status_file_path, kind, scope.is_full(), started_at).await;
+    }
+    if scope.is_full() {
+        let sample = status::SizeSample {
+            at: started_at,
+            size_bytes,
+        };
+        let database = target.database(config);
+        status::record_size(&config.status_file_path, kind, database, sample).await;
+    }
+    for (db_name, mark) in db_writes {
+        status::record_db_writes(&config.status_file_path, db_name, mark).await;
+    }
+}
+
+/// Record the outcome of a run that started at `started_at` in the status
+/// file and turn it into an artifact result for notifications.
+async fn uploaded_result(
+    config: &Config,
+    target: BackupTarget<'_>,
+    started_at: chrono::DateTime<chrono::Utc>,
+    result: anyhow::Result<(String, u64, ArtifactDetails)>,
+) -> ArtifactResult {
+    let (kind, database) = (target.kind, target.database(config));
+    let path = &config.status_file_path;
+    match result {
+        Err(e) if e.downcast_ref::<backup::db::DbUnchanged>().is_some() => {
+            status::record_unchanged(path, kind, database, started_at).await;
+            unchanged_result(kind)
+        }
+        Ok((file_name, size_bytes, details)) => {
+            status::record_run(path, kind, database, started_at, Ok(&file_name)).await;
+            ArtifactResult {
+                kind,
+                database: None,
+                file_name: Some(file_name),
+                size_bytes: Some(size_bytes),
+                link: details
+                    .web_view_link
+                    .clone()
+                    .filter(|_| config.notify_include_link),
+                error: None,
+                duration: None,
+                details: Some(details),
+            }
+        }
+        Err(e) => {
+            error!(error = %e, backup_type = %kind, "Backup failed");
+            status::record_run(path, kind, database, started_at, Err(e.to_string())).await;
+            ArtifactResult {
+                kind,
+                database: None,
+                file_name: None,
+                size_bytes: None,
+                link: None,
+                error: Some(format!("{:#}", e)),
+                duration: None,
+                details: None,
+            }
+        }
+    }
+}
+
+/// A successful run that stored nothing because the source had not changed.
+fn unchanged_result(kind: BackupKind) -> ArtifactResult {
+    ArtifactResult {
+        kind,
+        database: None,
+        file_name: None,
+        size_bytes: None,
+        link: None,
+        error: None,
+        duration: None,
+        details: None,
+    }
+}
+
+/// The per-type subfolder under the primary root and every mirror root. With
+/// `DB_NAMES` set, db resolves to `DB_NAME`'s subfolder within it.
+async fn resolve_type_folders(
+    config: &Config,
+    hub: &drive::auth::DriveHub,
+    kind: BackupKind,
+) -> anyhow::Result<Vec<String>> {
+    resolve_folders(
+        config,
+        hub,
+        &storage_folder(config, primary_target(config, kind)),
     )
-    .await?;
+    .await
+}
+
+/// `kind` as the single-target commands see it: db means `DB_NAME`, or the
+/// bundles holding it with `DB_BUNDLE`.
+fn primary_target(config: &Config, kind: BackupKind) -> BackupTarget<'_> {
+    BackupTarget {
+        kind,
+        db_name: (kind == BackupKind::Db && !config.db_bundle).then_some(config.db_name.as_str()),
+    }
+}
 
-    drive::prune::prune_old_backups(&hub, &folder_id, config.mc_retention_count).await?;
+/// Folder names, outermost first, that backups of `target` are stored under:
+/// the type folder, then the database's own folder when `DB_NAMES` is set.
+fn storage_folder<'a>(config: &Config, target: BackupTarget<'a>) -> Vec<&'a str> {
+    let mut path = vec![target.kind.folder_name()];
+    if config.db_subfolders
+        && let Some(db_name) = target.db_name
+    {
+        path.push(db_name);
+    }
+    path
+}
 
+/// Refuse to start a backup when `BACKUP_TEMP_DIR` has fewer free bytes or
+/// inodes than `TEMP_MIN_FREE_MB`/`TEMP_MIN_FREE_INODES`. If the filesystem
+/// cannot be queried the backup goes ahead.
+fn check_temp_space(config: &Config) -> anyhow::Result<()> {
+    let dir = &config.backup_temp_dir;
+    let space = match util::fs::free_space(dir) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, "Could not check free space in the backup temp directory, continuing");
+            return Ok(());
+        }
+    };
+
+    if space.available_bytes < config.temp_min_free_bytes {
+        error!(
+            path = %dir.display(),
+            available_bytes = space.available_bytes,
+            required_bytes = config.temp_min_free_bytes,
+            "Not enough free space in the backup temp directory"
+        );
+        bail!(
+            "Only {} free in {}, TEMP_MIN_FREE_MB requires {}",
+            util::format::humanize_bytes(space.available_bytes as f64),
+            dir.display(),
+            util::format::humanize_bytes(config.temp_min_free_bytes as f64)
+        );
+    }
+    if let Some(inodes) = space.available_inodes
+        && inodes < config.temp_min_free_inodes
+    {
+        error!(
+            path = %dir.display(),
+            available_inodes = inodes,
+            required_inodes = config.temp_min_free_inodes,
+            "Not enough free inodes in the backup temp directory"
+        );
+        bail!(
+            "Only {} free inodes on the filesystem of {}, TEMP_MIN_FREE_INODES requires {}; remove small files or move BACKUP_TEMP_DIR",
+            inodes,
+            dir.display(),
+            config.temp_min_free_inodes
+        );
+    }
     Ok(())
 }
+
+/// Produce the local artifact for `kind`, returning its path and whether it is
+/// a full backup or a differential one.
+async fn create_artifact(
+    config: &Config,
+    target: BackupTarget<'_>,
+    options: RunOptions,
+) -> anyhow::Result<(BackupArtifact, ArchiveScope)> {
+    let kind = target.kind;
+    let (mut artifact, scope) = match kind {
+        BackupKind::Db => {
+            let artifact = match target.db_name {
+                Some(db_name) => backup::db::backup_db(config, db_name).await?,
+                None => create_db_bundle(config).await?,
+            };
+            (artifact, ArchiveScope::Full)
+        }
+        BackupKind::Minecraft => {
+            let state = match status::StatusFile::load(&config.status_file_path).await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!(error = %e, "Could not read chain state, taking a full backup");
+                    status::StatusFile::default()
+                }
+            };
+            let (scope, reason) = if options.since_last {
+                // Status files written before start times were kept only have the end time
+                chain::decide_incremental(
+                    state
+                        .get(kind, None)
+                        .and_then(|s| s.last_success_started_at.or(s.last_success_at)),
+                    config.incremental_overlap,
+                    chrono::Utc::now(),
+                )
+            } else {
+                chain::decide_scope(
+                    config.mc_backup_mode,
+                    config.full_backup_every,
+                    state.chain(kind),
+                    chrono::Utc::now(),
+                )
+            };
+            info!(
+                full = scope.is_full(),
+                reason = %reason,
+                "Selected Minecraft backup scope"
+            );
+            (
+                backup::minecraft::backup_minecraft(config, scope).await?,
+                scope,
+            )
+        }
+    };
+    artifact.database = target.database(config).map(str::to_string);
+    Ok((encrypt_artifact(config, artifact).await?, scope))
+}
+
+/// With `BACKUP_ENCRYPTION_KEY`, replace `artifact` by an encrypted
+/// `<name>.enc` copy; the plaintext is removed.
+async fn encrypt_artifact(
+    config: &Config,
+    artifact: BackupArtifact,
+) -> anyhow::Result<BackupArtifact> {
+    let Some(key) = config.encryption_key else {
+        return Ok(artifact);
+    };
+
+    let plain = artifact.path;
+    let mut encrypted = plain.clone().into_os_string();
+    encrypted.push(crypto::ENCRYPTED_SUFFIX);
+    let encrypted = PathBuf::from(encrypted);
+
+    info!(path = %plain.display(), "Encrypting backup");
+    let (input, output) = (plain.clone(), encrypted.clone());
+    let result = match tokio::task::spawn_blocking(move || {
+        crypto::encrypt_file(&input, &output, &key)
+    })
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => Err(anyhow::anyhow!("Encryption task panicked: {}", e)),
+    };
+    remove_temp_file(&plain).await;
+    if let Err(e) = result {
+        remove_temp_file(&encrypted).await;
+        return Err(e);
+    }
+
+    // The checksum now has to cover the encrypted file
+    Ok(BackupArtifact {
+        path: encrypted,
+        sha256: None,
+        db_writes: artifact.db_writes,
+        database: artifact.database,
+    })
+}
+
+/// Dump every database of `DB_NAMES` concurrently and, once the last dump
+/// has finished, pack them all into one `DB_BUNDLE` archive. Any dump failing
+/// fails the bundle; with `DB_SKIP_UNCHANGED`, unchanged databases are left
+/// out of it and the run is unchanged when none has changed.
+async fn create_db_bundle(config: &Config) -> anyhow::Result<BackupArtifact> {
+    let dumps = futures::future::join_all(
+        config
+            .db_names
+            .iter()
+            .map(|db_name| backup::db::backup_db(config, db_name)),
+    )
+    .await;
+
+    let mut outputs = Vec::with_capacity(dumps.len());
+    let mut db_writes = Vec::new();
+    let mut writes_since = 0;
+    let mut failure = None;
+    for (db_name, dump) in config.db_names.iter().zip(dumps) {
+        match dump {
+            Ok(dump) => {
+                outputs.push((db_name.clone(), dump.path));
+                db_writes.extend(dump.db_writes);
+            }
+            Err(e) => match e.downcast_ref::<backup::db::DbUnchanged>() {
+                Some(unchanged) => {
+                    info!(db_name = %db_name, "Database unchanged, leaving it out of the bundle");
+                    writes_since += unchanged.writes_since;
+                }
+                None => {
+                    error!(db_name = %db_name, error = %e, "Dump for the bundle failed");
+                    failure.get_or_insert(e.context(format!("Failed to dump {}", db_name)));
+                }
+            },
+        }
+    }
+
+    let bundle = match failure {
+        Some(e) => Err(e),
+        None if outputs.is_empty() => Err(backup::db::DbUnchanged { writes_since }.into()),
+        None => backup::bundle::bundle_db_outputs(config, &outputs).await,
+    };
+    for (_, path) in &outputs {
+        remove_temp_file(path).await;
+    }
+
+    Ok(BackupArtifact {
+        path: bundle?,
+        sha256: None,
+        db_writes,
+        database: None,
+    })
+}
+
+/// Load the configuration from `path` when given, else from the environment.
+fn load_config(path: Option<&Path>) -> anyhow::Result<Config> {
+    match path {
+        Some(path) => Config::from_file(path),
+        None => Config::from_env(),
+    }
+}
+
+/// Load the configuration and apply its process-wide settings, logging why
+/// when it cannot be loaded.
+fn load_configured(path: Option<&Path>) -> Option<Config> {
+    let config = match load_config(path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!(error = %e, "Failed to load configuration");
+            return None;
+        }
+    };
+    drive::retry::configure(config.drive_retry);
+    drive::shared::configure(config.google_drive_shared_drive_id.clone());
+    metrics::configure(config.textfile_collector_dir.clone());
+    Some(config)
+}
+
+/// Run a command that needs no configuration, so it works before one exists.
+fn run_setup(command: SetupCommand, config_path: Option<&Path>) -> ExitCode {
+    match command {
+        SetupCommand::GenEnv => {
+            print!("{}", config::env_template::render());
+            ExitCode::SUCCESS
+        }
+        SetupCommand::GenSystemd(args) => run_gen_systemd(args, config_path),
+        SetupCommand::ValidateConfig => run_validate_config(config_path),
+    }
+}
+
+/// Run a command that reads the configuration and local state only; no temp
+/// directory, watchdog or runtime limit.
+async fn run_inspect(command: InspectCommand, config_path: Option<&Path>) -> ExitCode {
+    let Some(config) = load_configured(config_path) else {
+        return ExitCode::FAILURE;
+    };
+    let command_name = command.name();
+    let started_at = chrono::Utc::now();
+
+    let code = match command {
+        InspectCommand::CheckFreshness {
+            max_age,
+            backup_type,
+        } => run_check_freshness(&config, max_age, backup_type).await,
+        InspectCommand::Trend {
+            backup_type,
+            format,
+            horizon,
+        } => run_trend(&config, backup_type, format, horizon).await,
+        InspectCommand::Backends => {
+            print_backends(&config);
+            ExitCode::SUCCESS
+        }
+    };
+    finish(&config, command_name, started_at, code, None).await
+}
+
+/// Print the systemd unit pair for `gen-systemd`, pointing at this binary
+/// and at `config_path` when `--config` was given.
+fn run_gen_systemd(args: GenSystemdArgs, config_path: Option<&Path>) -> ExitCode {
+    let GenSystemdArgs {
+        run,
+        on_calendar,
+        env_file,
+        working_dir,
+        user,
+        read_write_paths,
+        name,
+    } = args;
+
+    let binary = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to determine the path of this binary");
+            return ExitCode::FAILURE;
+        }
+    };
+    let working_dir = match working_dir.map_or_else(std::env::current_dir, Ok) {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to determine the working directory");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // The service starts elsewhere, so a relative path would not resolve
+    let config_path = match config_path.map(std::path::absolute).transpose() {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to resolve the --config path");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    print!(
+        "{}",
+        systemd::render_units(&systemd::UnitOptions {
+            name: &name,
+            binary: &binary,
+            config: config_path.as_deref(),
+            args: &run,
+            on_calendar: &on_calendar,
+            env_file: &env_file,
+            working_dir: &working_dir,
+            user: user.as_deref(),
+            read_write_paths: &read_write_paths,
+        })
+    );
+    ExitCode::SUCCESS
+}
+
+/// Load the configuration and print every setting it resolved to, secrets
+/// redacted. Exits 0 when it loads, 1 otherwise.
+fn run_validate_config(path: Option<&Path>) -> ExitCode {
+    let file_values = match path.map(config::file::read_values).transpose() {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => {
+            println!("INVALID: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = Config::from_values(file_values.clone()) {
+        println!("INVALID: {:#}", e);
+        return ExitCode::FAILURE;
+    }
+
+    println!("OK: configuration is valid");
+    print!("{}", config::env_template::render_resolved(&file_values));
+    ExitCode::SUCCESS
+}
+
+/// Load the configuration and run the environment checks, printing one line
+/// per problem. Exits 0 when everything is usable, 1 otherwise.
+async fn run_config_check(config_path: Option<&Path>) -> ExitCode {
+    let problems = match load_config(config_path) {
+        Ok(config) => config::check::check_environment(&config).await,
+        Err(e) => vec![format!("configuration: {:#}", e)],
+    };
+
+    if problems.is_empty() {
+        println!("OK: configuration is valid");
+        return ExitCode::SUCCESS;
+    }
+
+    for problem in &problems {
+        println!("PROBLEM: {}", problem);
+    }
+    println!("{} problem(s) found", problems.len());
+    ExitCode::FAILURE
+}
+
+/// Print OK/STALE for each checked backup type. Exits 0 when all are fresh,
+/// 2 when any is stale or has never succeeded.
+async fn run_check_freshness(
+    config: &Config,
+    max_age: Duration,
+    backup_type: Option<BackupKind>,
+) -> ExitCode {
+    let state = match status::StatusFile::load(&config.status_file_path).await {
+        Ok(s) => s,
+        Err(e) => {
+            println!("UNKNOWN: {}", e);
+            return ExitCode::from(3);
+        }
+    };
+
+    // Disabled types no longer produce backups, so they can't go stale
+    let kinds: Vec<BackupKind> = match backup_type {
+        Some(kind) => vec![kind],
+        None => BackupKind::ALL
+            .into_iter()
+            .filter(|&kind| config.backup_enabled(kind))
+            .collect(),
+    };
+
+    let now = chrono::Utc::now();
+    let mut stale = false;
+
+    // Each database of DB_NAMES goes stale on its own
+    for target in backup_targets(config, &kinds) {
+        let database = target.database(config);
+        let label = match database {
+            Some(db_name) => format!("{} ({})", target.kind, db_name),
+            None => target.kind.to_string(),
+        };
+        match state
+            .get(target.kind, database)
+            .and_then(|s| s.last_success_at)
+        {
+            Some(last_success) => {
+                let age = (now - last_success).to_std().unwrap_or_default();
+                let age_str = format_duration(age);
+                if age > max_age {
+                    stale = true;
+                    println!(
+                        "STALE: {} last succeeded {} ago (max {})",
+                        label,
+                        age_str,
+                        format_duration(max_age)
+                    );
+                } else {
+                    println!("OK: {} last succeeded {} ago", label, age_str);
+                }
+            }
+            None => {
+                stale = true;
+                println!("STALE: {} has no recorded successful backup", label);
+            }
+        }
+    }
+
+    if stale {
+        ExitCode::from(2)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+async fn run_trend(
+    config: &Config,
+    backup_type: Option<BackupKind>,
+    format: trend::TrendFormat,
+    horizon: Duration,
+) -> ExitCode {
+    let state = match status::StatusFile::load(&config.status_file_path).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!(error = %e, "Failed to read backup history");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let kinds: Vec<BackupKind> = match backup_type {
+        Some(kind) => vec![kind],
+        None => BackupKind::ALL.to_vec(),
+    };
+
+    let targets: Vec<(BackupKind, Option<&str>)> = backup_targets(config, &kinds)
+        .into_iter()
+        .map(|t| (t.kind, t.database(config)))
+        .collect();
+    let trends = trend::compute(&state, &targets, horizon);
+    match trend::print(&trends, format) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!(error = %e, "Failed to print trend");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_backends(config: &Config) {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    println!(
+        "{:<2}{:<10} {:<11} {:<10} {:<9} {:<6}  DESCRIPTION",
+        "", "BACKEND", "CONFIGURED", "RESUMABLE", "CHECKSUM", "RANGED"
+    );
+    for backend in storage::available_backends() {
+        let caps = backend.capabilities();
+        let active = if backend.name() == config.storage_backend {
+            "*"
+        } else {
+            ""
+        };
+        println!(
+            "{:<2}{:<10} {:<11} {:<10} {:<9} {:<6}  {}",
+            active,
+            backend.name(),
+            yes_no(backend.is_configured(config)),
+            yes_no(caps.resumable_upload),
+            yes_no(caps.server_side_checksum),
+            yes_no(caps.ranged_download),
+            backend.description()
+        );
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, minutes) = (secs / 86_400, (secs % 86_400) / 3600, (secs % 3600) / 60);
+    if days > 0 {
+        format!("{}d{}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h{}m", hours, minutes)
+    } else {
+        format!("{}m{}s", minutes, secs % 60)
+    }
+}
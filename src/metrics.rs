@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::backup::BackupKind;
+use crate::config::config::Config;
+use crate::notify::ArtifactResult;
+use crate::status::StatusFile;
+
+const PREFIX: &str = "db_backup_goog";
+
+/// `TEXTFILE_COLLECTOR_DIR`, for metrics written outside a run summary.
+static TEXTFILE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Keeps the temp files of concurrent [`write_upload_progress`] calls apart.
+static UPLOAD_WRITE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Resumes counted per upload stream, starting from what the stream's file
+/// held when this process first wrote it.
+static UPLOAD_RESUMES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Set where [`write_upload_progress`] writes. Only the first call takes
+/// effect.
+pub fn configure(dir: Option<PathBuf>) {
+    let _ = TEXTFILE_DIR.set(dir);
+}
+
+/// The backup an upload carries. Each stream gets its own progress file with
+/// the same labels as the run metrics, so concurrent uploads don't clobber
+/// each other and every series keeps its label set from one upload to the next.
+#[derive(Debug, Clone)]
+pub struct UploadStream {
+    pub kind: BackupKind,
+    /// Set for db artifacts when `DB_NAMES` is.
+    pub database: Option<String>,
+}
+
+impl UploadStream {
+    fn file_name(&self, suffix: &str) -> String {
+        format!(
+            "{}_upload_{}{}",
+            PREFIX,
+            file_stem(self.kind, self.database.as_deref()),
+            suffix
+        )
+    }
+}
+
+/// Record that an upload of `stream` resumed from the last acknowledged
+/// byte after a failed chunk.
+pub fn record_upload_resume(stream: &UploadStream) {
+    if let Some(Some(dir)) = TEXTFILE_DIR.get() {
+        add_resumes(dir, stream, 1);
+    }
+}
+
+/// Add `count` to the resumes of `stream` and return the new total.
+fn add_resumes(dir: &Path, stream: &UploadStream, count: u64) -> u64 {
+    let mut resumes = UPLOAD_RESUMES.lock().unwrap_or_else(|e| e.into_inner());
+    let total = resumes
+        .entry(stream.file_name(""))
+        .or_insert_with(|| previous_resumes(&dir.join(stream.file_name(".prom"))));
+    *total += count;
+    *total
+}
+
+/// The resume counter a stream's progress file holds, so the counter keeps
+/// counting across runs; 0 when there is no readable file.
+fn previous_resumes(path: &Path) -> u64 {
+    let metric = format!("{}_upload_resumes_total{{", PREFIX);
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return 0;
+    };
+    text.lines()
+        .filter(|line| line.starts_with(&metric))
+        .find_map(|line| line.rsplit_once(' ')?.1.parse::<f64>().ok())
+        .map_or(0, |v| v as u64)
+}
+
+/// Replace `db_backup_goog_upload_<type>.prom` (or `..._db_<database>.prom`)
+/// with the progress of the Drive upload of `stream`: bytes Drive has
+/// acknowledged, the total, and a counter of every resume from the last
+/// acknowledged byte after a failed chunk. Called from the synchronous
+/// upload delegate; failures are logged only.
+pub fn write_upload_progress(stream: &UploadStream, acknowledged: u64, total: u64) {
+    let Some(Some(dir)) = TEXTFILE_DIR.get() else {
+        return;
+    };
+
+    let resumes = add_resumes(dir, stream, 0);
+
+    let mut text = String::new();
+    let label = labels(stream.kind, stream.database.as_deref());
+    gauge(
+        &mut text,
+        "upload_acknowledged_bytes",
+        "Bytes of the current Drive upload acknowledged so far.",
+        &label,
+        acknowledged as f64,
+    );
+    gauge(
+        &mut text,
+        "upload_total_bytes",
+        "Size of the current Drive upload.",
+        &label,
+        total as f64,
+    );
+    counter(
+        &mut text,
+        "upload_resumes_total",
+        "Drive uploads resumed after a failed chunk.",
+        &label,
+        resumes as f64,
+    );
+    text.push_str("# EOF\n");
+
+    // Written beside the target and renamed so the collector never reads half a file
+    let path = dir.join(stream.file_name(".prom"));
+    let temp_path = dir.join(format!(
+        ".{}.tmp-{}-{}",
+        stream.file_name(".prom"),
+        std::process::id(),
+        UPLOAD_WRITE_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written =
+        std::fs::write(&temp_path, text).and_then(|()| std::fs::rename(&temp_path, &path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        warn!(error = %e, path = %path.display(), "Failed to write upload progress metrics");
+    }
+}
+
+/// Write one `db_backup_goog_<type>.prom` file per artifact into `dir` for
+/// node_exporter's textfile collector, or `db_backup_goog_db_<database>.prom`
+/// with `DB_NAMES`. Each type gets its own file so a run of one type leaves
+/// the others' metrics in place. Failures are logged only.
+pub async fn write_textfiles(config: &Config, dir: &Path, artifacts: &[ArtifactResult]) {
+    // The last success of a failed type comes from the status file
+    let status = match StatusFile::load(&config.status_file_path).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, "Failed to read status file for metrics");
+            StatusFile::default()
+        }
+    };
+
+    for artifact in artifacts {
+        let kind = artifact.kind;
+        let last_success = if artifact.succeeded() {
+            Some(Utc::now())
+        } else {
+            status
+                .get(kind, artifact.database.as_deref())
+                .and_then(|s| s.last_success_at)
+        };
+
+        let mut text = String::new();
+        let label = labels(kind, artifact.database.as_deref());
+        gauge(
+            &mut text,
+            "last_run_success",
+            "Whether the most recent backup run succeeded.",
+            &label,
+            if artifact.succeeded() { 1.0 } else { 0.0 },
+        );
+        gauge(
+            &mut text,
+            "last_run_timestamp_seconds",
+            "Unix time the most recent backup run finished.",
+            &label,
+            Utc::now().timestamp() as f64,
+        );
+        if let Some(at) = last_success {
+            gauge(
+                &mut text,
+                "last_success_timestamp_seconds",
+                "Unix time of the last successful backup.",
+                &label,
+                at.timestamp() as f64,
+            );
+        }
+        if let Some(size) = artifact.size_bytes {
+            gauge(
+                &mut text,
+                "last_size_bytes",
+                "Size of the artifact produced by the most recent run.",
+                &label,
+                size as f64,
+            );
+        }
+        if let Some(duration) = artifact.duration {
+            gauge(
+                &mut text,
+                "last_duration_seconds",
+                "Time the most recent backup run took.",
+                &label,
+                duration.as_secs_f64(),
+            );
+        }
+        text.push_str("# EOF\n");
+
+        let path = dir.join(format!(
+            "{}_{}.prom",
+            PREFIX,
+            file_stem(kind, artifact.database.as_deref())
+        ));
+        match crate::util::fs::write_atomic(&path, text.as_bytes()).await {
+            Ok(()) => info!(path = %path.display(), "Wrote textfile collector metrics"),
+            Err(e) => warn!(error = %e, path = %path.display(), "Failed to write metrics file"),
+        }
+    }
+}
+
+/// `type="db",database="app"`, or just the type without a database.
+fn labels(kind: BackupKind, database: Option<&str>) -> String {
+    match database {
+        Some(db_name) => format!("type=\"{}\",database=\"{}\"", kind.as_str(), db_name),
+        None => format!("type=\"{}\"", kind.as_str()),
+    }
+}
+
+/// `db_app`, or just the type without a database.
+fn file_stem(kind: BackupKind, database: Option<&str>) -> String {
+    match database {
+        Some(db_name) => format!("{}_{}", kind.as_str(), db_name),
+        None => kind.as_str().to_string(),
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, labels: &str, value: f64) {
+    sample(out, "gauge", name, help, labels, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, labels: &str, value: f64) {
+    sample(out, "counter", name, help, labels, value);
+}
+
+fn sample(out: &mut String, kind: &str, name: &str, help: &str, labels: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+    let _ = writeln!(out, "{}_{}{{{}}} {}", PREFIX, name, labels, value);
+}
@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use super::{BackupEvent, EventStatus};
+use crate::config::config::SmtpConfig;
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest error excerpt put in a subject line.
+const SUBJECT_ERROR_CHARS: usize = 120;
+
+/// How the connection to `SMTP_HOST` is secured (`SMTP_TLS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS, which the relay must offer.
+    StartTls,
+    /// TLS from the first byte (SMTPS).
+    Tls,
+    /// Unencrypted, for a relay on localhost or a trusted network.
+    None,
+}
+
+/// Whether `SMTP_NOTIFY_ON_FAILURE` / `SMTP_NOTIFY_ON_SUCCESS` want `event`.
+pub fn wants(smtp: &SmtpConfig, event: &BackupEvent) -> bool {
+    match event.status() {
+        EventStatus::Success => smtp.notify_on_success,
+        EventStatus::PartialFailure | EventStatus::Failure => smtp.notify_on_failure,
+    }
+}
+
+/// Email `event` to every `SMTP_TO` recipient.
+pub async fn send_notification(smtp: &SmtpConfig, event: &BackupEvent) -> anyhow::Result<()> {
+    let from: Mailbox = match smtp.from.parse() {
+        Ok(m) => m,
+        Err(e) => bail!("SMTP_FROM '{}' is not a valid address: {}", smtp.from, e),
+    };
+    let mut builder = Message::builder()
+        .from(from)
+        .subject(subject(event))
+        .header(ContentType::TEXT_PLAIN);
+    for to in &smtp.to {
+        match to.parse::<Mailbox>() {
+            Ok(m) => builder = builder.to(m),
+            Err(e) => bail!("SMTP_TO '{}' is not a valid address: {}", to, e),
+        }
+    }
+    let message = match builder.body(body(event)) {
+        Ok(m) => m,
+        Err(e) => bail!("Failed to build notification email: {}", e),
+    };
+
+    let transport = match smtp.tls {
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host),
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host),
+        SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            &smtp.host,
+        )),
+    };
+    let mut transport = match transport {
+        Ok(t) => t.port(smtp.port).timeout(Some(SMTP_TIMEOUT)),
+        Err(e) => bail!("Failed to set up SMTP transport for {}: {}", smtp.host, e),
+    };
+    if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    if let Err(e) = transport.build().send(message).await {
+        bail!(
+            "SMTP delivery via {}:{} failed: {}",
+            smtp.host,
+            smtp.port,
+            e
+        );
+    }
+    Ok(())
+}
+
+/// `[backup] ✓ minecraft 2024-01-15` for a successful run, or
+/// `[backup] ✗ db: pg_dump exited 1` naming each failed artifact. Artifacts
+/// that failed with the same error share one entry, e.g.
+/// `[backup] ✗ db, minecraft: not enough free space`.
+fn subject(event: &BackupEvent) -> String {
+    let mut failures: Vec<(Vec<String>, String)> = Vec::new();
+    for artifact in &event.artifacts {
+        let Some(error) = artifact.error.as_deref() else {
+            continue;
+        };
+        let first_line: String = error
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .take(SUBJECT_ERROR_CHARS)
+            .collect();
+        match failures.iter_mut().find(|(_, e)| *e == first_line) {
+            Some((labels, _)) => labels.push(artifact.label()),
+            None => failures.push((vec![artifact.label()], first_line)),
+        }
+    }
+    if !failures.is_empty() {
+        let failures: Vec<String> = failures
+            .iter()
+            .map(|(labels, error)| format!("{}: {}", labels.join(", "), error))
+            .collect();
+        return format!("[backup] ✗ {}", failures.join("; "));
+    }
+
+    let labels: Vec<String> = event.artifacts.iter().map(|a| a.label()).collect();
+    format!(
+        "[backup] ✓ {} {}",
+        labels.join(", "),
+        event.finished_at.format("%Y-%m-%d")
+    )
+}
+
+fn body(event: &BackupEvent) -> String {
+    let mut text = event.summary_text();
+    text.push_str(&format!(
+        "\n\nCommand: {}\nStarted: {}\nFinished: {}\nDuration: {}s\n",
+        event.command,
+        event.started_at.to_rfc3339(),
+        event.finished_at.to_rfc3339(),
+        (event.finished_at - event.started_at).num_seconds()
+    ));
+
+    for artifact in &event.artifacts {
+        text.push_str(&format!("\n{}\n", artifact.label()));
+        let status = if artifact.succeeded() { "ok" } else { "failed" };
+        text.push_str(&format!("  status: {}\n", status));
+        if let Some(name) = &artifact.file_name {
+            text.push_str(&format!("  file: {}\n", name));
+        }
+        if let Some(size) = artifact.size_bytes {
+            text.push_str(&format!("  size_bytes: {}\n", size));
+        }
+        if let Some(duration) = artifact.duration {
+            text.push_str(&format!("  duration: {:.1}s\n", duration.as_secs_f64()));
+        }
+        if let Some(link) = &artifact.link {
+            text.push_str(&format!("  link: {}\n", link));
+        }
+        if let Some(error) = &artifact.error {
+            text.push_str(&format!("  error: {}\n", error));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::backup::BackupKind;
+    use crate::notify::ArtifactResult;
+
+    fn artifact(kind: BackupKind, database: Option<&str>, error: Option<&str>) -> ArtifactResult {
+        ArtifactResult {
+            kind,
+            database: database.map(str::to_string),
+            file_name: None,
+            size_bytes: None,
+            link: None,
+            error: error.map(str::to_string),
+            duration: None,
+            details: None,
+        }
+    }
+
+    fn event(artifacts: Vec<ArtifactResult>) -> BackupEvent {
+        let finished_at = Utc.with_ymd_and_hms(2024, 1, 15, 3, 0, 0).unwrap();
+        BackupEvent {
+            command: "all".to_string(),
+            started_at: finished_at,
+            finished_at,
+            artifacts,
+        }
+    }
+
+    #[test]
+    fn success_subject_names_artifacts_and_date() {
+        let event = event(vec![
+            artifact(BackupKind::Db, Some("app"), None),
+            artifact(BackupKind::Minecraft, None, None),
+        ]);
+        assert_eq!(subject(&event), "[backup] ✓ db (app), minecraft 2024-01-15");
+    }
+
+    #[test]
+    fn failure_subject_names_only_failed_artifacts() {
+        let event = event(vec![
+            artifact(BackupKind::Db, None, Some("pg_dump exited 1\nmore detail")),
+            artifact(BackupKind::Minecraft, None, None),
+        ]);
+        assert_eq!(subject(&event), "[backup] ✗ db: pg_dump exited 1");
+    }
+
+    #[test]
+    fn failure_subject_groups_identical_errors() {
+        let event = event(vec![
+            artifact(BackupKind::Db, Some("src"), Some("not enough free space")),
+            artifact(BackupKind::Db, Some("dst"), Some("not enough free space")),
+            artifact(BackupKind::Minecraft, None, Some("server still running")),
+        ]);
+        assert_eq!(
+            subject(&event),
+            "[backup] ✗ db (src), db (dst): not enough free space; minecraft: server still running"
+        );
+    }
+
+    #[test]
+    fn failure_subject_caps_error_length() {
+        let long = "x".repeat(SUBJECT_ERROR_CHARS + 50);
+        let event = event(vec![artifact(BackupKind::Db, None, Some(&long))]);
+        assert_eq!(
+            subject(&event),
+            format!("[backup] ✗ db: {}", "x".repeat(SUBJECT_ERROR_CHARS))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+pub mod email;
+pub mod on_success;
+pub mod webhook;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::backup::BackupKind;
+use crate::config::config::{Config, SmtpConfig};
+use crate::status::{self, RunOutcome};
+
+/// Which runs send a notification (`NOTIFY_ON`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyOn {
+    Always,
+    /// Only runs where some artifact failed.
+    Failure,
+    /// Only runs where a type's outcome differs from the previous run.
+    Change,
+}
+
+/// Outcome of producing and uploading a single backup artifact.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactResult {
+    pub kind: BackupKind,
+    /// The database a db artifact dumped; set only when `DB_NAMES` is.
+    pub database: Option<String>,
+    pub file_name: Option<String>,
+    pub size_bytes: Option<u64>,
+    /// Drive link to the uploaded file, present only with `NOTIFY_INCLUDE_LINK`.
+    pub link: Option<String>,
+    pub error: Option<String>,
+    /// Wall-clock time spent producing and storing the artifact.
+    #[serde(skip)]
+    pub duration: Option<std::time::Duration>,
+    /// Where the artifact was written and stored; passed to `ON_SUCCESS_CMD`.
+    #[serde(skip)]
+    pub details: Option<ArtifactDetails>,
+}
+
+/// Local and remote identity of a stored artifact.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactDetails {
+    /// Where the artifact was written; removed after upload unless `--dump-only`.
+    pub path: PathBuf,
+    /// Drive file id of the copy in the primary folder, or the B2 file id.
+    pub remote_id: Option<String>,
+    /// Present when the digest was taken while writing the artifact.
+    pub sha256: Option<String>,
+    /// Drive link of the copy in the primary folder.
+    pub web_view_link: Option<String>,
+}
+
+impl ArtifactResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The backup type, followed by the database when there is one.
+    pub fn label(&self) -> String {
+        match &self.database {
+            Some(db_name) => format!("{} ({})", self.kind, db_name),
+            None => self.kind.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventStatus {
+    Success,
+    PartialFailure,
+    Failure,
+}
+
+/// Summary of one command invocation, carrying the per-artifact results so a
+/// notification can say exactly what succeeded and what failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupEvent {
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub artifacts: Vec<ArtifactResult>,
+}
+
+impl BackupEvent {
+    pub fn status(&self) -> EventStatus {
+        let failed = self.artifacts.iter().filter(|a| !a.succeeded()).count();
+        if failed == 0 {
+            EventStatus::Success
+        } else if failed == self.artifacts.len() {
+            EventStatus::Failure
+        } else {
+            EventStatus::PartialFailure
+        }
+    }
+
+    /// Collapse the event into a command result, naming every failed artifact.
+    pub fn into_result(self) -> anyhow::Result<()> {
+        let failures: Vec<String> = self
+            .artifacts
+            .iter()
+            .filter_map(|a| a.error.as_ref().map(|e| format!("{}: {}", a.label(), e)))
+            .collect();
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        anyhow::bail!(
+            "{} of {} backups failed: {}",
+            failures.len(),
+            self.artifacts.len(),
+            failures.join("; ")
+        )
+    }
+
+    /// Plain-text rendering shared by the chat notifiers.
+    pub fn summary_text(&self) -> String {
+        let headline = match self.status() {
+            EventStatus::Success => "✅ Backup succeeded",
+            EventStatus::PartialFailure => "⚠️ Backup partially failed",
+            EventStatus::Failure => "❌ Backup failed",
+        };
+
+        let duration = (self.finished_at - self.started_at).num_seconds();
+        let mut text = format!("{} (`{}`, {}s)", headline, self.command, duration);
+
+        for artifact in &self.artifacts {
+            let label = artifact.label();
+            let line = match (&artifact.error, &artifact.file_name) {
+                (Some(err), _) => format!("\n• {} ✗ {}", label, err),
+                (None, Some(name)) => match artifact.size_bytes {
+                    Some(size) => format!("\n• {} ✓ `{}` ({} bytes)", label, name, size),
+                    None => format!("\n• {} ✓ `{}`", label, name),
+                },
+                (None, None) => format!("\n• {} ✓", label),
+            };
+            text.push_str(&line);
+            if let Some(link) = &artifact.link {
+                text.push_str(&format!(" {}", link));
+            }
+        }
+
+        text
+    }
+}
+
+/// Set once a failure email has gone out, so a run that then fails as a
+/// whole is not reported a second time.
+static FAILURE_EMAILED: AtomicBool = AtomicBool::new(false);
+
+/// Send `event` to every configured chat notifier, subject to `NOTIFY_ON`,
+/// and by email as `SMTP_NOTIFY_ON_*` asks. Delivery failures are logged and
+/// never fail the command.
+pub async fn dispatch(config: &Config, event: &BackupEvent) {
+    if let Some(smtp) = &config.smtp
+        && email::wants(smtp, event)
+    {
+        send_email(smtp, event).await;
+    }
+
+    if !should_notify(config, event).await {
+        info!(status = ?event.status(), notify_on = ?config.notify_on, "Notification suppressed by NOTIFY_ON");
+        return;
+    }
+
+    if let Some(url) = &config.notify_discord_webhook_url {
+        match webhook::send_discord(url, event).await {
+            Ok(()) => info!(status = ?event.status(), "Sent Discord notification"),
+            Err(e) => warn!(error = %e, "Failed to send Discord notification"),
+        }
+    }
+
+    if let Some(url) = &config.notify_slack_webhook_url {
+        match webhook::send_slack(url, event).await {
+            Ok(()) => info!(status = ?event.status(), "Sent Slack notification"),
+            Err(e) => warn!(error = %e, "Failed to send Slack notification"),
+        }
+    }
+}
+
+/// Email a backup command that failed as a whole, before or instead of the
+/// per-artifact [`dispatch`]: on `MAX_ARTIFACTS_PER_RUN`, low temp space or
+/// `MAX_RUNTIME_SECS`. Nothing is sent when a failure email already went out
+/// during this run.
+pub async fn email_run_failure(config: &Config, event: &BackupEvent) {
+    if let Some(smtp) = &config.smtp
+        && !FAILURE_EMAILED.load(Ordering::SeqCst)
+        && email::wants(smtp, event)
+    {
+        send_email(smtp, event).await;
+    }
+}
+
+async fn send_email(smtp: &SmtpConfig, event: &BackupEvent) {
+    match email::send_notification(smtp, event).await {
+        Ok(()) => {
+            if event.status() != EventStatus::Success {
+                FAILURE_EMAILED.store(true, Ordering::SeqCst);
+            }
+            info!(status = ?event.status(), "Sent email notification");
+        }
+        Err(e) => warn!(error = %e, "Failed to send email notification"),
+    }
+}
+
+async fn should_notify(config: &Config, event: &BackupEvent) -> bool {
+    match config.notify_on {
+        NotifyOn::Always => true,
+        NotifyOn::Failure => event.status() != EventStatus::Success,
+        NotifyOn::Change => {
+            let outcomes: Vec<(String, RunOutcome)> = event
+                .artifacts
+                .iter()
+                .map(|a| {
+                    let outcome = if a.succeeded() {
+                        RunOutcome::Success
+                    } else {
+                        RunOutcome::Failure
+                    };
+                    (a.label(), outcome)
+                })
+                .collect();
+            status::swap_notified(&config.status_file_path, &outcomes).await
+        }
+    }
+}
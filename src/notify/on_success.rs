@@ -0,0 +1,95 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::bail;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use super::{BackupEvent, EventStatus};
+use crate::config::config::Config;
+
+/// Longest `ON_SUCCESS_CMD` may run before it is killed.
+const ON_SUCCESS_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Run `ON_SUCCESS_CMD` through `sh -c` when every artifact of `event`
+/// succeeded, writing the artifacts as JSON to its stdin. A command that
+/// fails to start, exits non-zero or times out is logged as a warning and
+/// never fails the backup.
+pub async fn run(config: &Config, event: &BackupEvent) {
+    let Some(command) = &config.on_success_cmd else {
+        return;
+    };
+    if event.status() != EventStatus::Success {
+        info!(status = ?event.status(), "Skipping ON_SUCCESS_CMD; not every backup succeeded");
+        return;
+    }
+
+    match spawn(command, &payload(event)).await {
+        Ok(()) => info!("ON_SUCCESS_CMD completed"),
+        Err(e) => warn!(error = %e, "ON_SUCCESS_CMD failed; the backup itself succeeded"),
+    }
+}
+
+fn payload(event: &BackupEvent) -> String {
+    let artifacts: Vec<_> = event
+        .artifacts
+        .iter()
+        .map(|a| {
+            let details = a.details.as_ref();
+            json!({
+                "type": a.kind,
+                "database": a.database,
+                "file_name": a.file_name,
+                "path": details.map(|d| &d.path),
+                "remote_id": details.and_then(|d| d.remote_id.as_deref()),
+                "size_bytes": a.size_bytes,
+                "sha256": details.and_then(|d| d.sha256.as_deref()),
+                "web_view_link": details.and_then(|d| d.web_view_link.as_deref()),
+            })
+        })
+        .collect();
+    json!({
+        "command": event.command,
+        "started_at": event.started_at,
+        "finished_at": event.finished_at,
+        "artifacts": artifacts,
+    })
+    .to_string()
+}
+
+async fn spawn(command: &str, input: &str) -> anyhow::Result<()> {
+    info!(command = command, "Running ON_SUCCESS_CMD");
+    let mut child = match tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => bail!("Failed to spawn ON_SUCCESS_CMD: {}", e),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that ignores its input closes the pipe early; that is fine
+        if let Err(e) = stdin.write_all(input.as_bytes()).await
+            && e.kind() != std::io::ErrorKind::BrokenPipe
+        {
+            bail!("Failed to write ON_SUCCESS_CMD input: {}", e);
+        }
+    }
+
+    let status = match tokio::time::timeout(ON_SUCCESS_TIMEOUT, child.wait()).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => bail!("Failed to wait for ON_SUCCESS_CMD: {}", e),
+        Err(_) => bail!(
+            "ON_SUCCESS_CMD did not finish within {}s and was killed",
+            ON_SUCCESS_TIMEOUT.as_secs()
+        ),
+    };
+    if !status.success() {
+        bail!("ON_SUCCESS_CMD exited with status {}", status);
+    }
+    Ok(())
+}
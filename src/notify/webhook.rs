@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use serde_json::json;
+
+use super::BackupEvent;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub async fn send_discord(url: &str, event: &BackupEvent) -> anyhow::Result<()> {
+    // Discord rejects message content longer than 2000 characters
+    let mut content = event.summary_text();
+    if content.chars().count() > 2000 {
+        content = content.chars().take(1997).collect::<String>() + "...";
+    }
+    post_json(url, &json!({ "content": content })).await
+}
+
+pub async fn send_slack(url: &str, event: &BackupEvent) -> anyhow::Result<()> {
+    post_json(url, &json!({ "text": event.summary_text() })).await
+}
+
+async fn post_json(url: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(e) => bail!("Failed to build HTTP client: {}", e),
+    };
+
+    let response = match client.post(url).json(body).send().await {
+        Ok(r) => r,
+        Err(e) => bail!("Webhook request failed: {}", e),
+    };
+
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        bail!("Webhook returned HTTP {}: {}", status, text);
+    }
+
+    Ok(())
+}
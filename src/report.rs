@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::backup::BackupKind;
+use crate::build_info::{GIT_SHA, PROJECT_NAME, PROJECT_VERSION};
+use crate::config::config::Config;
+use crate::notify::ArtifactResult;
+
+/// How `EXIT_REPORT_PATH` is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReportMode {
+    /// Replace the file with the latest report.
+    Overwrite,
+    /// Add one JSON line per command.
+    Append,
+}
+
+/// One stored copy of an artifact.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadRecord {
+    pub backup_type: BackupKind,
+    pub file_name: String,
+    /// Drive folder id, or the B2 key prefix.
+    pub destination: String,
+    pub file_id: Option<String>,
+    pub sha256: Option<String>,
+}
+
+/// Backups deleted from one location by a prune.
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneRecord {
+    pub backup_type: BackupKind,
+    pub location: String,
+    pub deleted: u32,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    artifacts: Vec<ArtifactResult>,
+    uploads: Vec<UploadRecord>,
+    prunes: Vec<PruneRecord>,
+}
+
+/// Filled in as the command runs and drained into the exit report, so deeply
+/// nested upload and prune code needn't thread it through every return type.
+static RECORDED: Mutex<Recorded> = Mutex::new(Recorded {
+    artifacts: Vec::new(),
+    uploads: Vec::new(),
+    prunes: Vec::new(),
+});
+
+pub fn record_artifacts(artifacts: &[ArtifactResult]) {
+    if let Ok(mut r) = RECORDED.lock() {
+        r.artifacts.extend_from_slice(artifacts);
+    }
+}
+
+pub fn record_upload(upload: UploadRecord) {
+    if let Ok(mut r) = RECORDED.lock() {
+        r.uploads.push(upload);
+    }
+}
+
+pub fn record_prune(backup_type: BackupKind, location: &str, deleted: u32) {
+    if let Ok(mut r) = RECORDED.lock() {
+        r.prunes.push(PruneRecord {
+            backup_type,
+            location: location.to_string(),
+            deleted,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Success,
+    Failure,
+    Aborted,
+}
+
+#[derive(Debug, Serialize)]
+struct ExitReport<'a> {
+    command: &'a str,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+    duration_secs: f64,
+    status: ReportStatus,
+    error: Option<&'a str>,
+    artifacts: Vec<ArtifactReport>,
+    uploads: Vec<UploadRecord>,
+    prunes: Vec<PruneRecord>,
+    tool: &'static str,
+    version: &'static str,
+    git_sha: &'static str,
+}
+
+/// [`ArtifactResult`] with its duration, which notifications leave out.
+#[derive(Debug, Serialize)]
+struct ArtifactReport {
+    #[serde(flatten)]
+    result: ArtifactResult,
+    duration_secs: Option<f64>,
+}
+
+/// Write the report for this command to `EXIT_REPORT_PATH`, replacing the
+/// file or appending a JSON line per `EXIT_REPORT_MODE`. Either way the file
+/// is swapped in atomically. Failures are logged only.
+pub async fn write(
+    config: &Config,
+    command: &str,
+    started_at: DateTime<Utc>,
+    status: ReportStatus,
+    error: Option<&str>,
+) {
+    let Some(path) = &config.exit_report_path else {
+        return;
+    };
+
+    let recorded = match RECORDED.lock() {
+        Ok(mut r) => std::mem::take(&mut *r),
+        Err(_) => Recorded::default(),
+    };
+    let finished_at = Utc::now();
+    let report = ExitReport {
+        command,
+        started_at,
+        finished_at,
+        duration_secs: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+        status,
+        error,
+        artifacts: recorded
+            .artifacts
+            .into_iter()
+            .map(|result| ArtifactReport {
+                duration_secs: result.duration.map(|d| d.as_secs_f64()),
+                result,
+            })
+            .collect(),
+        uploads: recorded.uploads,
+        prunes: recorded.prunes,
+        tool: PROJECT_NAME,
+        version: PROJECT_VERSION,
+        git_sha: GIT_SHA,
+    };
+
+    let contents = match config.exit_report_mode {
+        ExitReportMode::Overwrite => serde_json::to_vec_pretty(&report),
+        ExitReportMode::Append => serde_json::to_vec(&report),
+    };
+    let mut contents = match contents {
+        Ok(c) => c,
+        Err(e) => {
+            warn!(error = %e, "Failed to serialize exit report");
+            return;
+        }
+    };
+
+    if config.exit_report_mode == ExitReportMode::Append {
+        contents.push(b'\n');
+        match tokio::fs::read(path).await {
+            Ok(mut existing) => {
+                if existing.last().is_some_and(|&b| b != b'\n') {
+                    existing.push(b'\n');
+                }
+                existing.extend_from_slice(&contents);
+                contents = existing;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Failed to read existing exit report");
+                return;
+            }
+        }
+    }
+
+    match crate::util::fs::write_atomic(path, &contents).await {
+        Ok(()) => info!(path = %path.display(), "Wrote exit report"),
+        Err(e) => warn!(error = %e, path = %path.display(), "Failed to write exit report"),
+    }
+}
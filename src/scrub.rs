@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use google_drive3::api::File as DriveFile;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::backup::BackupKind;
+use crate::drive::auth::DriveHub;
+use crate::drive::download::{ByteRange, download_range};
+
+/// Bytes fetched from the start of each backup.
+const HEAD_BYTES: u64 = 1024 * 1024;
+/// Bytes fetched from the end of each backup.
+const TAIL_BYTES: u64 = 64 * 1024;
+/// Decompressed bytes inspected for tar headers; bounds memory when the head
+/// compresses very well.
+const MAX_DECODED: usize = 8 * 1024 * 1024;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// Start of every pg_dump custom-format archive.
+const PG_DUMP_MAGIC: &[u8] = b"PGDMP";
+const TAR_BLOCK: usize = 512;
+
+/// Which backups `scrub` starts first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ScrubOrder {
+    Newest,
+    Oldest,
+}
+
+/// Outcome of scrubbing one backup, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrubStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl ScrubStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScrubStatus::Pass => "pass",
+            ScrubStatus::Warn => "warn",
+            ScrubStatus::Fail => "fail",
+        }
+    }
+}
+
+/// What `scrub` found for one stored backup.
+#[derive(Debug, Serialize)]
+pub struct ScrubReport {
+    pub backup_type: BackupKind,
+    pub name: String,
+    pub id: String,
+    pub status: ScrubStatus,
+    pub notes: Vec<String>,
+}
+
+impl ScrubReport {
+    fn note(&mut self, note: impl Into<String>) {
+        self.notes.push(note.into());
+    }
+
+    fn warn(&mut self, note: impl Into<String>) {
+        self.status = self.status.max(ScrubStatus::Warn);
+        self.notes.push(note.into());
+    }
+
+    fn fail(&mut self, note: impl Into<String>) {
+        self.status = ScrubStatus::Fail;
+        self.notes.push(note.into());
+    }
+}
+
+/// Drive's `md5Checksum` of each backup as first seen by `scrub`, keyed by
+/// file id. Later scrubs fail a backup whose checksum has changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScrubManifest {
+    #[serde(default)]
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub md5: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl ScrubManifest {
+    /// Load the manifest, returning an empty one if it does not exist yet.
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Failed to read scrub manifest");
+                bail!("Failed to read scrub manifest {}: {}", path.display(), e);
+            }
+        };
+
+        match serde_json::from_slice(&bytes) {
+            Ok(m) => Ok(m),
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Failed to parse scrub manifest");
+                bail!("Failed to parse scrub manifest {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        crate::util::fs::write_json_atomic(path, self).await
+    }
+
+    /// Drop entries named with `prefix` whose file is no longer stored, so
+    /// pruned backups don't accumulate.
+    pub fn forget_missing(&mut self, prefix: &str, present: &HashSet<String>) {
+        self.files
+            .retain(|id, entry| !entry.name.starts_with(prefix) || present.contains(id));
+    }
+}
+
+/// Start the report for one stored backup by comparing its md5 with the
+/// manifest, recording it there when first seen.
+pub fn check_manifest(
+    kind: BackupKind,
+    file: &DriveFile,
+    manifest: &mut ScrubManifest,
+) -> ScrubReport {
+    let mut report = ScrubReport {
+        backup_type: kind,
+        name: file.name.clone().unwrap_or_default(),
+        id: file.id.clone().unwrap_or_default(),
+        status: ScrubStatus::Pass,
+        notes: Vec::new(),
+    };
+    check_md5(&mut report, file.md5_checksum.as_deref(), manifest);
+    report
+}
+
+/// Finish `report` from [`check_manifest`] without downloading the backup:
+/// fetch the head to check the format magic, zstd frames and a sample of tar
+/// headers, and fetch the tail to confirm the full length is readable.
+pub async fn probe_file(
+    hub: &DriveHub,
+    file: &DriveFile,
+    mut report: ScrubReport,
+    dict_path: Option<&Path>,
+) -> ScrubReport {
+    let size = file.size.and_then(|s| u64::try_from(s).ok());
+    if size == Some(0) {
+        report.fail("file is empty");
+        return report;
+    }
+
+    match download_range(hub, &report.id, ByteRange::Head(HEAD_BYTES)).await {
+        Ok(head) => {
+            if let (Some(expected), Some(actual)) = (size, head.total_size)
+                && expected != actual
+            {
+                report.fail(format!(
+                    "Drive lists {} bytes but serves {}",
+                    expected, actual
+                ));
+            }
+            probe_head(&mut report, &head.bytes, dict_path);
+        }
+        Err(e) => report.fail(format!("head download failed: {}", e)),
+    }
+
+    match download_range(hub, &report.id, ByteRange::Tail(TAIL_BYTES)).await {
+        Ok(tail) => {
+            let expected = size.map(|s| s.min(TAIL_BYTES));
+            if let Some(expected) = expected
+                && tail.bytes.len() as u64 != expected
+            {
+                report.fail(format!(
+                    "tail returned {} bytes, expected {}",
+                    tail.bytes.len(),
+                    expected
+                ));
+            }
+            if report.name.ends_with(".tar") && !ends_with_tar_footer(&tail.bytes) {
+                report.fail("tar footer missing");
+            }
+        }
+        Err(e) => report.fail(format!("tail download failed: {}", e)),
+    }
+
+    match report.status {
+        ScrubStatus::Pass => info!(name = %report.name, "Scrub passed"),
+        ScrubStatus::Warn => warn!(name = %report.name, notes = ?report.notes, "Scrub warning"),
+        ScrubStatus::Fail => error!(name = %report.name, notes = ?report.notes, "Scrub failed"),
+    }
+    report
+}
+
+fn check_md5(report: &mut ScrubReport, md5: Option<&str>, manifest: &mut ScrubManifest) {
+    let Some(md5) = md5 else {
+        report.warn("Drive reported no md5Checksum");
+        return;
+    };
+
+    match manifest.files.get(&report.id) {
+        Some(entry) if entry.md5 == md5 => {}
+        Some(entry) => report.fail(format!(
+            "md5Checksum changed since {} (was {}, now {})",
+            entry.recorded_at.format("%Y-%m-%d"),
+            entry.md5,
+            md5
+        )),
+        None => {
+            manifest.files.insert(
+                report.id.clone(),
+                ManifestEntry {
+                    name: report.name.clone(),
+                    md5: md5.to_string(),
+                    recorded_at: Utc::now(),
+                },
+            );
+            report.note("md5Checksum recorded");
+        }
+    }
+}
+
+/// Check the head of the file against the format its name promises.
+fn probe_head(report: &mut ScrubReport, head: &[u8], dict_path: Option<&Path>) {
+    let name = report.name.clone();
+    let compressed = name.ends_with(".zst");
+    let content = if compressed {
+        if !head.starts_with(&ZSTD_MAGIC) {
+            report.fail("missing zstd frame magic");
+            return;
+        }
+        let needed = crate::backup::dict::frame_dict_id(head);
+        let dict = match crate::backup::dict::load_id(dict_path, needed) {
+            Ok(d) => d,
+            Err(e) => {
+                report.warn(format!("content not probed: {}", e));
+                return;
+            }
+        };
+        let dict = dict.as_deref();
+        if let Err(e) = crate::backup::dict::check_matches(needed, dict) {
+            report.warn(format!("content not probed: {}", e));
+            return;
+        }
+        match decode_zstd_prefix(head, dict) {
+            Ok(d) => d,
+            Err(e) => {
+                report.fail(format!("zstd stream is corrupt: {}", e));
+                return;
+            }
+        }
+    } else {
+        head.to_vec()
+    };
+
+    let inner = name.strip_suffix(".zst").unwrap_or(&name);
+    if inner.ends_with(".tar") {
+        match sample_tar_headers(&content) {
+            Ok(0) => report.warn("head too short to sample any tar header"),
+            Ok(n) => report.note(format!("{} tar headers verified", n)),
+            Err(e) => report.fail(e),
+        }
+    } else if inner.ends_with(".dump") {
+        if !content.starts_with(PG_DUMP_MAGIC) {
+            report.fail("missing pg_dump archive header");
+        }
+    } else {
+        report.note("content format not probed");
+    }
+}
+
+/// Decompress as much of `data` as it holds, with `dict` when the frame was
+/// written with one. Running out of input mid-frame is expected; any other
+/// decoder error means the stream is damaged.
+fn decode_zstd_prefix(data: &[u8], dict: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    use zstd::stream::raw::{Decoder, InBuffer, Operation, OutBuffer};
+
+    let mut decoder = match dict {
+        Some(dict) => Decoder::with_dictionary(dict)?,
+        None => Decoder::new()?,
+    };
+    let mut input = InBuffer::around(data);
+    let mut decoded = Vec::new();
+    let mut chunk = vec![0u8; 128 * 1024];
+
+    while decoded.len() < MAX_DECODED {
+        let consumed = input.pos();
+        let mut output = OutBuffer::around(chunk.as_mut_slice());
+        decoder.run(&mut input, &mut output)?;
+        let written = output.pos();
+        decoded.extend_from_slice(&chunk[..written]);
+        // Input consumed and nothing more buffered in the decoder
+        if input.pos() == data.len() && written < chunk.len() {
+            break;
+        }
+        if written == 0 && input.pos() == consumed {
+            break;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Walk the tar headers wholly contained in `data`, verifying each header
+/// checksum. Returns how many were checked.
+fn sample_tar_headers(data: &[u8]) -> Result<usize, String> {
+    let mut offset = 0;
+    let mut checked = 0;
+
+    while offset + TAR_BLOCK <= data.len() {
+        let block = &data[offset..offset + TAR_BLOCK];
+        // End-of-archive marker
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let header = tar::Header::from_byte_slice(block);
+        if header.as_ustar().is_none() && header.as_gnu().is_none() {
+            return Err(format!("no ustar magic in tar header at offset {}", offset));
+        }
+        let stored = match header.cksum() {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(format!(
+                    "unreadable tar checksum at offset {}: {}",
+                    offset, e
+                ));
+            }
+        };
+        if stored != header_checksum(block) {
+            return Err(format!("tar header checksum mismatch at offset {}", offset));
+        }
+        checked += 1;
+
+        let size = match header.entry_size() {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(format!(
+                    "unreadable tar entry size at offset {}: {}",
+                    offset, e
+                ));
+            }
+        };
+        offset += TAR_BLOCK;
+
+        // GNU sparse entries are followed by extension blocks while the
+        // previous block has its isextended flag set
+        let mut extended = header.as_gnu().is_some_and(|g| g.is_extended());
+        while extended && offset + TAR_BLOCK <= data.len() {
+            extended = data[offset + 504] != 0;
+            offset += TAR_BLOCK;
+        }
+
+        let padded = size.div_ceil(TAR_BLOCK as u64) * TAR_BLOCK as u64;
+        offset = match usize::try_from(padded)
+            .ok()
+            .and_then(|p| offset.checked_add(p))
+        {
+            Some(o) => o,
+            None => break,
+        };
+    }
+
+    Ok(checked)
+}
+
+/// Sum of the header bytes with the checksum field counted as spaces.
+fn header_checksum(block: &[u8]) -> u32 {
+    block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u32)
+        .sum()
+}
+
+/// An uncompressed tar ends in two zero blocks, padded to its record size.
+fn ends_with_tar_footer(tail: &[u8]) -> bool {
+    tail.len() >= 2 * TAR_BLOCK && tail[tail.len() - 2 * TAR_BLOCK..].iter().all(|&b| b == 0)
+}
+
+pub fn print(reports: &[ScrubReport]) {
+    let name_width = reports
+        .iter()
+        .map(|r| r.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len());
+
+    println!(
+        "{:<6}  {:<9}  {:<name_width$}  NOTES",
+        "STATUS", "TYPE", "NAME"
+    );
+    for r in reports {
+        println!(
+            "{:<6}  {:<9}  {:<name_width$}  {}",
+            r.status.as_str(),
+            r.backup_type.as_str(),
+            r.name,
+            r.notes.join("; ")
+        );
+    }
+}
@@ -1,14 +1,42 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{Layer, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::build_info::{PROJECT_NAME, PROJECT_VERSION};
 
+/// Returns the file and stdout writer guards, and the path of this session's
+/// log file. Dropping the file guard flushes everything logged so far.
 pub async fn setup_logger() -> (
     tracing_appender::non_blocking::WorkerGuard,
     tracing_appender::non_blocking::WorkerGuard,
+    PathBuf,
 ) {
     let app_start_time = chrono::Utc::now();
+
+    // 로거는 Config보다 먼저 초기화되므로 LOG_FORMAT을 위해 .env를 여기서도 읽음
+    dotenvy::dotenv().ok();
+    let log_format = match std::env::var("LOG_FORMAT") {
+        Ok(v) => match v.parse::<LogFormat>() {
+            Ok(f) => Some(f),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        Err(_) => None,
+    };
+    // 지정한 필드 값은 모든 출력에서 ***로 마스킹
+    let redact = Redact::from_env();
     // 로그 파일 및 디렉토리
     let log_dir: &Path = Path::new("./logs");
 
@@ -22,36 +50,85 @@ pub async fn setup_logger() -> (
 
     // tracing 파일 로거 구성 (비동기 논블로킹)
     // 파일 자동 생성
-    let file_appender = tracing_appender::rolling::never(
-        "./logs",
-        format!(
-            "{}_{}_{}.log",
-            PROJECT_NAME,
-            PROJECT_VERSION,
-            app_start_time.format("%Y%m%d_%H%M%S")
-        ),
+    let log_file_name = format!(
+        "{}_{}_{}.log",
+        PROJECT_NAME,
+        PROJECT_VERSION,
+        app_start_time.format("%Y%m%d_%H%M%S")
     );
+    let log_path = log_dir.join(&log_file_name);
+    let file_appender = tracing_appender::rolling::never("./logs", log_file_name);
 
     // 별도의 워커 스레드에서 로거를 실행하여 로깅이 작업 스레드 방해하지 않도록 설정
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
-    // 파일로 로깅할 때 JSON으로 구조적 로깅이 되도록, 그리고 터미널 아웃풋 캐릭터가 들어가지 않도록 설정
-    let file_layer = fmt::layer()
-        .json()
-        .with_ansi(false)
-        .with_file(true)
-        .with_line_number(true)
-        .with_writer(non_blocking)
-        .with_filter(tracing_subscriber::filter::LevelFilter::DEBUG);
+    // 파일로 로깅할 때 기본은 JSON 구조적 로깅, 터미널 아웃풋 캐릭터가 들어가지 않도록 설정
+    let file_layer = match log_format.unwrap_or(LogFormat::Json) {
+        LogFormat::Json if redact.is_active() => fmt::layer()
+            .event_format(RedactedJsonFormat {
+                redact: redact.clone(),
+            })
+            .fmt_fields(redact.clone())
+            .with_ansi(false)
+            .with_writer(non_blocking)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_ansi(false)
+            .with_file(true)
+            .with_line_number(true)
+            .with_writer(non_blocking)
+            .boxed(),
+        LogFormat::Pretty if redact.is_active() => fmt::layer()
+            .fmt_fields(redact.clone())
+            .with_ansi(false)
+            .with_writer(non_blocking)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(false)
+            .with_writer(non_blocking)
+            .boxed(),
+        LogFormat::Logfmt => fmt::layer()
+            .event_format(LogfmtFormat {
+                redact: redact.clone(),
+            })
+            .with_ansi(false)
+            .with_writer(non_blocking)
+            .boxed(),
+    }
+    .with_filter(tracing_subscriber::filter::LevelFilter::DEBUG);
 
     // tracing stdout 로거 구성
     let (non_blocking_stdout, stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
 
-    // 워커 스레드에서 로깅 구성
-    let stdout_layer = fmt::layer()
-        .pretty()
-        .with_writer(non_blocking_stdout)
-        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
+    // 워커 스레드에서 로깅 구성 (기본은 pretty)
+    let stdout_layer = match log_format.unwrap_or(LogFormat::Pretty) {
+        LogFormat::Json if redact.is_active() => fmt::layer()
+            .event_format(RedactedJsonFormat {
+                redact: redact.clone(),
+            })
+            .fmt_fields(redact.clone())
+            .with_writer(non_blocking_stdout)
+            .boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(non_blocking_stdout).boxed(),
+        // The pretty formatter records event fields itself, bypassing
+        // fmt_fields, so redaction falls back to the single-line format
+        LogFormat::Pretty if redact.is_active() => fmt::layer()
+            .fmt_fields(redact.clone())
+            .with_writer(non_blocking_stdout)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_writer(non_blocking_stdout)
+            .boxed(),
+        LogFormat::Logfmt => fmt::layer()
+            .event_format(LogfmtFormat { redact })
+            .with_ansi(false)
+            .with_writer(non_blocking_stdout)
+            .boxed(),
+    }
+    .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
 
     // 로거 초기화
     tracing_subscriber::registry()
@@ -59,5 +136,306 @@ pub async fn setup_logger() -> (
         .with(file_layer)
         .init();
 
-    (guard, stdout_guard)
+    (guard, stdout_guard, log_path)
+}
+
+/// Output format selected with `LOG_FORMAT`. When unset, the file log is JSON
+/// and stdout is pretty; when set, both layers use the chosen format.
+#[derive(Debug, Clone, Copy)]
+enum LogFormat {
+    Json,
+    Pretty,
+    Logfmt,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            other => Err(format!(
+                "Invalid LOG_FORMAT '{other}': expected json, pretty, or logfmt"
+            )),
+        }
+    }
+}
+
+/// Field names from `LOG_REDACT_FIELDS` whose values are logged as `***`.
+#[derive(Debug, Clone, Default)]
+struct Redact {
+    fields: Arc<HashSet<String>>,
+}
+
+const REDACTED: &str = "***";
+
+impl Redact {
+    fn from_env() -> Self {
+        let fields = match std::env::var("LOG_REDACT_FIELDS") {
+            Ok(v) => v
+                .split(',')
+                .map(|f| f.trim().to_ascii_lowercase())
+                .filter(|f| !f.is_empty())
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+        Redact {
+            fields: Arc::new(fields),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    fn applies_to(&self, field: &Field) -> bool {
+        self.is_active() && self.fields.contains(&field.name().to_ascii_lowercase())
+    }
+}
+
+/// Formats event and span fields as `message key=value ...`, masking
+/// redacted ones.
+impl<'writer> FormatFields<'writer> for Redact {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        let mut visitor = RedactingVisitor {
+            redact: self,
+            writer,
+            first: true,
+            result: Ok(()),
+        };
+        fields.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct RedactingVisitor<'a, 'writer> {
+    redact: &'a Redact,
+    writer: Writer<'writer>,
+    first: bool,
+    result: std::fmt::Result,
+}
+
+impl Visit for RedactingVisitor<'_, '_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if self.result.is_err() {
+            return;
+        }
+        let separator = if self.first { "" } else { " " };
+        self.first = false;
+        self.result = if field.name() == "message" {
+            write!(self.writer, "{separator}{value:?}")
+        } else if self.redact.applies_to(field) {
+            write!(self.writer, "{separator}{}={REDACTED}", field.name())
+        } else {
+            write!(self.writer, "{separator}{}={value:?}", field.name())
+        };
+    }
+}
+
+/// JSON lines shaped like tracing-subscriber's JSON output, used instead of it
+/// when redaction is on because the built-in formatter records event fields
+/// directly.
+struct RedactedJsonFormat {
+    redact: Redact,
+}
+
+impl<S, N> FormatEvent<S, N> for RedactedJsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut visitor = JsonVisitor {
+            redact: &self.redact,
+            fields: serde_json::Map::new(),
+        };
+        event.record(&mut visitor);
+
+        let mut object = serde_json::Map::new();
+        object.insert(
+            "timestamp".to_string(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+                .into(),
+        );
+        object.insert("level".to_string(), metadata.level().as_str().into());
+        object.insert("fields".to_string(), visitor.fields.into());
+        object.insert("target".to_string(), metadata.target().into());
+        if let Some(file) = metadata.file() {
+            object.insert("filename".to_string(), file.into());
+        }
+        if let Some(line) = metadata.line() {
+            object.insert("line_number".to_string(), line.into());
+        }
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<serde_json::Value> =
+                scope.from_root().map(|span| span.name().into()).collect();
+            object.insert("spans".to_string(), spans.into());
+        }
+
+        match serde_json::to_string(&object) {
+            Ok(line) => writeln!(writer, "{}", line),
+            Err(_) => Err(std::fmt::Error),
+        }
+    }
+}
+
+struct JsonVisitor<'a> {
+    redact: &'a Redact,
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: serde_json::Value) {
+        let value = if self.redact.applies_to(field) {
+            REDACTED.into()
+        } else {
+            value
+        };
+        self.fields.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.insert(field, format!("{value:?}").into());
+    }
+}
+
+/// Formats each event as a single `key=value` line:
+/// `ts=... level=info target=... span=... msg="..." field=value`.
+struct LogfmtFormat {
+    redact: Redact,
+}
+
+impl<S, N> FormatEvent<S, N> for LogfmtFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut line = String::new();
+
+        write_pair(
+            &mut line,
+            "ts",
+            &chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        );
+        write_pair(
+            &mut line,
+            "level",
+            &metadata.level().as_str().to_ascii_lowercase(),
+        );
+        write_pair(&mut line, "target", metadata.target());
+
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<&str> = scope.from_root().map(|span| span.name()).collect();
+            write_pair(&mut line, "span", &spans.join(">"));
+        }
+
+        let mut visitor = LogfmtVisitor {
+            redact: &self.redact,
+            message: None,
+            fields: String::new(),
+        };
+        event.record(&mut visitor);
+
+        if let Some(message) = visitor.message {
+            write_pair(&mut line, "msg", &message);
+        }
+        line.push_str(&visitor.fields);
+
+        if let Some(file) = metadata.file() {
+            write_pair(&mut line, "file", file);
+        }
+        if let Some(line_number) = metadata.line() {
+            write_pair(&mut line, "line", &line_number.to_string());
+        }
+
+        writeln!(writer, "{}", line.trim_start())
+    }
+}
+
+struct LogfmtVisitor<'a> {
+    redact: &'a Redact,
+    message: Option<String>,
+    fields: String,
+}
+
+impl Visit for LogfmtVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else if self.redact.applies_to(field) {
+            write_pair(&mut self.fields, field.name(), REDACTED);
+        } else {
+            write_pair(&mut self.fields, field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// Append ` key=value`, quoting and escaping the value when needed.
+fn write_pair(out: &mut String, key: &str, value: &str) {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control());
+
+    if !needs_quotes {
+        let _ = write!(out, " {key}={value}");
+        return;
+    }
+
+    out.push(' ');
+    out.push_str(key);
+    out.push_str("=\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
 }
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::backup::BackupKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunOutcome {
+    Success,
+    Failure,
+}
+
+/// Last-run state for a single backup target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupStatus {
+    pub last_run_at: DateTime<Utc>,
+    pub last_outcome: RunOutcome,
+    #[serde(default)]
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// When the last successful run started. Files changed after this may be
+    /// missing from its artifact, so `--since-last` picks up from here.
+    #[serde(default)]
+    pub last_success_started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_artifact: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Position in a differential backup chain: when it was last anchored by a
+/// full backup and how many differentials have been taken since.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainState {
+    pub last_full_at: DateTime<Utc>,
+    pub diffs_since_full: u32,
+}
+
+/// Size of one successfully uploaded full backup, kept for `trend`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SizeSample {
+    pub at: DateTime<Utc>,
+    pub size_bytes: u64,
+}
+
+/// Last timestamp put in an artifact name, with a counter of names issued.
+/// Keeps names increasing when the wall clock steps backwards.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NameStamp {
+    pub last_at: DateTime<Utc>,
+    pub sequence: u64,
+}
+
+/// Row-write counters of the database from `pg_stat_database`, as read
+/// before the last stored db backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbWriteMark {
+    /// `tup_inserted + tup_updated + tup_deleted`.
+    pub writes: u64,
+    /// When the counters were last reset; a change means they restarted.
+    pub stats_reset: Option<String>,
+}
+
+/// Samples kept per target; the oldest are dropped beyond this.
+const HISTORY_LIMIT: usize = 1000;
+
+/// Key of a backup target's run state and size history: the kind, or
+/// `db/<name>` for each database of `DB_NAMES`, so databases backed up in
+/// the same run don't overwrite each other's state.
+pub fn target_key(kind: BackupKind, database: Option<&str>) -> String {
+    match database {
+        Some(db_name) => format!("{}/{}", kind.as_str(), db_name),
+        None => kind.as_str().to_string(),
+    }
+}
+
+/// Persisted per-target run state, stored as JSON at `STATUS_FILE_PATH`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StatusFile {
+    #[serde(default)]
+    pub backups: BTreeMap<String, BackupStatus>,
+    #[serde(default)]
+    pub chains: BTreeMap<String, ChainState>,
+    #[serde(default)]
+    pub history: BTreeMap<String, Vec<SizeSample>>,
+    #[serde(default)]
+    pub stamps: BTreeMap<String, NameStamp>,
+    /// Outcome per kind as of the last notification check, for `NOTIFY_ON=change`.
+    #[serde(default)]
+    pub notified: BTreeMap<String, RunOutcome>,
+    /// Counters as of the last stored db backup, for `DB_SKIP_UNCHANGED`.
+    /// Written before `DB_NAMES` existed; superseded by `db_writes_by_name`.
+    #[serde(default)]
+    pub db_writes: Option<DbWriteMark>,
+    /// Counters as of the last stored backup of each database.
+    #[serde(default)]
+    pub db_writes_by_name: BTreeMap<String, DbWriteMark>,
+}
+
+impl StatusFile {
+    /// Load the status file, returning an empty state if it does not exist yet.
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Failed to read status file");
+                bail!("Failed to read status file {}: {}", path.display(), e);
+            }
+        };
+
+        match serde_json::from_slice(&bytes) {
+            Ok(s) => Ok(s),
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Failed to parse status file");
+                bail!("Failed to parse status file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Err(e) = crate::util::fs::write_json_atomic(path, self).await {
+            error!(error = %e, path = %path.display(), "Failed to write status file");
+            bail!("Failed to write status file: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, kind: BackupKind, database: Option<&str>) -> Option<&BackupStatus> {
+        self.backups.get(&target_key(kind, database))
+    }
+
+    pub fn chain(&self, kind: BackupKind) -> Option<&ChainState> {
+        self.chains.get(kind.as_str())
+    }
+
+    /// Recorded full-backup sizes for the target, oldest first.
+    pub fn history(&self, kind: BackupKind, database: Option<&str>) -> &[SizeSample] {
+        self.history
+            .get(&target_key(kind, database))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/// Serializes every load-modify-save of the status file in this process, so
+/// backups running concurrently don't lose each other's updates.
+static STATUS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Load the status file, apply `f` and save it, under [`STATUS_LOCK`]. A file
+/// that exists but cannot be read or parsed is left untouched and the update
+/// fails, rather than overwriting the recorded state with an empty one.
+async fn update<T>(path: &Path, f: impl FnOnce(&mut StatusFile) -> T) -> anyhow::Result<T> {
+    let _guard = STATUS_LOCK.lock().await;
+    let mut status = StatusFile::load(path).await?;
+    let value = f(&mut status);
+    status.save(path).await?;
+    Ok(value)
+}
+
+/// Record the outcome of a backup run that started at `started_at`. `Ok`
+/// carries the uploaded artifact name, `Err` the error message. Failures to
+/// persist are logged but never fatal.
+pub async fn record_run(
+    path: &Path,
+    kind: BackupKind,
+    database: Option<&str>,
+    started_at: DateTime<Utc>,
+    result: Result<&str, String>,
+) {
+    let key = target_key(kind, database);
+    let updated = update(path, |status| {
+        let now = Utc::now();
+        let previous = status.backups.remove(&key);
+        let last_success_at = previous.as_ref().and_then(|p| p.last_success_at);
+        let last_success_started_at = previous.as_ref().and_then(|p| p.last_success_started_at);
+        let last_artifact = previous.and_then(|p| p.last_artifact);
+
+        let entry = match result {
+            Ok(artifact) => BackupStatus {
+                last_run_at: now,
+                last_outcome: RunOutcome::Success,
+                last_success_at: Some(now),
+                last_success_started_at: Some(started_at),
+                last_artifact: Some(artifact.to_string()),
+                last_error: None,
+            },
+            Err(message) => BackupStatus {
+                last_run_at: now,
+                last_outcome: RunOutcome::Failure,
+                last_success_at,
+                last_success_started_at,
+                last_artifact,
+                last_error: Some(message),
+            },
+        };
+        status.backups.insert(key.clone(), entry);
+    })
+    .await;
+
+    if let Err(e) = updated {
+        warn!(error = %e, target = %key, "Failed to record backup status");
+    }
+}
+
+/// Record a run that found nothing to back up: it counts as a success, but
+/// the last artifact stays the one that still holds the data.
+pub async fn record_unchanged(
+    path: &Path,
+    kind: BackupKind,
+    database: Option<&str>,
+    started_at: DateTime<Utc>,
+) {
+    let key = target_key(kind, database);
+    let updated = update(path, |status| {
+        let now = Utc::now();
+        let last_artifact = status.backups.remove(&key).and_then(|p| p.last_artifact);
+        status.backups.insert(
+            key.clone(),
+            BackupStatus {
+                last_run_at: now,
+                last_outcome: RunOutcome::Success,
+                last_success_at: Some(now),
+                last_success_started_at: Some(started_at),
+                last_artifact,
+                last_error: None,
+            },
+        );
+    })
+    .await;
+
+    if let Err(e) = updated {
+        warn!(error = %e, target = %key, "Failed to record backup status");
+    }
+}
+
+/// Remember the write counters of a stored backup of `db_name`.
+pub async fn record_db_writes(path: &Path, db_name: &str, mark: &DbWriteMark) {
+    let updated = update(path, |status| {
+        status
+            .db_writes_by_name
+            .insert(db_name.to_string(), mark.clone());
+        status.db_writes = None;
+    })
+    .await;
+
+    if let Err(e) = updated {
+        warn!(error = %e, "Failed to record db write counters");
+    }
+}
+
+/// Advance the differential chain after a successful upload: a full backup
+/// re-anchors it, a differential extends it.
+pub async fn record_chain(path: &Path, kind: BackupKind, full: bool, at: DateTime<Utc>) {
+    let updated = update(path, |status| {
+        if full {
+            status.chains.insert(
+                kind.as_str().to_string(),
+                ChainState {
+                    last_full_at: at,
+                    diffs_since_full: 0,
+                },
+            );
+        } else if let Some(chain) = status.chains.get_mut(kind.as_str()) {
+            chain.diffs_since_full += 1;
+        }
+    })
+    .await;
+
+    if let Err(e) = updated {
+        warn!(error = %e, backup_type = %kind, "Failed to record backup chain state");
+    }
+}
+
+/// Append the size of a successful full backup to the history used by `trend`.
+/// Differentials and incrementals are not recorded, as their size says little
+/// about growth.
+pub async fn record_size(
+    path: &Path,
+    kind: BackupKind,
+    database: Option<&str>,
+    sample: SizeSample,
+) {
+    let key = target_key(kind, database);
+    let updated = update(path, |status| {
+        let samples = status.history.entry(key.clone()).or_default();
+        samples.push(sample);
+        if samples.len() > HISTORY_LIMIT {
+            let excess = samples.len() - HISTORY_LIMIT;
+            samples.drain(..excess);
+        }
+    })
+    .await;
+
+    if let Err(e) = updated {
+        warn!(error = %e, target = %key, "Failed to record backup size history");
+    }
+}
+
+/// Store `outcomes`, keyed by artifact label, as the last notified state and
+/// report whether any artifact's outcome differs from before. A label seen for
+/// the first time counts as a change only when it failed, so the first
+/// successful run stays quiet. When the state cannot be updated, every run
+/// counts as a change so no failure goes unnoticed.
+pub async fn swap_notified(path: &Path, outcomes: &[(String, RunOutcome)]) -> bool {
+    let updated = update(path, |status| {
+        let mut changed = false;
+        for (label, outcome) in outcomes {
+            let previous = status
+                .notified
+                .insert(label.clone(), *outcome)
+                .unwrap_or(RunOutcome::Success);
+            changed |= previous != *outcome;
+        }
+        changed
+    })
+    .await;
+
+    match updated {
+        Ok(changed) => changed,
+        Err(e) => {
+            warn!(error = %e, "Failed to record notification state");
+            true
+        }
+    }
+}
+
+/// Time to embed in the next artifact name of `kind`: now, or one
+/// `resolution` past the last name issued if the clock has not moved past it.
+/// A clock that stepped backwards (e.g. an NTP correction) is logged, since
+/// it would otherwise produce names sorting before older backups.
+pub async fn next_name_stamp(
+    path: &Path,
+    kind: BackupKind,
+    resolution: chrono::Duration,
+) -> NameStamp {
+    let now = Utc::now();
+    let updated = update(path, |status| {
+        let stamp = match status.stamps.get(kind.as_str()) {
+            Some(prev) => {
+                if now < prev.last_at {
+                    warn!(
+                        backup_type = %kind,
+                        last_stamp = %prev.last_at,
+                        now = %now,
+                        behind_ms = (prev.last_at - now).num_milliseconds(),
+                        "System clock moved backwards since the last backup; keeping artifact names in order"
+                    );
+                }
+                NameStamp {
+                    last_at: now.max(prev.last_at + resolution),
+                    sequence: prev.sequence + 1,
+                }
+            }
+            None => NameStamp {
+                last_at: now,
+                sequence: 1,
+            },
+        };
+        status.stamps.insert(kind.as_str().to_string(), stamp);
+        stamp
+    })
+    .await;
+
+    match updated {
+        Ok(stamp) => stamp,
+        Err(e) => {
+            warn!(error = %e, backup_type = %kind, "Failed to record artifact name stamp");
+            NameStamp {
+                last_at: now,
+                sequence: 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,883 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::bail;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncReadExt;
+use tracing::{error, info, warn};
+
+use super::{BackendCapabilities, Storage, StorageBackend, Stored, StoredObject};
+use crate::backup::checksum::{self, SIDECAR_EXTENSION, is_sidecar_name};
+use crate::backup::{BackupArtifact, BackupKind};
+use crate::config::config::{B2Config, Config};
+use crate::drive::prune::{PrunePolicy, RetentionCandidate};
+use crate::util::fs::{artifact_name, remove_temp_file};
+
+const AUTHORIZE_URL: &str = "https://api.backblazeb2.com/b2api/v2/b2_authorize_account";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Attempts per B2 call, including the first, before its error is returned.
+const MAX_ATTEMPTS: u32 = 5;
+/// First backoff delay when B2 does not send `Retry-After`; doubled per retry.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest `Retry-After` worth waiting for.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Backblaze B2 via its native API.
+pub struct B2Backend;
+
+impl StorageBackend for B2Backend {
+    fn name(&self) -> &'static str {
+        "b2"
+    }
+
+    fn description(&self) -> &'static str {
+        "Backblaze B2 (native API, application key)"
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            // Each part of a large file upload is retried on its own
+            resumable_upload: true,
+            // Every upload carries a SHA-1 that B2 verifies and stores
+            server_side_checksum: true,
+            ranged_download: true,
+        }
+    }
+
+    fn is_configured(&self, config: &Config) -> bool {
+        config.b2.is_some()
+    }
+
+    fn connect<'a>(
+        &'a self,
+        config: &'a Config,
+    ) -> BoxFuture<'a, anyhow::Result<Box<dyn Storage + 'a>>> {
+        Box::pin(async move {
+            let Some(b2) = &config.b2 else {
+                bail!("B2 is not configured");
+            };
+            let client = B2Client::connect(b2).await?;
+            let mut buckets = Vec::with_capacity(1 + b2.mirror_buckets.len());
+            for name in std::iter::once(&b2.bucket).chain(&b2.mirror_buckets) {
+                buckets.push(client.bucket(name).await?);
+            }
+            Ok(Box::new(B2Storage {
+                config,
+                client,
+                buckets,
+            }) as Box<dyn Storage + 'a>)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizeResponse {
+    account_id: String,
+    authorization_token: String,
+    api_url: String,
+    download_url: String,
+    recommended_part_size: u64,
+    absolute_minimum_part_size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListBucketsResponse {
+    buckets: Vec<BucketInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketInfo {
+    bucket_id: String,
+}
+
+/// A bucket backups are stored in, resolved from its name.
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadUrl {
+    upload_url: String,
+    authorization_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartLargeFileResponse {
+    file_id: String,
+}
+
+/// A stored object as reported by `b2_list_file_versions`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct B2File {
+    pub file_id: String,
+    pub file_name: String,
+    pub upload_timestamp: i64,
+    pub action: String,
+    #[serde(default)]
+    pub content_length: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListFileVersionsResponse {
+    files: Vec<B2File>,
+    next_file_name: Option<String>,
+    next_file_id: Option<String>,
+}
+
+/// An authorized B2 account session.
+pub struct B2Client {
+    http: reqwest::Client,
+    account_id: String,
+    api_url: String,
+    auth_token: String,
+    download_url: String,
+    part_size: u64,
+}
+
+impl B2Client {
+    /// Authorize with the application key.
+    pub async fn connect(config: &B2Config) -> anyhow::Result<Self> {
+        let http = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+            Ok(c) => c,
+            Err(e) => bail!("Failed to build HTTP client: {}", e),
+        };
+
+        let auth: AuthorizeResponse = with_retries("b2_authorize_account", || {
+            http.get(AUTHORIZE_URL)
+                .basic_auth(&config.key_id, Some(&config.app_key))
+                .send()
+        })
+        .await?;
+
+        info!("Authorized with Backblaze B2");
+        Ok(Self {
+            http,
+            account_id: auth.account_id,
+            api_url: auth.api_url,
+            auth_token: auth.authorization_token,
+            download_url: auth.download_url,
+            // Parts must be at least the absolute minimum; the recommended size
+            // keeps the part count (max 10,000) low
+            part_size: auth
+                .recommended_part_size
+                .max(auth.absolute_minimum_part_size),
+        })
+    }
+
+    /// Resolve the bucket called `name` to its id.
+    pub async fn bucket(&self, name: &str) -> anyhow::Result<Bucket> {
+        let buckets: ListBucketsResponse = self
+            .call(
+                "b2_list_buckets",
+                &json!({ "accountId": self.account_id, "bucketName": name }),
+            )
+            .await?;
+        match buckets.buckets.into_iter().next() {
+            Some(b) => Ok(Bucket {
+                id: b.bucket_id,
+                name: name.to_string(),
+            }),
+            None => {
+                error!(bucket = %name, "B2 bucket not found or not accessible with this key");
+                bail!("B2 bucket '{}' not found", name);
+            }
+        }
+    }
+
+    /// Download URL of `file_name` in `bucket`. Private buckets still need an
+    /// authorization token to fetch it.
+    pub fn file_url(&self, bucket: &Bucket, file_name: &str) -> String {
+        format!(
+            "{}/file/{}/{}",
+            self.download_url,
+            bucket.name,
+            encode_file_name(file_name)
+        )
+    }
+
+    /// Upload `path` as `file_name`, switching to a multi-part large file
+    /// upload when it is bigger than one part. Returns the B2 file id.
+    pub async fn upload_file(
+        &self,
+        bucket: &Bucket,
+        path: &Path,
+        file_name: &str,
+    ) -> anyhow::Result<String> {
+        let size = match tokio::fs::metadata(path).await {
+            Ok(m) => m.len(),
+            Err(e) => bail!("Failed to stat {}: {}", path.display(), e),
+        };
+
+        info!(
+            bucket = %bucket.name,
+            file_name = file_name,
+            size_bytes = size,
+            large_file = size > self.part_size,
+            "Starting upload to Backblaze B2"
+        );
+
+        let file_id = if size > self.part_size {
+            self.upload_large_file(bucket, path, file_name).await?
+        } else {
+            self.upload_small_file(bucket, path, file_name).await?
+        };
+
+        info!(bucket = %bucket.name, file_name = file_name, file_id = %file_id, "Upload to Backblaze B2 completed");
+        Ok(file_id)
+    }
+
+    async fn upload_small_file(
+        &self,
+        bucket: &Bucket,
+        path: &Path,
+        file_name: &str,
+    ) -> anyhow::Result<String> {
+        let data = match tokio::fs::read(path).await {
+            Ok(d) => d,
+            Err(e) => bail!("Failed to read {}: {}", path.display(), e),
+        };
+        let sha1 = hex::encode(Sha1::digest(&data));
+
+        // An upload URL that fails is not reused; each attempt asks for a new one
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let target: UploadUrl = self
+                .call("b2_get_upload_url", &json!({ "bucketId": bucket.id }))
+                .await?;
+            let sent = self
+                .http
+                .post(&target.upload_url)
+                .header("Authorization", &target.authorization_token)
+                .header("X-Bz-File-Name", encode_file_name(file_name))
+                .header("Content-Type", "b2/x-auto")
+                .header("X-Bz-Content-Sha1", &sha1)
+                .body(data.clone())
+                .send()
+                .await;
+            match upload_outcome::<B2File>("b2_upload_file", sent, attempt).await {
+                Outcome::Done(uploaded) => return Ok(uploaded.file_id),
+                Outcome::Retry(delay) => tokio::time::sleep(delay).await,
+                Outcome::Failed(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn upload_large_file(
+        &self,
+        bucket: &Bucket,
+        path: &Path,
+        file_name: &str,
+    ) -> anyhow::Result<String> {
+        let started: StartLargeFileResponse = self
+            .call(
+                "b2_start_large_file",
+                &json!({
+                    "bucketId": bucket.id,
+                    "fileName": file_name,
+                    "contentType": "b2/x-auto",
+                }),
+            )
+            .await?;
+        let file_id = started.file_id;
+
+        match self.upload_parts(path, &file_id).await {
+            Ok(part_sha1s) => {
+                let _: B2File = self
+                    .call(
+                        "b2_finish_large_file",
+                        &json!({ "fileId": file_id, "partSha1Array": part_sha1s }),
+                    )
+                    .await?;
+                Ok(file_id)
+            }
+            Err(e) => {
+                // Unfinished large files keep their parts (and cost storage)
+                // until cancelled
+                if let Err(cancel) = self
+                    .call::<serde_json::Value>(
+                        "b2_cancel_large_file",
+                        &json!({ "fileId": file_id }),
+                    )
+                    .await
+                {
+                    warn!(error = %cancel, file_id = %file_id, "Failed to cancel unfinished B2 large file");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Upload `path` part by part, holding one part in memory at a time. A
+    /// part that fails is sent again on a fresh part URL, so an interrupted
+    /// transfer resumes from that part instead of starting over. Returns the
+    /// SHA-1 of each part in order.
+    async fn upload_parts(&self, path: &Path, file_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut file = match tokio::fs::File::open(path).await {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to open {}: {}", path.display(), e),
+        };
+        let mut target: UploadUrl = self
+            .call("b2_get_upload_part_url", &json!({ "fileId": file_id }))
+            .await?;
+
+        let mut part_sha1s = Vec::new();
+        loop {
+            let mut part = Vec::with_capacity(self.part_size as usize);
+            let read = match (&mut file)
+                .take(self.part_size)
+                .read_to_end(&mut part)
+                .await
+            {
+                Ok(n) => n,
+                Err(e) => bail!("Failed to read {}: {}", path.display(), e),
+            };
+            if read == 0 {
+                break;
+            }
+
+            let part_number = part_sha1s.len() + 1;
+            let sha1 = hex::encode(Sha1::digest(&part));
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let sent = self
+                    .http
+                    .post(&target.upload_url)
+                    .header("Authorization", &target.authorization_token)
+                    .header("X-Bz-Part-Number", part_number)
+                    .header("X-Bz-Content-Sha1", &sha1)
+                    .body(part.clone())
+                    .send()
+                    .await;
+                match upload_outcome::<serde_json::Value>("b2_upload_part", sent, attempt).await {
+                    Outcome::Done(_) => break,
+                    Outcome::Retry(delay) => {
+                        warn!(
+                            file_id = file_id,
+                            part = part_number,
+                            attempt = attempt,
+                            "Retrying B2 part on a new upload URL"
+                        );
+                        tokio::time::sleep(delay).await;
+                        target = self
+                            .call("b2_get_upload_part_url", &json!({ "fileId": file_id }))
+                            .await?;
+                    }
+                    Outcome::Failed(e) => return Err(e),
+                }
+            }
+
+            info!(file_id = file_id, part = part_number, "Uploaded B2 part");
+            crate::systemd::progress();
+            part_sha1s.push(sha1);
+        }
+
+        Ok(part_sha1s)
+    }
+
+    /// Every version of every file in `bucket` whose name starts with `prefix`.
+    pub async fn list_file_versions(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+    ) -> anyhow::Result<Vec<B2File>> {
+        let mut files = Vec::new();
+        let mut start: Option<(String, String)> = None;
+
+        loop {
+            let mut body = json!({
+                "bucketId": bucket.id,
+                "prefix": prefix,
+                "maxFileCount": 1000,
+            });
+            if let Some((name, id)) = &start {
+                body["startFileName"] = json!(name);
+                body["startFileId"] = json!(id);
+            }
+
+            let page: ListFileVersionsResponse = self.call("b2_list_file_versions", &body).await?;
+            files.extend(page.files);
+
+            match (page.next_file_name, page.next_file_id) {
+                (Some(name), Some(id)) => start = Some((name, id)),
+                _ => break,
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Uploaded backups in `bucket` under `prefix` whose names (after the
+    /// prefix) start with `name_prefix`, newest first. Sidecars are returned
+    /// separately.
+    async fn list_backups(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        name_prefix: &str,
+    ) -> anyhow::Result<(Vec<B2File>, Vec<B2File>)> {
+        let (sidecars, mut files): (Vec<B2File>, Vec<B2File>) = self
+            .list_file_versions(bucket, &format!("{}{}", prefix, name_prefix))
+            .await?
+            .into_iter()
+            .filter(|f| f.action == "upload")
+            .partition(|f| is_sidecar_name(&f.file_name));
+        // Upload time is B2's clock; ties fall back to the increasing name
+        files.sort_by(|a, b| {
+            b.upload_timestamp
+                .cmp(&a.upload_timestamp)
+                .then_with(|| b.file_name.cmp(&a.file_name))
+        });
+        Ok((files, sidecars))
+    }
+
+    pub async fn delete_file_version(&self, file: &B2File) -> anyhow::Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                "b2_delete_file_version",
+                &json!({ "fileName": file.file_name, "fileId": file.file_id }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Delete all but the `keep` most recently uploaded backup chains under
+    /// `prefix` in `bucket` that match the policy's name prefix (see
+    /// [`PrunePolicy::select`]), each with its checksum sidecar. Returns the
+    /// number of backups deleted.
+    pub async fn prune(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        policy: &PrunePolicy<'_>,
+    ) -> anyhow::Result<u32> {
+        let (files, sidecars) = self
+            .list_backups(bucket, prefix, &policy.name_prefix)
+            .await?;
+
+        // Retention matches on the name without the folder-like prefix
+        let candidates: Vec<RetentionCandidate<'_>> = files
+            .iter()
+            .map(|f| RetentionCandidate {
+                id: &f.file_id,
+                name: f.file_name.strip_prefix(prefix).unwrap_or(&f.file_name),
+                created: chrono::DateTime::from_timestamp_millis(f.upload_timestamp),
+            })
+            .collect();
+
+        let total = files.len();
+        let mut deleted: u32 = 0;
+        for (index, reason) in policy.select(&candidates) {
+            let file = &files[index];
+            info!(bucket = %bucket.name, file_name = %file.file_name, file_id = %file.file_id, reason = %reason, "Deleting old backup");
+            if let Err(e) = self.delete_file_version(file).await {
+                error!(
+                    error = %e,
+                    file_name = %file.file_name,
+                    "Failed to delete file during pruning"
+                );
+                continue;
+            }
+            deleted += 1;
+
+            let sidecar_name = format!("{}.{}", file.file_name, SIDECAR_EXTENSION);
+            for sidecar in sidecars.iter().filter(|s| s.file_name == sidecar_name) {
+                if let Err(e) = self.delete_file_version(sidecar).await {
+                    warn!(error = %e, file_name = %sidecar.file_name, "Failed to delete checksum sidecar");
+                }
+            }
+        }
+
+        info!(
+            bucket = %bucket.name,
+            prefix = %prefix,
+            name_prefix = %policy.name_prefix,
+            deleted = deleted,
+            kept = policy.keep,
+            total_before = total,
+            "Pruning completed"
+        );
+
+        Ok(deleted)
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        operation: &str,
+        body: &serde_json::Value,
+    ) -> anyhow::Result<T> {
+        let url = format!("{}/b2api/v2/{}", self.api_url, operation);
+        with_retries(operation, || {
+            self.http
+                .post(&url)
+                .header("Authorization", &self.auth_token)
+                .json(body)
+                .send()
+        })
+        .await
+    }
+}
+
+/// A B2 session over `B2_BUCKET` and every `B2_MIRROR_BUCKETS` bucket.
+/// Destinations are `<bucket>/<prefix>/`.
+pub struct B2Storage<'a> {
+    config: &'a Config,
+    client: B2Client,
+    /// The primary bucket first, then the mirrors.
+    buckets: Vec<Bucket>,
+}
+
+impl B2Storage<'_> {
+    /// The bucket and key prefix of a destination.
+    fn split_destination<'d>(&self, destination: &'d str) -> anyhow::Result<(&Bucket, &'d str)> {
+        let (name, prefix) = destination.split_once('/').unwrap_or((destination, ""));
+        match self.buckets.iter().find(|b| b.name == name) {
+            Some(bucket) => Ok((bucket, prefix)),
+            None => bail!("{} is not in a configured B2 bucket", destination),
+        }
+    }
+
+    /// Upload the artifact and, with `UPLOAD_CHECKSUM_SIDECAR`, its sidecar
+    /// to one bucket. A failed sidecar upload is logged but does not fail it.
+    async fn upload_to(
+        &self,
+        bucket: &Bucket,
+        prefix: &str,
+        artifact: &BackupArtifact,
+        sidecar: Option<&Path>,
+    ) -> anyhow::Result<String> {
+        let file_name = artifact_name(&artifact.path);
+        let file_id = self
+            .client
+            .upload_file(bucket, &artifact.path, &format!("{}{}", prefix, file_name))
+            .await?;
+        if let Some(sidecar) = sidecar
+            && let Err(e) = self
+                .client
+                .upload_file(
+                    bucket,
+                    sidecar,
+                    &format!("{}{}", prefix, artifact_name(sidecar)),
+                )
+                .await
+        {
+            warn!(
+                error = %e,
+                bucket = %bucket.name,
+                "Failed to upload checksum sidecar; backup itself was uploaded"
+            );
+        }
+        Ok(file_id)
+    }
+}
+
+impl Storage for B2Storage<'_> {
+    fn upload<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        artifact: &'a BackupArtifact,
+        kind: BackupKind,
+    ) -> BoxFuture<'a, anyhow::Result<Stored>> {
+        Box::pin(async move {
+            let prefix = format!("{}/", folder.join("/"));
+            let file_name = artifact_name(&artifact.path);
+
+            let sidecar = if self.config.upload_checksum_sidecar {
+                // Reuse the digest taken while writing the artifact when there is one
+                let digest = match &artifact.sha256 {
+                    Some(digest) => Ok(digest.clone()),
+                    None => checksum::sha256_file(&artifact.path).await,
+                };
+                match digest {
+                    Ok(digest) => {
+                        match checksum::write_sha256_sidecar(&artifact.path, &digest).await {
+                            Ok(path) => Some(path),
+                            Err(e) => {
+                                warn!(error = %e, "Failed to write checksum sidecar; uploading without it");
+                                None
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "Failed to hash backup for its sidecar; uploading without it");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Built up front, as a stream mapping closure trips the Send check
+            // of this boxed future
+            let (prefix_ref, sidecar_ref) = (prefix.as_str(), sidecar.as_deref());
+            let uploads: Vec<_> = self
+                .buckets
+                .iter()
+                .map(|bucket| async move {
+                    let result = self
+                        .upload_to(bucket, prefix_ref, artifact, sidecar_ref)
+                        .await;
+                    (bucket, result)
+                })
+                .collect();
+            let results: Vec<(&Bucket, anyhow::Result<String>)> = stream::iter(uploads)
+                .buffer_unordered(self.config.fanout_concurrency.max(1))
+                .collect()
+                .await;
+            if let Some(sidecar) = &sidecar {
+                remove_temp_file(sidecar).await;
+            }
+
+            let mut stored = Stored::default();
+            let mut failures = Vec::new();
+            for (bucket, result) in results {
+                let file_id = match result {
+                    Ok(id) => id,
+                    Err(e) => {
+                        error!(error = %e, bucket = %bucket.name, "Upload to B2 bucket failed");
+                        failures.push(format!("{}: {}", bucket.name, e));
+                        continue;
+                    }
+                };
+                let destination = format!("{}/{}", bucket.name, prefix);
+                crate::report::record_upload(crate::report::UploadRecord {
+                    backup_type: kind,
+                    file_name: file_name.clone(),
+                    destination: destination.clone(),
+                    file_id: Some(file_id.clone()),
+                    sha256: artifact.sha256.clone(),
+                });
+                if bucket.name == self.buckets[0].name || stored.id.is_none() {
+                    stored.link = Some(
+                        self.client
+                            .file_url(bucket, &format!("{}{}", prefix, file_name)),
+                    );
+                    stored.id = Some(file_id);
+                }
+                stored.destinations.push(destination);
+            }
+
+            if stored.destinations.is_empty() {
+                bail!(
+                    "Upload of {} failed to all {} B2 buckets: {}",
+                    file_name,
+                    failures.len(),
+                    failures.join("; ")
+                );
+            }
+            if !failures.is_empty() {
+                warn!(
+                    file_name = %file_name,
+                    succeeded = stored.destinations.len(),
+                    failed = failures.len(),
+                    "Backup uploaded to only some B2 buckets"
+                );
+            }
+            // Keep the primary bucket first, as the caller prunes in this order
+            stored
+                .destinations
+                .sort_by_key(|d| !d.starts_with(&format!("{}/", self.buckets[0].name)));
+            Ok(stored)
+        })
+    }
+
+    fn list<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        name_prefix: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<StoredObject>>> {
+        Box::pin(async move {
+            let prefix = format!("{}/", folder.join("/"));
+            let (files, _) = self
+                .client
+                .list_backups(&self.buckets[0], &prefix, name_prefix)
+                .await?;
+            Ok(files
+                .into_iter()
+                .map(|f| StoredObject {
+                    name: f
+                        .file_name
+                        .strip_prefix(&prefix)
+                        .unwrap_or(&f.file_name)
+                        .to_string(),
+                    id: f.file_id,
+                    size_bytes: f.content_length,
+                    created_at: chrono::DateTime::from_timestamp_millis(f.upload_timestamp),
+                    // B2 checks every upload's SHA-1, so a stored size is always right
+                    suspect: false,
+                })
+                .collect())
+        })
+    }
+
+    fn destinations<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+    ) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let prefix = format!("{}/", folder.join("/"));
+            Ok(self
+                .buckets
+                .iter()
+                .map(|b| format!("{}/{}", b.name, prefix))
+                .collect())
+        })
+    }
+
+    fn prune<'a>(
+        &'a self,
+        destination: &'a str,
+        policy: &'a PrunePolicy<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<u32>> {
+        Box::pin(async move {
+            let (bucket, prefix) = self.split_destination(destination)?;
+            self.client.prune(bucket, prefix, policy).await
+        })
+    }
+}
+
+/// What to do after sending an upload request.
+enum Outcome<T> {
+    Done(T),
+    /// Wait this long, then try again on a new upload URL.
+    Retry(Duration),
+    Failed(anyhow::Error),
+}
+
+/// Classify the response to attempt number `attempt` of an upload. Uploads
+/// are retried on connection errors, timeouts, `401` (expired upload token),
+/// `408`, `429`, `500` and `503`, as B2 asks.
+async fn upload_outcome<T: DeserializeOwned>(
+    operation: &str,
+    sent: reqwest::Result<reqwest::Response>,
+    attempt: u32,
+) -> Outcome<T> {
+    let retryable = match &sent {
+        Ok(response) => matches!(response.status().as_u16(), 401 | 408 | 429 | 500 | 503),
+        Err(_) => true,
+    };
+    if retryable && attempt < MAX_ATTEMPTS {
+        let delay = match &sent {
+            Ok(response) => retry_delay(response, attempt),
+            Err(_) => backoff(attempt),
+        };
+        match &sent {
+            Ok(response) => {
+                warn!(operation = operation, status = %response.status(), attempt = attempt, delay = ?delay, "B2 upload failed, retrying")
+            }
+            Err(e) => {
+                warn!(operation = operation, error = %e, attempt = attempt, delay = ?delay, "B2 upload failed to connect, retrying")
+            }
+        }
+        return Outcome::Retry(delay);
+    }
+
+    match sent {
+        Ok(response) => match parse_response(operation, response).await {
+            Ok(v) => Outcome::Done(v),
+            Err(e) => Outcome::Failed(e),
+        },
+        Err(e) => Outcome::Failed(anyhow::anyhow!("{} request failed: {}", operation, e)),
+    }
+}
+
+/// Send the request `send` builds, retrying connection errors, `408`, `429`,
+/// `500` and `503` with backoff (or `Retry-After`), then parse the response.
+async fn with_retries<T, F, Fut>(operation: &str, mut send: F) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let response = match send().await {
+            Ok(r) => r,
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                let delay = backoff(attempt);
+                warn!(operation = operation, error = %e, attempt = attempt, delay = ?delay, "B2 request failed to connect, retrying");
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(e) => {
+                error!(operation = operation, error = %e, "B2 request failed");
+                bail!("{} request failed: {}", operation, e);
+            }
+        };
+
+        if matches!(response.status().as_u16(), 408 | 429 | 500 | 503) && attempt < MAX_ATTEMPTS {
+            let delay = retry_delay(&response, attempt);
+            if delay > MAX_RETRY_AFTER {
+                return parse_response(operation, response).await;
+            }
+            warn!(operation = operation, status = %response.status(), attempt = attempt, delay = ?delay, "B2 is busy, retrying");
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+        return parse_response(operation, response).await;
+    }
+}
+
+/// `Retry-After` seconds when B2 sent them, otherwise exponential backoff.
+fn retry_delay(response: &reqwest::Response, attempt: u32) -> Duration {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| backoff(attempt))
+}
+
+/// Delay before retry `attempt` (1-based): [`BASE_DELAY`] doubled each time.
+fn backoff(attempt: u32) -> Duration {
+    BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+}
+
+async fn parse_response<T: DeserializeOwned>(
+    operation: &str,
+    response: reqwest::Response,
+) -> anyhow::Result<T> {
+    let status = response.status();
+    if !status.is_success() {
+        // Errors are {"status", "code", "message"}
+        let text = response.text().await.unwrap_or_default();
+        error!(operation = operation, status = %status, body = %text, "B2 API call failed");
+        bail!("{} returned HTTP {}: {}", operation, status, text);
+    }
+
+    match response.json().await {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("Failed to parse {} response: {}", operation, e),
+    }
+}
+
+/// Percent-encode a file name for the `X-Bz-File-Name` header; `/` is kept.
+fn encode_file_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
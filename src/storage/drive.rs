@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::bail;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use tracing::{error, info, warn};
+
+use super::{BackendCapabilities, Storage, StorageBackend, Stored, StoredObject};
+use crate::backup::{self, BackupArtifact, BackupKind};
+use crate::config::config::Config;
+use crate::drive::auth::{DriveAccounts, DriveHub};
+use crate::drive::prune::{self, PrunePolicy};
+use crate::drive::upload::{self, StorageQuotaExceeded, UploadedFile};
+use crate::metrics::UploadStream;
+use crate::util::fs::{artifact_name, remove_temp_file};
+use crate::{build_info, report, util};
+
+/// Google Drive via the Drive v3 API.
+pub struct DriveBackend;
+
+impl StorageBackend for DriveBackend {
+    fn name(&self) -> &'static str {
+        "drive"
+    }
+
+    fn description(&self) -> &'static str {
+        "Google Drive (Drive v3 API, OAuth user credentials)"
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resumable_upload: true,
+            // Drive reports md5Checksum for every binary file
+            server_side_checksum: true,
+            ranged_download: true,
+        }
+    }
+
+    fn is_configured(&self, config: &Config) -> bool {
+        !config.google_drive_folder_id.trim().is_empty()
+            && config
+                .google_credentials_paths
+                .iter()
+                .all(|path| path.is_file())
+    }
+
+    fn connect<'a>(
+        &'a self,
+        config: &'a Config,
+    ) -> BoxFuture<'a, anyhow::Result<Box<dyn Storage + 'a>>> {
+        Box::pin(async move {
+            let accounts =
+                DriveAccounts::build(&config.google_credentials_paths, config.auth_retry).await?;
+            Ok(Box::new(DriveStorage { config, accounts }) as Box<dyn Storage + 'a>)
+        })
+    }
+}
+
+/// A Drive session over every configured account. Destinations are folder
+/// ids under the primary root and each `GOOGLE_DRIVE_MIRROR_FOLDER_IDS` root.
+pub struct DriveStorage<'a> {
+    config: &'a Config,
+    accounts: DriveAccounts,
+}
+
+impl Storage for DriveStorage<'_> {
+    fn upload<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        artifact: &'a BackupArtifact,
+        kind: BackupKind,
+    ) -> BoxFuture<'a, anyhow::Result<Stored>> {
+        Box::pin(async move {
+            let folder_ids = resolve_folders(self.config, self.accounts.hub(), folder).await?;
+            let (destinations, primary) =
+                upload_artifact(self.config, &self.accounts, &folder_ids, artifact, kind).await?;
+            Ok(Stored {
+                destinations,
+                id: primary.as_ref().and_then(|f| f.id.clone()),
+                link: primary.and_then(|f| f.web_view_link),
+            })
+        })
+    }
+
+    fn list<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        name_prefix: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<StoredObject>>> {
+        Box::pin(async move {
+            let hub = self.accounts.hub();
+            let folder_ids = resolve_folders(self.config, hub, folder).await?;
+            match folder_ids.first() {
+                Some(folder_id) => list_folder(hub, folder_id, name_prefix).await,
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+
+    fn destinations<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+    ) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        Box::pin(resolve_folders(self.config, self.accounts.hub(), folder))
+    }
+
+    fn prune<'a>(
+        &'a self,
+        destination: &'a str,
+        policy: &'a PrunePolicy<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<u32>> {
+        Box::pin(prune::prune_old_backups(
+            self.accounts.hub(),
+            destination,
+            policy,
+        ))
+    }
+}
+
+/// Backups in the Drive folder `folder_id` whose names start with
+/// `name_prefix`, newest first, without checksum sidecars.
+pub async fn list_folder(
+    hub: &DriveHub,
+    folder_id: &str,
+    name_prefix: &str,
+) -> anyhow::Result<Vec<StoredObject>> {
+    let mut objects = Vec::new();
+    for file in prune::list_all_files_in_folder(hub, folder_id).await? {
+        let suspect = prune::size_mismatch(&file).is_some();
+        let (Some(id), Some(name)) = (file.id, file.name) else {
+            continue;
+        };
+        if !name.starts_with(name_prefix) || backup::checksum::is_sidecar_name(&name) {
+            continue;
+        }
+        objects.push(StoredObject {
+            id,
+            name,
+            size_bytes: file.size.and_then(|s| u64::try_from(s).ok()),
+            created_at: file.created_time,
+            suspect,
+        });
+    }
+    Ok(objects)
+}
+
+/// The folder at `path` under the primary root and every mirror root,
+/// created where missing.
+pub async fn resolve_folders(
+    config: &Config,
+    hub: &DriveHub,
+    path: &[&str],
+) -> anyhow::Result<Vec<String>> {
+    let mut folder_ids = Vec::with_capacity(1 + config.google_drive_mirror_folder_ids.len());
+    let primary = primary_root(config, hub).await?;
+    let roots = std::iter::once(&primary).chain(config.google_drive_mirror_folder_ids.iter());
+    for root in roots {
+        let mut folder_id = root.clone();
+        for name in path {
+            folder_id =
+                upload::find_or_create_folder(hub, &folder_id, name, &folder_metadata(config))
+                    .await?;
+        }
+        folder_ids.push(folder_id);
+    }
+    Ok(folder_ids)
+}
+
+/// The primary root folder: `GOOGLE_DRIVE_FOLDER_ID`, or the folder
+/// `GOOGLE_DRIVE_FOLDER_PATH` leads to from it, resolved once per run.
+pub async fn primary_root(config: &Config, hub: &DriveHub) -> anyhow::Result<String> {
+    static RESOLVED: tokio::sync::OnceCell<String> = tokio::sync::OnceCell::const_new();
+
+    if config.google_drive_folder_path.is_empty() {
+        return Ok(config.google_drive_folder_id.clone());
+    }
+    let metadata = folder_metadata(config);
+    RESOLVED
+        .get_or_try_init(|| {
+            upload::resolve_folder_path(
+                hub,
+                &config.google_drive_folder_id,
+                &config.google_drive_folder_path,
+                &metadata,
+            )
+        })
+        .await
+        .cloned()
+}
+
+/// Metadata for folders the tool creates: `DRIVE_FOLDER_DESCRIPTION`, plus
+/// `managed_by`/`managed_by_version` app properties with `DRIVE_FOLDER_TAG`.
+pub fn folder_metadata(config: &Config) -> upload::FolderMetadata {
+    let mut app_properties = HashMap::new();
+    if config.drive_folder_tag {
+        app_properties.insert(
+            "managed_by".to_string(),
+            build_info::PROJECT_NAME.to_string(),
+        );
+        app_properties.insert(
+            "managed_by_version".to_string(),
+            build_info::PROJECT_VERSION.to_string(),
+        );
+    }
+    upload::FolderMetadata {
+        description: config.drive_folder_description.clone(),
+        app_properties,
+    }
+}
+
+/// Upload a backup artifact to every folder in `folder_ids`, at most
+/// `FANOUT_CONCURRENCY` at a time. Each upload opens its own handle on the
+/// file. Succeeds if at least one folder received the file and returns the
+/// folders that did, plus the copy in the primary folder (shared per
+/// `DRIVE_GRANT_READER` when set). When `UPLOAD_CHECKSUM_SIDECAR` is set, a `<name>.sha256`
+/// sidecar verifiable with `sha256sum -c` follows into the same folders; a
+/// failed sidecar upload is logged but does not fail the backup.
+async fn upload_artifact(
+    config: &Config,
+    accounts: &DriveAccounts,
+    folder_ids: &[String],
+    artifact: &BackupArtifact,
+    kind: BackupKind,
+) -> anyhow::Result<(Vec<String>, Option<UploadedFile>)> {
+    let path = artifact.path.as_path();
+    // Built up front, as a stream mapping closure trips the Send check of
+    // the boxed future this runs in
+    let uploads: Vec<_> = folder_ids
+        .iter()
+        .map(|folder_id| async move {
+            let result = upload_with_failover(config, accounts, folder_id, artifact, kind).await;
+            (folder_id.clone(), result)
+        })
+        .collect();
+    let results: Vec<(String, anyhow::Result<UploadedFile>)> = stream::iter(uploads)
+        .buffer_unordered(config.fanout_concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut uploaded_to = Vec::with_capacity(results.len());
+    let mut primary_file = None;
+    let mut failures = Vec::new();
+    for (folder_id, result) in results {
+        match result {
+            Ok(file) => {
+                report::record_upload(report::UploadRecord {
+                    backup_type: kind,
+                    file_name: artifact_name(path),
+                    destination: folder_id.clone(),
+                    file_id: file.id.clone(),
+                    sha256: artifact.sha256.clone(),
+                });
+                if primary_file.is_none() || folder_ids.first() == Some(&folder_id) {
+                    primary_file = Some(file);
+                }
+                uploaded_to.push(folder_id);
+            }
+            Err(e) => failures.push((folder_id, e)),
+        }
+    }
+
+    if uploaded_to.is_empty() {
+        if failures.len() == 1
+            && let Some((_, e)) = failures.pop()
+        {
+            return Err(e);
+        }
+        let details: Vec<String> = failures
+            .iter()
+            .map(|(folder_id, e)| format!("{}: {}", folder_id, e))
+            .collect();
+        error!(
+            path = %path.display(),
+            failed = failures.len(),
+            "Upload failed to every target folder"
+        );
+        bail!(
+            "Upload of {} failed to all {} folders: {}",
+            path.display(),
+            failures.len(),
+            details.join("; ")
+        );
+    }
+
+    if !failures.is_empty() {
+        for (folder_id, e) in &failures {
+            error!(error = %e, folder_id = %folder_id, "Fan-out upload to folder failed");
+        }
+        warn!(
+            path = %path.display(),
+            succeeded = uploaded_to.len(),
+            failed = failures.len(),
+            "Backup uploaded to only some target folders"
+        );
+    }
+
+    if let Some(file) = &primary_file
+        && let (Some(grant), Some(id)) = (&config.drive_grant_reader, file.id.as_deref())
+        && let Err(e) = upload::grant_reader(accounts.hub(), id, grant).await
+    {
+        warn!(error = %e, "Failed to share uploaded backup; its link may not be accessible");
+    }
+
+    if !config.upload_checksum_sidecar {
+        return Ok((uploaded_to, primary_file));
+    }
+
+    let sidecar = async {
+        // Reuse the digest taken while writing the artifact when there is one
+        let digest = match &artifact.sha256 {
+            Some(digest) => digest.clone(),
+            None => backup::checksum::sha256_file(path).await?,
+        };
+        let sidecar_path = backup::checksum::write_sha256_sidecar(path, &digest).await?;
+        let properties = upload_properties(config, kind);
+        let mut uploaded = Ok(());
+        for folder_id in &uploaded_to {
+            if let Err(e) = upload::upload_file(
+                accounts.hub(),
+                folder_id,
+                &sidecar_path,
+                &properties,
+                None,
+                None,
+            )
+            .await
+            {
+                uploaded = Err(e);
+            }
+        }
+        remove_temp_file(&sidecar_path).await;
+        uploaded
+    }
+    .await;
+
+    if let Err(e) = sidecar {
+        warn!(
+            error = %e,
+            path = %path.display(),
+            "Failed to upload checksum sidecar; backup itself was uploaded"
+        );
+    }
+
+    Ok((uploaded_to, primary_file))
+}
+
+/// Upload `path` with the active account, moving on to the next configured
+/// account when Drive rejects the current one for quota or authorization.
+/// With `CONFIRM_UPLOAD_VISIBLE` the upload only counts once Drive serves it.
+async fn upload_with_failover(
+    config: &Config,
+    accounts: &DriveAccounts,
+    folder_id: &str,
+    artifact: &BackupArtifact,
+    kind: BackupKind,
+) -> anyhow::Result<UploadedFile> {
+    let mut index = accounts.active_index();
+    loop {
+        let hub = accounts.hub_at(index);
+        let err = match upload_with_quota_recovery(config, hub, folder_id, artifact, kind).await {
+            Ok(file) => {
+                if config.confirm_upload_visible
+                    && let Some(id) = file.id.as_deref()
+                {
+                    upload::confirm_visible(hub, id).await?;
+                }
+                return Ok(file);
+            }
+            Err(e) => e,
+        };
+
+        if !upload::is_account_failover_error(&err) {
+            return Err(err);
+        }
+        index = match accounts.fail_over(index) {
+            Some(next) => next,
+            None => {
+                error!(error = %err, "Every configured Google account rejected the upload");
+                return Err(err);
+            }
+        };
+    }
+}
+
+/// Upload `artifact`; if Drive reports the storage quota is exhausted and
+/// `PRUNE_ON_QUOTA` is set, run the type's retention prune and retry once.
+async fn upload_with_quota_recovery(
+    config: &Config,
+    hub: &DriveHub,
+    folder_id: &str,
+    artifact: &BackupArtifact,
+    kind: BackupKind,
+) -> anyhow::Result<UploadedFile> {
+    let path = artifact.path.as_path();
+    let stream = UploadStream {
+        kind,
+        database: artifact.database.clone(),
+    };
+    let stream = Some(&stream);
+    let properties = upload_properties(config, kind);
+    let description = upload_description(config, kind, path).await;
+    let description = description.as_deref();
+    let attempt = match check_free_quota(config, hub, path).await {
+        Ok(()) => upload::upload_file(hub, folder_id, path, &properties, description, stream).await,
+        Err(e) => Err(e),
+    };
+    let err = match attempt {
+        Ok(file) => return Ok(file),
+        Err(e) => e,
+    };
+
+    if err.downcast_ref::<StorageQuotaExceeded>().is_none() || !config.prune_on_quota {
+        return Err(err);
+    }
+
+    let Some(policy) = prune::retention_policy(config, kind, &config.prune_keep_ids) else {
+        warn!(
+            backup_type = %kind,
+            "Drive quota exceeded but no retention is configured for this type; cannot prune"
+        );
+        return Err(err);
+    };
+
+    warn!(
+        backup_type = %kind,
+        "Drive storage quota exceeded, running emergency prune before retrying upload"
+    );
+    let deleted = prune::prune_old_backups(hub, folder_id, &policy).await?;
+    report::record_prune(kind, folder_id, deleted);
+    if deleted == 0 {
+        warn!(backup_type = %kind, "Emergency prune freed nothing; not retrying upload");
+        return Err(err);
+    }
+
+    info!(deleted = deleted, "Retrying upload after emergency prune");
+    upload::upload_file(hub, folder_id, path, &properties, description, stream).await
+}
+
+/// Fail fast with [`StorageQuotaExceeded`] when the account lacks room for
+/// `path` plus `QUOTA_PREFLIGHT_MARGIN_MB`, rather than at the end of a long
+/// transfer. If the quota cannot be read the upload goes ahead.
+async fn check_free_quota(config: &Config, hub: &DriveHub, path: &Path) -> anyhow::Result<()> {
+    let Some(margin) = config.quota_preflight_margin else {
+        return Ok(());
+    };
+    // Shared Drive files don't count against the account's quota
+    if config.google_drive_shared_drive_id.is_some() {
+        return Ok(());
+    }
+
+    let available = match upload::available_quota(hub).await {
+        Ok(Some(a)) => a,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            warn!(error = %e, "Could not check free Drive space, uploading anyway");
+            return Ok(());
+        }
+    };
+    let size = tokio::fs::metadata(path).await?.len();
+    let needed = size.saturating_add(margin);
+    if available >= needed {
+        return Ok(());
+    }
+
+    error!(
+        available_bytes = available,
+        size_bytes = size,
+        margin_bytes = margin,
+        "Not enough free Drive space for upload"
+    );
+    Err(StorageQuotaExceeded {
+        file_name: artifact_name(path),
+    }
+    .into())
+}
+
+/// Drive `appProperties` attached to every uploaded file of `kind`.
+fn upload_properties(config: &Config, kind: BackupKind) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+    properties.insert("backup_type".to_string(), kind.as_str().to_string());
+    if let Some(host) = &config.host_tag {
+        properties.insert("source_host".to_string(), host.clone());
+    }
+    properties
+}
+
+/// `UPLOAD_DESCRIPTION_TEMPLATE` filled in for the backup at `path`.
+async fn upload_description(config: &Config, kind: BackupKind, path: &Path) -> Option<String> {
+    let template = config.upload_description_template.as_deref()?;
+    let host = match &config.host_tag {
+        Some(host) => host.clone(),
+        None => gethostname::gethostname().to_string_lossy().into_owned(),
+    };
+    let size = match tokio::fs::metadata(path).await {
+        Ok(m) => util::format::humanize_bytes(m.len() as f64),
+        Err(_) => "unknown size".to_string(),
+    };
+    Some(
+        template
+            .replace("{type}", kind.as_str())
+            .replace("{host}", &host)
+            .replace("{size}", &size)
+            .replace("{version}", build_info::PROJECT_VERSION),
+    )
+}
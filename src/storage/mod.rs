@@ -0,0 +1,121 @@
+pub mod b2;
+pub mod drive;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use crate::backup::{BackupArtifact, BackupKind};
+use crate::config::config::Config;
+use crate::drive::prune::PrunePolicy;
+
+/// Optional features a storage backend may support.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackendCapabilities {
+    /// Uploads can resume after an interrupted transfer.
+    pub resumable_upload: bool,
+    /// The backend reports a checksum of stored objects.
+    pub server_side_checksum: bool,
+    /// Objects can be downloaded by byte range.
+    pub ranged_download: bool,
+}
+
+/// A destination backups can be stored in.
+pub trait StorageBackend: Send + Sync {
+    /// Identifier used in `STORAGE_BACKEND`.
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn capabilities(&self) -> BackendCapabilities;
+
+    /// Whether `config` carries everything this backend needs.
+    fn is_configured(&self, config: &Config) -> bool;
+
+    /// Authenticate with the credentials in `config` and open a session.
+    fn connect<'a>(
+        &'a self,
+        config: &'a Config,
+    ) -> BoxFuture<'a, anyhow::Result<Box<dyn Storage + 'a>>>;
+}
+
+/// Where an uploaded artifact ended up.
+#[derive(Debug, Default)]
+pub struct Stored {
+    /// Every destination (Drive folder id, B2 bucket and prefix) that
+    /// received the artifact, primary first. Retention is applied to these.
+    pub destinations: Vec<String>,
+    /// Id of the copy at the primary destination.
+    pub id: Option<String>,
+    /// Link to that copy, when the backend has one.
+    pub link: Option<String>,
+}
+
+/// One stored backup as [`Storage::list`] reports it.
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub id: String,
+    pub name: String,
+    pub size_bytes: Option<u64>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// The stored size differs from the size recorded at upload.
+    pub suspect: bool,
+}
+
+/// An open session with a backend, through which backup runs upload, list
+/// and prune. Folders are given outermost first, e.g. `["DB_Backups", "app"]`.
+pub trait Storage: Send + Sync {
+    /// Upload `artifact` of `kind` into `folder` at the primary destination
+    /// and every mirror, with a checksum sidecar when
+    /// `UPLOAD_CHECKSUM_SIDECAR` is set. Succeeds if at least one destination
+    /// received it.
+    fn upload<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        artifact: &'a BackupArtifact,
+        kind: BackupKind,
+    ) -> BoxFuture<'a, anyhow::Result<Stored>>;
+
+    /// Backups in `folder` at the primary destination whose names start with
+    /// `name_prefix`, newest first. Checksum sidecars are left out.
+    fn list<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+        name_prefix: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<StoredObject>>>;
+
+    /// `folder` at the primary destination and every mirror, primary first,
+    /// in the form [`Stored::destinations`] uses.
+    fn destinations<'a>(
+        &'a self,
+        folder: &'a [&'a str],
+    ) -> BoxFuture<'a, anyhow::Result<Vec<String>>>;
+
+    /// Apply `policy` at `destination`, one of [`Storage::destinations`].
+    /// Returns the number of backups deleted.
+    fn prune<'a>(
+        &'a self,
+        destination: &'a str,
+        policy: &'a PrunePolicy<'a>,
+    ) -> BoxFuture<'a, anyhow::Result<u32>>;
+}
+
+/// Every backend compiled into this binary.
+pub fn available_backends() -> Vec<Box<dyn StorageBackend>> {
+    vec![Box::new(drive::DriveBackend), Box::new(b2::B2Backend)]
+}
+
+/// Names of every compiled-in backend, for config validation messages.
+pub fn backend_names() -> Vec<&'static str> {
+    available_backends().iter().map(|b| b.name()).collect()
+}
+
+/// Open a session with the backend `STORAGE_BACKEND` selects.
+pub async fn connect(config: &Config) -> anyhow::Result<Box<dyn Storage + '_>> {
+    let backend: &'static dyn StorageBackend = match config.storage_backend.as_str() {
+        "drive" => &drive::DriveBackend,
+        "b2" => &b2::B2Backend,
+        other => bail!("STORAGE_BACKEND '{}' is not a compiled-in backend", other),
+    };
+    backend.connect(config).await
+}
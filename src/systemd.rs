@@ -0,0 +1,241 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use sd_notify::NotifyState;
+use tracing::{debug, info, warn};
+
+/// Whether we were started by systemd with `Type=notify` (or a watchdog).
+pub fn is_enabled() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Tell systemd start-up has finished (`READY=1`).
+pub fn ready() {
+    send(&[NotifyState::Ready]);
+}
+
+/// Publish the current phase as the unit's `STATUS=` line.
+pub fn status(phase: &str) {
+    send(&[NotifyState::Status(phase)]);
+}
+
+/// Half the unit's `WatchdogSec=`, set by [`init_watchdog`]; `None` when no
+/// watchdog is configured.
+static WATCHDOG_INTERVAL: OnceLock<Option<Duration>> = OnceLock::new();
+/// Milliseconds after [`WATCHDOG_EPOCH`] of the last `WATCHDOG=1` ping.
+static LAST_PING_MS: AtomicU64 = AtomicU64::new(0);
+static WATCHDOG_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Read the unit's `WatchdogSec=`, if any. The watchdog is then fed by
+/// [`progress`] from the archive, dump and upload loops rather than by a
+/// timer, so a run that hangs stops pinging and systemd kills it.
+pub fn init_watchdog() {
+    let interval = WATCHDOG_INTERVAL.get_or_init(|| {
+        if !is_enabled() {
+            return None;
+        }
+        let mut usec: u64 = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) || usec == 0 {
+            return None;
+        }
+        Some(Duration::from_micros(usec / 2))
+    });
+    if let Some(interval) = interval {
+        WATCHDOG_EPOCH.get_or_init(Instant::now);
+        info!(interval = ?interval, "systemd watchdog enabled");
+    }
+}
+
+/// Note that the run is making progress, pinging `WATCHDOG=1` if half the
+/// watchdog interval has passed since the last ping. Cheap enough to call
+/// for every archive entry or buffer.
+pub fn progress() {
+    let Some(Some(interval)) = WATCHDOG_INTERVAL.get() else {
+        return;
+    };
+    let Some(epoch) = WATCHDOG_EPOCH.get() else {
+        return;
+    };
+    let now_ms = epoch.elapsed().as_millis() as u64;
+    let last_ms = LAST_PING_MS.load(Ordering::Relaxed);
+    if now_ms.saturating_sub(last_ms) < interval.as_millis() as u64 {
+        return;
+    }
+    // Only the caller that wins the swap pings
+    if LAST_PING_MS
+        .compare_exchange(last_ms, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        send(&[NotifyState::Watchdog]);
+    }
+}
+
+/// Sleep for `duration` on purpose (a blackout deferral or a retry backoff)
+/// while keeping the watchdog fed, since waiting is not a hang.
+pub async fn sleep(duration: Duration) {
+    let slice = match WATCHDOG_INTERVAL.get() {
+        Some(Some(interval)) => *interval,
+        _ => {
+            tokio::time::sleep(duration).await;
+            return;
+        }
+    };
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        progress();
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return;
+        }
+        tokio::time::sleep(slice.min(deadline - now)).await;
+    }
+}
+
+fn send(state: &[NotifyState]) {
+    if !is_enabled() {
+        return;
+    }
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!(error = %e, "Failed to notify systemd");
+    } else {
+        debug!("Sent systemd notification");
+    }
+}
+
+/// What `gen-systemd` fills into the generated unit pair.
+pub struct UnitOptions<'a> {
+    /// Unit name without the `.service`/`.timer` suffix.
+    pub name: &'a str,
+    pub binary: &'a Path,
+    /// Passed as `--config` ahead of `args`, so the service reads the same
+    /// config file as the command that generated it.
+    pub config: Option<&'a Path>,
+    /// Subcommand and flags to run, e.g. `all` or `minecraft --since-last`.
+    pub args: &'a str,
+    /// systemd calendar expression, e.g. `daily` or `*-*-* 03:30:00`.
+    pub on_calendar: &'a str,
+    pub env_file: &'a Path,
+    /// Where relative paths such as `./status.json` resolve; kept writable.
+    pub working_dir: &'a Path,
+    pub user: Option<&'a str>,
+    /// Writable on top of the working directory under `ProtectSystem=strict`.
+    pub read_write_paths: &'a [PathBuf],
+}
+
+/// A `.service` and `.timer` pair running the binary with `args` on
+/// `on_calendar`, each headed by the path it belongs at. The service is
+/// `Type=notify` with a watchdog, which the binary feeds while it makes
+/// progress, and is sandboxed
+/// read-only apart from the working directory and `read_write_paths`.
+pub fn render_units(opts: &UnitOptions) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# /etc/systemd/system/{}.service", opts.name);
+    let _ = writeln!(out, "[Unit]");
+    let _ = writeln!(
+        out,
+        "Description={} {}",
+        opts.name,
+        escape_specifiers(opts.args)
+    );
+    let _ = writeln!(out, "Wants=network-online.target");
+    let _ = writeln!(out, "After=network-online.target");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Service]");
+    let _ = writeln!(out, "Type=notify");
+    let _ = write!(
+        out,
+        "ExecStart={}",
+        exec_word(&opts.binary.to_string_lossy())
+    );
+    if let Some(config) = opts.config {
+        let _ = write!(out, " --config {}", exec_word(&config.to_string_lossy()));
+    }
+    // Already split into words by whoever wrote them
+    let _ = writeln!(out, " {}", escape_specifiers(opts.args).replace('$', "$$"));
+    let _ = writeln!(out, "EnvironmentFile={}", path_value(opts.env_file));
+    let _ = writeln!(out, "WorkingDirectory={}", path_value(opts.working_dir));
+    if let Some(user) = opts.user {
+        let _ = writeln!(out, "User={}", user);
+    }
+    // Pinged as archive, dump and upload work progresses
+    let _ = writeln!(out, "WatchdogSec=10min");
+    let _ = writeln!(out, "Nice=10");
+    let _ = writeln!(out, "IOSchedulingClass=idle");
+    let _ = writeln!(out, "NoNewPrivileges=yes");
+    let _ = writeln!(out, "ProtectSystem=strict");
+    let _ = writeln!(out, "ProtectHome=read-only");
+    let _ = writeln!(out, "PrivateTmp=yes");
+    let _ = writeln!(out, "PrivateDevices=yes");
+    let _ = writeln!(out, "ProtectKernelTunables=yes");
+    let _ = writeln!(out, "ProtectKernelModules=yes");
+    let _ = writeln!(out, "ProtectControlGroups=yes");
+    let _ = writeln!(out, "RestrictSUIDSGID=yes");
+    let _ = writeln!(out, "RestrictRealtime=yes");
+    let _ = writeln!(out, "LockPersonality=yes");
+    let _ = writeln!(out, "RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6");
+    let _ = write!(
+        out,
+        "ReadWritePaths={}",
+        quote_word(&path_value(opts.working_dir))
+    );
+    for path in opts.read_write_paths {
+        let _ = write!(out, " {}", quote_word(&path_value(path)));
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "# /etc/systemd/system/{}.timer", opts.name);
+    let _ = writeln!(out, "[Unit]");
+    let _ = writeln!(
+        out,
+        "Description=Run {} {} on schedule",
+        opts.name,
+        escape_specifiers(opts.args)
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Timer]");
+    let _ = writeln!(out, "OnCalendar={}", opts.on_calendar);
+    // Catch up on a run missed while the machine was off
+    let _ = writeln!(out, "Persistent=true");
+    let _ = writeln!(out, "RandomizedDelaySec=5min");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Install]");
+    let _ = writeln!(out, "WantedBy=timers.target");
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "# Install with: systemctl daemon-reload && systemctl enable --now {}.timer",
+        opts.name
+    );
+    out
+}
+
+/// `%` starts a unit specifier such as `%h`; `%%` is a literal one.
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// A path for a setting that takes exactly one, such as `EnvironmentFile=`.
+fn path_value(path: &Path) -> String {
+    escape_specifiers(&path.to_string_lossy())
+}
+
+/// Double-quote `word` for a setting systemd splits on whitespace, such as
+/// `ReadWritePaths=`, when it would otherwise be split or unescaped.
+fn quote_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return word.to_string();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// [`quote_word`] for `ExecStart=`, which also expands `$NAME`.
+fn exec_word(word: &str) -> String {
+    quote_word(&escape_specifiers(word).replace('$', "$$"))
+}
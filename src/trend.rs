@@ -0,0 +1,245 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::backup::BackupKind;
+use crate::status::{SizeSample, StatusFile};
+use crate::util::format::humanize_bytes;
+
+const SECS_PER_WEEK: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrendFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+/// Growth fitted over a target's size history.
+#[derive(Debug, Serialize)]
+pub struct Growth {
+    pub bytes_per_week: f64,
+    /// Relative to the latest sample.
+    pub percent_per_week: f64,
+    pub projected_at: DateTime<Utc>,
+    pub projected_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KindTrend {
+    pub backup_type: BackupKind,
+    /// The database, for each of `DB_NAMES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    pub samples: Vec<SizeSample>,
+    /// `None` with fewer than two samples or no time between them.
+    pub growth: Option<Growth>,
+}
+
+/// Build the trend for each of `targets`, a kind and the database it is
+/// labelled with, from the recorded history, projecting `horizon` past the
+/// latest sample.
+pub fn compute(
+    state: &StatusFile,
+    targets: &[(BackupKind, Option<&str>)],
+    horizon: Duration,
+) -> Vec<KindTrend> {
+    targets
+        .iter()
+        .map(|&(kind, database)| {
+            let samples = state.history(kind, database).to_vec();
+            let growth = fit_growth(&samples, horizon);
+            KindTrend {
+                backup_type: kind,
+                database: database.map(str::to_string),
+                samples,
+                growth,
+            }
+        })
+        .collect()
+}
+
+/// Theil–Sen line through (time, size): the slope is the median of the slopes
+/// between every pair of samples and the intercept the median residual, so a
+/// few outliers (a one-off huge dump, a half-empty world) do not skew it the
+/// way a least-squares fit would. History is capped at a thousand samples, so
+/// the quadratic number of pairs stays manageable.
+fn fit_growth(samples: &[SizeSample], horizon: Duration) -> Option<Growth> {
+    let first = samples.first()?;
+    let last = samples.last()?;
+    if samples.len() < 2 || last.at <= first.at {
+        return None;
+    }
+
+    // Seconds since the first sample keep the values small enough for f64
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|s| {
+            let x = (s.at - first.at).num_seconds() as f64;
+            (x, s.size_bytes as f64)
+        })
+        .collect();
+    let mut slopes = Vec::with_capacity(points.len() * (points.len() - 1) / 2);
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        for (x2, y2) in &points[i + 1..] {
+            if x2 != x1 {
+                slopes.push((y2 - y1) / (x2 - x1));
+            }
+        }
+    }
+    let slope = median(&mut slopes)?;
+    let mut residuals: Vec<f64> = points.iter().map(|(x, y)| y - slope * x).collect();
+    let intercept = median(&mut residuals)?;
+
+    let bytes_per_week = slope * SECS_PER_WEEK;
+    let percent_per_week = if last.size_bytes > 0 {
+        bytes_per_week / last.size_bytes as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    let horizon = chrono::Duration::from_std(horizon).unwrap_or(chrono::Duration::zero());
+    let projected_at = last.at + horizon;
+    let projected_x = (projected_at - first.at).num_seconds() as f64;
+    let projected_bytes = (intercept + slope * projected_x).max(0.0) as u64;
+
+    Some(Growth {
+        bytes_per_week,
+        percent_per_week,
+        projected_at,
+        projected_bytes,
+    })
+}
+
+/// Median of `values`, reordering them; `None` when empty.
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+pub fn print(trends: &[KindTrend], format: TrendFormat) -> anyhow::Result<()> {
+    match format {
+        TrendFormat::Text => print_text(trends),
+        TrendFormat::Json => {
+            let json = match serde_json::to_string_pretty(trends) {
+                Ok(j) => j,
+                Err(e) => anyhow::bail!("Failed to serialize trend: {}", e),
+            };
+            println!("{}", json);
+        }
+        TrendFormat::Csv => {
+            println!("backup_type,database,at,size_bytes");
+            for trend in trends {
+                for sample in &trend.samples {
+                    println!(
+                        "{},{},{},{}",
+                        trend.backup_type,
+                        trend.database.as_deref().unwrap_or_default(),
+                        sample.at.to_rfc3339(),
+                        sample.size_bytes
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_text(trends: &[KindTrend]) {
+    for trend in trends {
+        match &trend.database {
+            Some(db_name) => println!("{} ({}):", trend.backup_type, db_name),
+            None => println!("{}:", trend.backup_type),
+        }
+        if trend.samples.is_empty() {
+            println!("  no recorded backups");
+            continue;
+        }
+        for sample in &trend.samples {
+            println!(
+                "  {}  {:>12}",
+                sample.at.format("%Y-%m-%d %H:%M"),
+                humanize_bytes(sample.size_bytes as f64)
+            );
+        }
+        match &trend.growth {
+            Some(g) => println!(
+                "  growth {}/week ({:+.1}%/week), projected {} by {}",
+                humanize_bytes(g.bytes_per_week),
+                g.percent_per_week,
+                humanize_bytes(g.projected_bytes as f64),
+                g.projected_at.format("%Y-%m-%d")
+            ),
+            None => println!("  not enough history to estimate growth"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn weekly(sizes: &[u64]) -> Vec<SizeSample> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(week, &size_bytes)| SizeSample {
+                at: start + chrono::Duration::weeks(week as i64),
+                size_bytes,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_growth_is_fitted_and_projected() {
+        let samples = weekly(&[10 * GIB, 11 * GIB, 12 * GIB, 13 * GIB]);
+        let growth = fit_growth(&samples, Duration::from_secs(4 * 7 * 24 * 60 * 60)).unwrap();
+        assert!((growth.bytes_per_week - GIB as f64).abs() < 1.0);
+        assert!((growth.percent_per_week - 100.0 / 13.0).abs() < 1e-9);
+        assert_eq!(
+            growth.projected_at,
+            samples[3].at + chrono::Duration::weeks(4)
+        );
+        assert_eq!(growth.projected_bytes, 17 * GIB);
+    }
+
+    #[test]
+    fn single_outlier_does_not_skew_the_fit() {
+        let samples = weekly(&[10 * GIB, 11 * GIB, 500 * GIB, 13 * GIB, 14 * GIB]);
+        let growth = fit_growth(&samples, Duration::ZERO).unwrap();
+        assert!((growth.bytes_per_week - GIB as f64).abs() < 1.0);
+        assert_eq!(growth.projected_bytes, 14 * GIB);
+    }
+
+    #[test]
+    fn shrinking_projection_stops_at_zero() {
+        let samples = weekly(&[3 * GIB, 2 * GIB, GIB]);
+        let growth = fit_growth(&samples, Duration::from_secs(10 * 7 * 24 * 60 * 60)).unwrap();
+        assert!(growth.bytes_per_week < 0.0);
+        assert_eq!(growth.projected_bytes, 0);
+    }
+
+    #[test]
+    fn too_little_history_gives_no_fit() {
+        assert!(fit_growth(&[], Duration::ZERO).is_none());
+        assert!(fit_growth(&weekly(&[GIB]), Duration::ZERO).is_none());
+
+        let mut same_time = weekly(&[GIB, 2 * GIB]);
+        same_time[1].at = same_time[0].at;
+        assert!(fit_growth(&same_time, Duration::ZERO).is_none());
+    }
+}
@@ -0,0 +1,12 @@
+/// Render a byte count with a binary unit, e.g. `1.5 GiB`. Negative values
+/// (such as a shrinking growth rate) keep their sign.
+pub fn humanize_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, warn};
+
+/// Serialize `value` as pretty JSON and replace `path` with it atomically:
+/// the JSON is written and fsynced to a temp file in the same directory, then
+/// renamed over the target. A crash leaves either the old or the new file,
+/// never a truncated one.
+pub async fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let json = match serde_json::to_vec_pretty(value) {
+        Ok(j) => j,
+        Err(e) => bail!("Failed to serialize {}: {}", path.display(), e),
+    };
+    write_atomic(path, &json).await
+}
+
+/// Replace `path` with `contents` atomically, as [`write_json_atomic`] does.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        error!(error = %e, path = %dir.display(), "Failed to create directory");
+        bail!("Failed to create directory {}: {}", dir.display(), e);
+    }
+
+    let file_name = match path.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => bail!("{} has no file name", path.display()),
+    };
+    let temp_path = dir.join(format!(".{}.tmp-{}", file_name, std::process::id()));
+
+    if let Err(e) = write_synced(&temp_path, contents).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        error!(error = %e, path = %temp_path.display(), "Failed to write temp file");
+        bail!("Failed to write {}: {}", temp_path.display(), e);
+    }
+
+    if let Err(e) = tokio::fs::rename(&temp_path, path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        error!(error = %e, path = %path.display(), "Failed to move temp file into place");
+        bail!("Failed to replace {}: {}", path.display(), e);
+    }
+
+    // Persist the rename itself; losing it only means the previous version
+    // survives a crash, so this is not fatal
+    match tokio::fs::File::open(&dir).await {
+        Ok(d) => {
+            if let Err(e) = d.sync_all().await {
+                warn!(error = %e, path = %dir.display(), "Failed to fsync directory");
+            }
+        }
+        Err(e) => warn!(error = %e, path = %dir.display(), "Failed to open directory for fsync"),
+    }
+
+    Ok(())
+}
+
+async fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await
+}
+
+/// Compress `src` into `dest` with zstd at the level backups use, returning
+/// the compressed size. `src` is left in place.
+pub async fn zstd_compress(src: &Path, dest: &Path) -> anyhow::Result<u64> {
+    let src = src.to_path_buf();
+    let dest_path = dest.to_path_buf();
+    let dest = dest_path.clone();
+    let result = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+        let mut input = match std::fs::File::open(&src) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to open {}: {}", src.display(), e),
+        };
+        let output = match std::fs::File::create(&dest) {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to create {}: {}", dest.display(), e),
+        };
+        let mut encoder = match zstd::Encoder::new(output, 3) {
+            Ok(enc) => enc,
+            Err(e) => bail!("Failed to create zstd encoder: {}", e),
+        };
+        if let Err(e) = std::io::copy(&mut input, &mut encoder) {
+            bail!("Failed to compress {}: {}", src.display(), e);
+        }
+        let output = match encoder.finish() {
+            Ok(f) => f,
+            Err(e) => bail!("Failed to finalize zstd compression: {}", e),
+        };
+        match output.metadata() {
+            Ok(m) => Ok(m.len()),
+            Err(e) => bail!("Failed to stat {}: {}", dest.display(), e),
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Ok(size)) => Ok(size),
+        Ok(Err(e)) => {
+            let _ = tokio::fs::remove_file(&dest_path).await;
+            Err(e)
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&dest_path).await;
+            bail!("Compression task panicked: {}", e);
+        }
+    }
+}
+
+/// Free space on the filesystem holding a path, as `statvfs` reports it.
+#[derive(Debug, Clone, Copy)]
+pub struct FreeSpace {
+    /// Bytes available to unprivileged users.
+    pub available_bytes: u64,
+    /// Inodes available to unprivileged users; `None` on filesystems without
+    /// a fixed inode table (btrfs, many network filesystems), which report 0.
+    pub available_inodes: Option<u64>,
+}
+
+pub fn free_space(path: &Path) -> anyhow::Result<FreeSpace> {
+    let stat = match rustix::fs::statvfs(path) {
+        Ok(s) => s,
+        Err(e) => bail!("Failed to statvfs {}: {}", path.display(), e),
+    };
+    Ok(FreeSpace {
+        available_bytes: stat.f_bavail.saturating_mul(stat.f_frsize),
+        available_inodes: (stat.f_files > 0).then_some(stat.f_favail),
+    })
+}
+
+/// The file name of `path`, for logs and remote object names.
+pub fn artifact_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
+
+/// Remove a temp file once it has been uploaded; a failure is only logged.
+pub async fn remove_temp_file(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        error!(
+            error = %e,
+            path = %path.display(),
+            "Failed to remove temp file after upload"
+        );
+    }
+}